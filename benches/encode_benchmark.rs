@@ -2,17 +2,23 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use scarust::protocols::all::*;
 use scarust::*;
 
-fn test_encode() {
+fn test_encode() -> Vec<u8> {
     let p = Ether!().set_src(Value::Random)
         / Dot1Q!()
         / IP!().set_dst(Value::Random)
         / UDP!()
         / "asdfg".to_string();
-    let out = p.fill().encode();
+    p.fill().encode()
 }
 
 fn encode_benchmark(c: &mut Criterion) {
-    c.bench_function("encode ether+ip+udp", |b| b.iter(|| test_encode()));
+    c.bench_function("encode ether+ip+udp", |b| b.iter(test_encode));
+
+    let p = (Ether!() / IP!() / UDP!() / "asdfg".to_string()).fill();
+    let mut buf = [0u8; 1500];
+    c.bench_function("encode_to ether+ip+udp", |b| {
+        b.iter(|| p.encode_to(&mut buf).unwrap())
+    });
 }
 
 criterion_group!(benches, encode_benchmark);
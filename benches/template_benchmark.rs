@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn packet(sport: u16) -> LayerStack {
+    Ether!() / IP!(dst = "192.0.2.1") / UDP!(sport = sport, dport = 53) / "asdfg".to_string()
+}
+
+fn template_benchmark(c: &mut Criterion) {
+    let mut buf = [0u8; 1500];
+    let mut sport = 0u16;
+    c.bench_function("fill+encode_to ether+ip+udp, new sport", |b| {
+        b.iter(|| {
+            sport = sport.wrapping_add(1);
+            packet(sport).fill().encode_to(&mut buf).unwrap()
+        })
+    });
+
+    let t = packet(0).compile();
+    let slot = t.slot("UDP.sport").unwrap();
+    c.bench_function("template render ether+ip+udp, new sport", |b| {
+        b.iter(|| {
+            sport = sport.wrapping_add(1);
+            t.render(&mut buf, &[(slot, sport as u64)]).unwrap()
+        })
+    });
+    c.bench_function("template render by path ether+ip+udp, new sport", |b| {
+        b.iter(|| {
+            sport = sport.wrapping_add(1);
+            t.render(&mut buf, &[("UDP.sport", sport as u64)]).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, template_benchmark);
+criterion_main!(benches);
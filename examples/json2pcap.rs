@@ -0,0 +1,26 @@
+use scarust::protocols::pcap_file::*;
+
+/*
+ * Turn the output of pcap2json (possibly edited) back into a capture:
+ * cargo run --example json2pcap -- packets.json out.pcap
+ */
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (json_name, pcap_name) = match (args.next(), args.next()) {
+        (Some(j), Some(p)) => (j, p),
+        _ => {
+            eprintln!("usage: json2pcap <input.json> <output.pcap>");
+            std::process::exit(1);
+        }
+    };
+    let json = std::fs::read_to_string(&json_name).expect("can not read the JSON file");
+    let pcap = match pcapFile::from_json(&json) {
+        Ok(pcap) => pcap,
+        Err(e) => {
+            eprintln!("{}: {}", &json_name, e);
+            std::process::exit(1);
+        }
+    };
+    pcap.write(&pcap_name).expect("can not write the pcap file");
+    eprintln!("{} packets written to {}", pcap.d.packets.len(), &pcap_name);
+}
@@ -0,0 +1,21 @@
+use scarust::protocols::pcap_file::*;
+use scarust::tshark::*;
+use scarust::*;
+
+/* pcap2tshark <file.pcap> [json|pdml], the output tshark -T json -x or -T pdml would give */
+fn main() {
+    let fname = std::env::args()
+        .nth(1)
+        .expect("usage: pcap2tshark <file.pcap> [json|pdml]");
+    let format = std::env::args()
+        .nth(2)
+        .unwrap_or_else(|| "json".to_string());
+    let bytes = std::fs::read(&fname).expect("could not read the file");
+    let binding = PcapFile!().decode(&bytes).unwrap();
+    let pcap = binding.0.get_layer(PcapFile!()).unwrap();
+    let packets = tshark_packets_from_pcap(pcap);
+    match format.as_str() {
+        "pdml" => print!("{}", to_pdml(&packets)),
+        _ => print!("{}", to_tshark_json(&packets, true)),
+    }
+}
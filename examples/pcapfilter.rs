@@ -0,0 +1,39 @@
+use scarust::bpf::*;
+use scarust::protocols::pcap_file::*;
+use scarust::*;
+
+/*
+ * pcapfilter <in.pcap> <filter> [out.pcap], the packets matching a tcpdump filter;
+ * pcapfilter -d <filter> prints the program as "tcpdump -d" does
+ */
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let usage = "usage: pcapfilter <in.pcap> <filter> [out.pcap] | pcapfilter -d <filter>";
+    if args.len() < 3 {
+        panic!("{}", usage);
+    }
+    let prog = BpfProgram::compile(&args[2]).unwrap_or_else(|e| panic!("{}", e));
+    if args[1] == "-d" {
+        print!("{}", prog.dump());
+        return;
+    }
+    let bytes = std::fs::read(&args[1]).expect("could not read the file");
+    let binding = PcapFile!().decode(&bytes).unwrap();
+    let pcap = binding.0.get_layer(PcapFile!()).unwrap();
+    let mut out = pcap.clone();
+    out.d.packets = pcap
+        .d
+        .packets
+        .iter()
+        .filter(|p| prog.matches_packet(p))
+        .cloned()
+        .collect();
+    println!(
+        "{} of {} packets match",
+        out.d.packets.len(),
+        pcap.d.packets.len()
+    );
+    if let Some(fname) = args.get(3) {
+        out.write(fname).expect("could not write the file");
+    }
+}
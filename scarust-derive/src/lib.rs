@@ -36,6 +36,8 @@ struct NetprotoStructField {
     next: Option<(syn::Ident, syn::Ident)>,
     skip_encdec_unless: Option<syn::Expr>,
     set: Option<syn::Ident>,
+    bits: Option<(usize, usize)>,
+    alias: Option<syn::LitStr>,
 }
 
 macro_rules! vec_newtype {
@@ -54,6 +56,13 @@ struct FillNetprotoStructField(NetprotoStructField);
 struct EncodeNetprotoStructField(NetprotoStructField);
 struct DecodeNetprotoStructField(NetprotoStructField);
 struct ChainDecodeNetprotoStructField(NetprotoStructField);
+struct FieldInfoNetprotoStructField(NetprotoStructField);
+struct FieldDescNetprotoStructField(NetprotoStructField);
+struct GetFieldNetprotoStructField(NetprotoStructField);
+struct SetFieldNetprotoStructField(NetprotoStructField);
+struct ExpandCountNetprotoStructField(NetprotoStructField);
+struct ExpandFieldNetprotoStructField(NetprotoStructField);
+struct FuzzNetprotoStructField(NetprotoStructField);
 
 use proc_macro2::{Punct, Spacing, Span, TokenStream, TokenTree};
 use quote::{ToTokens, TokenStreamExt};
@@ -127,6 +136,7 @@ impl ToTokens for EncodeNetprotoStructField {
                 }
             }
         };
+        let tk2 = record_field_span(&self.0, quote! { out.len() }, tk2, &self.0.encode);
         tokens.extend(tk2);
     }
 }
@@ -208,10 +218,167 @@ impl ToTokens for DecodeNetprotoStructField {
                 #tk2
             }
         };
+        let tk2 = record_field_span(&self.0, quote! { ci }, tk2, &self.0.decode);
         tokens.extend(tk2);
     }
 }
 
+/*
+ * Wrap the encode or decode code of a field so that it records the bytes it covered
+ * into the dissection map. "Skip" fields only have a span if they declare their bits,
+ * it starts where the field that really encodes/decodes them does.
+ */
+fn record_field_span(
+    field: &NetprotoStructField,
+    pos: TokenStream,
+    code: TokenStream,
+    encdec: &Option<syn::Expr>,
+) -> TokenStream {
+    let name = field.name.clone();
+    let is_skip = encdec
+        .as_ref()
+        .map(|e| e.to_token_stream().to_string() == "Skip")
+        .unwrap_or(false);
+    let bits = match field.bits {
+        Some((bit_offset, bit_width)) => quote! { Some((#bit_offset, #bit_width)) },
+        None => quote! { None },
+    };
+    if is_skip {
+        if let Some((bit_offset, bit_width)) = field.bits {
+            let byte_len = (bit_offset + bit_width + 7) / 8;
+            quote! {
+                if let Some(m) = map.as_deref_mut() {
+                    m.add(layer_name, stringify!(#name), #pos, #pos + #byte_len, #bits);
+                }
+            }
+        } else {
+            quote! {}
+        }
+    } else {
+        quote! {
+            let field_start = #pos;
+            #code
+            if #pos > field_start {
+                if let Some(m) = map.as_deref_mut() {
+                    m.add(layer_name, stringify!(#name), field_start, #pos, #bits);
+                }
+            }
+        }
+    }
+}
+
+/* where the view getter of a field reads it */
+#[derive(Clone, Copy)]
+enum ViewPlace {
+    /* decoded by its type at the offset in the header */
+    Bytes(usize),
+    /* the bits of the big-endian number in header[offset..offset + len] */
+    Bits {
+        offset: usize,
+        len: usize,
+        shift: usize,
+        mask: u64,
+    },
+}
+
+/*
+ * The places of the fields up to the first one whose width is only known once decoded:
+ * a custom decoder without bits, a conditional field, or a type of no fixed width.
+ * The Skip fields with bits and the field which decodes them are a group of
+ * whole bytes, as in record_field_span().
+ */
+fn view_field_places(
+    fields: &[NetprotoStructField],
+    is_decode_skip: &dyn Fn(&NetprotoStructField) -> bool,
+) -> Vec<Option<ViewPlace>> {
+    let value_typ = |f: &NetprotoStructField| -> Option<String> {
+        if !f.is_value {
+            return None;
+        }
+        let iter = f.ty.clone().into_iter().skip(2);
+        let len = iter.clone().count();
+        Some(iter.take(len - 1).collect::<TokenStream>().to_string())
+    };
+    let width = |f: &NetprotoStructField| -> Option<usize> {
+        match value_typ(f)?.as_str() {
+            "u8" | "i8" => Some(1),
+            "u16" | "i16" => Some(2),
+            "u32" | "i32" | "Ipv4Address" => Some(4),
+            "u64" | "i64" => Some(8),
+            "MacAddr" => Some(6),
+            _ => None,
+        }
+    };
+    let bits_typ = |f: &NetprotoStructField| {
+        matches!(
+            value_typ(f).as_deref(),
+            Some("u8" | "u16" | "u32" | "u64" | "bool")
+        )
+    };
+    let mut places = vec![None; fields.len()];
+    let mut offset = Some(0);
+    let mut i = 0;
+    while i < fields.len() {
+        let f = &fields[i];
+        if f.skip_encdec_unless.is_some() {
+            offset = None;
+        }
+        let at = match offset {
+            Some(at) => at,
+            None => break,
+        };
+        if f.bits.is_some() {
+            // the Skip fields with bits, up to the one which decodes them
+            let end = (i..fields.len())
+                .find(|&j| fields[j].bits.is_none() || !is_decode_skip(&fields[j]));
+            match end {
+                Some(j) if fields[j].bits.is_some() && fields[j].decode.is_some() => {
+                    let group = &fields[i..=j];
+                    let nbits = group
+                        .iter()
+                        .filter_map(|g| g.bits)
+                        .map(|(o, w)| o + w)
+                        .max()
+                        .unwrap_or(0);
+                    let len = (nbits + 7) / 8;
+                    if len > 8 || group.iter().any(|g| g.skip_encdec_unless.is_some()) {
+                        break;
+                    }
+                    for (k, g) in group.iter().enumerate() {
+                        let (bit_offset, bit_width) = g.bits.unwrap();
+                        if bits_typ(g) && bit_width < 64 {
+                            places[i + k] = Some(ViewPlace::Bits {
+                                offset: at,
+                                len,
+                                shift: len * 8 - bit_offset - bit_width,
+                                mask: (1u64 << bit_width) - 1,
+                            });
+                        }
+                    }
+                    offset = Some(at + len);
+                    i = j + 1;
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        if is_decode_skip(f) {
+            // no bytes of its own, its value comes from a later decoder
+            i += 1;
+            continue;
+        }
+        match (&f.decode, width(f)) {
+            (None, Some(w)) => {
+                places[i] = Some(ViewPlace::Bytes(at));
+                offset = Some(at + w);
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    places
+}
+
 impl ToTokens for ChainDecodeNetprotoStructField {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let name = self.0.name.clone();
@@ -234,11 +401,15 @@ impl ToTokens for ChainDecodeNetprotoStructField {
                 Span::call_site(),
             );
             quote! {
-                if let Some(next) = (*#registry_lookup_name).get(&#varname) {
-                    if let Some((decode, delta)) = (next.MakeLayer)().decode(&buf[ci..]) {
+                let registered = (*#registry_lookup_name).get(&#varname).map(|next| next.MakeLayer);
+                if let Some(make_next) = ctx.options.next_layer(stringify!(#next_tbl), #varname as u64, registered) {
+                    let map_mark = map.as_deref_mut().map(|m| m.enter(map_base + ci, map_layer_index + layers.len()));
+                    if let Some((decode, delta)) = make_next().decode_into_map_with_context(&buf[ci..], map.as_deref_mut(), ctx) {
                         let mut down_layers = decode.layers;
                         layers.append(&mut down_layers);
                         ci += delta;
+                    } else if let (Some(m), Some(mark)) = (map.as_deref_mut(), map_mark) {
+                        m.rollback(mark);
                     }
                 }
             }
@@ -249,6 +420,183 @@ impl ToTokens for ChainDecodeNetprotoStructField {
     }
 }
 
+impl ToTokens for FieldInfoNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+
+        // the fields selecting the next layer also show that layer's name
+        let symbolic = if let Some((next_tbl, next_key)) = &self.0.next {
+            let registry_lookup_name = Ident::new(
+                &format!("{}_BY_{}", &next_tbl, &next_key),
+                Span::call_site(),
+            );
+            quote! {
+                match &self.#name {
+                    Value::Set(v) => (*#registry_lookup_name).get(v).map(|next| next.Name),
+                    _ => None,
+                }
+            }
+        } else {
+            quote! { None }
+        };
+
+        let tk2 = quote! {
+            FieldInfo {
+                name: stringify!(#name),
+                value: format!("{:?}", &self.#name),
+                symbolic: #symbolic,
+            },
+        };
+        tokens.extend(tk2);
+    }
+}
+
+impl ToTokens for FieldDescNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+        let typ = self.0.ty.clone();
+        // "Value < u8 >" => "Value<u8>"
+        let typ_str = typ.to_string().replace(' ', "");
+        let get_def_X = Ident::new(&format!("get_default_{}", &name), Span::call_site());
+
+        let default = field_to_field_value(&self.0, quote! { Self::#get_def_X() });
+        let tk2 = quote! {
+            FieldDesc {
+                name: stringify!(#name),
+                typ: #typ_str,
+                default: #default,
+            },
+        };
+        tokens.extend(tk2);
+    }
+}
+
+impl ToTokens for GetFieldNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+
+        let pattern = match &self.0.alias {
+            Some(alias) => quote! { stringify!(#name) | #alias },
+            None => quote! { stringify!(#name) },
+        };
+
+        let value = field_to_field_value(&self.0, quote! { self.#name });
+        let tk2 = quote! {
+            #pattern => Some(#value),
+        };
+        tokens.extend(tk2);
+    }
+}
+
+impl ToTokens for SetFieldNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+        let typ = self.0.ty.clone();
+
+        let pattern = match &self.0.alias {
+            Some(alias) => quote! { stringify!(#name) | #alias },
+            None => quote! { stringify!(#name) },
+        };
+
+        let conv = field_conv(&self.0);
+        let from_field_value = if self.0.is_value {
+            quote! { <#typ>::from_field_value_with(&value, #conv) }
+        } else {
+            quote! { (#conv.from)(&value) }
+        };
+        let tk2 = quote! {
+            #pattern => {
+                match #from_field_value {
+                    Some(v) => {
+                        self.#name = v;
+                        Ok(())
+                    }
+                    None => Err(FieldAccessError::BadValue { field: name.to_string(), value }),
+                }
+            }
+        };
+        tokens.extend(tk2);
+    }
+}
+
+fn is_value_field(f: &NetprotoStructField) -> bool {
+    f.is_value && f.ty.to_string().starts_with("Value")
+}
+
+/* the FieldConv of the field's type, or of the type in its Value: DynField's if it has it */
+fn field_conv(f: &NetprotoStructField) -> TokenStream {
+    let typ = f.ty.clone();
+    let conv_typ: TokenStream = if f.is_value {
+        let iter = typ.into_iter().skip(2);
+        let len = iter.clone().count();
+        iter.take(len - 1).collect()
+    } else {
+        typ
+    };
+    quote! { (&&FieldConvProbe::<#conv_typ>::new()).field_conv() }
+}
+
+/* the field as a FieldValue */
+fn field_to_field_value(f: &NetprotoStructField, val: TokenStream) -> TokenStream {
+    let conv = field_conv(f);
+    if f.is_value {
+        quote! { (#val).to_field_value_with(#conv) }
+    } else {
+        quote! { (#conv.to)(&(#val)) }
+    }
+}
+
+impl ToTokens for ExpandCountNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+        if !is_value_field(&self.0) {
+            return;
+        }
+        let value_conv = field_conv(&self.0);
+        let tk2 = quote! {
+            if let Some(n) = self.#name.expand_len_with(#value_conv) {
+                out.push((stringify!(#name), n));
+            }
+        };
+        tokens.extend(tk2);
+    }
+}
+
+impl ToTokens for ExpandFieldNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+        if !is_value_field(&self.0) {
+            return;
+        }
+        let value_conv = field_conv(&self.0);
+        let tk2 = quote! {
+            stringify!(#name) => match self.#name.expand_nth_with(n, #value_conv) {
+                Some(v) => {
+                    self.#name = Value::Set(v);
+                    true
+                }
+                None => false,
+            },
+        };
+        tokens.extend(tk2);
+    }
+}
+
+impl ToTokens for FuzzNetprotoStructField {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = self.0.name.clone();
+        if !is_value_field(&self.0) {
+            return;
+        }
+        let tk2 = quote! {
+            if !matches!(self.#name, Value::Auto) {
+                self.#name = Value::Random;
+            }
+        };
+        tokens.extend(tk2);
+    }
+}
+
 impl ToTokens for ImplDefaultNetprotoStructField {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let name = self.0.name.clone();
@@ -274,6 +622,7 @@ impl ToTokens for ImplDefaultNetprotoStructField {
 impl ToTokens for FillNetprotoStructField {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let name = self.0.name.clone();
+        let value_conv = field_conv(&self.0);
         let varname = Ident::new(&format!("__{}", &name), Span::call_site());
         let conv = self.0.conv.clone();
         let typ = self.0.ty.clone();
@@ -305,6 +654,30 @@ impl ToTokens for FillNetprotoStructField {
             quote! {}
         };
 
+        // Dyn values are computed from the stack, a List or Range which was not
+        // expanded is filled with its first value
+        let fill_computed_variants = quote! {
+            Value::Dyn(f) => {
+                let ctx = FieldContext { stack, layer_index: my_index, counter: fill_ctx.counter };
+                let #varname: #fixed_typ = f(&ctx);
+                out = out.#name(#varname);
+            },
+            Value::RandomWith(c) => {
+                if let Some(#varname) = c.sample_with(&mut *fill_ctx.rng, #value_conv) {
+                    out = out.#name(#varname);
+                }
+            },
+            Value::List(v) => {
+                if let Some(#varname) = v.first().cloned() {
+                    out = out.#name(#varname);
+                }
+            },
+            Value::Range(lo, _) => {
+                let #varname: #fixed_typ = lo.clone();
+                out = out.#name(#varname);
+            },
+        };
+
         let fill_func = if let Some(fill_tok) = self.0.fill.clone() {
             let fill_expr = {
                 match fill_tok {
@@ -338,9 +711,7 @@ impl ToTokens for FillNetprotoStructField {
                         #set_statement
                     },
                     Value::Random => {
-                        use rand::Rng;
-                        let mut rng = rand::thread_rng();
-                        let #varname: #fixed_typ = rng.gen();
+                        let #varname: #fixed_typ = fill_ctx.gen();
                         out = out.#name(#varname);
                     },
                     Value::Func(x) => {
@@ -350,6 +721,7 @@ impl ToTokens for FillNetprotoStructField {
                     Value::Set(x) => {
                         // Already taken care by clone
                     }
+                    #fill_computed_variants
                 }
             }
         } else {
@@ -368,14 +740,13 @@ impl ToTokens for FillNetprotoStructField {
                             out = out.#name(#varname);
                         },
                         Value::Random => {
-                            use rand::Rng;
-                            let mut rng = rand::thread_rng();
-                            let #varname: #fixed_typ = rng.gen();
+                            let #varname: #fixed_typ = fill_ctx.gen();
                             out = out.#name(#varname);
                         },
                         Value::Set(x) => {
                             // Already taken care by clone
                         },
+                        #fill_computed_variants
                     }
                 }
             } else {
@@ -438,7 +809,17 @@ impl ToTokens for FieldMethodsNetprotoStructField {
             }
         };
 
-        let derived_assignment_func = if self.0.add_conversion {
+        // numbers and addresses can also be given as a list or a range of values
+        let expandable = ["u8", "u16", "u32", "u64", "Ipv4Address", "MacAddr"]
+            .contains(&fixed_typ.to_string().replace(' ', "").as_str());
+        let derived_assignment_func = if is_value_field(&self.0) && expandable {
+            quote! {
+                pub fn #name<V: IntoValue<#fixed_typ>>(mut self, #name: V) -> Self {
+                    self.#name = #name.into_value();
+                    self
+                }
+            }
+        } else if self.0.add_conversion {
             quote! {
                 #do_assignment_with_conversion
             }
@@ -558,6 +939,7 @@ impl ToTokens for LayerRegistryEntry {
                 Name: stringify!(#macroname),
                 #key: #value,
                 MakeLayer: #make_name_layer,
+                View: #name::VIEW,
             };
         };
         tokens.extend(tk);
@@ -568,7 +950,7 @@ impl ToTokens for LayerRegistryEntry {
 struct LayerRegistry {
     place: syn::Ident,
     key: syn::Ident,
-    value: syn::Ident,
+    value: syn::Type,
 }
 impl ToTokens for LayerRegistry {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
@@ -587,6 +969,7 @@ impl ToTokens for LayerRegistry {
                 pub Name: &'static str,
                 pub #key: #value,
                 pub MakeLayer: fn() -> Box<dyn Layer>,
+                pub View: ViewKind,
             }
             #[distributed_slice]
             pub static #place: [#desc_name];
@@ -623,6 +1006,9 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
     let mut nproto_packed = None::<usize>;
     let mut nproto_register: Vec<LayerRegistryEntry> = vec![];
     let mut nproto_registries: Vec<LayerRegistry> = vec![];
+    let mut nproto_heuristic: Vec<(syn::Ident, syn::Expr)> = vec![];
+    let mut nproto_heuristics = None::<syn::Ident>;
+    let mut nproto_decode_hook = None::<syn::Expr>;
     let default_encoder: TokenStream = "BinaryBigEndian".parse().unwrap();
     let default_decoder: TokenStream = "BinaryBigEndian".parse().unwrap();
     let mut nproto_encoder = default_encoder;
@@ -630,6 +1016,11 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
     let mut nproto_decode_suppress = false;
     let mut nproto_encode_suppress = false;
     let mut nproto_greedy_decode = true;
+    let mut nproto_verify = None::<syn::Expr>;
+    let mut nproto_patch = None::<syn::Expr>;
+    let mut nproto_dependent_fields = None::<syn::Expr>;
+    let mut nproto_decode_len = None::<syn::Expr>;
+    let mut nproto_summary = None::<syn::Expr>;
 
     // let source = input.to_string();
     // Parse the string representation into a syntax tree
@@ -672,6 +1063,31 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                     });
                     return Ok(());
                 }
+                // #[nproto(heuristic(UDP_HEURISTICS = probe_fn))], guess this layer from a payload
+                if meta.path.is_ident("heuristic") {
+                    let content;
+                    parenthesized!(content in meta.input);
+                    let place: syn::Ident = content.parse()?;
+                    let eq_token: Token![=] = content.parse()?;
+                    let probe: syn::Expr = content.parse()?;
+                    nproto_heuristic.push((place, probe));
+                    return Ok(());
+                }
+                // #[nproto(decode_hook = _expr_)], called with the DecodeContext once the fields are decoded
+                if meta.path.is_ident("decode_hook") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_decode_hook = Some(val_expr);
+                    return Ok(());
+                }
+                // #[nproto(heuristics(UDP_HEURISTICS))], where to look when no registry has the payload
+                if meta.path.is_ident("heuristics") {
+                    let content;
+                    parenthesized!(content in meta.input);
+                    let place: syn::Ident = content.parse()?;
+                    nproto_heuristics = Some(place);
+                    return Ok(());
+                }
                 if meta.path.is_ident("registry") {
                     let content;
                     parenthesized!(content in meta.input);
@@ -682,7 +1098,7 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                     let key: syn::Ident = content.parse()?;
                     println!("KEY: {:?}", &key);
                     let eq_token: Option<Token![:]> = content.parse()?;
-                    let value: syn::Ident = content.parse()?;
+                    let value: syn::Type = content.parse()?;
                     println!("VAL: {:?}", &value);
                     let name = name.clone();
                     nproto_registries.push(LayerRegistry { place, key, value });
@@ -697,6 +1113,41 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                     nproto_align = Some(n);
                     return Ok(());
                 }
+                // #[nproto(summary = _expr_)]
+                if meta.path.is_ident("summary") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_summary = Some(val_expr);
+                    return Ok(());
+                }
+                // #[nproto(decode_len = _expr_)]
+                if meta.path.is_ident("decode_len") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_decode_len = Some(val_expr);
+                    return Ok(());
+                }
+                // #[nproto(verify = _expr_)]
+                if meta.path.is_ident("verify") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_verify = Some(val_expr);
+                    return Ok(());
+                }
+                // #[nproto(patch = _expr_)]
+                if meta.path.is_ident("patch") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_patch = Some(val_expr);
+                    return Ok(());
+                }
+                // #[nproto(dependent_fields = _expr_)]
+                if meta.path.is_ident("dependent_fields") {
+                    let eq_token: Option<Token![=]> = meta.input.parse()?;
+                    let val_expr: syn::Expr = meta.input.parse()?;
+                    nproto_dependent_fields = Some(val_expr);
+                    return Ok(());
+                }
 
                 // #[nproto(encoder(X))]
                 if meta.path.is_ident("encoder") {
@@ -757,6 +1208,13 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
     let encode_fields_idents = vec_newtype!(idents, EncodeNetprotoStructField);
     let decode_fields_idents = vec_newtype!(idents, DecodeNetprotoStructField);
     let chained_fields_idents = vec_newtype!(idents, ChainDecodeNetprotoStructField);
+    let field_info_idents = vec_newtype!(idents, FieldInfoNetprotoStructField);
+    let field_desc_idents = vec_newtype!(idents, FieldDescNetprotoStructField);
+    let get_field_idents = vec_newtype!(idents, GetFieldNetprotoStructField);
+    let set_field_idents = vec_newtype!(idents, SetFieldNetprotoStructField);
+    let expand_count_idents = vec_newtype!(idents, ExpandCountNetprotoStructField);
+    let expand_field_idents = vec_newtype!(idents, ExpandFieldNetprotoStructField);
+    let fuzz_idents = vec_newtype!(idents, FuzzNetprotoStructField);
 
     let assign_in_macro = quote! {
                     // $ip.$ident = TryFrom::try_from($e).unwrap();
@@ -764,9 +1222,59 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                     $ip = $ip.$ident($e);
     };
 
+    let decode_hook_code = if let Some(hook) = &nproto_decode_hook {
+        quote! {
+            #hook(&mut layer, buf, ci, ctx);
+        }
+    } else {
+        quote! {}
+    };
+
+    let heuristic_records: Vec<TokenStream> = nproto_heuristic
+        .iter()
+        .map(|(place, probe)| {
+            let record_name = Ident::new(
+                &format!("{}_{}_HeuristicRecord", &name, &place),
+                Span::call_site(),
+            );
+            quote! {
+                #[distributed_slice(#place)]
+                static #record_name: HeuristicItem = HeuristicItem {
+                    name: stringify!(#macroname),
+                    probe: #probe,
+                    make_layer: #make_name_layer,
+                };
+            }
+        })
+        .collect();
+
+    // only when the registries had nothing for the payload
+    let heuristic_decode_code = if let Some(place) = &nproto_heuristics {
+        quote! {
+            if layers.len() == 1 && ci < buf.len() {
+                if let Some(make_next) = ctx.options.heuristic_layer(&#place, &buf[ci..]) {
+                    let map_mark = map.as_deref_mut().map(|m| m.enter(map_base + ci, map_layer_index + layers.len()));
+                    if let Some((decode, delta)) = make_next().decode_into_map_with_context(&buf[ci..], map.as_deref_mut(), ctx) {
+                        let mut down_layers = decode.layers;
+                        layers.append(&mut down_layers);
+                        ci += delta;
+                    } else if let (Some(m), Some(mark)) = (map.as_deref_mut(), map_mark) {
+                        m.rollback(mark);
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     let greedy_decode_code = if nproto_greedy_decode {
         quote! {
                 if ci < buf.len() {
+                    if let Some(m) = map.as_deref_mut() {
+                        m.enter(map_base, map_layer_index + layers.len());
+                        m.add("raw", "data", ci, buf.len(), None);
+                    }
                     let decode = self.decode_as_raw(&buf[ci..]);
                     let mut down_layers = decode.layers;
                     layers.append(&mut down_layers);
@@ -777,38 +1285,304 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
         quote! {}
     };
 
+    // bytes beyond the length declared by the layer itself are padding, not payload
+    let (decode_len_limit_code, decode_len_padding_code) = if let Some(decode_len_expr) =
+        &nproto_decode_len
+    {
+        (
+            quote! {
+                let full_buf = buf;
+                let buf = match #decode_len_expr(&layer) {
+                    Some(declared_len) if declared_len >= ci && declared_len < full_buf.len() => &full_buf[..declared_len],
+                    _ => full_buf,
+                };
+            },
+            quote! {
+                if buf.len() < full_buf.len() {
+                    if let Some(m) = map.as_deref_mut() {
+                        m.enter(map_base, map_layer_index + layers.len());
+                        m.add("padding", "data", buf.len(), full_buf.len(), None);
+                    }
+                    let decode = self.decode_as_padding(&full_buf[buf.len()..], layers.len());
+                    let mut down_layers = decode.layers;
+                    layers.append(&mut down_layers);
+                    ci = full_buf.len();
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
     let decode_function = if nproto_decode_suppress {
         quote! {}
     } else {
         quote! {
             fn decode(&self, buf: &[u8]) -> Option<(LayerStack, usize)> {
-                type DDD = BinaryBigEndian;
-                use std::collections::HashMap;
-                let mut ci: usize = 0;
-                let mut layer = #macroname!();
-
-                #(#decode_fields_idents)*
+                self.decode_with_decoder_and_map::<BinaryBigEndian>(buf, None, &mut DecodeContext::new())
+            }
+            fn decode_into_map(&self, buf: &[u8], map: Option<&mut DissectionMap>) -> Option<(LayerStack, usize)> {
+                self.decode_with_decoder_and_map::<BinaryBigEndian>(buf, map, &mut DecodeContext::new())
+            }
+            fn decode_into_map_with_context(&self, buf: &[u8], map: Option<&mut DissectionMap>, ctx: &mut DecodeContext) -> Option<(LayerStack, usize)> {
+                self.decode_with_decoder_and_map::<BinaryBigEndian>(buf, map, ctx)
+            }
+        }
+    };
+    let encode_function = if nproto_encode_suppress {
+        quote! {}
+    } else {
+        quote! {
+            fn encode(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> Vec<u8> {
+                self.encode_with_encoder_and_map::<BinaryBigEndian>(stack, my_index, encoded_data, None)
+            }
+            fn encode_into_map(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec, map: Option<&mut DissectionMap>) -> Vec<u8> {
+                self.encode_with_encoder_and_map::<BinaryBigEndian>(stack, my_index, encoded_data, map)
+            }
+        }
+    };
 
-                let mut layers = vec![layer.embox()];
+    let summary_function = if let Some(summary_expr) = &nproto_summary {
+        quote! {
+            fn summary(&self) -> String {
+                #summary_expr(self)
+            }
+        }
+    } else {
+        quote! {}
+    };
 
-                #(#chained_fields_idents)*
+    let verify_function = if let Some(verify_expr) = &nproto_verify {
+        quote! {
+            fn verify(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> Vec<FieldMismatch> {
+                #verify_expr(self, stack, my_index, encoded_data)
+            }
+        }
+    } else {
+        quote! {}
+    };
 
-                #greedy_decode_code
+    let patch_function = if let Some(patch_expr) = &nproto_patch {
+        quote! {
+            fn patch_encoded(&self, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+                #patch_expr(self, stack, my_index, header, payload)
+            }
+        }
+    } else {
+        quote! {}
+    };
 
-                Some((LayerStack { layers, filled: true }, ci))
+    let dependent_fields_function = if let Some(dependent_expr) = &nproto_dependent_fields {
+        quote! {
+            fn dependent_fields(&self, stack: &LayerStack, my_index: usize, layout: &PacketLayout) -> Vec<DependentField> {
+                #dependent_expr(self, stack, my_index, layout)
             }
         }
+    } else {
+        quote! {}
     };
-    let encode_function = if nproto_encode_suppress {
+
+    // every layer can be made by its name, except the registries sentinel
+    let name_registration = if nproto_registries.is_empty() {
+        let record_name = Ident::new(
+            &format!("{}_LAYER_NAMES_RegistrationRecord", &name),
+            Span::call_site(),
+        );
+        quote! {
+            #[distributed_slice(LAYER_NAMES)]
+            static #record_name: LAYER_NAMES_Item = LAYER_NAMES_Item {
+                Name: stringify!(#macroname),
+                LayerName: stringify!(#macroname),
+                MakeLayer: #make_name_layer,
+                View: #name::VIEW,
+            };
+        }
+    } else {
         quote! {}
+    };
+
+
+    // a read-only view of the layer in a buffer, its fields decoded when asked for
+    let view_name = Ident::new(&format!("{}View", &name), Span::call_site());
+    let view_step_fn = Ident::new(&format!("view_step_{}", &name), Span::call_site());
+    let is_decode_skip = |f: &NetprotoStructField| {
+        f.decode
+            .as_ref()
+            .map(|e| e.to_token_stream().to_string() == "Skip")
+            .unwrap_or(false)
+    };
+    let view_decode_idents: Vec<TokenStream> = idents
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let decode = DecodeNetprotoStructField(f.clone());
+            quote! {
+                #decode
+                if upto == #i {
+                    return Some((layer, ci));
+                }
+            }
+        })
+        .collect();
+    // where each field is in the header, when that is known without decoding the fields before it
+    let view_places = view_field_places(&idents, &is_decode_skip);
+    let view_getter_idents: Vec<TokenStream> = idents
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let fname = f.name.clone();
+            // a field decoded by a later one has its value once that one is decoded
+            let upto = match (i..idents.len()).find(|&j| !is_decode_skip(&idents[j])) {
+                Some(j) => quote! { #j },
+                None => quote! { usize::MAX },
+            };
+            let decoded = quote! {
+                #name::view_fields_upto::<BinaryBigEndian>(self.buf, #upto)
+            };
+            if !f.is_value {
+                let typ = f.ty.clone();
+                return quote! {
+                    pub fn #fname(&self) -> #typ {
+                        #decoded.map(|(layer, _)| layer).unwrap_or_else(|| #macroname!()).#fname
+                    }
+                };
+            }
+            let iter = f.ty.clone().into_iter().skip(2);
+            let len = iter.clone().count();
+            let fixed_typ: TokenStream = iter.take(len - 1).collect();
+            let body = match view_places[i] {
+                Some(ViewPlace::Bytes(offset)) => quote! {
+                    match self.buf.get(#offset..).and_then(|b| <#fixed_typ as Decode>::decode::<BinaryBigEndian>(b)) {
+                        Some((v, _)) => v,
+                        None => Default::default(),
+                    }
+                },
+                Some(ViewPlace::Bits { offset, len, shift, mask }) => {
+                    let bits = quote! {
+                        self.buf.iter().skip(#offset).take(#len).fold(0u64, |acc, b| acc << 8 | *b as u64) >> #shift & #mask
+                    };
+                    if fixed_typ.to_string() == "bool" {
+                        quote! { (#bits) != 0 }
+                    } else {
+                        quote! { (#bits) as #fixed_typ }
+                    }
+                }
+                None => quote! {
+                    #decoded.map(|(layer, _)| layer.#fname.value()).unwrap_or_default()
+                },
+            };
+            quote! {
+                pub fn #fname(&self) -> #fixed_typ {
+                    #body
+                }
+            }
+        })
+        .collect();
+    let view_next_idents: Vec<TokenStream> = idents
+        .iter()
+        .filter_map(|f| {
+            let (next_tbl, next_key) = f.next.as_ref()?;
+            let fname = f.name.clone();
+            let registry_lookup_name = Ident::new(
+                &format!("{}_BY_{}", &next_tbl, &next_key),
+                Span::call_site(),
+            );
+            Some(quote! {
+                if next.is_none() {
+                    next = (*#registry_lookup_name).get(&layer.#fname.value()).map(|item| item.View);
+                }
+            })
+        })
+        .collect();
+    let view_len_code = if let Some(decode_len_expr) = &nproto_decode_len {
+        quote! {
+            match #decode_len_expr(&layer) {
+                Some(declared_len) if declared_len >= ci && declared_len < buf.len() => declared_len,
+                _ => buf.len(),
+            }
+        }
+    } else {
+        quote! { buf.len() }
+    };
+    let view_step_code = if nproto_decode_suppress {
+        quote! { None }
     } else {
         quote! {
-            fn encode(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> Vec<u8> {
-                let layer = self;
-                type EEE = BinaryBigEndian;
-                let mut out: Vec<u8> = vec![];
-                #(#encode_fields_idents)*
-                out
+            let (layer, ci) = #name::view_fields_upto::<BinaryBigEndian>(buf, usize::MAX)?;
+            let mut next: Option<ViewKind> = None;
+            #(#view_next_idents)*
+            Some(ViewStep {
+                header_len: ci,
+                len: #view_len_code,
+                next,
+            })
+        }
+    };
+    let view_code = quote! {
+        fn #view_step_fn(buf: &[u8]) -> Option<ViewStep> {
+            #view_step_code
+        }
+
+        impl #name {
+            pub const VIEW: ViewKind = ViewKind {
+                name: stringify!(#name),
+                type_id: TypeId::of::<#name>,
+                step: #view_step_fn,
+                make_layer: #make_name_layer,
+            };
+
+            /* decode the fields up to the one at the index, for the view getters */
+            #[doc(hidden)]
+            pub fn view_fields_upto<DDD: Decoder>(buf: &[u8], upto: usize) -> Option<(Self, usize)> {
+                let layer_name: &'static str = stringify!(#name);
+                let mut map: Option<&mut DissectionMap> = None;
+                let mut ci: usize = 0;
+                let mut layer = #macroname!();
+                #(#view_decode_idents)*
+                Some((layer, ci))
+            }
+        }
+
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug)]
+        pub struct #view_name<'a> {
+            buf: &'a [u8],
+            header_len: usize,
+        }
+
+        // a "len" getter is the length field of the header
+        #[allow(clippy::len_without_is_empty)]
+        impl<'a> #view_name<'a> {
+            pub fn new(buf: &'a [u8]) -> Option<Self> {
+                let step = #view_step_fn(buf)?;
+                Some(#view_name {
+                    buf: &buf[..step.len],
+                    header_len: step.header_len,
+                })
+            }
+            pub fn header(&self) -> &'a [u8] {
+                &self.buf[..self.header_len]
+            }
+            pub fn payload(&self) -> &'a [u8] {
+                &self.buf[self.header_len..]
+            }
+            pub fn to_layer(&self) -> #name {
+                #name::view_fields_upto::<BinaryBigEndian>(self.buf, usize::MAX)
+                    .map(|(layer, _)| layer)
+                    .unwrap_or_else(|| #macroname!())
+            }
+            #(#view_getter_idents)*
+        }
+
+        impl<'a> HeaderView<'a> for #view_name<'a> {
+            fn kind() -> ViewKind {
+                #name::VIEW
+            }
+            fn from_layer_view(view: &LayerView<'a>) -> Self {
+                #view_name {
+                    buf: view.bytes,
+                    header_len: view.header_len,
+                }
             }
         }
     };
@@ -819,6 +1593,12 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
 
         #( #nproto_register )*
 
+        #( #heuristic_records )*
+
+        #name_registration
+
+        #view_code
+
         impl<T: Layer> Div<T> for #name {
             type Output = LayerStack;
             fn div(mut self, rhs: T) -> Self::Output {
@@ -830,24 +1610,45 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
 
         impl #name {
             fn encode_with_encoder<EEE: Encoder>(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> Vec<u8> {
+                self.encode_with_encoder_and_map::<EEE>(stack, my_index, encoded_data, None)
+            }
+            /* the spans are recorded relative to the start of this layer */
+            fn encode_with_encoder_and_map<EEE: Encoder>(&self, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec, mut map: Option<&mut DissectionMap>) -> Vec<u8> {
                 let layer = self;
+                let layer_name: &'static str = stringify!(#name);
                 let mut out: Vec<u8> = vec![];
                 #(#encode_fields_idents)*
                 out
             }
             fn decode_with_decoder<DDD: Decoder>(&self, buf: &[u8]) -> Option<(LayerStack, usize)> {
+                self.decode_with_decoder_and_map::<DDD>(buf, None, &mut DecodeContext::new())
+            }
+            fn decode_with_decoder_in_context<DDD: Decoder>(&self, buf: &[u8], ctx: &mut DecodeContext) -> Option<(LayerStack, usize)> {
+                self.decode_with_decoder_and_map::<DDD>(buf, None, ctx)
+            }
+            fn decode_with_decoder_and_map<DDD: Decoder>(&self, buf: &[u8], mut map: Option<&mut DissectionMap>, ctx: &mut DecodeContext) -> Option<(LayerStack, usize)> {
                 use std::collections::HashMap;
+                let layer_name: &'static str = stringify!(#name);
+                let (map_base, map_layer_index) = map.as_deref().map(|m| (m.base, m.layer_index)).unwrap_or((0, 0));
                 let mut ci: usize = 0;
                 let mut layer = #macroname!();
 
                 #(#decode_fields_idents)*
 
+                #decode_len_limit_code
+
+                #decode_hook_code
+
                 let mut layers = vec![layer.embox()];
 
                 #(#chained_fields_idents)*
 
+                #heuristic_decode_code
+
                 #greedy_decode_code
 
+                #decode_len_padding_code
+
                 Some((LayerStack { layers, filled: true }, ci))
             }
 
@@ -885,6 +1686,11 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                 Box::new((*self).clone())
             }
             fn fill(&self, stack: &LayerStack, my_index: usize, out_stack: &mut LayerStack) {
+                let mut rng = rand::thread_rng();
+                let mut fill_ctx = FillContext { counter: 0, rng: &mut rng };
+                self.fill_with(stack, my_index, &mut fill_ctx, out_stack)
+            }
+            fn fill_with(&self, stack: &LayerStack, my_index: usize, fill_ctx: &mut FillContext, out_stack: &mut LayerStack) {
                 let mut out: #name = self.clone();
                 #(#fill_fields_idents)*
                 out_stack.layers.push(Box::new(out))
@@ -893,6 +1699,54 @@ pub fn network_protocol(input: proc_macro::TokenStream) -> proc_macro::TokenStre
             #encode_function
 
             #decode_function
+
+            #verify_function
+
+            #patch_function
+
+            #dependent_fields_function
+
+            #summary_function
+
+            fn field_infos(&self) -> Vec<FieldInfo> {
+                vec![#(#field_info_idents)*]
+            }
+
+            fn field_descs(&self) -> Vec<FieldDesc> {
+                vec![#(#field_desc_idents)*]
+            }
+
+            fn get_field(&self, name: &str) -> Option<FieldValue> {
+                match name {
+                    #(#get_field_idents)*
+                    _ => None,
+                }
+            }
+
+            fn set_field_value(&mut self, name: &str, value: FieldValue) -> Result<(), FieldAccessError> {
+                match name {
+                    #(#set_field_idents)*
+                    _ => Err(FieldAccessError::NoSuchField(name.to_string())),
+                }
+            }
+
+            fn expand_counts(&self) -> Vec<(&'static str, u64)> {
+                let mut out = vec![];
+                #(#expand_count_idents)*
+                out
+            }
+
+            fn fuzz_fields(&mut self) {
+                #(#fuzz_idents)*
+            }
+
+            #[allow(unused_variables)]
+            fn expand_field(&mut self, name: &str, n: u64) -> bool {
+                match name {
+                    #(#expand_field_idents)*
+                    _ => false,
+                }
+            }
         }
 
 
@@ -1145,6 +1999,8 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                         let mut nproto_decode = None::<syn::Expr>;
                         let mut nproto_skip_encdec_unless = None::<syn::Expr>;
                         let mut nproto_set = None::<syn::Ident>;
+                        let mut nproto_bits = None::<(usize, usize)>;
+                        let mut nproto_alias = None::<syn::LitStr>;
                         let name = f.ident.clone().unwrap();
                         // eprintln!("FIELD: {:#?}", f.ty);
                         for attr in &f.attrs {
@@ -1168,6 +2024,26 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                                         return Ok(());
                                     }
 
+                                    // #[nproto(bits(_offset_, _width_))], counted from the most significant bit
+                                    if meta.path.is_ident("bits") {
+                                        let content;
+                                        parenthesized!(content in meta.input);
+                                        let offset: LitInt = content.parse()?;
+                                        let _comma: Token![,] = content.parse()?;
+                                        let width: LitInt = content.parse()?;
+                                        nproto_bits =
+                                            Some((offset.base10_parse()?, width.base10_parse()?));
+                                        return Ok(());
+                                    }
+
+                                    // #[nproto(alias = "name")], another name for get_field/set_field
+                                    if meta.path.is_ident("alias") {
+                                        let eq_token: Option<Token![=]> = meta.input.parse()?;
+                                        let alias: syn::LitStr = meta.input.parse()?;
+                                        nproto_alias = Some(alias);
+                                        return Ok(());
+                                    }
+
                                     // #[nproto(auto = _expr_)]
                                     if meta.path.is_ident("auto") {
                                         let eq_token: Option<Token![=]> = meta.input.parse()?;
@@ -1286,6 +2162,8 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                                     decode: nproto_decode,
                                     skip_encdec_unless: nproto_skip_encdec_unless,
                                     set: nproto_set,
+                                    bits: nproto_bits,
+                                    alias: nproto_alias,
                                 });
                             }
                             Type::Path(typepath)
@@ -1305,6 +2183,8 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                                     decode: nproto_decode,
                                     skip_encdec_unless: nproto_skip_encdec_unless,
                                     set: nproto_set,
+                                    bits: nproto_bits,
+                                    alias: nproto_alias,
                                 });
                             }
                             Type::Path(typepath)
@@ -1324,6 +2204,8 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                                     decode: nproto_decode,
                                     skip_encdec_unless: nproto_skip_encdec_unless,
                                     set: nproto_set,
+                                    bits: nproto_bits,
+                                    alias: nproto_alias,
                                 });
                             }
                             Type::Path(typepath) => {
@@ -1341,6 +2223,8 @@ fn netproto_struct_fields(default_encoder: &TokenStream, data: &Data) -> Vec<Net
                                     decode: nproto_decode,
                                     skip_encdec_unless: nproto_skip_encdec_unless,
                                     set: nproto_set,
+                                    bits: nproto_bits,
+                                    alias: nproto_alias,
                                 });
                             }
                             _ => {
@@ -0,0 +1,2113 @@
+/*
+ * Classic BPF: a compiler for the pcap-filter language (the tcpdump filters,
+ * e.g. "udp and dst port 4789" or "vlan 100 and ip host 10.0.0.1"),
+ * an interpreter to run the programs over the raw packet bytes,
+ * and the "tcpdump -d" listing of the programs.
+ *
+ * The code generation and the optimizations follow the ones of libpcap,
+ * so for the same filter the listing is the same as tcpdump's; the comparisons
+ * of arithmetic expressions, as in "tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x47455420",
+ * compute the same values with other instructions than tcpdump's.
+ * Only the Ethernet link type is supported, and primitives such as "broadcast",
+ * "multicast" or "mpls" are rejected as unsupported.
+ */
+
+use crate::protocols::pcap_file::*;
+use crate::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/* instruction classes */
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_LDX: u16 = 0x01;
+pub const BPF_ST: u16 = 0x02;
+pub const BPF_STX: u16 = 0x03;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_MISC: u16 = 0x07;
+
+/* load sizes */
+pub const BPF_W: u16 = 0x00;
+pub const BPF_H: u16 = 0x08;
+pub const BPF_B: u16 = 0x10;
+
+/* load modes */
+pub const BPF_IMM: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_IND: u16 = 0x40;
+pub const BPF_MEM: u16 = 0x60;
+pub const BPF_LEN: u16 = 0x80;
+pub const BPF_MSH: u16 = 0xa0;
+
+/* ALU operations */
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_MUL: u16 = 0x20;
+pub const BPF_DIV: u16 = 0x30;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+pub const BPF_NEG: u16 = 0x80;
+pub const BPF_MOD: u16 = 0x90;
+pub const BPF_XOR: u16 = 0xa0;
+
+/* jumps */
+pub const BPF_JA: u16 = 0x00;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_JSET: u16 = 0x40;
+
+/* operand sources */
+pub const BPF_K: u16 = 0x00;
+pub const BPF_X: u16 = 0x08;
+pub const BPF_A: u16 = 0x10;
+
+/* misc operations */
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
+pub const BPF_MEMWORDS: usize = 16;
+
+/* what tcpdump returns for the accepted packets */
+pub const DEFAULT_SNAPLEN: u32 = 262144;
+
+/* the layout of the Linux struct sock_filter, so a program can be given to the kernel as is */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct BpfInsn {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl BpfInsn {
+    pub fn stmt(code: u16, k: u32) -> Self {
+        BpfInsn {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    pub fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        BpfInsn { code, jt, jf, k }
+    }
+
+    fn class(&self) -> u16 {
+        self.code & 0x07
+    }
+
+    /* the line of "tcpdump -d" for the instruction at index n */
+    pub fn image(&self, n: usize) -> String {
+        let code = self.code;
+        let k = self.k;
+        let (op, operand): (&str, String) = match self.class() {
+            BPF_RET => match code & 0x18 {
+                BPF_K => ("ret", format!("#{}", k)),
+                BPF_X => ("ret", "x".to_string()),
+                _ => ("ret", "a".to_string()),
+            },
+            BPF_LD => {
+                let op = match code & 0x18 {
+                    BPF_H => "ldh",
+                    BPF_B => "ldb",
+                    _ => "ld",
+                };
+                match code & 0xe0 {
+                    BPF_ABS => (op, format!("[{}]", k)),
+                    BPF_IND => (op, format!("[x + {}]", k)),
+                    BPF_LEN => ("ld", "#pktlen".to_string()),
+                    BPF_MEM => ("ld", format!("M[{}]", k)),
+                    BPF_IMM => ("ld", format!("#0x{:x}", k)),
+                    _ => ("unimp", format!("0x{:x}", code)),
+                }
+            }
+            BPF_LDX => match code & 0xe0 {
+                BPF_MSH => ("ldxb", format!("4*([{}]&0xf)", k)),
+                BPF_LEN => ("ldx", "#pktlen".to_string()),
+                BPF_MEM => ("ldx", format!("M[{}]", k)),
+                BPF_IMM => ("ldx", format!("#0x{:x}", k)),
+                _ => ("unimp", format!("0x{:x}", code)),
+            },
+            BPF_ST => ("st", format!("M[{}]", k)),
+            BPF_STX => ("stx", format!("M[{}]", k)),
+            BPF_ALU => {
+                let op = match code & 0xf0 {
+                    BPF_ADD => "add",
+                    BPF_SUB => "sub",
+                    BPF_MUL => "mul",
+                    BPF_DIV => "div",
+                    BPF_MOD => "mod",
+                    BPF_AND => "and",
+                    BPF_OR => "or",
+                    BPF_XOR => "xor",
+                    BPF_LSH => "lsh",
+                    BPF_RSH => "rsh",
+                    _ => "neg",
+                };
+                let operand = if op == "neg" {
+                    String::new()
+                } else if code & BPF_X != 0 {
+                    "x".to_string()
+                } else if ["and", "or", "xor"].contains(&op) {
+                    format!("#0x{:x}", k)
+                } else {
+                    format!("#{}", k)
+                };
+                (op, operand)
+            }
+            BPF_JMP => {
+                let op = match code & 0xf0 {
+                    BPF_JA => {
+                        return format!("({:03}) {:<8} {}", n, "ja", n + 1 + k as usize);
+                    }
+                    BPF_JEQ => "jeq",
+                    BPF_JGT => "jgt",
+                    BPF_JGE => "jge",
+                    _ => "jset",
+                };
+                let operand = if code & BPF_X != 0 {
+                    "x".to_string()
+                } else {
+                    format!("#0x{:x}", k)
+                };
+                return format!(
+                    "({:03}) {:<8} {:<16} jt {}\tjf {}",
+                    n,
+                    op,
+                    operand,
+                    n + 1 + self.jt as usize,
+                    n + 1 + self.jf as usize
+                );
+            }
+            _ => match code & 0xf8 {
+                BPF_TXA => ("txa", String::new()),
+                _ => ("tax", String::new()),
+            },
+        };
+        format!("({:03}) {:<8} {}", n, op, operand)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BpfError {
+    Syntax { pos: usize, expected: &'static str },
+    Unsupported { pos: usize, what: String },
+    BadValue { pos: usize, value: String },
+    /* a conditional jump does not fit in the 8 bits of jt/jf */
+    TooLong,
+}
+
+impl fmt::Display for BpfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { pos, expected } => write!(f, "at offset {}: expected {}", pos, expected),
+            Self::Unsupported { pos, what } => {
+                write!(f, "at offset {}: {} is not supported", pos, what)
+            }
+            Self::BadValue { pos, value } => write!(f, "at offset {}: bad value {:?}", pos, value),
+            Self::TooLong => write!(f, "the filter is too long for the 8-bit jumps"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct BpfProgram {
+    pub insns: Vec<BpfInsn>,
+}
+
+impl BpfProgram {
+    pub fn compile(filter: &str) -> Result<Self, BpfError> {
+        Self::compile_with_snaplen(filter, DEFAULT_SNAPLEN)
+    }
+
+    pub fn compile_with_snaplen(filter: &str, snaplen: u32) -> Result<Self, BpfError> {
+        let mut p = FilterParser {
+            toks: lex(filter)?,
+            idx: 0,
+            end: filter.len(),
+            g: Gen::new(),
+            last: None,
+        };
+        let root = if p.toks.is_empty() {
+            None
+        } else {
+            let c = p.expr()?;
+            if p.idx < p.toks.len() {
+                return Err(p.syntax("'and', 'or' or the end"));
+            }
+            Some(c)
+        };
+        let mut g = p.g;
+        let accept = g.leaf(snaplen);
+        let reject = g.leaf(0);
+        let root = match root {
+            Some(c) => {
+                g.patch(&c.t, accept);
+                g.patch(&c.f, reject);
+                c.head
+            }
+            None => accept,
+        };
+        let mut cfg = Cfg {
+            blocks: g.blocks,
+            root,
+        };
+        cfg.optimize();
+        Ok(BpfProgram { insns: cfg.emit()? })
+    }
+
+    /* the value the program returns: 0 drops the packet, otherwise it is the length to keep */
+    pub fn run(&self, data: &[u8]) -> u32 {
+        self.run_with_len(data, data.len() as u32)
+    }
+
+    /* as run(), for a capture of the first bytes of a longer packet */
+    pub fn run_with_len(&self, data: &[u8], wirelen: u32) -> u32 {
+        let load = |off: u32, size: usize| -> Option<u32> {
+            let off = off as usize;
+            let bytes = data.get(off..off.checked_add(size)?)?;
+            Some(bytes.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+        };
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0u32; BPF_MEMWORDS];
+        let mut pc = 0;
+        while let Some(i) = self.insns.get(pc) {
+            pc += 1;
+            let k = i.k;
+            let size = match i.code & 0x18 {
+                BPF_H => 2,
+                BPF_B => 1,
+                _ => 4,
+            };
+            match i.class() {
+                BPF_RET => {
+                    return match i.code & 0x18 {
+                        BPF_K => k,
+                        BPF_X => x,
+                        _ => a,
+                    }
+                }
+                BPF_LD => {
+                    let v = match i.code & 0xe0 {
+                        BPF_IMM => Some(k),
+                        BPF_ABS => load(k, size),
+                        BPF_IND => load(x.wrapping_add(k), size),
+                        BPF_LEN => Some(wirelen),
+                        BPF_MEM => mem.get(k as usize).copied(),
+                        _ => None,
+                    };
+                    match v {
+                        Some(v) => a = v,
+                        None => return 0,
+                    }
+                }
+                BPF_LDX => {
+                    let v = match i.code & 0xe0 {
+                        BPF_IMM => Some(k),
+                        BPF_LEN => Some(wirelen),
+                        BPF_MEM => mem.get(k as usize).copied(),
+                        BPF_MSH => load(k, 1).map(|b| (b & 0xf) << 2),
+                        _ => None,
+                    };
+                    match v {
+                        Some(v) => x = v,
+                        None => return 0,
+                    }
+                }
+                BPF_ST | BPF_STX => match mem.get_mut(k as usize) {
+                    Some(m) => *m = if i.class() == BPF_ST { a } else { x },
+                    None => return 0,
+                },
+                BPF_ALU => {
+                    let v = if i.code & BPF_X != 0 { x } else { k };
+                    a = match i.code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(v),
+                        BPF_SUB => a.wrapping_sub(v),
+                        BPF_MUL => a.wrapping_mul(v),
+                        BPF_DIV | BPF_MOD if v == 0 => return 0,
+                        BPF_DIV => a / v,
+                        BPF_MOD => a % v,
+                        BPF_AND => a & v,
+                        BPF_OR => a | v,
+                        BPF_XOR => a ^ v,
+                        BPF_LSH => a.checked_shl(v).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(v).unwrap_or(0),
+                        BPF_NEG => a.wrapping_neg(),
+                        _ => return 0,
+                    }
+                }
+                BPF_JMP => {
+                    let v = if i.code & BPF_X != 0 { x } else { k };
+                    let taken = match i.code & 0xf0 {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        BPF_JEQ => a == v,
+                        BPF_JGT => a > v,
+                        BPF_JGE => a >= v,
+                        BPF_JSET => a & v != 0,
+                        _ => return 0,
+                    };
+                    pc += if taken { i.jt } else { i.jf } as usize;
+                }
+                _ => {
+                    if i.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+            }
+        }
+        0
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.run(data) != 0
+    }
+
+    /* the original length of the packet is what the "len", "less" and "greater" see */
+    pub fn matches_packet(&self, pkt: &pcapPacket) -> bool {
+        let wirelen = match pkt.orig_len {
+            Value::Set(x) => x,
+            _ => pkt.data.len() as u32,
+        };
+        self.run_with_len(&pkt.data, wirelen) != 0
+    }
+
+    /* the listing "tcpdump -d" prints */
+    pub fn dump(&self) -> String {
+        self.to_string()
+    }
+
+    /* attach as the socket filter, e.g. of an AF_PACKET socket */
+    #[cfg(target_os = "linux")]
+    pub fn attach(&self, fd: std::os::unix::io::RawFd) -> std::io::Result<()> {
+        let prog = libc::sock_fprog {
+            len: self.insns.len() as u16,
+            filter: self.insns.as_ptr() as *mut libc::sock_filter,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &prog as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+}
+
+impl fmt::Display for BpfProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, i) in self.insns.iter().enumerate() {
+            writeln!(f, "{}", i.image(n))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BpfProgram {
+    type Err = BpfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BpfProgram::compile(s)
+    }
+}
+
+/*
+ * The code is generated as blocks of statements ending with a conditional jump,
+ * with the unpatched true and false exits of each sub-expression kept in a Chain.
+ */
+const NONE: usize = usize::MAX;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Block {
+    stmts: Vec<BpfInsn>,
+    /* a conditional jump, or a return for the two leaves */
+    jump: BpfInsn,
+    jt: usize,
+    jf: usize,
+}
+
+impl Block {
+    fn is_leaf(&self) -> bool {
+        self.jump.class() == BPF_RET
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Chain {
+    head: usize,
+    t: Vec<(usize, bool)>,
+    f: Vec<(usize, bool)>,
+}
+
+const ETHERTYPE_IP: u32 = 0x0800;
+const ETHERTYPE_ARP: u32 = 0x0806;
+const ETHERTYPE_REVARP: u32 = 0x8035;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+const ETHERTYPE_8021Q: u32 = 0x8100;
+const ETHERTYPE_8021AD: u32 = 0x88a8;
+const ETHERTYPE_8021QINQ: u32 = 0x9100;
+
+const IPPROTO_ICMP: u32 = 1;
+const IPPROTO_IGMP: u32 = 2;
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+const IPPROTO_FRAGMENT: u32 = 44;
+const IPPROTO_ICMPV6: u32 = 58;
+const IPPROTO_SCTP: u32 = 132;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Proto {
+    Ether,
+    Ip,
+    Ip6,
+    Arp,
+    Rarp,
+    Tcp,
+    Udp,
+    Sctp,
+    Icmp,
+    Icmp6,
+    Igmp,
+}
+
+impl Proto {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "ether" => Proto::Ether,
+            "ip" => Proto::Ip,
+            "ip6" => Proto::Ip6,
+            "arp" => Proto::Arp,
+            "rarp" => Proto::Rarp,
+            "tcp" => Proto::Tcp,
+            "udp" => Proto::Udp,
+            "sctp" => Proto::Sctp,
+            "icmp" => Proto::Icmp,
+            "icmp6" => Proto::Icmp6,
+            "igmp" => Proto::Igmp,
+            _ => return None,
+        })
+    }
+
+    fn ip_proto(&self) -> Option<u32> {
+        Some(match self {
+            Proto::Tcp => IPPROTO_TCP,
+            Proto::Udp => IPPROTO_UDP,
+            Proto::Sctp => IPPROTO_SCTP,
+            Proto::Icmp => IPPROTO_ICMP,
+            Proto::Icmp6 => IPPROTO_ICMPV6,
+            Proto::Igmp => IPPROTO_IGMP,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dir {
+    Default,
+    Src,
+    Dst,
+    Or,
+    And,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Default,
+    Host,
+    Net,
+    Port,
+    PortRange,
+}
+
+/* the qualifiers of the last primitive, for the "host a or b" shorthand */
+#[derive(Clone, Copy, Debug)]
+struct Quals {
+    proto: Option<Proto>,
+    dir: Dir,
+    kind: Kind,
+}
+
+struct Gen {
+    blocks: Vec<Block>,
+    off_linktype: u32,
+    off_nl: u32,
+}
+
+impl Gen {
+    fn new() -> Self {
+        Gen {
+            blocks: vec![],
+            off_linktype: 12,
+            off_nl: 14,
+        }
+    }
+
+    fn leaf(&mut self, k: u32) -> usize {
+        self.blocks.push(Block {
+            stmts: vec![],
+            jump: BpfInsn::stmt(BPF_RET | BPF_K, k),
+            jt: NONE,
+            jf: NONE,
+        });
+        self.blocks.len() - 1
+    }
+
+    fn block(&mut self, stmts: Vec<BpfInsn>, jcode: u16, k: u32) -> Chain {
+        self.blocks.push(Block {
+            stmts,
+            jump: BpfInsn::stmt(BPF_JMP | jcode | BPF_K, k),
+            jt: NONE,
+            jf: NONE,
+        });
+        let b = self.blocks.len() - 1;
+        Chain {
+            head: b,
+            t: vec![(b, true)],
+            f: vec![(b, false)],
+        }
+    }
+
+    fn patch(&mut self, exits: &[(usize, bool)], target: usize) {
+        for (b, side) in exits {
+            if *side {
+                self.blocks[*b].jt = target;
+            } else {
+                self.blocks[*b].jf = target;
+            }
+        }
+    }
+
+    fn and(&mut self, a: Chain, b: Chain) -> Chain {
+        self.patch(&a.t, b.head);
+        let mut f = a.f;
+        f.extend(b.f);
+        Chain {
+            head: a.head,
+            t: b.t,
+            f,
+        }
+    }
+
+    fn or(&mut self, a: Chain, b: Chain) -> Chain {
+        self.patch(&a.f, b.head);
+        let mut t = a.t;
+        t.extend(b.t);
+        Chain {
+            head: a.head,
+            t,
+            f: b.f,
+        }
+    }
+
+    fn not(&mut self, a: Chain) -> Chain {
+        Chain {
+            head: a.head,
+            t: a.f,
+            f: a.t,
+        }
+    }
+
+    fn load(size: u16, off: u32) -> Vec<BpfInsn> {
+        vec![BpfInsn::stmt(BPF_LD | size | BPF_ABS, off)]
+    }
+
+    /* the loads of the transport header, past the variable length IPv4 header */
+    fn load_tran(&self, size: u16, off: u32) -> Vec<BpfInsn> {
+        vec![
+            BpfInsn::stmt(BPF_LDX | BPF_B | BPF_MSH, self.off_nl),
+            BpfInsn::stmt(BPF_LD | size | BPF_IND, self.off_nl + off),
+        ]
+    }
+
+    fn cmp(&mut self, size: u16, off: u32, v: u32) -> Chain {
+        self.block(Self::load(size, off), BPF_JEQ, v)
+    }
+
+    fn mcmp(&mut self, size: u16, off: u32, v: u32, mask: u32) -> Chain {
+        let mut s = Self::load(size, off);
+        if mask != 0xffffffff {
+            s.push(BpfInsn::stmt(BPF_ALU | BPF_AND | BPF_K, mask));
+        }
+        self.block(s, BPF_JEQ, v)
+    }
+
+    fn linktype(&mut self, etype: u32) -> Chain {
+        self.cmp(BPF_H, self.off_linktype, etype)
+    }
+
+    /* not a fragment other than the first one */
+    fn ipfrag(&mut self) -> Chain {
+        let b = self.block(Self::load(BPF_H, self.off_nl + 6), BPF_JSET, 0x1fff);
+        self.not(b)
+    }
+
+    fn ip_proto(&mut self, v: u32) -> Chain {
+        let b0 = self.linktype(ETHERTYPE_IP);
+        let b1 = self.cmp(BPF_B, self.off_nl + 9, v);
+        self.and(b0, b1)
+    }
+
+    /* the next header of IPv6, directly or after a fragment header */
+    fn ip6_proto(&mut self, v: u32) -> Chain {
+        let b0 = self.linktype(ETHERTYPE_IPV6);
+        let frag = self.cmp(BPF_B, self.off_nl + 6, IPPROTO_FRAGMENT);
+        let after = self.cmp(BPF_B, self.off_nl + 40, v);
+        let b1 = self.and(frag, after);
+        let direct = self.cmp(BPF_B, self.off_nl + 6, v);
+        let b1 = self.or(direct, b1);
+        self.and(b0, b1)
+    }
+
+    fn any_proto(&mut self, v: u32) -> Chain {
+        let b0 = self.ip6_proto(v);
+        let b1 = self.ip_proto(v);
+        self.or(b0, b1)
+    }
+
+    fn proto(&mut self, p: Proto) -> Chain {
+        match p {
+            Proto::Ether => unreachable!("'ether' alone is rejected by the parser"),
+            Proto::Ip => self.linktype(ETHERTYPE_IP),
+            Proto::Ip6 => self.linktype(ETHERTYPE_IPV6),
+            Proto::Arp => self.linktype(ETHERTYPE_ARP),
+            Proto::Rarp => self.linktype(ETHERTYPE_REVARP),
+            Proto::Icmp | Proto::Igmp => self.ip_proto(p.ip_proto().unwrap()),
+            Proto::Icmp6 => self.ip6_proto(IPPROTO_ICMPV6),
+            Proto::Tcp | Proto::Udp | Proto::Sctp => self.any_proto(p.ip_proto().unwrap()),
+        }
+    }
+
+    fn dir_or_and(&mut self, dir: Dir, src: Chain, dst: Chain) -> Chain {
+        match dir {
+            Dir::And => self.and(src, dst),
+            _ => self.or(src, dst),
+        }
+    }
+
+    fn hostop(&mut self, addr: u32, mask: u32, dir: Dir, etype: u32, src: u32, dst: u32) -> Chain {
+        let one = |g: &mut Self, off: u32| {
+            let b0 = g.linktype(etype);
+            let b1 = g.mcmp(BPF_W, g.off_nl + off, addr, mask);
+            g.and(b0, b1)
+        };
+        match dir {
+            Dir::Src => one(self, src),
+            Dir::Dst => one(self, dst),
+            _ => {
+                let b0 = one(self, src);
+                let b1 = one(self, dst);
+                self.dir_or_and(dir, b0, b1)
+            }
+        }
+    }
+
+    fn host(&mut self, addr: u32, mask: u32, dir: Dir, proto: Option<Proto>) -> Chain {
+        match proto {
+            Some(Proto::Ip) => self.hostop(addr, mask, dir, ETHERTYPE_IP, 12, 16),
+            Some(Proto::Arp) => self.hostop(addr, mask, dir, ETHERTYPE_ARP, 14, 24),
+            Some(Proto::Rarp) => self.hostop(addr, mask, dir, ETHERTYPE_REVARP, 14, 24),
+            _ => {
+                let b0 = self.hostop(addr, mask, dir, ETHERTYPE_IP, 12, 16);
+                let b1 = self.hostop(addr, mask, dir, ETHERTYPE_ARP, 14, 24);
+                let b1 = self.or(b0, b1);
+                let b0 = self.hostop(addr, mask, dir, ETHERTYPE_REVARP, 14, 24);
+                self.or(b1, b0)
+            }
+        }
+    }
+
+    /* the 6 bytes compared as a word and a halfword */
+    fn ehostop(&mut self, mac: &[u8; 6], dir: Dir) -> Chain {
+        let one = |g: &mut Self, off: u32| {
+            let w = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+            let h = u16::from_be_bytes([mac[0], mac[1]]) as u32;
+            let b0 = g.cmp(BPF_W, off + 2, w);
+            let b1 = g.cmp(BPF_H, off, h);
+            g.and(b0, b1)
+        };
+        match dir {
+            Dir::Src => one(self, 6),
+            Dir::Dst => one(self, 0),
+            _ => {
+                let b0 = one(self, 6);
+                let b1 = one(self, 0);
+                self.dir_or_and(dir, b0, b1)
+            }
+        }
+    }
+
+    /* the checks of the next header, tried as sctp, tcp and udp without a protocol */
+    fn tran_protos(&mut self, off: u32, proto: Option<u32>) -> Chain {
+        match proto {
+            Some(p) => self.cmp(BPF_B, off, p),
+            None => {
+                let b0 = self.cmp(BPF_B, off, IPPROTO_SCTP);
+                let b1 = self.cmp(BPF_B, off, IPPROTO_TCP);
+                let b1 = self.or(b0, b1);
+                let b0 = self.cmp(BPF_B, off, IPPROTO_UDP);
+                self.or(b1, b0)
+            }
+        }
+    }
+
+    fn portatoms(
+        &mut self,
+        v6: bool,
+        dir: Dir,
+        atom: &dyn Fn(&mut Self, bool, u32) -> Chain,
+    ) -> Chain {
+        match dir {
+            Dir::Src => atom(self, v6, 0),
+            Dir::Dst => atom(self, v6, 2),
+            _ => {
+                let b0 = atom(self, v6, 0);
+                let b1 = atom(self, v6, 2);
+                self.dir_or_and(dir, b0, b1)
+            }
+        }
+    }
+
+    fn port_load(&self, v6: bool, off: u32) -> Vec<BpfInsn> {
+        if v6 {
+            Self::load(BPF_H, self.off_nl + 40 + off)
+        } else {
+            self.load_tran(BPF_H, off)
+        }
+    }
+
+    /* the IPv6 variant first, as libpcap has it */
+    fn port_or_range(
+        &mut self,
+        proto: Option<u32>,
+        dir: Dir,
+        atom: &dyn Fn(&mut Self, bool, u32) -> Chain,
+    ) -> Chain {
+        let b0 = self.linktype(ETHERTYPE_IPV6);
+        let b1 = self.tran_protos(self.off_nl + 6, proto);
+        let b2 = self.portatoms(true, dir, atom);
+        let b1 = self.and(b1, b2);
+        let v6 = self.and(b0, b1);
+
+        let b0 = self.linktype(ETHERTYPE_IP);
+        let b1 = self.tran_protos(self.off_nl + 9, proto);
+        let b2 = self.ipfrag();
+        let b1 = self.and(b1, b2);
+        let b2 = self.portatoms(false, dir, atom);
+        let b1 = self.and(b1, b2);
+        let v4 = self.and(b0, b1);
+        self.or(v6, v4)
+    }
+
+    fn port(&mut self, port: u32, proto: Option<u32>, dir: Dir) -> Chain {
+        self.port_or_range(proto, dir, &|g: &mut Self, v6, off| {
+            g.block(g.port_load(v6, off), BPF_JEQ, port)
+        })
+    }
+
+    fn portrange(&mut self, lo: u32, hi: u32, proto: Option<u32>, dir: Dir) -> Chain {
+        self.port_or_range(proto, dir, &|g: &mut Self, v6, off| {
+            let b0 = g.block(g.port_load(v6, off), BPF_JGE, lo);
+            let b1 = g.block(g.port_load(v6, off), BPF_JGT, hi);
+            let b1 = g.not(b1);
+            g.and(b0, b1)
+        })
+    }
+
+    fn vlan(&mut self, id: Option<u32>) -> Chain {
+        let b0 = self.linktype(ETHERTYPE_8021Q);
+        let b1 = self.linktype(ETHERTYPE_8021AD);
+        let b1 = self.or(b0, b1);
+        let b0 = self.linktype(ETHERTYPE_8021QINQ);
+        let mut b = self.or(b1, b0);
+        if let Some(id) = id {
+            let b1 = self.mcmp(BPF_H, self.off_nl, id, 0x0fff);
+            b = self.and(b, b1);
+        }
+        /* whatever follows looks past the tag */
+        self.off_linktype += 4;
+        self.off_nl += 4;
+        b
+    }
+
+    fn len(&mut self, op: RelOp, v: u32) -> Chain {
+        let s = vec![BpfInsn::stmt(BPF_LD | BPF_W | BPF_LEN, 0)];
+        self.relation(s, op, BPF_K, v)
+    }
+
+    /* the comparison of what the statements load with a constant (BPF_K) or with X (BPF_X) */
+    fn relation(&mut self, s: Vec<BpfInsn>, op: RelOp, src: u16, v: u32) -> Chain {
+        let (jcode, negate) = match op {
+            RelOp::Eq => (BPF_JEQ, false),
+            RelOp::Ne => (BPF_JEQ, true),
+            RelOp::Gt => (BPF_JGT, false),
+            RelOp::Le => (BPF_JGT, true),
+            RelOp::Ge => (BPF_JGE, false),
+            RelOp::Lt => (BPF_JGE, true),
+        };
+        let b = self.block(s, jcode | src, v);
+        if negate {
+            self.not(b)
+        } else {
+            b
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RelOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/*
+ * The libpcap optimizations that matter for these programs: the loads of a value
+ * already in a register are dropped, the jumps whose outcome is known from
+ * an earlier comparison of the same value go straight to the outcome,
+ * and the identical blocks are merged.
+ */
+struct Cfg {
+    blocks: Vec<Block>,
+    root: usize,
+}
+
+type Edge = (usize, bool);
+
+#[derive(Default)]
+struct Flow {
+    order: Vec<usize>,
+    preds: Vec<Vec<Edge>>,
+    entry: Vec<(u32, u32)>,
+    exit: Vec<(u32, u32)>,
+    /* whether A and X are used before being set, in the block or after it */
+    live: Vec<(bool, bool)>,
+    dom: Vec<BTreeSet<Edge>>,
+}
+
+#[derive(Default)]
+struct ValueNumbers {
+    map: HashMap<(u16, u32, u32), u32>,
+    next: u32,
+}
+
+impl ValueNumbers {
+    fn get(&mut self, code: u16, k: u32, arg: u32) -> u32 {
+        let next = &mut self.next;
+        *self.map.entry((code, k, arg)).or_insert_with(|| {
+            *next += 1;
+            *next
+        })
+    }
+
+    fn fresh(&mut self) -> u32 {
+        self.next += 1;
+        self.next
+    }
+}
+
+impl Cfg {
+    fn succ(&self, b: usize, side: bool) -> usize {
+        if side {
+            self.blocks[b].jt
+        } else {
+            self.blocks[b].jf
+        }
+    }
+
+    fn set_succ(&mut self, b: usize, side: bool, target: usize) {
+        if side {
+            self.blocks[b].jt = target;
+        } else {
+            self.blocks[b].jf = target;
+        }
+    }
+
+    /* reachable blocks, each after all of its predecessors */
+    fn topo_order(&self) -> Vec<usize> {
+        fn visit(cfg: &Cfg, b: usize, seen: &mut Vec<bool>, out: &mut Vec<usize>) {
+            if seen[b] {
+                return;
+            }
+            seen[b] = true;
+            if !cfg.blocks[b].is_leaf() {
+                visit(cfg, cfg.blocks[b].jt, seen, out);
+                visit(cfg, cfg.blocks[b].jf, seen, out);
+            }
+            out.push(b);
+        }
+        let mut seen = vec![false; self.blocks.len()];
+        let mut out = vec![];
+        visit(self, self.root, &mut seen, &mut out);
+        out.reverse();
+        out
+    }
+
+    /* the A and X values after the statements, and whether the loads of a known value are dropped */
+    fn eval(
+        stmts: &mut Vec<BpfInsn>,
+        (mut a, mut x): (u32, u32),
+        vn: &mut ValueNumbers,
+        drop_loads: bool,
+    ) -> (u32, u32) {
+        let mut kept = vec![];
+        for s in stmts.iter() {
+            let mut keep = true;
+            match s.class() {
+                BPF_LD => {
+                    let v = match s.code & 0xe0 {
+                        BPF_IND if x == 0 => vn.fresh(),
+                        BPF_IND => vn.get(s.code, s.k, x),
+                        BPF_MEM => vn.fresh(),
+                        _ => vn.get(s.code, s.k, 0),
+                    };
+                    keep = !(drop_loads && v == a && a != 0);
+                    a = v;
+                }
+                BPF_LDX => {
+                    let v = match s.code & 0xe0 {
+                        BPF_MEM => vn.fresh(),
+                        _ => vn.get(s.code, s.k, 0),
+                    };
+                    keep = !(drop_loads && v == x && x != 0);
+                    x = v;
+                }
+                BPF_ALU => {
+                    a = if a == 0 || s.code & BPF_X != 0 {
+                        vn.fresh()
+                    } else {
+                        vn.get(s.code, s.k, a)
+                    };
+                }
+                BPF_MISC => {
+                    if s.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+                _ => {}
+            }
+            if keep {
+                kept.push(*s);
+            }
+        }
+        *stmts = kept;
+        (a, x)
+    }
+
+    /* does the block use A or X before setting them */
+    fn uses(b: &Block) -> (bool, bool, bool, bool) {
+        let (mut use_a, mut use_x, mut def_a, mut def_x) = (false, false, false, false);
+        let mut use_of = |a: bool, x: bool, def_a: bool, def_x: bool| {
+            use_a |= a && !def_a;
+            use_x |= x && !def_x;
+        };
+        for s in b.stmts.iter().chain(std::iter::once(&b.jump)) {
+            let from_x = s.code & BPF_X != 0;
+            match s.class() {
+                BPF_LD => {
+                    use_of(false, s.code & 0xe0 == BPF_IND, def_a, def_x);
+                    def_a = true;
+                }
+                BPF_LDX => def_x = true,
+                BPF_ST => use_of(true, false, def_a, def_x),
+                BPF_STX => use_of(false, true, def_a, def_x),
+                BPF_ALU | BPF_JMP => use_of(true, from_x, def_a, def_x),
+                BPF_RET => use_of(s.code & 0x18 == BPF_A, s.code & 0x18 == BPF_X, def_a, def_x),
+                _ => {
+                    if s.code & 0xf8 == BPF_TXA {
+                        use_of(false, true, def_a, def_x);
+                        def_a = true;
+                    } else {
+                        use_of(true, false, def_a, def_x);
+                        def_x = true;
+                    }
+                }
+            }
+        }
+        (use_a, use_x, def_a, def_x)
+    }
+
+    fn flow(&mut self, drop_loads: bool) -> Flow {
+        let n = self.blocks.len();
+        let mut f = Flow {
+            order: self.topo_order(),
+            preds: vec![vec![]; n],
+            entry: vec![(0, 0); n],
+            exit: vec![(0, 0); n],
+            live: vec![(false, false); n],
+            dom: vec![BTreeSet::new(); n],
+        };
+        for &b in f.order.iter() {
+            if !self.blocks[b].is_leaf() {
+                f.preds[self.blocks[b].jt].push((b, true));
+                f.preds[self.blocks[b].jf].push((b, false));
+            }
+        }
+        let mut vn = ValueNumbers::default();
+        for &b in f.order.clone().iter() {
+            let mut entry = None;
+            for (p, side) in f.preds[b].iter() {
+                let e = f.exit[*p];
+                entry = Some(match entry {
+                    None => e,
+                    Some((a, x)) => (if a == e.0 { a } else { 0 }, if x == e.1 { x } else { 0 }),
+                });
+                let mut d = f.dom[*p].clone();
+                d.insert((*p, *side));
+                if f.preds[b].first() == Some(&(*p, *side)) {
+                    f.dom[b] = d;
+                } else {
+                    f.dom[b] = f.dom[b].intersection(&d).copied().collect();
+                }
+            }
+            let entry = entry.unwrap_or((0, 0));
+            f.entry[b] = entry;
+            f.exit[b] = Self::eval(&mut self.blocks[b].stmts, entry, &mut vn, drop_loads);
+        }
+        for &b in f.order.iter().rev() {
+            let (use_a, use_x, def_a, def_x) = Self::uses(&self.blocks[b]);
+            let (mut la, mut lx) = (use_a, use_x);
+            if !self.blocks[b].is_leaf() {
+                for s in [self.blocks[b].jt, self.blocks[b].jf] {
+                    la |= !def_a && f.live[s].0;
+                    lx |= !def_x && f.live[s].1;
+                }
+            }
+            f.live[b] = (la, lx);
+        }
+        f
+    }
+
+    /* where the jump of child goes when the edge was taken before it, if known */
+    fn fold(&self, f: &Flow, child: usize, (p, sense): Edge) -> Option<usize> {
+        let c = &self.blocks[child];
+        let q = &self.blocks[p];
+        if c.is_leaf() || c.jump.code != q.jump.code || c.jump.code & BPF_X != 0 {
+            return None;
+        }
+        let a = f.exit[child].0;
+        if a == 0 || a != f.exit[p].0 {
+            return None;
+        }
+        if c.jump.k == q.jump.k {
+            Some(if sense { c.jt } else { c.jf })
+        } else if sense && c.jump.code & 0xf0 == BPF_JEQ {
+            Some(c.jf)
+        } else {
+            None
+        }
+    }
+
+    /* skipping succ is fine if target sees the same registers it uses */
+    fn conflict(&self, f: &Flow, pred: usize, succ: usize, target: usize) -> bool {
+        let (la, lx) = f.live[target];
+        let (pa, px) = f.exit[pred];
+        let (sa, sx) = f.exit[succ];
+        (la && (pa == 0 || pa != sa)) || (lx && (px == 0 || px != sx))
+    }
+
+    /* redirect one edge past a block whose outcome is known; false when there is none */
+    fn thread_one(&mut self, f: &Flow) -> bool {
+        for &p in f.order.iter() {
+            if self.blocks[p].is_leaf() {
+                continue;
+            }
+            for side in [true, false] {
+                let succ = self.succ(p, side);
+                if self.blocks[succ].is_leaf() {
+                    continue;
+                }
+                let (jt, jf) = (self.blocks[succ].jt, self.blocks[succ].jf);
+                if jt == jf && !self.conflict(f, p, succ, jt) {
+                    self.set_succ(p, side, jt);
+                    return true;
+                }
+                let mut doms = f.dom[p].clone();
+                doms.insert((p, side));
+                for d in doms {
+                    if let Some(target) = self.fold(f, succ, d) {
+                        if !self.conflict(f, p, succ, target) {
+                            self.set_succ(p, side, target);
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn intern_one(&mut self, f: &Flow) -> bool {
+        for (i, &b0) in f.order.iter().enumerate() {
+            for &b1 in f.order[i + 1..].iter() {
+                let (x, y) = (&self.blocks[b0], &self.blocks[b1]);
+                if x != y {
+                    continue;
+                }
+                let (la, lx) = f.live[b0];
+                if (la && (f.entry[b0].0 == 0 || f.entry[b0].0 != f.entry[b1].0))
+                    || (lx && (f.entry[b0].1 == 0 || f.entry[b0].1 != f.entry[b1].1))
+                {
+                    continue;
+                }
+                for b in self.blocks.iter_mut() {
+                    if b.jt == b1 {
+                        b.jt = b0;
+                    }
+                    if b.jf == b1 {
+                        b.jf = b0;
+                    }
+                }
+                if self.root == b1 {
+                    self.root = b0;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn optimize(&mut self) {
+        loop {
+            self.flow(true);
+            let f = self.flow(false);
+            if self.thread_one(&f) || self.intern_one(&f) {
+                continue;
+            }
+            let root = &self.blocks[self.root];
+            let next = root.jt;
+            if !root.is_leaf() && next == root.jf && !f.live[next].0 && !f.live[next].1 {
+                self.root = next;
+                continue;
+            }
+            break;
+        }
+    }
+
+    /* the blocks in the order of libpcap's convert_code_r: false branch placed after the true one */
+    fn emit(&self) -> Result<Vec<BpfInsn>, BpfError> {
+        fn visit(cfg: &Cfg, b: usize, seen: &mut Vec<bool>, out: &mut Vec<usize>) {
+            if seen[b] {
+                return;
+            }
+            seen[b] = true;
+            if !cfg.blocks[b].is_leaf() {
+                visit(cfg, cfg.blocks[b].jf, seen, out);
+                visit(cfg, cfg.blocks[b].jt, seen, out);
+            }
+            out.push(b);
+        }
+        let mut seen = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        visit(self, self.root, &mut seen, &mut order);
+        order.reverse();
+
+        let mut pos = vec![0; self.blocks.len()];
+        let mut n = 0;
+        for &b in order.iter() {
+            pos[b] = n;
+            n += self.blocks[b].stmts.len() + 1;
+        }
+        let mut out = vec![];
+        for &b in order.iter() {
+            let blk = &self.blocks[b];
+            out.extend(blk.stmts.iter().copied());
+            let mut j = blk.jump;
+            if !blk.is_leaf() {
+                let next = out.len() + 1;
+                let off = |t: usize| u8::try_from(pos[t] - next).map_err(|_| BpfError::TooLong);
+                j.jt = off(blk.jt)?;
+                j.jf = off(blk.jf)?;
+            }
+            out.push(j);
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Tok {
+    Id(String),
+    Num(u32),
+    /* one to four octets, as in "10.1" of "net 10.1" */
+    Ip(Vec<u8>),
+    Mac([u8; 6]),
+    Sym(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<<", ">>", "<=", ">=", "(", ")", "[", "]", ":", "&", "|", "^", "=",
+    "<", ">", "!", "+", "-", "*", "/", "%",
+];
+
+fn lex_mac(s: &str) -> Option<([u8; 6], usize)> {
+    let mut mac = [0u8; 6];
+    let mut len = 0;
+    for (i, m) in mac.iter_mut().enumerate() {
+        if i > 0 {
+            if !s[len..].starts_with(':') {
+                return None;
+            }
+            len += 1;
+        }
+        let digits = s[len..]
+            .chars()
+            .take(2)
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        *m = u8::from_str_radix(&s[len..len + digits], 16).ok()?;
+        len += digits;
+    }
+    match s[len..].chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == ':' => None,
+        _ => Some((mac, len)),
+    }
+}
+
+fn lex(s: &str) -> Result<Vec<(usize, Tok)>, BpfError> {
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        if let Some((mac, len)) = lex_mac(rest) {
+            out.push((pos, Tok::Mac(mac)));
+            pos += len;
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let bad = || BpfError::BadValue {
+                pos,
+                value: word.to_string(),
+            };
+            let tok = if word.contains('.') {
+                let octets: Result<Vec<u8>, _> = word.split('.').map(|o| o.parse::<u8>()).collect();
+                match octets {
+                    Ok(o) if o.len() <= 4 => Tok::Ip(o),
+                    _ => return Err(bad()),
+                }
+            } else if let Some(hex) = word.strip_prefix("0x") {
+                Tok::Num(u32::from_str_radix(hex, 16).map_err(|_| bad())?)
+            } else {
+                Tok::Num(word.parse().map_err(|_| bad())?)
+            };
+            out.push((pos, tok));
+            pos += len;
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' || c == '\\' {
+            let start = if c == '\\' { pos + 1 } else { pos };
+            let word = &s[start..];
+            let len = word
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_-.".contains(c)))
+                .unwrap_or(word.len());
+            out.push((pos, Tok::Id(word[..len].to_string())));
+            pos = start + len;
+            continue;
+        }
+        match SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+            Some(sym) => {
+                out.push((pos, Tok::Sym(sym)));
+                pos += sym.len();
+            }
+            None => {
+                return Err(BpfError::Syntax {
+                    pos,
+                    expected: "a filter expression",
+                })
+            }
+        }
+    }
+    Ok(out)
+}
+
+const KEYWORDS: &[&str] = &[
+    "and",
+    "or",
+    "not",
+    "src",
+    "dst",
+    "host",
+    "net",
+    "mask",
+    "port",
+    "portrange",
+    "proto",
+    "vlan",
+    "less",
+    "greater",
+    "len",
+    "ether",
+    "ip",
+    "ip6",
+    "arp",
+    "rarp",
+    "tcp",
+    "udp",
+    "sctp",
+    "icmp",
+    "icmp6",
+    "igmp",
+];
+
+/* the primitives of pcap-filter(7) this compiler does not have */
+const UNSUPPORTED_PRIMITIVES: &[&str] = &[
+    "broadcast",
+    "multicast",
+    "gateway",
+    "mpls",
+    "pppoed",
+    "pppoes",
+    "geneve",
+    "inbound",
+    "outbound",
+    "ifname",
+    "llc",
+    "wlan",
+    "type",
+    "subtype",
+    "atalk",
+    "decnet",
+    "iso",
+    "stp",
+    "ipx",
+    "netbeui",
+    "ah",
+    "esp",
+    "pim",
+    "vrrp",
+    "carp",
+];
+
+fn port_by_name(name: &str) -> Option<u32> {
+    Some(match name {
+        "ftp" => 21,
+        "ssh" => 22,
+        "telnet" => 23,
+        "smtp" => 25,
+        "domain" => 53,
+        "bootps" => 67,
+        "bootpc" => 68,
+        "http" => 80,
+        "ntp" => 123,
+        "snmp" => 161,
+        "bgp" => 179,
+        "https" => 443,
+        _ => return None,
+    })
+}
+
+/* the named offsets and values of the packet accessors, as in "tcp[tcpflags] & tcp-syn != 0" */
+fn constant_by_name(name: &str) -> Option<u32> {
+    Some(match name {
+        "icmptype" => 0,
+        "icmpcode" => 1,
+        "tcpflags" => 13,
+        "tcp-fin" => 0x01,
+        "tcp-syn" => 0x02,
+        "tcp-rst" => 0x04,
+        "tcp-push" => 0x08,
+        "tcp-ack" => 0x10,
+        "tcp-urg" => 0x20,
+        "icmp-echoreply" => 0,
+        "icmp-unreach" => 3,
+        "icmp-redirect" => 5,
+        "icmp-echo" => 8,
+        "icmp-timxceed" => 11,
+        _ => return None,
+    })
+}
+
+struct FilterParser {
+    toks: Vec<(usize, Tok)>,
+    idx: usize,
+    end: usize,
+    g: Gen,
+    last: Option<Quals>,
+}
+
+impl FilterParser {
+    fn pos(&self) -> usize {
+        self.toks.get(self.idx).map(|t| t.0).unwrap_or(self.end)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.idx).map(|t| &t.1)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Tok> {
+        self.toks.get(self.idx + n).map(|t| &t.1)
+    }
+
+    fn syntax(&self, expected: &'static str) -> BpfError {
+        BpfError::Syntax {
+            pos: self.pos(),
+            expected,
+        }
+    }
+
+    fn unsupported(&self, what: &str) -> BpfError {
+        BpfError::Unsupported {
+            pos: self.pos(),
+            what: what.to_string(),
+        }
+    }
+
+    fn is_id(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Id(x)) if x == word)
+    }
+
+    fn is_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Sym(x)) if *x == sym)
+    }
+
+    fn eat_id(&mut self, word: &str) -> bool {
+        let yes = self.is_id(word);
+        if yes {
+            self.idx += 1;
+        }
+        yes
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        let yes = self.is_sym(sym);
+        if yes {
+            self.idx += 1;
+        }
+        yes
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.peek().cloned();
+        self.idx += 1;
+        t
+    }
+
+    fn eat_and(&mut self) -> bool {
+        self.eat_id("and") || self.eat_sym("&&")
+    }
+
+    fn eat_or(&mut self) -> bool {
+        self.eat_id("or") || self.eat_sym("||")
+    }
+
+    fn at_relop(&self) -> bool {
+        ["=", "==", "!=", ">", ">=", "<", "<="]
+            .iter()
+            .any(|op| self.is_sym(op))
+    }
+
+    /* a value without qualifiers, which takes the ones of the previous primitive */
+    fn at_bare_value(&self) -> bool {
+        match self.peek() {
+            Some(Tok::Num(_)) | Some(Tok::Ip(_)) | Some(Tok::Mac(_)) => true,
+            Some(Tok::Id(x)) => !KEYWORDS.contains(&x.as_str()),
+            _ => false,
+        }
+    }
+
+    /* "and" and "or" have the same precedence and group to the left */
+    fn expr(&mut self) -> Result<Chain, BpfError> {
+        let mut c = self.term()?;
+        loop {
+            let and = if self.eat_and() {
+                true
+            } else if self.eat_or() {
+                false
+            } else {
+                return Ok(c);
+            };
+            let next = match self.last {
+                Some(q) if self.at_bare_value() => self.qualified(q)?,
+                _ => self.term()?,
+            };
+            c = if and {
+                self.g.and(c, next)
+            } else {
+                self.g.or(c, next)
+            };
+        }
+    }
+
+    fn term(&mut self) -> Result<Chain, BpfError> {
+        if self.eat_id("not") || self.eat_sym("!") {
+            let c = self.term()?;
+            return Ok(self.g.not(c));
+        }
+        if self.is_sym("(") {
+            /* "(tcp[0] + 1) > 5" compares an arithmetic expression in parentheses */
+            let start = self.idx;
+            if self.arth().is_ok() && self.at_relop() {
+                self.idx = start;
+                return self.relation();
+            }
+            self.idx = start + 1;
+            let c = self.expr()?;
+            if !self.eat_sym(")") {
+                return Err(self.syntax("')'"));
+            }
+            return Ok(c);
+        }
+        self.primitive()
+    }
+
+    fn number(&mut self) -> Result<u32, BpfError> {
+        match self.peek() {
+            Some(Tok::Num(n)) => {
+                let n = *n;
+                self.idx += 1;
+                Ok(n)
+            }
+            Some(Tok::Id(name)) => match constant_by_name(name) {
+                Some(n) => {
+                    self.idx += 1;
+                    Ok(n)
+                }
+                None => Err(self.syntax("a number")),
+            },
+            _ => Err(self.syntax("a number")),
+        }
+    }
+
+    fn number_upto(&mut self, max: u32) -> Result<u32, BpfError> {
+        let pos = self.pos();
+        match self.number()? {
+            n if n <= max => Ok(n),
+            n => Err(BpfError::BadValue {
+                pos,
+                value: n.to_string(),
+            }),
+        }
+    }
+
+    fn primitive(&mut self) -> Result<Chain, BpfError> {
+        let pos = self.pos();
+        if self.eat_id("less") {
+            let n = self.number()?;
+            return Ok(self.g.len(RelOp::Le, n));
+        }
+        if self.eat_id("greater") {
+            let n = self.number()?;
+            return Ok(self.g.len(RelOp::Ge, n));
+        }
+        if self.is_id("len") || matches!(self.peek(), Some(Tok::Num(_)) | Some(Tok::Sym("-"))) {
+            return self.relation();
+        }
+        if self.eat_id("vlan") {
+            let id = match self.peek() {
+                Some(Tok::Num(_)) => Some(self.number_upto(4095)?),
+                _ => None,
+            };
+            return Ok(self.g.vlan(id));
+        }
+        if self.eat_id("proto") {
+            let p = self.proto_number(None)?;
+            return Ok(self.g.any_proto(p));
+        }
+
+        let proto = match self.peek() {
+            Some(Tok::Id(name)) => Proto::from_name(name),
+            _ => None,
+        };
+        if proto.is_some() && matches!(self.peek_at(1), Some(Tok::Sym("["))) {
+            return self.relation();
+        }
+        if let Some(p) = proto {
+            self.idx += 1;
+            if self.eat_id("proto") {
+                return match p {
+                    Proto::Ether => {
+                        let v = self.ether_proto_number()?;
+                        Ok(self.g.linktype(v))
+                    }
+                    Proto::Ip => {
+                        let v = self.proto_number(Some(p))?;
+                        Ok(self.g.ip_proto(v))
+                    }
+                    Proto::Ip6 => {
+                        let v = self.proto_number(Some(p))?;
+                        Ok(self.g.ip6_proto(v))
+                    }
+                    _ => Err(self.unsupported("'proto' after this protocol")),
+                };
+            }
+        }
+        if let Some(Tok::Id(name)) = self.peek() {
+            if UNSUPPORTED_PRIMITIVES.contains(&name.as_str()) {
+                return Err(self.unsupported(&format!("the '{}' primitive", name)));
+            }
+        }
+        let dir = self.dir();
+        let kind = if self.eat_id("host") {
+            Kind::Host
+        } else if self.eat_id("net") {
+            Kind::Net
+        } else if self.eat_id("port") {
+            Kind::Port
+        } else if self.eat_id("portrange") {
+            Kind::PortRange
+        } else {
+            Kind::Default
+        };
+        match proto {
+            Some(p) if dir == Dir::Default && kind == Kind::Default && !self.at_bare_value() => {
+                if p == Proto::Ether {
+                    return Err(BpfError::Syntax {
+                        pos,
+                        expected: "'host', 'src', 'dst' or 'proto' after 'ether'",
+                    });
+                }
+                self.last = None;
+                return Ok(self.g.proto(p));
+            }
+            _ => {}
+        }
+        let q = Quals { proto, dir, kind };
+        self.qualified(q)
+    }
+
+    fn dir(&mut self) -> Dir {
+        for (first, other, d) in [("src", "dst", Dir::Src), ("dst", "src", Dir::Dst)] {
+            if self.is_id(first) {
+                self.idx += 1;
+                let combined = |p: &Self, w: &str| {
+                    matches!(p.peek(), Some(Tok::Id(x)) if x == w)
+                        && matches!(p.peek_at(1), Some(Tok::Id(x)) if x == other)
+                };
+                if combined(self, "or") {
+                    self.idx += 2;
+                    return Dir::Or;
+                }
+                if combined(self, "and") {
+                    self.idx += 2;
+                    return Dir::And;
+                }
+                return d;
+            }
+        }
+        Dir::Default
+    }
+
+    fn proto_number(&mut self, _proto: Option<Proto>) -> Result<u32, BpfError> {
+        if let Some(Tok::Id(name)) = self.peek() {
+            if let Some(v) = Proto::from_name(name).and_then(|p| p.ip_proto()) {
+                self.idx += 1;
+                return Ok(v);
+            }
+        }
+        self.number_upto(255)
+    }
+
+    fn ether_proto_number(&mut self) -> Result<u32, BpfError> {
+        let v = match self.peek() {
+            Some(Tok::Id(name)) => match name.as_str() {
+                "ip" => Some(ETHERTYPE_IP),
+                "ip6" => Some(ETHERTYPE_IPV6),
+                "arp" => Some(ETHERTYPE_ARP),
+                "rarp" => Some(ETHERTYPE_REVARP),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(v) = v {
+            self.idx += 1;
+            return Ok(v);
+        }
+        self.number_upto(0xffff)
+    }
+
+    /* host, net, port or portrange with its value */
+    fn qualified(&mut self, q: Quals) -> Result<Chain, BpfError> {
+        self.last = Some(q);
+        let pos = self.pos();
+        let bad = |v: &Tok| BpfError::BadValue {
+            pos,
+            value: format!("{:?}", v),
+        };
+        let tok = self.next().ok_or(BpfError::Syntax {
+            pos,
+            expected: "a value",
+        })?;
+        let tran = match q.proto {
+            None => None,
+            Some(p @ (Proto::Tcp | Proto::Udp | Proto::Sctp)) => p.ip_proto(),
+            Some(_) if matches!(q.kind, Kind::Port | Kind::PortRange) => {
+                self.idx -= 1;
+                return Err(self.unsupported("this protocol with 'port'"));
+            }
+            Some(_) => None,
+        };
+        /* "net 10" is the same as "net 10.0.0.0/8" */
+        let tok = match (q.kind, tok) {
+            (Kind::Net, Tok::Num(n)) if n <= 255 => Tok::Ip(vec![n as u8]),
+            (_, tok) => tok,
+        };
+        match (q.kind, &tok) {
+            (Kind::Port, Tok::Num(n)) if *n <= 0xffff => Ok(self.g.port(*n, tran, q.dir)),
+            (Kind::Port, Tok::Id(name)) => match port_by_name(name) {
+                Some(n) => Ok(self.g.port(n, tran, q.dir)),
+                None => Err(bad(&tok)),
+            },
+            (Kind::PortRange, Tok::Num(lo)) => {
+                if !self.eat_sym("-") {
+                    return Err(self.syntax("'-'"));
+                }
+                let hi = self.number_upto(0xffff)?;
+                if *lo > 0xffff {
+                    return Err(bad(&tok));
+                }
+                let (lo, hi) = if *lo <= hi { (*lo, hi) } else { (hi, *lo) };
+                Ok(self.g.portrange(lo, hi, tran, q.dir))
+            }
+            (Kind::Default | Kind::Host, Tok::Mac(mac))
+                if matches!(q.proto, None | Some(Proto::Ether)) =>
+            {
+                Ok(self.g.ehostop(mac, q.dir))
+            }
+            (Kind::Default | Kind::Host, Tok::Ip(o)) | (Kind::Net, Tok::Ip(o)) => {
+                if !matches!(q.proto, None | Some(Proto::Ip | Proto::Arp | Proto::Rarp)) {
+                    self.idx -= 1;
+                    return Err(self.unsupported("this protocol with an IPv4 address"));
+                }
+                let mut octets = [0u8; 4];
+                octets[..o.len()].copy_from_slice(o);
+                let addr = u32::from_be_bytes(octets);
+                let mut mask = if q.kind == Kind::Net {
+                    u32::MAX.checked_shl(32 - 8 * o.len() as u32).unwrap_or(0)
+                } else if o.len() == 4 {
+                    u32::MAX
+                } else {
+                    return Err(bad(&tok));
+                };
+                if q.kind == Kind::Net {
+                    if self.eat_sym("/") {
+                        let len = self.number()?;
+                        if len > 32 {
+                            return Err(bad(&Tok::Num(len)));
+                        }
+                        mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+                    } else if self.eat_id("mask") {
+                        match self.next() {
+                            Some(Tok::Ip(m)) if m.len() == 4 => {
+                                mask = u32::from_be_bytes([m[0], m[1], m[2], m[3]]);
+                            }
+                            _ => return Err(self.syntax("a netmask")),
+                        }
+                    }
+                    if addr & !mask != 0 {
+                        return Err(bad(&tok));
+                    }
+                }
+                Ok(self.g.host(addr, mask, q.dir, q.proto))
+            }
+            (_, Tok::Id(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                self.idx -= 1;
+                Err(self.unsupported("a name lookup"))
+            }
+            _ => {
+                self.idx -= 1;
+                Err(self.syntax("a value for the qualifiers"))
+            }
+        }
+    }
+
+    /* expr relop expr, where the expressions are the arithmetic of pcap-filter(7) */
+    fn relation(&mut self) -> Result<Chain, BpfError> {
+        let lhs = self.arth()?;
+        let op = match self.next() {
+            Some(Tok::Sym("=")) | Some(Tok::Sym("==")) => RelOp::Eq,
+            Some(Tok::Sym("!=")) => RelOp::Ne,
+            Some(Tok::Sym(">")) => RelOp::Gt,
+            Some(Tok::Sym(">=")) => RelOp::Ge,
+            Some(Tok::Sym("<")) => RelOp::Lt,
+            Some(Tok::Sym("<=")) => RelOp::Le,
+            _ => {
+                self.idx -= 1;
+                return Err(self.syntax("a comparison"));
+            }
+        };
+        let rhs = self.arth()?;
+        self.last = None;
+        let mut checks = vec![];
+        let bits = match (&lhs, &rhs) {
+            (Arth::Bin(BPF_AND, x, m), Arth::Num(0)) => match **m {
+                Arth::Num(m) => Some((x, m)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let cmp = match (bits, op, &rhs) {
+            /* "x & m != 0" and "x & m = 0" are a test of the bits */
+            (Some((x, m)), RelOp::Ne, _) => {
+                let s = self.arth_stmts(x, 0, &mut checks)?;
+                self.g.block(s, BPF_JSET, m)
+            }
+            (Some((x, m)), RelOp::Eq, _) => {
+                let s = self.arth_stmts(x, 0, &mut checks)?;
+                let b = self.g.block(s, BPF_JSET, m);
+                self.g.not(b)
+            }
+            (_, _, Arth::Num(v)) => {
+                let s = self.arth_stmts(&lhs, 0, &mut checks)?;
+                self.g.relation(s, op, BPF_K, *v)
+            }
+            /* the right side goes to M[0] and then to X */
+            _ => {
+                let mut s = self.arth_stmts(&rhs, 0, &mut checks)?;
+                s.push(BpfInsn::stmt(BPF_ST, 0));
+                s.extend(self.arth_stmts(&lhs, 1, &mut checks)?);
+                s.push(BpfInsn::stmt(BPF_LDX | BPF_MEM, 0));
+                self.g.relation(s, op, BPF_X, 0)
+            }
+        };
+        let mut c = cmp;
+        for check in checks.into_iter().rev() {
+            c = self.g.and(check, c);
+        }
+        Ok(c)
+    }
+
+    /* the binary operators from the loosest to the tightest, as in libpcap's grammar */
+    fn arth(&mut self) -> Result<Arth, BpfError> {
+        const LEVELS: &[&[(&str, u16)]] = &[
+            &[("|", BPF_OR), ("^", BPF_XOR)],
+            &[("&", BPF_AND)],
+            &[("<<", BPF_LSH), (">>", BPF_RSH)],
+            &[("+", BPF_ADD), ("-", BPF_SUB)],
+            &[("*", BPF_MUL), ("/", BPF_DIV), ("%", BPF_MOD)],
+        ];
+        self.arth_level(LEVELS)
+    }
+
+    fn arth_level(&mut self, levels: &[&[(&str, u16)]]) -> Result<Arth, BpfError> {
+        let (ops, tighter) = match levels.split_first() {
+            Some(l) => l,
+            None => return self.arth_unary(),
+        };
+        let mut e = self.arth_level(tighter)?;
+        'more: loop {
+            for (sym, op) in ops.iter() {
+                let pos = self.pos();
+                if self.eat_sym(sym) {
+                    let rhs = self.arth_level(tighter)?;
+                    e = Arth::bin(*op, e, rhs).ok_or(BpfError::BadValue {
+                        pos,
+                        value: "a division by zero".to_string(),
+                    })?;
+                    continue 'more;
+                }
+            }
+            return Ok(e);
+        }
+    }
+
+    fn arth_unary(&mut self) -> Result<Arth, BpfError> {
+        if self.eat_sym("-") {
+            return Ok(match self.arth_unary()? {
+                Arth::Num(n) => Arth::Num(n.wrapping_neg()),
+                e => Arth::Neg(Box::new(e)),
+            });
+        }
+        if self.eat_sym("(") {
+            let e = self.arth()?;
+            if !self.eat_sym(")") {
+                return Err(self.syntax("')'"));
+            }
+            return Ok(e);
+        }
+        if self.eat_id("len") {
+            return Ok(Arth::Len);
+        }
+        let proto = match self.peek() {
+            Some(Tok::Id(name)) => Proto::from_name(name),
+            _ => None,
+        };
+        match proto {
+            Some(p) => {
+                self.idx += 1;
+                self.accessor(p)
+            }
+            None => Ok(Arth::Num(self.number()?)),
+        }
+    }
+
+    /* proto[expr(:size)] */
+    fn accessor(&mut self, p: Proto) -> Result<Arth, BpfError> {
+        if !self.eat_sym("[") {
+            return Err(self.syntax("'['"));
+        }
+        let index = self.arth()?;
+        let size = if self.eat_sym(":") {
+            match self.number()? {
+                1 => BPF_B,
+                2 => BPF_H,
+                4 => BPF_W,
+                _ => return Err(self.syntax("a size of 1, 2 or 4")),
+            }
+        } else {
+            BPF_B
+        };
+        if !self.eat_sym("]") {
+            return Err(self.syntax("']'"));
+        }
+        Ok(Arth::Load(p, Box::new(index), size))
+    }
+
+    /*
+     * The statements that leave the value of the expression in A.
+     * The values of the right operands that are not constants are kept
+     * in M[reg] and up while the left operands are computed.
+     */
+    fn arth_stmts(
+        &mut self,
+        e: &Arth,
+        reg: u32,
+        checks: &mut Vec<Chain>,
+    ) -> Result<Vec<BpfInsn>, BpfError> {
+        if reg as usize >= BPF_MEMWORDS {
+            return Err(self.unsupported("an expression that needs this many registers"));
+        }
+        Ok(match e {
+            Arth::Num(n) => vec![BpfInsn::stmt(BPF_LD | BPF_IMM, *n)],
+            Arth::Len => vec![BpfInsn::stmt(BPF_LD | BPF_W | BPF_LEN, 0)],
+            Arth::Neg(x) => {
+                let mut s = self.arth_stmts(x, reg, checks)?;
+                s.push(BpfInsn::stmt(BPF_ALU | BPF_NEG, 0));
+                s
+            }
+            Arth::Bin(op, x, y) => match **y {
+                Arth::Num(k) => {
+                    let mut s = self.arth_stmts(x, reg, checks)?;
+                    s.push(BpfInsn::stmt(BPF_ALU | op | BPF_K, k));
+                    s
+                }
+                _ => {
+                    let mut s = self.arth_stmts(y, reg, checks)?;
+                    s.push(BpfInsn::stmt(BPF_ST, reg));
+                    s.extend(self.arth_stmts(x, reg + 1, checks)?);
+                    s.push(BpfInsn::stmt(BPF_LDX | BPF_MEM, reg));
+                    s.push(BpfInsn::stmt(BPF_ALU | op | BPF_X, 0));
+                    s
+                }
+            },
+            Arth::Load(p, index, size) => {
+                let g = &mut self.g;
+                let nl = g.off_nl;
+                /* the start of the protocol header */
+                let base = match p {
+                    Proto::Ether => 0,
+                    Proto::Ip | Proto::Arp | Proto::Rarp => {
+                        checks.push(g.proto(*p));
+                        nl
+                    }
+                    Proto::Ip6 => {
+                        checks.push(g.proto(*p));
+                        nl + 40
+                    }
+                    Proto::Tcp | Proto::Udp | Proto::Sctp | Proto::Icmp | Proto::Igmp => {
+                        let ip = g.proto(Proto::Ip);
+                        let b0 = g.proto(*p);
+                        let b1 = g.ipfrag();
+                        let tran = g.and(b0, b1);
+                        checks.push(ip);
+                        checks.push(tran);
+                        nl
+                    }
+                    Proto::Icmp6 => {
+                        checks.push(g.proto(*p));
+                        nl + 40
+                    }
+                };
+                let tran = matches!(
+                    p,
+                    Proto::Tcp | Proto::Udp | Proto::Sctp | Proto::Icmp | Proto::Igmp
+                );
+                match (**index).clone() {
+                    Arth::Num(off) if tran => self.g.load_tran(*size, off),
+                    Arth::Num(off) => Gen::load(*size, base + off),
+                    index => {
+                        let mut s = self.arth_stmts(&index, reg, checks)?;
+                        if tran {
+                            /* the index plus the length of the IPv4 header */
+                            s.push(BpfInsn::stmt(BPF_ST, reg));
+                            s.push(BpfInsn::stmt(BPF_LDX | BPF_B | BPF_MSH, nl));
+                            s.push(BpfInsn::stmt(BPF_LD | BPF_MEM, reg));
+                            s.push(BpfInsn::stmt(BPF_ALU | BPF_ADD | BPF_X, 0));
+                        }
+                        s.push(BpfInsn::stmt(BPF_MISC | BPF_TAX, 0));
+                        s.push(BpfInsn::stmt(BPF_LD | size | BPF_IND, base));
+                        s
+                    }
+                }
+            }
+        })
+    }
+}
+
+/* an arithmetic expression of a comparison, e.g. "tcp[12:1] & 0xf0" or "len - 14" */
+#[derive(Clone, Debug)]
+enum Arth {
+    Num(u32),
+    Len,
+    /* proto[index:size] */
+    Load(Proto, Box<Arth>, u16),
+    Neg(Box<Arth>),
+    Bin(u16, Box<Arth>, Box<Arth>),
+}
+
+impl Arth {
+    /* the operation, computed right away on constants; None for a division by zero */
+    fn bin(op: u16, x: Arth, y: Arth) -> Option<Arth> {
+        let (a, b) = match (&x, &y) {
+            (_, Arth::Num(0)) if op == BPF_DIV || op == BPF_MOD => return None,
+            (Arth::Num(a), Arth::Num(b)) => (*a, *b),
+            _ => return Some(Arth::Bin(op, Box::new(x), Box::new(y))),
+        };
+        Some(Arth::Num(match op {
+            BPF_ADD => a.wrapping_add(b),
+            BPF_SUB => a.wrapping_sub(b),
+            BPF_MUL => a.wrapping_mul(b),
+            BPF_DIV => a / b,
+            BPF_MOD => a % b,
+            BPF_AND => a & b,
+            BPF_OR => a | b,
+            BPF_XOR => a ^ b,
+            BPF_LSH => a.checked_shl(b).unwrap_or(0),
+            _ => a.checked_shr(b).unwrap_or(0),
+        }))
+    }
+}
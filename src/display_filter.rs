@@ -0,0 +1,926 @@
+/*
+ * Wireshark-style display filters, evaluated on the decoded layers, e.g.
+ * Ip.ttl < 5 && Udp.dport == 4789 && Vxlan.vni == 100
+ *
+ * The layers and fields are found by name through the LAYER_NAMES registry and
+ * get_field(), so every protocol made with the NetworkProtocol derive can be filtered on.
+ * A layer name alone tests for its presence, a field alone for the field having a value.
+ * When a layer is in the stack more than once, the test is true if it holds for any of them,
+ * unless the layer is picked as in LayerStack::get(): "Udp[1].dport".
+ * "!=" holds when none of the values is equal, as in the recent Wireshark versions.
+ * A literal or a slice the type of the field can never match, as "Ip.src == 10.0.0.0/40"
+ * or "Ip.ttl[0:1]", is a parse error rather than a test that is always false.
+ */
+
+use crate::*;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisplayFilterError {
+    Syntax {
+        pos: usize,
+        expected: &'static str,
+    },
+    UnknownLayer {
+        pos: usize,
+        name: String,
+    },
+    UnknownField {
+        pos: usize,
+        layer: String,
+        field: String,
+        fields: Vec<&'static str>,
+    },
+    BadValue {
+        pos: usize,
+        value: String,
+    },
+}
+
+impl fmt::Display for DisplayFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { pos, expected } => write!(f, "at offset {}: expected {}", pos, expected),
+            Self::UnknownLayer { pos, name } => write!(
+                f,
+                "at offset {}: unknown layer {:?} (known: {})",
+                pos,
+                name,
+                layer_names().join(", ")
+            ),
+            Self::UnknownField {
+                pos,
+                layer,
+                field,
+                fields,
+            } => write!(
+                f,
+                "at offset {}: {} has no field {:?} (fields: {})",
+                pos,
+                layer,
+                field,
+                fields.join(", ")
+            ),
+            Self::BadValue { pos, value } => write!(f, "at offset {}: bad value {:?}", pos, value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/* Wireshark's [i:len], [i-j], [i], [:len] and [i:]; a negative start counts from the end */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slice {
+    pub start: i64,
+    pub len: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldRef {
+    pub layer: &'static str,
+    pub nth: Option<usize>,
+    /* without a field the reference is to the bytes of the layer */
+    pub field: Option<String>,
+    pub slice: Option<Slice>,
+}
+
+/* a constant as written; what it means depends on the field it is compared with */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Literal {
+    pub text: String,
+    pub quoted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Field(FieldRef),
+    Literal(Literal),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetItem {
+    Value(Literal),
+    Range(Literal, Literal),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Present(FieldRef),
+    Compare(Operand, CmpOp, Operand),
+    Contains(Operand, Operand),
+    In(Operand, Vec<SetItem>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayFilter {
+    pub expr: FilterExpr,
+}
+
+impl DisplayFilter {
+    pub fn parse(s: &str) -> Result<Self, DisplayFilterError> {
+        let mut p = Parser {
+            toks: lex(s)?,
+            idx: 0,
+            end: s.len(),
+        };
+        let expr = p.or()?;
+        if p.idx < p.toks.len() {
+            return Err(p.syntax("'&&', '||' or the end"));
+        }
+        Ok(DisplayFilter { expr })
+    }
+
+    pub fn matches(&self, stack: &LayerStack) -> bool {
+        let mut ctx = EvalContext {
+            stack,
+            encoded: None,
+        };
+        ctx.eval(&self.expr)
+    }
+}
+
+impl FromStr for DisplayFilter {
+    type Err = DisplayFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DisplayFilter::parse(s)
+    }
+}
+
+/* a value taken from the packet */
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Val {
+    Num(i128),
+    Bool(bool),
+    Ipv4(u32),
+    Bytes(Vec<u8>),
+    Str(String),
+}
+
+impl Val {
+    fn from_field(v: FieldValue) -> Option<Val> {
+        Some(match v {
+            FieldValue::Auto | FieldValue::Random => return None,
+            FieldValue::Bool(b) => Val::Bool(b),
+            FieldValue::UInt(x) => Val::Num(x as i128),
+            FieldValue::Int(x) => Val::Num(x as i128),
+            FieldValue::Ipv4(a) => Val::Ipv4(u32::from(a.0)),
+            FieldValue::Mac(m) => Val::Bytes(m.0.bytes().to_vec()),
+            FieldValue::Bytes(b) => Val::Bytes(b),
+            FieldValue::Str(s) => Val::Str(s),
+        })
+    }
+
+    fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Val::Bytes(b) => Some(b.clone()),
+            Val::Str(s) => Some(s.as_bytes().to_vec()),
+            Val::Ipv4(a) => Some(a.to_be_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn slice(&self, s: &Slice) -> Option<Val> {
+        let b = self.bytes()?;
+        let start = if s.start < 0 {
+            b.len().checked_sub(s.start.unsigned_abs() as usize)?
+        } else {
+            s.start as usize
+        };
+        let end = match s.len {
+            Some(len) => start.checked_add(len)?,
+            None => b.len(),
+        };
+        b.get(start..end).map(|x| Val::Bytes(x.to_vec()))
+    }
+}
+
+fn parse_num(s: &str) -> Option<i128> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let v = if let Some(hex) = s.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).ok()?
+    } else {
+        s.parse::<i128>().ok()?
+    };
+    Some(if neg { -v } else { v })
+}
+
+/* "aa:bb:cc" or "aa-bb-cc" */
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    let sep = if s.contains(':') { ':' } else { '-' };
+    if !s.contains(sep) {
+        return None;
+    }
+    s.split(sep)
+        .map(|b| {
+            if b.is_empty() || b.len() > 2 {
+                None
+            } else {
+                u8::from_str_radix(b, 16).ok()
+            }
+        })
+        .collect()
+}
+
+/* an address and the mask of "10.0.0.0/8" */
+fn parse_ipv4(s: &str) -> Option<(u32, u32)> {
+    let (addr, len) = match s.split_once('/') {
+        Some((a, l)) => (a, l.parse::<u32>().ok().filter(|l| *l <= 32)?),
+        None => (s, 32),
+    };
+    let addr: std::net::Ipv4Addr = addr.parse().ok()?;
+    Some((u32::from(addr), u32::MAX.checked_shl(32 - len).unwrap_or(0)))
+}
+
+/* a value of the kind of the fields of the type, and the width of their bytes if it is fixed */
+fn kind_of_type(typ: &str) -> Option<(Val, Option<usize>)> {
+    let t = typ
+        .strip_prefix("Value<")
+        .and_then(|t| t.strip_suffix('>'))
+        .unwrap_or(typ);
+    Some(match t {
+        "u8" => (Val::Num(0), Some(1)),
+        "u16" => (Val::Num(0), Some(2)),
+        "u32" => (Val::Num(0), Some(4)),
+        "u64" => (Val::Num(0), Some(8)),
+        "bool" => (Val::Bool(false), None),
+        "Ipv4Address" => (Val::Ipv4(0), Some(4)),
+        "MacAddr" => (Val::Bytes(vec![]), Some(6)),
+        "Vec<u8>" => (Val::Bytes(vec![]), None),
+        "String" => (Val::Str(String::new()), None),
+        _ => return None,
+    })
+}
+
+impl FieldRef {
+    /* the kind of the values, when the type of the field tells it */
+    fn kind(&self) -> Option<(Val, Option<usize>)> {
+        if let Some(s) = &self.slice {
+            return Some((Val::Bytes(vec![]), s.len));
+        }
+        match &self.field {
+            None => Some((Val::Bytes(vec![]), None)),
+            Some(f) => {
+                let layer = make_layer_by_name(self.layer)?;
+                let desc = layer.field_descs().into_iter().find(|d| d.name == f)?;
+                kind_of_type(desc.typ)
+            }
+        }
+    }
+}
+
+impl Slice {
+    /* false if the values of the kind never have the bytes */
+    fn fits(&self, (v, width): &(Val, Option<usize>)) -> bool {
+        if v.bytes().is_none() {
+            return false;
+        }
+        let w = match width {
+            Some(w) => *w as i64,
+            None => return true,
+        };
+        let start = if self.start < 0 {
+            w + self.start
+        } else {
+            self.start
+        };
+        let end = match self.len {
+            Some(len) => start + len as i64,
+            None => w,
+        };
+        start >= 0 && end <= w
+    }
+}
+
+impl Literal {
+    /* the literal as a value of the same kind as v, if it can be one */
+    fn as_kind_of(&self, v: &Val) -> Option<Val> {
+        let t = self.text.as_str();
+        if self.quoted {
+            return match v {
+                Val::Str(_) => Some(Val::Str(self.text.clone())),
+                Val::Bytes(_) => Some(Val::Bytes(self.text.as_bytes().to_vec())),
+                _ => None,
+            };
+        }
+        match v {
+            Val::Num(_) => parse_num(t).map(Val::Num),
+            Val::Bool(_) => match t {
+                "true" | "1" => Some(Val::Bool(true)),
+                "false" | "0" => Some(Val::Bool(false)),
+                _ => None,
+            },
+            Val::Ipv4(_) => parse_ipv4(t).map(|(a, _)| Val::Ipv4(a)),
+            Val::Bytes(_) => parse_bytes(t)
+                .or_else(|| {
+                    /* a single byte, as in Raw.load[0] == 45 */
+                    if t.len() <= 2 {
+                        u8::from_str_radix(t, 16).ok().map(|b| vec![b])
+                    } else {
+                        None
+                    }
+                })
+                .map(Val::Bytes),
+            Val::Str(_) => Some(Val::Str(self.text.clone())),
+        }
+    }
+
+    /* "10.0.0.0/8" equals the addresses in the subnet */
+    fn equals(&self, v: &Val) -> bool {
+        if let (Val::Ipv4(a), false) = (v, self.quoted) {
+            if let Some((net, mask)) = parse_ipv4(&self.text) {
+                return a & mask == net & mask;
+            }
+        }
+        self.as_kind_of(v).as_ref() == Some(v)
+    }
+}
+
+fn compare(a: &Val, b: &Val) -> Option<Ordering> {
+    match (a, b) {
+        (Val::Num(x), Val::Num(y)) => Some(x.cmp(y)),
+        (Val::Bool(x), Val::Bool(y)) => Some(x.cmp(y)),
+        (Val::Ipv4(x), Val::Ipv4(y)) => Some(x.cmp(y)),
+        (Val::Str(x), Val::Str(y)) => Some(x.cmp(y)),
+        (x, y) => Some(x.bytes()?.cmp(&y.bytes()?)),
+    }
+}
+
+fn holds(op: CmpOp, ord: Ordering) -> bool {
+    match op {
+        CmpOp::Eq => ord == Ordering::Equal,
+        CmpOp::Ne => ord != Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+    }
+}
+
+fn contains(hay: &Val, needle: &Val) -> bool {
+    match (hay.bytes(), needle.bytes()) {
+        (Some(h), Some(n)) => n.is_empty() || h.windows(n.len()).any(|w| w == n.as_slice()),
+        _ => false,
+    }
+}
+
+struct EvalContext<'a> {
+    stack: &'a LayerStack,
+    /* the bytes of the packet and where each layer is, for the layer slices */
+    encoded: Option<(Vec<u8>, DissectionMap)>,
+}
+
+impl<'a> EvalContext<'a> {
+    fn layers(&self, r: &FieldRef) -> Vec<usize> {
+        let all = self
+            .stack
+            .layers
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.layer_name().eq_ignore_ascii_case(r.layer))
+            .map(|(i, _)| i);
+        match r.nth {
+            Some(n) => all.skip(n).take(1).collect(),
+            None => all.collect(),
+        }
+    }
+
+    fn layer_bytes(&mut self, idx: usize) -> Option<Vec<u8>> {
+        if self.encoded.is_none() {
+            self.encoded = Some(self.stack.clone().encode_with_map());
+        }
+        let (bytes, map) = self.encoded.as_ref()?;
+        let spans = map.layer_spans(idx);
+        let start = spans.iter().map(|s| s.offset).min()?;
+        let end = spans.iter().map(|s| s.offset + s.length).max()?;
+        bytes.get(start..end).map(|b| b.to_vec())
+    }
+
+    fn values(&mut self, r: &FieldRef) -> Vec<Val> {
+        let mut out = vec![];
+        for idx in self.layers(r) {
+            let v = match &r.field {
+                Some(f) => self.stack.layers[idx]
+                    .get_field(f)
+                    .and_then(Val::from_field),
+                None => self.layer_bytes(idx).map(Val::Bytes),
+            };
+            let v = match (v, &r.slice) {
+                (Some(v), Some(s)) => v.slice(s),
+                (v, _) => v,
+            };
+            out.extend(v);
+        }
+        out
+    }
+
+    fn eval(&mut self, e: &FilterExpr) -> bool {
+        match e {
+            FilterExpr::And(a, b) => self.eval(a) && self.eval(b),
+            FilterExpr::Or(a, b) => self.eval(a) || self.eval(b),
+            FilterExpr::Not(a) => !self.eval(a),
+            FilterExpr::Present(r) => match (&r.field, r.slice) {
+                (None, None) => !self.layers(r).is_empty(),
+                _ => !self.values(r).is_empty(),
+            },
+            FilterExpr::Compare(a, CmpOp::Ne, b) => {
+                !self.eval(&FilterExpr::Compare(a.clone(), CmpOp::Eq, b.clone()))
+                    && self.any_value(a)
+            }
+            FilterExpr::Compare(a, op, b) => self.pairs(a, b, |x, y| match (x, y) {
+                (Side::Val(x), Side::Val(y)) => compare(x, y).map(|o| holds(*op, o)),
+                (Side::Val(v), Side::Lit(l)) if *op == CmpOp::Eq => Some(l.equals(v)),
+                (Side::Lit(l), Side::Val(v)) if *op == CmpOp::Eq => Some(l.equals(v)),
+                (Side::Val(v), Side::Lit(l)) => l
+                    .as_kind_of(v)
+                    .and_then(|y| compare(v, &y))
+                    .map(|o| holds(*op, o)),
+                (Side::Lit(l), Side::Val(v)) => l
+                    .as_kind_of(v)
+                    .and_then(|x| compare(&x, v))
+                    .map(|o| holds(*op, o)),
+                _ => None,
+            }),
+            FilterExpr::Contains(a, b) => self.pairs(a, b, |x, y| match (x, y) {
+                (Side::Val(x), Side::Val(y)) => Some(contains(x, y)),
+                (Side::Val(v), Side::Lit(l)) => {
+                    let needle =
+                        match v {
+                            Val::Str(_) => Val::Str(l.text.clone()),
+                            _ if l.quoted => Val::Str(l.text.clone()),
+                            _ => Val::Bytes(parse_bytes(&l.text).or_else(|| {
+                                u8::from_str_radix(&l.text, 16).ok().map(|b| vec![b])
+                            })?),
+                        };
+                    Some(contains(v, &needle))
+                }
+                _ => None,
+            }),
+            FilterExpr::In(a, items) => {
+                let vals = match a {
+                    Operand::Field(r) => self.values(r),
+                    Operand::Literal(_) => vec![],
+                };
+                vals.iter().any(|v| {
+                    items.iter().any(|item| match item {
+                        SetItem::Value(l) => l.equals(v),
+                        SetItem::Range(lo, hi) => {
+                            let ge = lo.as_kind_of(v).and_then(|lo| compare(v, &lo));
+                            let le = hi.as_kind_of(v).and_then(|hi| compare(v, &hi));
+                            matches!(ge, Some(Ordering::Greater | Ordering::Equal))
+                                && matches!(le, Some(Ordering::Less | Ordering::Equal))
+                        }
+                    })
+                })
+            }
+        }
+    }
+
+    fn any_value(&mut self, o: &Operand) -> bool {
+        match o {
+            Operand::Field(r) => !self.values(r).is_empty(),
+            Operand::Literal(_) => true,
+        }
+    }
+
+    /* true if the test holds for any pair of the values of the two sides */
+    fn pairs(
+        &mut self,
+        a: &Operand,
+        b: &Operand,
+        test: impl Fn(&Side, &Side) -> Option<bool>,
+    ) -> bool {
+        let a = self.sides_of(a);
+        let b = self.sides_of(b);
+        a.iter()
+            .any(|x| b.iter().any(|y| test(x, y).unwrap_or(false)))
+    }
+
+    fn sides_of<'o>(&mut self, o: &'o Operand) -> Vec<Side<'o>> {
+        match o {
+            Operand::Field(r) => self.values(r).into_iter().map(Side::Val).collect(),
+            Operand::Literal(l) => vec![Side::Lit(l)],
+        }
+    }
+}
+
+enum Side<'o> {
+    Val(Val),
+    Lit(&'o Literal),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Tok {
+    Word(String),
+    Str(String),
+    Sym(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", "{", "}", "[", "]", ",",
+];
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.:/-".contains(c)
+}
+
+fn lex(s: &str) -> Result<Vec<(usize, Tok)>, DisplayFilterError> {
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        if c == '"' {
+            let mut val = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, e)) => val.push(e),
+                        None => break rest.len(),
+                    },
+                    Some((_, ch)) => val.push(ch),
+                    None => {
+                        return Err(DisplayFilterError::Syntax {
+                            pos: s.len(),
+                            expected: "'\"'",
+                        })
+                    }
+                }
+            };
+            out.push((pos, Tok::Str(val)));
+            pos += end + 1;
+            continue;
+        }
+        if is_word_char(c) {
+            let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+            out.push((pos, Tok::Word(rest[..len].to_string())));
+            pos += len;
+            continue;
+        }
+        match SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+            Some(sym) => {
+                out.push((pos, Tok::Sym(sym)));
+                pos += sym.len();
+            }
+            None => {
+                return Err(DisplayFilterError::Syntax {
+                    pos,
+                    expected: "a filter expression",
+                })
+            }
+        }
+    }
+    Ok(out)
+}
+
+struct Parser {
+    toks: Vec<(usize, Tok)>,
+    idx: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn pos(&self) -> usize {
+        self.toks.get(self.idx).map(|t| t.0).unwrap_or(self.end)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.idx).map(|t| &t.1)
+    }
+
+    fn syntax(&self, expected: &'static str) -> DisplayFilterError {
+        DisplayFilterError::Syntax {
+            pos: self.pos(),
+            expected,
+        }
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        let yes = matches!(self.peek(), Some(Tok::Sym(x)) if *x == sym);
+        if yes {
+            self.idx += 1;
+        }
+        yes
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let yes = matches!(self.peek(), Some(Tok::Word(x)) if x == word);
+        if yes {
+            self.idx += 1;
+        }
+        yes
+    }
+
+    fn or(&mut self) -> Result<FilterExpr, DisplayFilterError> {
+        let mut e = self.and()?;
+        while self.eat_sym("||") || self.eat_word("or") {
+            e = FilterExpr::Or(Box::new(e), Box::new(self.and()?));
+        }
+        Ok(e)
+    }
+
+    fn and(&mut self) -> Result<FilterExpr, DisplayFilterError> {
+        let mut e = self.not()?;
+        while self.eat_sym("&&") || self.eat_word("and") {
+            e = FilterExpr::And(Box::new(e), Box::new(self.not()?));
+        }
+        Ok(e)
+    }
+
+    fn not(&mut self) -> Result<FilterExpr, DisplayFilterError> {
+        if self.eat_sym("!") || self.eat_word("not") {
+            return Ok(FilterExpr::Not(Box::new(self.not()?)));
+        }
+        if self.eat_sym("(") {
+            let e = self.or()?;
+            if !self.eat_sym(")") {
+                return Err(self.syntax("')'"));
+            }
+            return Ok(e);
+        }
+        self.test()
+    }
+
+    fn cmp_op(&mut self) -> Option<CmpOp> {
+        let op = match self.peek()? {
+            Tok::Sym("==") => CmpOp::Eq,
+            Tok::Sym("!=") => CmpOp::Ne,
+            Tok::Sym("<") => CmpOp::Lt,
+            Tok::Sym("<=") => CmpOp::Le,
+            Tok::Sym(">") => CmpOp::Gt,
+            Tok::Sym(">=") => CmpOp::Ge,
+            Tok::Word(w) => match w.as_str() {
+                "eq" => CmpOp::Eq,
+                "ne" => CmpOp::Ne,
+                "lt" => CmpOp::Lt,
+                "le" => CmpOp::Le,
+                "gt" => CmpOp::Gt,
+                "ge" => CmpOp::Ge,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.idx += 1;
+        Some(op)
+    }
+
+    fn test(&mut self) -> Result<FilterExpr, DisplayFilterError> {
+        let lpos = self.pos();
+        let left = self.operand()?;
+        if let Some(op) = self.cmp_op() {
+            let rpos = self.pos();
+            let right = self.operand()?;
+            match (&left, &right) {
+                (Operand::Literal(l), r) => check_literal(r, l, lpos)?,
+                (l, Operand::Literal(r)) => check_literal(l, r, rpos)?,
+                _ => {}
+            }
+            return Ok(FilterExpr::Compare(left, op, right));
+        }
+        if self.eat_word("contains") {
+            let right = self.operand()?;
+            return Ok(FilterExpr::Contains(left, right));
+        }
+        if self.eat_word("in") {
+            let set = self.set(&left)?;
+            return Ok(FilterExpr::In(left, set));
+        }
+        match left {
+            Operand::Field(r) => Ok(FilterExpr::Present(r)),
+            Operand::Literal(_) => Err(self.syntax("a comparison")),
+        }
+    }
+
+    /* {53 4789 1000..2000}, with optional commas */
+    fn set(&mut self, left: &Operand) -> Result<Vec<SetItem>, DisplayFilterError> {
+        if !self.eat_sym("{") {
+            return Err(self.syntax("'{'"));
+        }
+        let mut items = vec![];
+        while !self.eat_sym("}") {
+            self.eat_sym(",");
+            let pos = self.pos();
+            let item = match self.toks.get(self.idx).map(|t| t.1.clone()) {
+                Some(Tok::Word(w)) => match w.split_once("..") {
+                    Some((lo, hi)) => {
+                        check_literal(left, &literal(lo), pos)?;
+                        check_literal(left, &literal(hi), pos + lo.len() + 2)?;
+                        SetItem::Range(literal(lo), literal(hi))
+                    }
+                    None => {
+                        check_literal(left, &literal(&w), pos)?;
+                        SetItem::Value(literal(&w))
+                    }
+                },
+                Some(Tok::Str(s)) => SetItem::Value(Literal {
+                    text: s,
+                    quoted: true,
+                }),
+                Some(Tok::Sym("}")) => continue,
+                _ => {
+                    return Err(DisplayFilterError::Syntax {
+                        pos,
+                        expected: "a value or '}'",
+                    })
+                }
+            };
+            self.idx += 1;
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn operand(&mut self) -> Result<Operand, DisplayFilterError> {
+        let pos = self.pos();
+        let word = match self.toks.get(self.idx).map(|t| t.1.clone()) {
+            Some(Tok::Str(s)) => {
+                self.idx += 1;
+                return Ok(Operand::Literal(Literal {
+                    text: s,
+                    quoted: true,
+                }));
+            }
+            Some(Tok::Word(w)) => w,
+            _ => return Err(self.syntax("a field or a value")),
+        };
+        self.idx += 1;
+        let first = word.chars().next().unwrap();
+        let is_field = (first.is_ascii_alphabetic() || first == '_')
+            && parse_bytes(&word).is_none()
+            && word != "true"
+            && word != "false";
+        if !is_field {
+            return Ok(Operand::Literal(literal(&word)));
+        }
+        let (layer_name, mut field) = match word.split_once('.') {
+            Some((l, f)) => (l.to_string(), Some((f.to_string(), pos + l.len() + 1))),
+            None => (word.clone(), None),
+        };
+        let layer = make_layer_by_name(&layer_name).ok_or(DisplayFilterError::UnknownLayer {
+            pos,
+            name: layer_name.clone(),
+        })?;
+        let mut nth = None;
+        let mut slice = None;
+        if field.is_none() && self.eat_sym("[") {
+            let mark = self.idx;
+            /* "Udp[1].dport" picks the layer, "Udp[0:2]" is the bytes of the layer */
+            match (
+                self.peek().cloned(),
+                self.toks.get(self.idx + 1),
+                self.toks.get(self.idx + 2),
+            ) {
+                (Some(Tok::Word(n)), Some((_, Tok::Sym("]"))), Some((fpos, Tok::Word(f))))
+                    if f.starts_with('.') && n.parse::<usize>().is_ok() =>
+                {
+                    nth = n.parse().ok();
+                    field = Some((f[1..].to_string(), fpos + 1));
+                    self.idx += 3;
+                }
+                _ => {
+                    self.idx = mark;
+                    slice = Some(self.slice()?);
+                }
+            }
+        }
+        let field = match field {
+            Some((name, fpos)) => {
+                let descs = layer.field_descs();
+                /* the aliases are not in the descriptions, but get_field() knows them */
+                if descs.iter().any(|d| d.name == name) || layer.get_field(&name).is_some() {
+                    Some(name)
+                } else {
+                    return Err(DisplayFilterError::UnknownField {
+                        pos: fpos,
+                        layer: layer.layer_name().to_string(),
+                        field: name,
+                        fields: descs.iter().map(|d| d.name).collect(),
+                    });
+                }
+            }
+            None => None,
+        };
+        let mut r = FieldRef {
+            layer: layer.layer_name(),
+            nth,
+            field,
+            slice,
+        };
+        if r.field.is_some() && self.eat_sym("[") {
+            let spos = self.pos();
+            let spec = match self.peek() {
+                Some(Tok::Word(w)) => w.clone(),
+                _ => String::new(),
+            };
+            let s = self.slice()?;
+            /* Ip.ttl[0] or Ip.src[2:4] would never have a value */
+            if let Some(kind) = r.kind() {
+                if !s.fits(&kind) {
+                    return Err(DisplayFilterError::BadValue {
+                        pos: spos,
+                        value: spec,
+                    });
+                }
+            }
+            r.slice = Some(s);
+        }
+        Ok(Operand::Field(r))
+    }
+
+    /* after the '[' */
+    fn slice(&mut self) -> Result<Slice, DisplayFilterError> {
+        let pos = self.pos();
+        let spec = match self.peek() {
+            Some(Tok::Word(w)) => w.clone(),
+            _ => return Err(self.syntax("a slice")),
+        };
+        self.idx += 1;
+        if !self.eat_sym("]") {
+            return Err(self.syntax("']'"));
+        }
+        let bad = || DisplayFilterError::BadValue {
+            pos,
+            value: spec.clone(),
+        };
+        let num = |s: &str| s.parse::<i64>().map_err(|_| bad());
+        let slice = if let Some((a, b)) = spec.split_once(':') {
+            Slice {
+                start: if a.is_empty() { 0 } else { num(a)? },
+                len: if b.is_empty() {
+                    None
+                } else {
+                    Some(b.parse::<usize>().map_err(|_| bad())?)
+                },
+            }
+        } else if let Some((a, b)) = spec[1..].split_once('-') {
+            let a = num(&spec[..a.len() + 1])?;
+            let b = num(b)?;
+            if a < 0 || b < a {
+                return Err(bad());
+            }
+            Slice {
+                start: a,
+                len: Some((b - a + 1) as usize),
+            }
+        } else {
+            Slice {
+                start: num(&spec)?,
+                len: Some(1),
+            }
+        };
+        Ok(slice)
+    }
+}
+
+/* the literals the values of the field can never be, as 10.0.0.0/40 or 300 for a u8, are errors */
+fn check_literal(other: &Operand, lit: &Literal, pos: usize) -> Result<(), DisplayFilterError> {
+    let (v, width) = match other {
+        Operand::Field(r) => match r.kind() {
+            Some(k) => k,
+            None => return Ok(()),
+        },
+        Operand::Literal(_) => return Ok(()),
+    };
+    let ok = match lit.as_kind_of(&v) {
+        Some(Val::Num(n)) => width.is_none_or(|w| n >= 0 && n < 1i128 << (8 * w)),
+        Some(_) => true,
+        None => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(DisplayFilterError::BadValue {
+            pos,
+            value: lit.text.clone(),
+        })
+    }
+}
+
+fn literal(s: &str) -> Literal {
+    Literal {
+        text: s.to_string(),
+        quoted: false,
+    }
+}
@@ -0,0 +1,407 @@
+/*
+ * Conversation table: per-flow packet and byte counters at L2, L3 and L4
+ */
+
+use crate::protocols::ether::*;
+use crate::protocols::ip::*;
+use crate::protocols::pcap_file::*;
+use crate::protocols::tcp::*;
+use crate::protocols::udp::*;
+use crate::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ConversationType {
+    Ethernet,
+    Ipv4,
+    Tcp,
+    Udp,
+}
+
+impl ConversationType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConversationType::Ethernet => "ethernet",
+            ConversationType::Ipv4 => "ipv4",
+            ConversationType::Tcp => "tcp",
+            ConversationType::Udp => "udp",
+        }
+    }
+}
+
+/*
+ * Symmetric conversation key: endpoint "a" is always the lower one,
+ * so both directions of a conversation map to the same key.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FlowKey {
+    Ethernet {
+        a: MacAddr,
+        b: MacAddr,
+    },
+    Ipv4 {
+        a: Ipv4Address,
+        b: Ipv4Address,
+    },
+    Tcp {
+        a: Ipv4Address,
+        a_port: u16,
+        b: Ipv4Address,
+        b_port: u16,
+    },
+    Udp {
+        a: Ipv4Address,
+        a_port: u16,
+        b: Ipv4Address,
+        b_port: u16,
+    },
+}
+
+impl FlowKey {
+    /* the key, and whether the packet went from "a" to "b" */
+    fn ethernet(src: MacAddr, dst: MacAddr) -> (FlowKey, bool) {
+        if src <= dst {
+            (FlowKey::Ethernet { a: src, b: dst }, true)
+        } else {
+            (FlowKey::Ethernet { a: dst, b: src }, false)
+        }
+    }
+
+    fn ipv4(src: Ipv4Address, dst: Ipv4Address) -> (FlowKey, bool) {
+        if src <= dst {
+            (FlowKey::Ipv4 { a: src, b: dst }, true)
+        } else {
+            (FlowKey::Ipv4 { a: dst, b: src }, false)
+        }
+    }
+
+    fn l4(tcp: bool, src: (Ipv4Address, u16), dst: (Ipv4Address, u16)) -> (FlowKey, bool) {
+        let a_to_b = src <= dst;
+        let ((a, a_port), (b, b_port)) = if a_to_b { (src, dst) } else { (dst, src) };
+        let key = if tcp {
+            FlowKey::Tcp {
+                a,
+                a_port,
+                b,
+                b_port,
+            }
+        } else {
+            FlowKey::Udp {
+                a,
+                a_port,
+                b,
+                b_port,
+            }
+        };
+        (key, a_to_b)
+    }
+
+    pub fn conversation_type(&self) -> ConversationType {
+        match self {
+            FlowKey::Ethernet { .. } => ConversationType::Ethernet,
+            FlowKey::Ipv4 { .. } => ConversationType::Ipv4,
+            FlowKey::Tcp { .. } => ConversationType::Tcp,
+            FlowKey::Udp { .. } => ConversationType::Udp,
+        }
+    }
+
+    /* (address A, port A, address B, port B) as shown in the export */
+    pub fn endpoints(&self) -> (String, Option<u16>, String, Option<u16>) {
+        match self {
+            FlowKey::Ethernet { a, b } => (format!("{:?}", a), None, format!("{:?}", b), None),
+            FlowKey::Ipv4 { a, b } => (format!("{:?}", a), None, format!("{:?}", b), None),
+            FlowKey::Tcp {
+                a,
+                a_port,
+                b,
+                b_port,
+            }
+            | FlowKey::Udp {
+                a,
+                a_port,
+                b,
+                b_port,
+            } => (
+                format!("{:?}", a),
+                Some(*a_port),
+                format!("{:?}", b),
+                Some(*b_port),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlowDirectionStats {
+    pub packets: u64,
+    pub bytes: u64,
+    /* all the TCP flags seen in this direction, or-ed together */
+    pub tcp_flags: u8,
+    pub syn: u64,
+    pub fin: u64,
+    pub rst: u64,
+}
+
+impl FlowDirectionStats {
+    fn add(&mut self, frame_len: usize, tcp_flags: Option<u8>) {
+        self.packets += 1;
+        self.bytes += frame_len as u64;
+        if let Some(flags) = tcp_flags {
+            self.tcp_flags |= flags;
+            if flags & TCP_FLAG_SYN != 0 {
+                self.syn += 1;
+            }
+            if flags & TCP_FLAG_FIN != 0 {
+                self.fin += 1;
+            }
+            if flags & TCP_FLAG_RST != 0 {
+                self.rst += 1;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlowStats {
+    pub a_to_b: FlowDirectionStats,
+    pub b_to_a: FlowDirectionStats,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+}
+
+impl FlowStats {
+    pub fn packets(&self) -> u64 {
+        self.a_to_b.packets + self.b_to_a.packets
+    }
+    pub fn bytes(&self) -> u64 {
+        self.a_to_b.bytes + self.b_to_a.bytes
+    }
+    pub fn duration(&self) -> Duration {
+        self.last_seen.saturating_sub(self.first_seen)
+    }
+}
+
+/* One line of the export, laid out like the Wireshark conversations table */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConversationRow {
+    pub address_a: String,
+    pub port_a: Option<u16>,
+    pub address_b: String,
+    pub port_b: Option<u16>,
+    pub packets: u64,
+    pub bytes: u64,
+    pub packets_a_to_b: u64,
+    pub bytes_a_to_b: u64,
+    pub packets_b_to_a: u64,
+    pub bytes_b_to_a: u64,
+    /* seconds since the first packet in the table */
+    pub rel_start: f64,
+    pub duration: f64,
+    /* TCP flags seen in each direction, e.g. "SYN,ACK" */
+    pub tcp_flags_a_to_b: Option<String>,
+    pub tcp_flags_b_to_a: Option<String>,
+}
+
+pub fn tcp_flags_string(flags: u8) -> String {
+    let names = [
+        (TCP_FLAG_FIN, "FIN"),
+        (TCP_FLAG_SYN, "SYN"),
+        (TCP_FLAG_RST, "RST"),
+        (TCP_FLAG_PSH, "PSH"),
+        (TCP_FLAG_ACK, "ACK"),
+        (TCP_FLAG_URG, "URG"),
+    ];
+    names
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FlowTable {
+    flows: HashMap<FlowKey, FlowStats>,
+    first_seen: Option<Duration>,
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    pub fn get(&self, key: &FlowKey) -> Option<&FlowStats> {
+        self.flows.get(key)
+    }
+
+    /* decode the packet as Ethernet and account it with its captured length */
+    pub fn push_packet(&mut self, pkt: &pcapPacket) {
+        if let Some((stack, _)) = Ether!().decode(&pkt.data) {
+            self.push_with_len(&stack, pkt.timestamp(), pkt.data.len());
+        }
+    }
+
+    /* account a decoded stack, using its encoded length as the frame size */
+    pub fn push(&mut self, stack: &LayerStack, timestamp: Duration) {
+        let frame_len = stack.clone().encode().len();
+        self.push_with_len(stack, timestamp, frame_len);
+    }
+
+    /*
+     * The outermost Ethernet, IPv4 and TCP/UDP headers of the stack each
+     * count the packet towards their conversation.
+     */
+    pub fn push_with_len(&mut self, stack: &LayerStack, timestamp: Duration, frame_len: usize) {
+        let mut keys = vec![];
+        if let Some(eth) = stack.get_layer(Ether!()) {
+            keys.push((FlowKey::ethernet(eth.src.value(), eth.dst.value()), None));
+        }
+        if let Some(&ip_index) = stack.indices_of(IP!()).first() {
+            let ip = stack.item_at(IP!(), ip_index).unwrap();
+            keys.push((FlowKey::ipv4(ip.src.value(), ip.dst.value()), None));
+            let next = ip_index + 1;
+            if next < stack.layers.len() {
+                if let Some(tcp) = stack.item_at(TCP!(), next) {
+                    let key = FlowKey::l4(
+                        true,
+                        (ip.src.value(), tcp.sport.value()),
+                        (ip.dst.value(), tcp.dport.value()),
+                    );
+                    keys.push((key, Some(tcp.flags.value())));
+                } else if let Some(udp) = stack.item_at(UDP!(), next) {
+                    let key = FlowKey::l4(
+                        false,
+                        (ip.src.value(), udp.sport.value()),
+                        (ip.dst.value(), udp.dport.value()),
+                    );
+                    keys.push((key, None));
+                }
+            }
+        }
+        if keys.is_empty() {
+            return;
+        }
+
+        if self.first_seen.is_none_or(|t| timestamp < t) {
+            self.first_seen = Some(timestamp);
+        }
+        for ((key, a_to_b), tcp_flags) in keys {
+            let stats = self.flows.entry(key).or_insert_with(|| FlowStats {
+                first_seen: timestamp,
+                last_seen: timestamp,
+                ..Default::default()
+            });
+            stats.first_seen = stats.first_seen.min(timestamp);
+            stats.last_seen = stats.last_seen.max(timestamp);
+            let dir = if a_to_b {
+                &mut stats.a_to_b
+            } else {
+                &mut stats.b_to_a
+            };
+            dir.add(frame_len, tcp_flags);
+        }
+    }
+
+    /* the conversations of one type, in the order they started */
+    pub fn conversations(&self, typ: ConversationType) -> Vec<(&FlowKey, &FlowStats)> {
+        let mut out: Vec<(&FlowKey, &FlowStats)> = self
+            .flows
+            .iter()
+            .filter(|(k, _)| k.conversation_type() == typ)
+            .collect();
+        out.sort_by(|(ka, a), (kb, b)| (a.first_seen, ka).cmp(&(b.first_seen, kb)));
+        out
+    }
+
+    pub fn rows(&self, typ: ConversationType) -> Vec<ConversationRow> {
+        let table_start = self.first_seen.unwrap_or_default();
+        self.conversations(typ)
+            .into_iter()
+            .map(|(key, stats)| {
+                let (address_a, port_a, address_b, port_b) = key.endpoints();
+                let is_tcp = typ == ConversationType::Tcp;
+                ConversationRow {
+                    address_a,
+                    port_a,
+                    address_b,
+                    port_b,
+                    packets: stats.packets(),
+                    bytes: stats.bytes(),
+                    packets_a_to_b: stats.a_to_b.packets,
+                    bytes_a_to_b: stats.a_to_b.bytes,
+                    packets_b_to_a: stats.b_to_a.packets,
+                    bytes_b_to_a: stats.b_to_a.bytes,
+                    rel_start: stats.first_seen.saturating_sub(table_start).as_secs_f64(),
+                    duration: stats.duration().as_secs_f64(),
+                    tcp_flags_a_to_b: if is_tcp {
+                        Some(tcp_flags_string(stats.a_to_b.tcp_flags))
+                    } else {
+                        None
+                    },
+                    tcp_flags_b_to_a: if is_tcp {
+                        Some(tcp_flags_string(stats.b_to_a.tcp_flags))
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /* all conversation types, keyed by type name */
+    pub fn to_json(&self) -> String {
+        let mut out = serde_json::Map::new();
+        for typ in &[
+            ConversationType::Ethernet,
+            ConversationType::Ipv4,
+            ConversationType::Tcp,
+            ConversationType::Udp,
+        ] {
+            out.insert(
+                typ.name().to_string(),
+                serde_json::to_value(self.rows(*typ)).unwrap(),
+            );
+        }
+        serde_json::to_string_pretty(&out).unwrap()
+    }
+
+    /* one conversation type, with the Wireshark column headers */
+    pub fn to_csv(&self, typ: ConversationType) -> String {
+        let mut out = String::from(
+            "\"Address A\",\"Port A\",\"Address B\",\"Port B\",\"Packets\",\"Bytes\",\
+             \"Packets A \u{2192} B\",\"Bytes A \u{2192} B\",\"Packets B \u{2192} A\",\
+             \"Bytes B \u{2192} A\",\"Rel Start\",\"Duration\",\"TCP Flags A \u{2192} B\",\
+             \"TCP Flags B \u{2192} A\"\n",
+        );
+        let port = |p: Option<u16>| p.map(|p| p.to_string()).unwrap_or_default();
+        for row in self.rows(typ) {
+            out.push_str(&format!(
+                "\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{:.6}\",\"{:.6}\",\"{}\",\"{}\"\n",
+                row.address_a,
+                port(row.port_a),
+                row.address_b,
+                port(row.port_b),
+                row.packets,
+                row.bytes,
+                row.packets_a_to_b,
+                row.bytes_a_to_b,
+                row.packets_b_to_a,
+                row.bytes_b_to_a,
+                row.rel_start,
+                row.duration,
+                row.tcp_flags_a_to_b.as_deref().unwrap_or_default(),
+                row.tcp_flags_b_to_a.as_deref().unwrap_or_default()
+            ));
+        }
+        out
+    }
+}
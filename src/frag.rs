@@ -0,0 +1,321 @@
+/*
+ * IPv4 fragmentation and reassembly
+ */
+
+use crate::protocols::ip::*;
+use crate::protocols::raw::*;
+use crate::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FragmentOrder {
+    InOrder,
+    Reverse,
+    Random,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FragmentOptions {
+    /* maximum size of each fragment, IP header included */
+    pub mtu: usize,
+    /* payload bytes in the first fragment, for tiny-fragment tests */
+    pub first_fragment_len: Option<usize>,
+    /* payload bytes each fragment repeats from the previous one */
+    pub overlap: usize,
+    pub order: FragmentOrder,
+}
+
+impl Default for FragmentOptions {
+    fn default() -> Self {
+        FragmentOptions {
+            mtu: 1500,
+            first_fragment_len: None,
+            overlap: 0,
+            order: FragmentOrder::InOrder,
+        }
+    }
+}
+
+/* fragment offsets are in 8-byte units */
+fn round_down_8(n: usize) -> usize {
+    n & !7
+}
+
+/*
+ * Header length and payload bytes of the Ip layer at ip_index. Decoded fragments
+ * may carry bogus upper layers, so the payload is taken by the declared length.
+ */
+fn ip_header_and_payload(stack: &LayerStack, ip_index: usize) -> (usize, Vec<u8>) {
+    let ip = stack.item_at(IP!(), ip_index).unwrap();
+    let end = match ip.len {
+        Value::Set(_) => stack.layers.len(),
+        _ => stack.payload_range(ip_index).end,
+    };
+    let sub = LayerStack {
+        layers: stack.layers[ip_index..end].to_vec(),
+        filled: true,
+    };
+    let mut bytes = sub.encode();
+    let hdr_len = bytes
+        .first()
+        .map(|b| ((b & 0xf) as usize) * 4)
+        .unwrap_or(0)
+        .min(bytes.len());
+    /* a bogus length, e.g. 0 after GRO, still leaves the header */
+    if let Value::Set(len) = ip.len {
+        bytes.truncate((len as usize).max(hdr_len));
+    }
+    (hdr_len, bytes[hdr_len..].to_vec())
+}
+
+/* Split the first Ip layer of the stack into fragments of at most mtu bytes */
+pub fn fragment(stack: &LayerStack, mtu: usize) -> Vec<LayerStack> {
+    fragment_with(
+        stack,
+        &FragmentOptions {
+            mtu,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn fragment_with(stack: &LayerStack, opts: &FragmentOptions) -> Vec<LayerStack> {
+    fragment_with_rng(stack, opts, &mut rand::thread_rng())
+}
+
+/* fragment with FragmentOrder::Random shuffling with the given generator, e.g. a seeded one */
+pub fn fragment_with_rng<R: RngCore>(
+    stack: &LayerStack,
+    opts: &FragmentOptions,
+    rng: &mut R,
+) -> Vec<LayerStack> {
+    let filled = if stack.filled {
+        stack.clone()
+    } else {
+        stack.fill()
+    };
+    let ip_index = match filled.indices_of(IP!()).first() {
+        Some(&i) => i,
+        None => return vec![filled],
+    };
+    let ip = filled.item_at(IP!(), ip_index).unwrap().clone();
+    let (hdr_len, payload) = ip_header_and_payload(&filled, ip_index);
+
+    let max_chunk = round_down_8(opts.mtu.saturating_sub(hdr_len)).max(8);
+    let first_chunk = opts
+        .first_fragment_len
+        .map(|l| round_down_8(l).max(8))
+        .unwrap_or(max_chunk)
+        .min(max_chunk);
+    let overlap = round_down_8(opts.overlap).min(max_chunk - 8);
+
+    /* fragmenting a fragment keeps its offset and MF bit */
+    let base_offset = ip.frag_offset();
+    let orig_flags = ip.frag.value() & !IP_FRAG_OFFSET_MASK;
+    let last_mf = ip.more_fragments();
+
+    let mut out = vec![];
+    let mut start = 0;
+    let mut chunk = first_chunk;
+    loop {
+        let end = (start + chunk).min(payload.len());
+        let is_last = end == payload.len();
+        let mut flags = orig_flags & !IP_FLAG_MF;
+        if !is_last || last_mf {
+            flags |= IP_FLAG_MF;
+        }
+        let offset = ((base_offset + start) / 8) as u16;
+        let mut frag_ip = ip.clone().frag(flags | offset);
+        frag_ip.len = Value::Auto;
+        frag_ip.chksum = Value::Auto;
+        let mut layers = filled.layers[..ip_index].to_vec();
+        layers.push(frag_ip.embox());
+        layers.push(Raw!(payload[start..end].to_vec()).embox());
+        out.push(LayerStack {
+            layers,
+            filled: false,
+        });
+        if is_last {
+            break;
+        }
+        // always move forward, even when the overlap exceeds a tiny fragment
+        start = end.saturating_sub(overlap).max(start + 8);
+        chunk = max_chunk;
+    }
+
+    match opts.order {
+        FragmentOrder::InOrder => {}
+        FragmentOrder::Reverse => out.reverse(),
+        FragmentOrder::Random => {
+            use rand::seq::SliceRandom;
+            out.shuffle(rng);
+        }
+    }
+    out
+}
+
+/* Which copy of the data wins when fragments overlap */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    First,
+    Last,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct FragKey {
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    proto: u8,
+    id: u16,
+}
+
+#[derive(Clone, Debug)]
+struct FragBuffer {
+    first_seen: Duration,
+    /* the layers up to and including the Ip of the offset 0 fragment */
+    head: Option<Vec<Box<dyn Layer>>>,
+    chunks: Vec<(usize, Vec<u8>)>,
+    total_len: Option<usize>,
+}
+
+/*
+ * Collects IPv4 fragments and emits the reassembled datagrams,
+ * with the payload decoded past the Ip layer.
+ */
+#[derive(Clone, Debug)]
+pub struct Defragmenter {
+    pub timeout: Duration,
+    pub overlap_policy: OverlapPolicy,
+    pending: HashMap<FragKey, FragBuffer>,
+}
+
+impl Default for Defragmenter {
+    fn default() -> Self {
+        Defragmenter {
+            timeout: Duration::from_secs(30),
+            overlap_policy: OverlapPolicy::First,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl Defragmenter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn overlap_policy(mut self, policy: OverlapPolicy) -> Self {
+        self.overlap_policy = policy;
+        self
+    }
+
+    /* number of datagrams still waiting for fragments */
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /* drop the incomplete datagrams first seen more than timeout before now */
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, buf| now.saturating_sub(buf.first_seen) <= timeout);
+        before - self.pending.len()
+    }
+
+    /*
+     * Feed a decoded stack seen at time "now". Stacks that are not fragments
+     * come straight back; fragments are held until their datagram is complete.
+     */
+    pub fn push(&mut self, stack: LayerStack, now: Duration) -> Option<LayerStack> {
+        self.expire(now);
+        let ip_index = match stack.indices_of(IP!()).first() {
+            Some(&i) => i,
+            None => return Some(stack),
+        };
+        let ip = stack.item_at(IP!(), ip_index).unwrap().clone();
+        if !ip.is_fragment() {
+            return Some(stack);
+        }
+        let stack = if stack.filled { stack } else { stack.fill() };
+        let (_hdr_len, payload) = ip_header_and_payload(&stack, ip_index);
+
+        let key = FragKey {
+            src: ip.src.value(),
+            dst: ip.dst.value(),
+            proto: ip.proto.value(),
+            id: ip.id.value(),
+        };
+        let buf = self.pending.entry(key.clone()).or_insert(FragBuffer {
+            first_seen: now,
+            head: None,
+            chunks: vec![],
+            total_len: None,
+        });
+        let offset = ip.frag_offset();
+        if offset == 0 && buf.head.is_none() {
+            buf.head = Some(stack.layers[..ip_index + 1].to_vec());
+        }
+        if !ip.more_fragments() {
+            buf.total_len = Some(offset + payload.len());
+        }
+        buf.chunks.push((offset, payload));
+
+        let data = Self::assemble(buf, self.overlap_policy)?;
+        let buf = self.pending.remove(&key).unwrap();
+        Some(Self::redecode(buf.head.unwrap(), &data))
+    }
+
+    /* the reassembled payload, once every byte of it has arrived */
+    fn assemble(buf: &FragBuffer, policy: OverlapPolicy) -> Option<Vec<u8>> {
+        let total_len = buf.total_len?;
+        buf.head.as_ref()?;
+        let mut data = vec![0u8; total_len];
+        let mut have = vec![false; total_len];
+        for (offset, chunk) in &buf.chunks {
+            for (i, b) in chunk.iter().enumerate() {
+                let pos = offset + i;
+                if pos >= total_len {
+                    break;
+                }
+                if !have[pos] || policy == OverlapPolicy::Last {
+                    data[pos] = *b;
+                    have[pos] = true;
+                }
+            }
+        }
+        if have.iter().all(|h| *h) {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    fn redecode(mut head: Vec<Box<dyn Layer>>, data: &[u8]) -> LayerStack {
+        let ip_layer = head.pop().unwrap();
+        let ip = ip_layer.downcast_ref::<Ip>().unwrap();
+        let proto = ip.proto.value();
+        let mut ip = ip.clone().frag(ip.frag.value() & IP_FLAG_DF);
+        ip.len = Value::Auto;
+        ip.chksum = Value::Auto;
+        head.push(ip.embox());
+
+        let decoded = IANA_LAYERS_BY_Proto
+            .get(&proto)
+            .and_then(|next| (next.MakeLayer)().decode(data));
+        match decoded {
+            Some((mut payload, _)) => head.append(&mut payload.layers),
+            None => head.append(&mut Raw!().decode_as_raw(data).layers),
+        }
+        LayerStack {
+            layers: head,
+            filled: false,
+        }
+    }
+}
@@ -0,0 +1,377 @@
+/*
+ * Field-aware mutation of a seed packet, for protocol robustness testing
+ */
+
+use crate::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /* leave the checksums as they are in the seed packet */
+    Keep,
+    /* recompute the checksums after the mutation */
+    Fix,
+    /* recompute the checksums, then corrupt one of them */
+    Break,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    BitFlip,
+    Boundary,
+    LengthLie,
+    Truncate,
+}
+
+pub const ALL_MUTATION_KINDS: &[MutationKind] = &[
+    MutationKind::BitFlip,
+    MutationKind::Boundary,
+    MutationKind::LengthLie,
+    MutationKind::Truncate,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    /* the bit counts from the least significant one of the field value */
+    BitFlip {
+        layer: usize,
+        field: &'static str,
+        bit: usize,
+    },
+    Boundary {
+        layer: usize,
+        field: &'static str,
+        value: u64,
+    },
+    LengthLie {
+        layer: usize,
+        field: &'static str,
+        value: u64,
+    },
+    Truncate {
+        len: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mutant {
+    pub mutation: Mutation,
+    pub bytes: Vec<u8>,
+}
+
+/* a field of the seed packet, with its width in bits */
+#[derive(Clone, Debug)]
+struct FieldSlot {
+    layer: usize,
+    field: &'static str,
+    offset: usize,
+    length: usize,
+    width: usize,
+    value: FieldValue,
+}
+
+pub fn is_checksum_field(name: &str) -> bool {
+    name.contains("chksum") || name.contains("checksum")
+}
+
+/* lengths and header lengths, by the field names the protocols use */
+pub fn is_length_field(name: &str) -> bool {
+    name.ends_with("len") || name.contains("length") || name == "ihl" || name == "dataofs"
+}
+
+/* 0, 1, the values around the middle and the top of a field of this many bits */
+pub fn boundary_values(width: usize) -> Vec<u64> {
+    let width = width.clamp(1, 64);
+    let max = u64::MAX >> (64 - width);
+    let half = 1u64 << (width - 1);
+    let mut out = vec![0, 1, half - 1, half, max - 1, max];
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+/*
+ * Makes variants of a seed packet: bit flips within a field, boundary values
+ * for the width of a field, wrong values in the length fields, and truncation.
+ * The seed is usually a decoded packet, so its fields hold the values seen on the wire.
+ */
+pub struct Mutator {
+    seed: LayerStack,
+    seed_len: usize,
+    fields: Vec<FieldSlot>,
+    rng: StdRng,
+    checksums: ChecksumMode,
+    kinds: Vec<MutationKind>,
+}
+
+impl Mutator {
+    pub fn new(seed: &LayerStack) -> Self {
+        let (bytes, map) = seed.clone().encode_with_map();
+        let fields = map
+            .spans
+            .iter()
+            .filter(|s| s.layer_index < seed.layers.len())
+            .filter_map(|s| {
+                let value = seed.layers[s.layer_index].get_field(s.field)?;
+                Some(FieldSlot {
+                    layer: s.layer_index,
+                    field: s.field,
+                    offset: s.offset,
+                    length: s.length,
+                    width: s.bits.map(|(_, w)| w).unwrap_or(s.length * 8).min(64),
+                    value,
+                })
+            })
+            .collect();
+        Mutator {
+            seed: seed.clone(),
+            seed_len: bytes.len(),
+            fields,
+            rng: StdRng::from_entropy(),
+            checksums: ChecksumMode::Fix,
+            kinds: ALL_MUTATION_KINDS.to_vec(),
+        }
+    }
+
+    /* the same seed gives the same sequence of mutants */
+    pub fn seeded(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn checksums(mut self, mode: ChecksumMode) -> Self {
+        self.checksums = mode;
+        self
+    }
+
+    pub fn kinds(mut self, kinds: &[MutationKind]) -> Self {
+        self.kinds = kinds.to_vec();
+        self
+    }
+
+    /* one random mutant, of one of the enabled kinds */
+    pub fn mutate(&mut self) -> Mutant {
+        for _ in 0..16 {
+            if self.kinds.is_empty() {
+                break;
+            }
+            let kind = self.kinds[self.rng.gen_range(0..self.kinds.len())];
+            let m = match kind {
+                MutationKind::BitFlip => self.bit_flip(),
+                MutationKind::Boundary => self.boundary(),
+                MutationKind::LengthLie => self.length_lie(),
+                MutationKind::Truncate => self.truncate(),
+            };
+            if let Some(m) = m {
+                return m;
+            }
+        }
+        let bytes = self.encode(self.seed.clone());
+        Mutant {
+            mutation: Mutation::Truncate { len: bytes.len() },
+            bytes,
+        }
+    }
+
+    /* every boundary value of every numeric field, in the order of the fields */
+    pub fn boundary_mutants(&mut self) -> Vec<Mutant> {
+        let slots: Vec<FieldSlot> = self.mutable_fields(numeric_value).cloned().collect();
+        let mut out = vec![];
+        for slot in slots {
+            for value in boundary_values(slot.width) {
+                let mutation = Mutation::Boundary {
+                    layer: slot.layer,
+                    field: slot.field,
+                    value,
+                };
+                out.extend(self.apply(&slot, FieldValue::UInt(value), mutation));
+            }
+        }
+        out
+    }
+
+    fn mutable_fields(
+        &self,
+        accept: fn(&FieldValue) -> Option<u64>,
+    ) -> impl Iterator<Item = &FieldSlot> {
+        self.fields
+            .iter()
+            .filter(move |f| !is_checksum_field(f.field) && accept(&f.value).is_some())
+    }
+
+    fn pick(&mut self, slots: Vec<FieldSlot>) -> Option<FieldSlot> {
+        if slots.is_empty() {
+            return None;
+        }
+        let i = self.rng.gen_range(0..slots.len());
+        Some(slots[i].clone())
+    }
+
+    fn bit_flip(&mut self) -> Option<Mutant> {
+        let slots = self.mutable_fields(any_value).cloned().collect();
+        let slot = self.pick(slots)?;
+        let value = match &slot.value {
+            FieldValue::Mac(m) => FieldValue::Bytes(m.0.bytes().to_vec()),
+            x => x.clone(),
+        };
+        let (value, bit) = match value {
+            FieldValue::Bytes(mut b) if !b.is_empty() => {
+                let bit = self.rng.gen_range(0..b.len() * 8);
+                let last = b.len() - 1;
+                b[last - bit / 8] ^= 1 << (bit % 8);
+                (FieldValue::Bytes(b), bit)
+            }
+            x => {
+                let v = numeric_value(&x)?;
+                let bit = self.rng.gen_range(0..slot.width);
+                (FieldValue::UInt(v ^ (1 << bit)), bit)
+            }
+        };
+        let mutation = Mutation::BitFlip {
+            layer: slot.layer,
+            field: slot.field,
+            bit,
+        };
+        self.apply(&slot, value, mutation)
+    }
+
+    fn boundary(&mut self) -> Option<Mutant> {
+        let slots = self.mutable_fields(numeric_value).cloned().collect();
+        let slot = self.pick(slots)?;
+        /* a value the field already has would not be a mutation */
+        let current = numeric_value(&slot.value);
+        let values: Vec<u64> = boundary_values(slot.width)
+            .into_iter()
+            .filter(|v| Some(*v) != current)
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        let value = values[self.rng.gen_range(0..values.len())];
+        let mutation = Mutation::Boundary {
+            layer: slot.layer,
+            field: slot.field,
+            value,
+        };
+        self.apply(&slot, FieldValue::UInt(value), mutation)
+    }
+
+    fn length_lie(&mut self) -> Option<Mutant> {
+        let slots = self
+            .mutable_fields(numeric_value)
+            .filter(|f| is_length_field(f.field))
+            .cloned()
+            .collect();
+        let slot = self.pick(slots)?;
+        let actual = numeric_value(&slot.value)?;
+        let max = u64::MAX >> (64 - slot.width.clamp(1, 64));
+        let lies = [
+            actual.saturating_add(1),
+            actual.saturating_sub(1),
+            actual.saturating_add(self.rng.gen_range(2..=64)),
+            0,
+            max,
+        ];
+        let lies: Vec<u64> = lies
+            .iter()
+            .map(|x| x.min(&max))
+            .copied()
+            .filter(|x| *x != actual)
+            .collect();
+        if lies.is_empty() {
+            return None;
+        }
+        let value = lies[self.rng.gen_range(0..lies.len())];
+        let mutation = Mutation::LengthLie {
+            layer: slot.layer,
+            field: slot.field,
+            value,
+        };
+        self.apply(&slot, FieldValue::UInt(value), mutation)
+    }
+
+    /* cut the packet, mostly at a field boundary */
+    fn truncate(&mut self) -> Option<Mutant> {
+        if self.seed_len == 0 {
+            return None;
+        }
+        let len = if !self.fields.is_empty() && self.rng.gen_bool(0.75) {
+            let f = &self.fields[self.rng.gen_range(0..self.fields.len())];
+            f.offset + f.length * self.rng.gen_range(0..=1)
+        } else {
+            self.rng.gen_range(0..self.seed_len)
+        };
+        let len = len.min(self.seed_len - 1);
+        let mut bytes = self.encode(self.seed.clone());
+        bytes.truncate(len);
+        Some(Mutant {
+            mutation: Mutation::Truncate { len },
+            bytes,
+        })
+    }
+
+    fn apply(&mut self, slot: &FieldSlot, value: FieldValue, mutation: Mutation) -> Option<Mutant> {
+        let mut stack = self.seed.clone();
+        stack.layers[slot.layer]
+            .set_field_value(slot.field, value)
+            .ok()?;
+        Some(Mutant {
+            mutation,
+            bytes: self.encode(stack),
+        })
+    }
+
+    fn encode(&mut self, mut stack: LayerStack) -> Vec<u8> {
+        if self.checksums != ChecksumMode::Keep {
+            for l in stack.layers.iter_mut() {
+                for d in l.field_descs() {
+                    if is_checksum_field(d.name) {
+                        let _ = l.set_field_value(d.name, FieldValue::Auto);
+                    }
+                }
+            }
+            stack.filled = false;
+        }
+        let (mut bytes, map) = stack.encode_with_map();
+        if self.checksums == ChecksumMode::Break {
+            let spans: Vec<&FieldSpan> = map
+                .spans
+                .iter()
+                .filter(|s| is_checksum_field(s.field))
+                .filter(|s| s.length > 0 && s.offset + s.length <= bytes.len())
+                .collect();
+            if !spans.is_empty() {
+                let s = spans[self.rng.gen_range(0..spans.len())];
+                let bit = self.rng.gen_range(0..s.length * 8);
+                bytes[s.offset + bit / 8] ^= 0x80 >> (bit % 8);
+            }
+        }
+        bytes
+    }
+}
+
+impl Iterator for Mutator {
+    type Item = Mutant;
+
+    fn next(&mut self) -> Option<Mutant> {
+        Some(self.mutate())
+    }
+}
+
+/* numbers, and IPv4 addresses as numbers */
+fn numeric_value(v: &FieldValue) -> Option<u64> {
+    match v {
+        FieldValue::UInt(x) => Some(*x),
+        FieldValue::Ipv4(x) => Some(u32::from(x.0) as u64),
+        _ => None,
+    }
+}
+
+fn any_value(v: &FieldValue) -> Option<u64> {
+    match v {
+        FieldValue::Mac(_) => Some(0),
+        FieldValue::Bytes(b) if !b.is_empty() => Some(0),
+        x => numeric_value(x),
+    }
+}
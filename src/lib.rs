@@ -13,6 +13,7 @@ fn readme_test() {
     }
 }
 
+use std::any::Any;
 pub use std::any::TypeId;
 use std::collections::HashMap;
 use std::convert::TryFrom;
@@ -23,6 +24,7 @@ use std::ops::Deref;
 pub use std::ops::Div;
 use std::ops::Index;
 use std::str::FromStr;
+use std::sync::Arc;
 #[macro_use]
 extern crate mopa;
 extern crate itertools;
@@ -35,8 +37,9 @@ pub struct ParseNumberError;
 use crate::Value::Random;
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
+use rand::RngCore;
 
-use linkme::distributed_slice;
+pub use linkme::distributed_slice;
 
 use crate::encdec::binary_big_endian::BinaryBigEndian;
 #[derive(NetworkProtocol, Debug, Clone, Serialize, Deserialize)]
@@ -46,9 +49,512 @@ use crate::encdec::binary_big_endian::BinaryBigEndian;
 #[nproto(registry(UDP_SRC_PORT_APPS, SrcPort: u16))]
 #[nproto(registry(UDP_DST_PORT_APPS, DstPort: u16))]
 #[nproto(registry(BOOTP_VENDORS, VendorCookie: u32))]
+#[nproto(registry(LAYER_NAMES, LayerName: &'static str))]
 /* Only here as a target of derive + attribute macros to make registries */
 struct protocolRegistriesSentinel;
 
+/* make a layer by its macro name, e.g. "IP" or "Ether", ignoring the case */
+pub fn make_layer_by_name(name: &str) -> Option<Box<dyn Layer>> {
+    if let Some(item) = LAYER_NAMES_BY_LayerName.get(name) {
+        return Some((item.MakeLayer)());
+    }
+    LAYER_NAMES
+        .iter()
+        .find(|item| item.LayerName.eq_ignore_ascii_case(name))
+        .map(|item| (item.MakeLayer)())
+}
+
+pub fn layer_names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = LAYER_NAMES.iter().map(|item| item.LayerName).collect();
+    names.sort();
+    names
+}
+
+/*
+ * A guess of the layer from the payload, for ports without a registered layer.
+ * The probe gives its confidence from 0 (not this protocol) to 100 (certainly this protocol).
+ * The entries are made by #[nproto(heuristic(UDP_HEURISTICS = probe_fn))].
+ */
+#[derive(Clone, Debug)]
+pub struct HeuristicItem {
+    pub name: &'static str,
+    pub probe: fn(&[u8]) -> u8,
+    pub make_layer: fn() -> Box<dyn Layer>,
+}
+
+#[distributed_slice]
+pub static UDP_HEURISTICS: [HeuristicItem];
+
+#[distributed_slice]
+pub static TCP_HEURISTICS: [HeuristicItem];
+
+/* the layers whose probe gives a confidence above 0, most confident first */
+pub fn probe_heuristics(table: &[HeuristicItem], buf: &[u8]) -> Vec<(&'static str, u8)> {
+    let mut out: Vec<(&'static str, u8)> = table
+        .iter()
+        .map(|item| (item.name, (item.probe)(buf).min(100)))
+        .filter(|(_, confidence)| *confidence > 0)
+        .collect();
+    /* the link order of the table is not fixed, the names are */
+    out.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    out
+}
+
+/*
+ * State which outlives the decode of one packet, for the protocols which need to know
+ * what came before: templates, negotiated ports, payload types and the like.
+ * A protocol keeps its state under a type of its own with state() and state_mut(),
+ * from the #[nproto(decode_hook = ...)] function, and it may change the options,
+ * e.g. bind the port of a data connection it saw negotiated, for the packets decoded after it.
+ */
+#[derive(Default)]
+pub struct DecodeContext {
+    pub options: DecodeOptions,
+    state: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl fmt::Debug for DecodeContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodeContext")
+            .field("options", &self.options)
+            .field("states", &self.state.len())
+            .finish()
+    }
+}
+
+impl DecodeContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_options(options: DecodeOptions) -> Self {
+        DecodeContext {
+            options,
+            ..Default::default()
+        }
+    }
+
+    pub fn state<T: Any + Send>(&self) -> Option<&T> {
+        self.state
+            .get(&TypeId::of::<T>())
+            .and_then(|s| s.downcast_ref::<T>())
+    }
+
+    /* the state of the type, made with its default on the first use */
+    pub fn state_mut<T: Any + Send + Default>(&mut self) -> &mut T {
+        self.state
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut::<T>()
+            .unwrap()
+    }
+
+    pub fn set_state<T: Any + Send>(&mut self, state: T) {
+        self.state.insert(TypeId::of::<T>(), Box::new(state));
+    }
+
+    pub fn take_state<T: Any + Send>(&mut self) -> Option<T> {
+        self.state
+            .remove(&TypeId::of::<T>())
+            .and_then(|s| s.downcast::<T>().ok())
+            .map(|s| *s)
+    }
+
+    /* forget the state of all the protocols, keeping the options */
+    pub fn clear(&mut self) {
+        self.state.clear();
+    }
+}
+
+/*
+ * Walking over a layer in a buffer without decoding it into its struct.
+ * Each layer has one, as Ip::VIEW, and the registries have it for the next layers.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct ViewKind {
+    pub name: &'static str,
+    pub type_id: fn() -> TypeId,
+    pub step: fn(&[u8]) -> Option<ViewStep>,
+    pub make_layer: fn() -> Box<dyn Layer>,
+}
+
+impl PartialEq for ViewKind {
+    fn eq(&self, other: &Self) -> bool {
+        (self.type_id)() == (other.type_id)()
+    }
+}
+
+impl Eq for ViewKind {}
+
+/*
+ * What the step of a view found: the length of the header, the length of the header
+ * and the payload it declares (the rest of the buffer is padding), and the layer
+ * the registries give for the payload.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewStep {
+    pub header_len: usize,
+    pub len: usize,
+    pub next: Option<ViewKind>,
+}
+
+/* a layer of a StackView: its bytes, header and payload, from offset in the packet */
+#[derive(Clone, Copy, Debug)]
+pub struct LayerView<'a> {
+    pub kind: ViewKind,
+    pub offset: usize,
+    pub bytes: &'a [u8],
+    pub header_len: usize,
+}
+
+impl<'a> LayerView<'a> {
+    pub fn name(&self) -> &'static str {
+        self.kind.name
+    }
+    pub fn header(&self) -> &'a [u8] {
+        &self.bytes[..self.header_len]
+    }
+    pub fn payload(&self) -> &'a [u8] {
+        &self.bytes[self.header_len..]
+    }
+}
+
+/* the typed views made by the derive, IpView<'a>, UdpView<'a> and so on */
+pub trait HeaderView<'a>: Sized {
+    fn kind() -> ViewKind;
+    fn from_layer_view(view: &LayerView<'a>) -> Self;
+}
+
+pub const STACK_VIEW_DEPTH: usize = 16;
+
+/*
+ * The layers of a packet found through the registries like decode() does, as views
+ * of the buffer. A field at an offset known at derive time is read straight from the
+ * buffer when asked for, e.g. view.get::<IpView>()?.ttl(), the others are decoded from
+ * the start of the header. The walk allocates only where a layer's decoders do, apart
+ * from filling the registries on first use. The DecodeOptions, heuristics and decode
+ * hooks are not used, and the walk stops at STACK_VIEW_DEPTH layers.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct StackView<'a> {
+    buf: &'a [u8],
+    first: ViewKind,
+    layers: [Option<LayerView<'a>>; STACK_VIEW_DEPTH],
+    count: usize,
+}
+
+impl<'a> StackView<'a> {
+    pub fn new(first: ViewKind, buf: &'a [u8]) -> StackView<'a> {
+        let mut out = StackView {
+            buf,
+            first,
+            layers: [None; STACK_VIEW_DEPTH],
+            count: 0,
+        };
+        let mut kind = first;
+        let mut offset = 0;
+        let mut end = buf.len();
+        while out.count < STACK_VIEW_DEPTH {
+            let step = match (kind.step)(&buf[offset..end]) {
+                Some(step) => step,
+                None => break,
+            };
+            out.layers[out.count] = Some(LayerView {
+                kind,
+                offset,
+                bytes: &buf[offset..offset + step.len],
+                header_len: step.header_len,
+            });
+            out.count += 1;
+            match step.next {
+                Some(next) if step.header_len < step.len => {
+                    kind = next;
+                    end = offset + step.len;
+                    offset += step.header_len;
+                }
+                _ => break,
+            }
+        }
+        out
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn layer(&self, idx: usize) -> Option<&LayerView<'a>> {
+        self.layers[..self.count].get(idx)?.as_ref()
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &LayerView<'a>> {
+        self.layers[..self.count].iter().flatten()
+    }
+
+    /* the first layer of the type, e.g. get::<UdpView>() */
+    pub fn get<V: HeaderView<'a>>(&self) -> Option<V> {
+        let kind = V::kind();
+        self.layers()
+            .find(|l| l.kind == kind)
+            .map(|l| V::from_layer_view(l))
+    }
+
+    /* the payload of the last layer, for which no layer was found */
+    pub fn rest(&self) -> &'a [u8] {
+        match self.count {
+            0 => self.buf,
+            n => self.layers[n - 1].unwrap().payload(),
+        }
+    }
+
+    /* decode the packet into the owned layers, when they are needed after all */
+    pub fn to_stack(&self) -> LayerStack {
+        let first = (self.first.make_layer)();
+        match first.decode(self.buf) {
+            Some((stack, _)) => stack,
+            None => first.decode_as_raw(self.buf),
+        }
+    }
+}
+
+/* the registries which select the next layer, and which can be overridden for a decode */
+pub const REGISTRIES: &[&str] = &[
+    "ETHERTYPE_LAYERS",
+    "IANA_LAYERS",
+    "ICMP_TYPES",
+    "UDP_SRC_PORT_APPS",
+    "UDP_DST_PORT_APPS",
+    "BOOTP_VENDORS",
+];
+
+fn static_bindings(registry: &str) -> Vec<(u64, &'static str)> {
+    match registry {
+        "ETHERTYPE_LAYERS" => ETHERTYPE_LAYERS
+            .iter()
+            .map(|x| (x.Ethertype as u64, x.Name))
+            .collect(),
+        "IANA_LAYERS" => IANA_LAYERS
+            .iter()
+            .map(|x| (x.Proto as u64, x.Name))
+            .collect(),
+        "ICMP_TYPES" => ICMP_TYPES.iter().map(|x| (x.Type as u64, x.Name)).collect(),
+        "UDP_SRC_PORT_APPS" => UDP_SRC_PORT_APPS
+            .iter()
+            .map(|x| (x.SrcPort as u64, x.Name))
+            .collect(),
+        "UDP_DST_PORT_APPS" => UDP_DST_PORT_APPS
+            .iter()
+            .map(|x| (x.DstPort as u64, x.Name))
+            .collect(),
+        "BOOTP_VENDORS" => BOOTP_VENDORS
+            .iter()
+            .map(|x| (x.VendorCookie as u64, x.Name))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeOptionsError {
+    UnknownRegistry(String),
+    UnknownLayer(String),
+}
+
+impl fmt::Display for DecodeOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownRegistry(r) => {
+                write!(f, "no registry {:?} (known: {})", r, REGISTRIES.join(", "))
+            }
+            Self::UnknownLayer(l) => write!(f, "no layer {:?}", l),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingSource {
+    /* made by #[nproto(register(...))] */
+    Static,
+    /* set in the DecodeOptions */
+    Session,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistryBinding {
+    pub registry: &'static str,
+    pub key: u64,
+    pub layer: &'static str,
+    pub source: BindingSource,
+}
+
+impl fmt::Display for RegistryBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self.source {
+            BindingSource::Static => "static",
+            BindingSource::Session => "session",
+        };
+        write!(
+            f,
+            "{} {} => {} ({})",
+            self.registry, self.key, self.layer, source
+        )
+    }
+}
+
+/*
+ * "Decode as" for one decode: bindings added to the registries, replacing the
+ * registered ones, or removing them so that the payload stays Raw.
+ * A binding in the options always wins over the registered one for the same key,
+ * and the last bind() or unbind() of a key is the one in effect.
+ * As without options, a layer looks at its registries in the order of its fields,
+ * so e.g. a binding of the UDP source port is found before one of the destination port.
+ * Only when none of them gives a layer, and the heuristics are enabled, the probes
+ * of the layer's heuristics table (e.g. UDP_HEURISTICS) get a look at the payload.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /* None is a removed binding */
+    overrides: HashMap<(&'static str, u64), Option<&'static LAYER_NAMES_Item>>,
+    /* the lowest confidence accepted from a probe, None while the heuristics are off */
+    min_confidence: Option<u8>,
+    skipped_heuristics: Vec<&'static str>,
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn registry(name: &str) -> Result<&'static str, DecodeOptionsError> {
+        REGISTRIES
+            .iter()
+            .find(|r| r.eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| DecodeOptionsError::UnknownRegistry(name.to_string()))
+    }
+
+    fn layer_item(layer: &str) -> Result<&'static LAYER_NAMES_Item, DecodeOptionsError> {
+        LAYER_NAMES_BY_LayerName
+            .get(layer)
+            .or_else(|| {
+                LAYER_NAMES
+                    .iter()
+                    .find(|item| item.LayerName.eq_ignore_ascii_case(layer))
+            })
+            .ok_or_else(|| DecodeOptionsError::UnknownLayer(layer.to_string()))
+    }
+
+    /* decode the payload as the layer, e.g. bind("UDP_DST_PORT_APPS", 8472, "VXLAN") */
+    pub fn bind(
+        &mut self,
+        registry: &str,
+        key: u64,
+        layer: &str,
+    ) -> Result<(), DecodeOptionsError> {
+        let registry = Self::registry(registry)?;
+        let item = Self::layer_item(layer)?;
+        self.overrides.insert((registry, key), Some(item));
+        Ok(())
+    }
+
+    /* leave the payload as Raw, even if a layer is registered for the key */
+    pub fn unbind(&mut self, registry: &str, key: u64) -> Result<(), DecodeOptionsError> {
+        let registry = Self::registry(registry)?;
+        self.overrides.insert((registry, key), None);
+        Ok(())
+    }
+
+    /* forget the bind() or unbind() of the key, going back to the registered layer */
+    pub fn reset(&mut self, registry: &str, key: u64) -> Result<(), DecodeOptionsError> {
+        let registry = Self::registry(registry)?;
+        self.overrides.remove(&(registry, key));
+        Ok(())
+    }
+
+    /* used by the generated decode code, with what the static registry has for the key */
+    pub fn next_layer(
+        &self,
+        registry: &str,
+        key: u64,
+        registered: Option<fn() -> Box<dyn Layer>>,
+    ) -> Option<fn() -> Box<dyn Layer>> {
+        if self.overrides.is_empty() {
+            return registered;
+        }
+        match REGISTRIES.iter().find(|r| **r == registry) {
+            Some(r) => match self.overrides.get(&(*r, key)) {
+                Some(item) => item.map(|item| item.MakeLayer),
+                None => registered,
+            },
+            None => registered,
+        }
+    }
+
+    /* try the probes of the payloads without a registered layer; a probe of 0 never wins */
+    pub fn enable_heuristics(&mut self, min_confidence: u8) {
+        self.min_confidence = Some(min_confidence.max(1));
+    }
+
+    pub fn disable_heuristics(&mut self) {
+        self.min_confidence = None;
+    }
+
+    /* never pick the layer by its probe */
+    pub fn skip_heuristic(&mut self, layer: &str) -> Result<(), DecodeOptionsError> {
+        let item = Self::layer_item(layer)?;
+        self.skipped_heuristics.push(item.LayerName);
+        Ok(())
+    }
+
+    /* used by the generated decode code: the most confident of the probes, if any is enough */
+    pub fn heuristic_layer(
+        &self,
+        table: &[HeuristicItem],
+        buf: &[u8],
+    ) -> Option<fn() -> Box<dyn Layer>> {
+        let min_confidence = self.min_confidence?;
+        let name = probe_heuristics(table, buf)
+            .into_iter()
+            .find(|(name, _)| !self.skipped_heuristics.contains(name))
+            .filter(|(_, confidence)| *confidence >= min_confidence)?
+            .0;
+        table
+            .iter()
+            .find(|item| item.name == name)
+            .map(|item| item.make_layer)
+    }
+
+    /* the bindings in effect for the registry, by key */
+    pub fn bindings(&self, registry: &str) -> Result<Vec<RegistryBinding>, DecodeOptionsError> {
+        let registry = Self::registry(registry)?;
+        let mut out: Vec<RegistryBinding> = static_bindings(registry)
+            .into_iter()
+            .filter(|(key, _)| !self.overrides.contains_key(&(registry, *key)))
+            .map(|(key, layer)| RegistryBinding {
+                registry,
+                key,
+                layer,
+                source: BindingSource::Static,
+            })
+            .collect();
+        for ((r, key), item) in self.overrides.iter() {
+            if let (true, Some(item)) = (*r == registry, item) {
+                out.push(RegistryBinding {
+                    registry,
+                    key: *key,
+                    layer: item.LayerName,
+                    source: BindingSource::Session,
+                });
+            }
+        }
+        out.sort_by_key(|b| b.key);
+        Ok(out)
+    }
+}
+
 pub trait Encoder {
     fn encode_u8(v1: u8) -> Vec<u8>;
     fn encode_u16(v1: u16) -> Vec<u8>;
@@ -109,11 +615,7 @@ impl Decode for Ipv4Address {
 
 impl Decode for MacAddr {
     fn decode<D: Decoder>(buf: &[u8]) -> Option<(Self, usize)> {
-        if let Some((mac_vec, count)) = D::decode_vec(buf, 6) {
-            Some((MacAddr::from(&mac_vec[..]), count))
-        } else {
-            None
-        }
+        Some((MacAddr::from(buf.get(..6)?), 6))
     }
 }
 
@@ -169,14 +671,156 @@ impl Encode for Vec<u8> {
     }
 }
 
-#[derive(PartialEq, Clone, Eq)]
+/*
+ * What a Value::Dyn closure gets when the stack is filled: the stack being filled,
+ * the index of the layer the field is in, and the number of the packet made
+ * so far by the same generator (0 for a plain fill()).
+ */
+pub struct FieldContext<'a> {
+    pub stack: &'a LayerStack,
+    pub layer_index: usize,
+    pub counter: u64,
+}
+
+impl<'a> FieldContext<'a> {
+    /* a field of the stack by its path, like LayerStack::get() */
+    pub fn get(&self, path: &str) -> Option<FieldValue> {
+        self.stack.get(path).ok()
+    }
+}
+
+pub type DynValueFn<T> = Arc<dyn Fn(&FieldContext) -> T + Send + Sync>;
+
+/*
+ * State carried through a fill: the packet counter for the Dyn values,
+ * and the random number generator the Random values draw from.
+ */
+pub struct FillContext<'a> {
+    pub counter: u64,
+    pub rng: &'a mut dyn RngCore,
+}
+
+impl<'a> FillContext<'a> {
+    pub fn gen<T>(&mut self) -> T
+    where
+        Standard: Distribution<T>,
+    {
+        self.rng.gen()
+    }
+}
+
+/* random values limited to a range, a set of values, or the bits of a mask */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomConstraint<T> {
+    Range(T, T),
+    Choice(Vec<T>),
+    /* the bits set in the mask are random, the others are from the value */
+    Mask { value: T, mask: T },
+}
+
+impl<T: DynField> RandomConstraint<T> {
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<T> {
+        self.sample_with(rng, FieldConv::of())
+    }
+}
+
+impl<T> RandomConstraint<T> {
+    /* the value standing for the others outside of fill(), which draws them */
+    pub fn first(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        match self {
+            Self::Range(lo, _) => Some(lo.clone()),
+            Self::Choice(v) => v.first().cloned(),
+            Self::Mask { value, .. } => Some(value.clone()),
+        }
+    }
+
+    /* None if the values can not be stepped through, see FieldConv */
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R, conv: FieldConv<T>) -> Option<T> {
+        match self {
+            Self::Range(lo, hi) => {
+                let lo = (conv.to)(lo);
+                let (start, _) = field_value_index(&lo)?;
+                let (end, _) = field_value_index(&(conv.to)(hi))?;
+                if end < start {
+                    return None;
+                }
+                (conv.from)(&field_value_at(&lo, rng.gen_range(start..=end))?)
+            }
+            Self::Choice(v) if !v.is_empty() => {
+                (conv.from)(&(conv.to)(&v[rng.gen_range(0..v.len())]))
+            }
+            Self::Choice(_) => None,
+            Self::Mask { value, mask } => {
+                let value = (conv.to)(value);
+                let (v, _) = field_value_index(&value)?;
+                let (m, _) = field_value_index(&(conv.to)(mask))?;
+                let r: u64 = rng.gen();
+                (conv.from)(&field_value_at(&value, (v & !m) | (r & m))?)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Value<T> {
     Auto,
     Random,
     Func(fn() -> T),
     Set(T),
+    /* computed by fill() from the stack, see FieldContext */
+    Dyn(DynValueFn<T>),
+    /* like Random, within the constraint */
+    RandomWith(RandomConstraint<T>),
+    /* several values, LayerStack::expand() makes a packet with each */
+    List(Vec<T>),
+    /* all the values from the first to the second one, inclusive */
+    Range(T, T),
+}
+
+impl<T> Value<T> {
+    pub fn from_fn<F>(f: F) -> Self
+    where
+        F: Fn(&FieldContext) -> T + Send + Sync + 'static,
+    {
+        Value::Dyn(Arc::new(f))
+    }
+
+    pub fn random_range(lo: T, hi: T) -> Self {
+        Value::RandomWith(RandomConstraint::Range(lo, hi))
+    }
+
+    pub fn random_choice(values: Vec<T>) -> Self {
+        Value::RandomWith(RandomConstraint::Choice(values))
+    }
+
+    pub fn random_mask(value: T, mask: T) -> Self {
+        Value::RandomWith(RandomConstraint::Mask { value, mask })
+    }
+}
+
+/* closures are equal only to themselves */
+impl<T: PartialEq> PartialEq for Value<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Auto, Self::Auto) => true,
+            (Self::Random, Self::Random) => true,
+            (Self::Func(a), Self::Func(b)) => *a as usize == *b as usize,
+            (Self::Set(a), Self::Set(b)) => a == b,
+            (Self::Dyn(a), Self::Dyn(b)) => Arc::ptr_eq(a, b),
+            (Self::RandomWith(a), Self::RandomWith(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Range(a1, a2), Self::Range(b1, b2)) => a1 == b1 && a2 == b2,
+            _ => false,
+        }
+    }
 }
 
+impl<T: Eq> Eq for Value<T> {}
+
 impl<T: Serialize> Serialize for Value<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -186,8 +830,30 @@ impl<T: Serialize> Serialize for Value<T> {
         match data {
             Value::Auto => serializer.serialize_str("<auto>"),
             Value::Random => serializer.serialize_str("<random>"),
-            Value::Func(f) => panic!("Serializing functions is not supported"),
+            // the function itself can not be stored, only what it gives now
+            Value::Func(f) => f().serialize(serializer),
             Value::Set(v) => v.serialize(serializer),
+            Value::Dyn(_) => Err(serde::ser::Error::custom(
+                "a Dyn value depends on the stack and can not be serialized, fill() the stack first",
+            )),
+            Value::RandomWith(c) => {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("random", c)?;
+                map.end()
+            }
+            Value::List(v) => {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("list", v)?;
+                map.end()
+            }
+            Value::Range(lo, hi) => {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("range", &(lo, hi))?;
+                map.end()
+            }
         }
     }
 }
@@ -219,10 +885,14 @@ impl<'de, T: Deserialize<'de> + FromStr> Deserialize<'de> for Value<T> {
                     "<auto>" => Ok(Value::Auto),
                     "<random>" => Ok(Value::Random),
                     _ => {
-                        // Try to parse the string as T
-                        T::from_str(value).map(Value::Set).map_err(|_| {
-                            E::custom(format!("Failed to parse '{}' as Set value", value))
-                        })
+                        // T may itself be stored as a string (e.g. a unit enum variant),
+                        // otherwise try to parse the string as T
+                        T::deserialize(de::value::StrDeserializer::<E>::new(value))
+                            .or_else(|_| T::from_str(value))
+                            .map(Value::Set)
+                            .map_err(|_| {
+                                E::custom(format!("Failed to parse '{}' as Set value", value))
+                            })
                     }
                 }
             }
@@ -266,7 +936,23 @@ impl<'de, T: Deserialize<'de> + FromStr> Deserialize<'de> for Value<T> {
             where
                 A: MapAccess<'de>,
             {
-                Deserialize::deserialize(de::value::MapAccessDeserializer::new(map)).map(Value::Set)
+                // {"list": [..]}, {"range": [lo, hi]} and {"random": {..}},
+                // anything else is the value itself
+                let v: serde_json::Value =
+                    Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                let one_key = v.as_object().filter(|m| m.len() == 1);
+                match one_key.and_then(|m| m.iter().next()) {
+                    Some((k, list)) if k == "list" => Vec::<T>::deserialize(list.clone())
+                        .map(Value::List)
+                        .map_err(A::Error::custom),
+                    Some((k, range)) if k == "range" => <(T, T)>::deserialize(range.clone())
+                        .map(|(lo, hi)| Value::Range(lo, hi))
+                        .map_err(A::Error::custom),
+                    Some((k, c)) if k == "random" => RandomConstraint::<T>::deserialize(c.clone())
+                        .map(Value::RandomWith)
+                        .map_err(A::Error::custom),
+                    _ => T::deserialize(v).map(Value::Set).map_err(A::Error::custom),
+                }
             }
         }
 
@@ -288,6 +974,13 @@ where
             }
             Self::Set(x) => x.clone(),
             Self::Func(f) => f(),
+            /* without the stack there is nothing to compute it from, fill() does that */
+            Self::Dyn(_) => Default::default(),
+            /* used as is, without expand(), the first value stands for the others */
+            Self::List(v) => v.first().cloned().unwrap_or_default(),
+            Self::Range(lo, _) => lo.clone(),
+            /* fill() draws the constrained values, here the first one stands for the others */
+            Self::RandomWith(c) => c.first().unwrap_or_default(),
         }
     }
 }
@@ -305,6 +998,22 @@ impl<T: std::fmt::Display> fmt::Display for Value<T> {
             Self::Random => f.write_str(&format!("Random")),
             Self::Set(x) => x.fmt(f),
             Self::Func(x) => f.write_str(&format!("Fn: {:?}", x)),
+            Self::Dyn(_) => f.write_str("Dyn"),
+            Self::RandomWith(RandomConstraint::Range(lo, hi)) => {
+                write!(f, "Random({}..={})", lo, hi)
+            }
+            Self::RandomWith(RandomConstraint::Choice(v)) => {
+                let items: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+                write!(f, "Random([{}])", items.join(", "))
+            }
+            Self::RandomWith(RandomConstraint::Mask { value, mask }) => {
+                write!(f, "Random({} mask {})", value, mask)
+            }
+            Self::List(v) => {
+                let items: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Self::Range(lo, hi) => write!(f, "{}..={}", lo, hi),
         }
     }
 }
@@ -316,6 +1025,10 @@ impl<T: std::fmt::Debug> fmt::Debug for Value<T> {
             Self::Random => f.write_str(&format!("Random")),
             Self::Set(x) => f.write_str(&format!("{:?}", &x)),
             Self::Func(x) => f.write_str(&format!("Fn: {:?}", x)),
+            Self::Dyn(_) => f.write_str("Dyn"),
+            Self::RandomWith(c) => f.write_str(&format!("Random({:?})", c)),
+            Self::List(v) => f.write_str(&format!("{:?}", v)),
+            Self::Range(lo, hi) => f.write_str(&format!("{:?}..={:?}", lo, hi)),
         }
     }
 }
@@ -343,12 +1056,12 @@ impl<T: FromStr> FromStr for Value<T> {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match T::from_str(s) {
             Ok(res) => Ok(Self::Set(res)),
-            Err(e) => panic!("Could not parse!"),
+            Err(_) => Err(ValueParseError::Error),
         }
     }
 }
 
-#[derive(PartialEq, Clone, Eq)]
+#[derive(PartialEq, Clone, Eq, Hash, PartialOrd, Ord)]
 pub struct MacAddr(mac_address::MacAddress);
 
 impl fmt::Debug for MacAddr {
@@ -390,13 +1103,13 @@ impl<'de> Deserialize<'de> for MacAddr {
         impl<'de> Visitor<'de> for MacAddrVisitor {
             type Value = MacAddr;
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("MacAddr")
+                formatter.write_str("a MAC address string like \"00:01:02:03:04:05\"")
             }
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: Error,
             {
-                panic!("TBD")
+                MacAddr::from_str(v).map_err(|_| E::custom(format!("invalid MAC address {:?}", v)))
             }
         }
 
@@ -445,6 +1158,12 @@ impl From<Value<MacAddr>> for MacAddr {
             }
             Value::Set(x) => x.clone(),
             Value::Func(x) => x(),
+            Value::Dyn(_) => {
+                panic!("can not return value of dyn mac addr without the stack");
+            }
+            Value::RandomWith(c) => c.first().unwrap_or_default(),
+            Value::List(v) => v.first().cloned().unwrap_or_default(),
+            Value::Range(lo, _) => lo,
         }
     }
 }
@@ -469,7 +1188,7 @@ impl Distribution<MacAddr> for Standard {
     }
 }
 
-#[derive(PartialEq, Clone, Eq)]
+#[derive(PartialEq, Clone, Eq, Hash, PartialOrd, Ord)]
 pub struct Ipv4Address(std::net::Ipv4Addr);
 
 impl fmt::Debug for Ipv4Address {
@@ -511,14 +1230,14 @@ impl<'de> Deserialize<'de> for Ipv4Address {
         impl<'de> Visitor<'de> for Ipv4Visitor {
             type Value = Ipv4Address;
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                panic!("TBD1");
-                formatter.write_str("Ipv4Address")
+                formatter.write_str("an IPv4 address string like \"192.0.2.1\"")
             }
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: Error,
             {
-                panic!("TBD")
+                Ipv4Address::from_str(v)
+                    .map_err(|_| E::custom(format!("invalid IPv4 address {:?}", v)))
             }
         }
 
@@ -623,113 +1342,1063 @@ pub struct FunnyTest {
     pub bar: Option<u32>,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct EncodingVecVec {
-    data: Vec<Vec<u8>>,
+/*
+ * The encoded layers, in one buffer filled from the back: the layers are encoded
+ * last to first and each goes right before the ones above it, so the packet ends up
+ * in one piece with nothing to join. The buffer is either owned, moving to a bigger
+ * allocation when the headroom runs out, or a slice given by the caller.
+ */
+#[derive(Debug, Default)]
+pub struct EncodingVecVec<'a> {
+    buf: EncodingBuf<'a>,
+    /* the encoded data is buf[start..] */
+    start: usize,
+    /* for each layer, the distance from its first byte to the end of the buffer */
+    from_end: Vec<usize>,
     curr_idx: usize,
 }
 
-impl EncodingVecVec {
-    fn len(&self) -> usize {
-        // take into account the "phantom" layers
-        self.data.len() + self.curr_idx + 1
-    }
+#[derive(Debug)]
+enum EncodingBuf<'a> {
+    Owned(Vec<u8>),
+    Borrowed(&'a mut [u8]),
 }
-impl Index<usize> for EncodingVecVec {
-    type Output = Vec<u8>;
 
-    fn index(&self, idx: usize) -> &Self::Output {
-        if idx > self.curr_idx {
-            &self.data[idx - self.curr_idx - 1]
-        } else {
-            panic!("encoding data at layer {} not yet ready", idx);
-        }
+impl Default for EncodingBuf<'_> {
+    fn default() -> Self {
+        EncodingBuf::Owned(vec![])
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct LayerStack {
-    pub filled: bool,
-    pub layers: Vec<Box<dyn Layer>>,
-}
-
-/*
-impl Serialize for LayerStack {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut len = self.layers.len();
-        let mut seq = serializer.serialize_tuple(len)?;
-        for (i, ll) in (&self.layers).into_iter().enumerate() {
-            seq.serialize_element(ll)?;
+impl<'a> EncodingVecVec<'a> {
+    fn with_buf(buf: EncodingBuf<'a>, nlayers: usize) -> Self {
+        let start = match &buf {
+            EncodingBuf::Owned(v) => v.len(),
+            EncodingBuf::Borrowed(b) => b.len(),
+        };
+        EncodingVecVec {
+            buf,
+            start,
+            from_end: vec![0; nlayers + 1],
+            curr_idx: nlayers,
         }
-        seq.end()
-    }
-}
-*/
-/*
-impl Serialize for dyn Layer {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let s = format!("FIXME {:?}", self);
-        serializer.serialize_str(&s)
-        // eprintln!("SERIALIZE: {}\n", s);
-        // self.downcast_mut::<T>().unwrap().serialize(serializer)
-        // self.downcast_ref::<T>().unwrap().serialize(serializer)
     }
-}
-*/
 
-impl LayerStack {
-    pub fn gg<T: Layer + Clone>(layer: Box<dyn Layer>) -> T {
-        if layer.type_id() == TypeId::of::<T>() {
-            (*layer.downcast_ref::<T>().unwrap()).clone()
-        } else {
-            panic!(
-                " wrong typeid {:?} and {:?}",
-                layer.type_id(),
-                TypeId::of::<T>()
-            );
+    fn bytes(&self) -> &[u8] {
+        match &self.buf {
+            EncodingBuf::Owned(v) => v,
+            EncodingBuf::Borrowed(b) => b,
         }
     }
 
-    pub fn g<T: Layer>(&self, idx: T) -> &T {
-        self[TypeId::of::<T>()].downcast_ref().unwrap()
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match &mut self.buf {
+            EncodingBuf::Owned(v) => v,
+            EncodingBuf::Borrowed(b) => b,
+        }
     }
 
-    pub fn item_at<T: Layer>(&self, item: T, idx: usize) -> Option<&T> {
-        self.layers[idx].downcast_ref()
+    /* how many bytes are encoded so far */
+    fn used(&self) -> usize {
+        self.bytes().len() - self.start
     }
 
-    pub fn get_layer<T: Layer>(&self, item: T) -> Option<&T> {
-        for ll in &self.layers {
-            if ll.type_id_is(TypeId::of::<T>()) {
-                return Some(ll.downcast_ref().unwrap());
-            }
+    /* the payload of the layer at my_index in one piece, see LayerStack::payload_range() */
+    pub fn payload(&self, stack: &LayerStack, my_index: usize) -> &[u8] {
+        let range = stack.payload_range(my_index);
+        if range.start <= self.curr_idx {
+            panic!("encoding data at layer {} not yet ready", range.start);
         }
-        return None;
-    }
-    pub fn get_innermost_layer<T: Layer>(&self, item: T) -> Option<&T> {
-        for ref layer in self.layers.iter().rev() {
-            if layer.type_id_is(TypeId::of::<T>()) {
-                return Some(layer.downcast_ref().unwrap());
-            }
+        let total = self.bytes().len();
+        &self.bytes()[total - self.from_end[range.start]..total - self.from_end[range.end]]
+    }
+
+    /* put the encoded layer right before the layers above it */
+    fn prepend(&mut self, idx: usize, data: &[u8]) {
+        if self.start < data.len() {
+            /* a caller's slice which is too short is swapped for a vector, to learn the whole length */
+            let used = self.used();
+            let new_len = std::cmp::max(2 * self.bytes().len(), used + data.len()).max(64);
+            let mut v = vec![0; new_len];
+            v[new_len - used..].copy_from_slice(&self.bytes()[self.start..]);
+            self.buf = EncodingBuf::Owned(v);
+            self.start = new_len - used;
         }
-        return None;
-    }
-    pub fn get_layer_mut<T: Layer>(&mut self, item: T) -> Option<&mut T> {
-        for ll in &mut self.layers {
-            if ll.type_id_is(TypeId::of::<T>()) {
-                return Some(ll.downcast_mut().unwrap());
+        let start = self.start - data.len();
+        self.bytes_mut()[start..start + data.len()].copy_from_slice(data);
+        self.start = start;
+        self.from_end[idx] = self.bytes().len() - start;
+    }
+
+    /* let the layer just prepended fix its fields up, seeing its header and its payload */
+    fn patch(&mut self, stack: &LayerStack, idx: usize) {
+        let total = self.bytes().len();
+        let header_start = total - self.from_end[idx];
+        let header_end = total - self.from_end[idx + 1];
+        let payload_end = total - self.from_end[stack.payload_range(idx).end];
+        let (header, payload) =
+            self.bytes_mut()[header_start..payload_end].split_at_mut(header_end - header_start);
+        stack.layers[idx].patch_encoded(stack, idx, header, payload);
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        match self.buf {
+            EncodingBuf::Owned(mut v) => {
+                v.drain(..self.start);
+                v
             }
+            EncodingBuf::Borrowed(b) => b[self.start..].to_vec(),
         }
-        return None;
     }
+}
 
-    pub fn layers_of<T: Layer>(&self, item: T) -> Vec<&T> {
+/* the error of LayerStack::encode_to() */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    BufferTooSmall { needed: usize, available: usize },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::BufferTooSmall { needed, available } => write!(
+                f,
+                "the packet needs {} bytes, the buffer has {}",
+                needed, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/* where the layers and their fields are in an encoded packet */
+#[derive(Clone, Debug, Default)]
+pub struct PacketLayout {
+    pub map: DissectionMap,
+    /* the offset of each layer, then the length of the packet */
+    starts: Vec<usize>,
+}
+
+impl PacketLayout {
+    pub fn layer(&self, idx: usize) -> std::ops::Range<usize> {
+        self.starts[idx]..self.starts[idx + 1]
+    }
+
+    /* the bytes of the layers in LayerStack::payload_range() */
+    pub fn payload(&self, stack: &LayerStack, idx: usize) -> std::ops::Range<usize> {
+        let range = stack.payload_range(idx);
+        self.starts[range.start]..self.starts[range.end]
+    }
+
+    pub fn field(&self, idx: usize, name: &str) -> Option<std::ops::Range<usize>> {
+        self.map
+            .field(idx, name)
+            .map(|span| span.offset..span.offset + span.length)
+    }
+}
+
+/*
+ * A field of a layer computed from other bytes of the packet: a length, or the
+ * internet checksum of the byte ranges in sums, each summed from its own start.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DependentField {
+    pub field: &'static str,
+    pub sums: Vec<std::ops::Range<usize>>,
+}
+
+impl DependentField {
+    pub fn length(field: &'static str) -> Self {
+        DependentField {
+            field,
+            sums: vec![],
+        }
+    }
+
+    pub fn checksum(field: &'static str, sums: Vec<std::ops::Range<usize>>) -> Self {
+        DependentField { field, sums }
+    }
+}
+
+/* A field whose decoded value disagrees with the value recomputed from the rest of the stack */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldMismatch {
+    pub layer_index: usize,
+    pub layer: &'static str,
+    pub field: &'static str,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl FieldMismatch {
+    pub fn check<T: Into<u64> + PartialEq>(
+        layer: &dyn Layer,
+        layer_index: usize,
+        field: &'static str,
+        expected: T,
+        actual: T,
+    ) -> Option<FieldMismatch> {
+        if expected == actual {
+            None
+        } else {
+            Some(FieldMismatch {
+                layer_index,
+                layer: layer.layer_name(),
+                field,
+                expected: expected.into(),
+                actual: actual.into(),
+            })
+        }
+    }
+}
+
+/* The bytes a field was decoded from or encoded into */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldSpan {
+    pub layer_index: usize,
+    pub layer: &'static str,
+    pub field: &'static str,
+    pub offset: usize,
+    pub length: usize,
+    /* bit offset and width within the bytes, from the most significant bit, for sub-byte fields */
+    pub bits: Option<(usize, usize)>,
+}
+
+/*
+ * Field spans of a whole packet. The base and layer_index are the
+ * position of the layer being decoded, kept up to date by the generated code.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DissectionMap {
+    pub spans: Vec<FieldSpan>,
+    pub base: usize,
+    pub layer_index: usize,
+}
+
+impl DissectionMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /* start a new layer at the given offset, return the mark to roll back to if it fails */
+    pub fn enter(&mut self, base: usize, layer_index: usize) -> usize {
+        self.base = base;
+        self.layer_index = layer_index;
+        self.spans.len()
+    }
+
+    pub fn rollback(&mut self, mark: usize) {
+        self.spans.truncate(mark);
+    }
+
+    /* record a field covering start..end of the current layer's buffer */
+    pub fn add(
+        &mut self,
+        layer: &'static str,
+        field: &'static str,
+        start: usize,
+        end: usize,
+        bits: Option<(usize, usize)>,
+    ) {
+        self.spans.push(FieldSpan {
+            layer_index: self.layer_index,
+            layer,
+            field,
+            offset: self.base + start,
+            length: end - start,
+            bits,
+        });
+    }
+
+    pub fn field(&self, layer_index: usize, field: &str) -> Option<&FieldSpan> {
+        self.spans
+            .iter()
+            .find(|s| s.layer_index == layer_index && s.field == field)
+    }
+
+    pub fn layer_spans(&self, layer_index: usize) -> Vec<&FieldSpan> {
+        self.spans
+            .iter()
+            .filter(|s| s.layer_index == layer_index)
+            .collect()
+    }
+
+    /* the fields covering the byte at offset, e.g. to find what was clicked in a hex view */
+    pub fn at(&self, offset: usize) -> Vec<&FieldSpan> {
+        self.spans
+            .iter()
+            .filter(|s| s.offset <= offset && offset < s.offset + s.length)
+            .collect()
+    }
+}
+
+/* A field as displayed by show(): "symbolic" is the name of the layer the value selects */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub value: String,
+    pub symbolic: Option<&'static str>,
+}
+
+/* A field value accessed by name at runtime, see Layer::get_field() and Layer::set_field() */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldValue {
+    Auto,
+    Random,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    Ipv4(Ipv4Address),
+    Mac(MacAddr),
+    Bytes(Vec<u8>),
+    Str(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("Auto"),
+            Self::Random => f.write_str("Random"),
+            Self::Bool(x) => write!(f, "{}", x),
+            Self::UInt(x) => write!(f, "{}", x),
+            Self::Int(x) => write!(f, "{}", x),
+            Self::Ipv4(x) => write!(f, "{:?}", x),
+            Self::Mac(x) => write!(f, "{:?}", x),
+            Self::Bytes(x) => write!(f, "{:02x?}", x),
+            Self::Str(x) => f.write_str(x),
+        }
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(s: &str) -> Self {
+        Self::Str(s.to_string())
+    }
+}
+
+impl From<u64> for FieldValue {
+    fn from(x: u64) -> Self {
+        Self::UInt(x)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(x: i64) -> Self {
+        Self::Int(x)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(x: bool) -> Self {
+        Self::Bool(x)
+    }
+}
+
+impl FieldValue {
+    /* integers may also be given as strings, in decimal or as "0x..." */
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::UInt(x) => Some(*x),
+            Self::Int(x) => u64::try_from(*x).ok(),
+            Self::Bool(x) => Some(*x as u64),
+            Self::Str(s) => {
+                let s = s.trim();
+                match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                    None => s.parse().ok(),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Int(x) => Some(*x),
+            Self::Str(s) if s.trim().starts_with('-') => s.trim().parse().ok(),
+            x => x.as_u64().and_then(|x| i64::try_from(x).ok()),
+        }
+    }
+}
+
+/* Conversion of a field type to and from FieldValue, for the access to the fields by name */
+pub trait DynField: Sized {
+    fn to_field_value(&self) -> FieldValue;
+    fn from_field_value(v: &FieldValue) -> Option<Self>;
+}
+
+/*
+ * The conversions of a field type, as functions: DynField's, or for a type without it
+ * its Debug form, which can not be set by name and has no lists or ranges.
+ */
+pub struct FieldConv<T> {
+    pub to: fn(&T) -> FieldValue,
+    pub from: fn(&FieldValue) -> Option<T>,
+}
+
+impl<T> Clone for FieldConv<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for FieldConv<T> {}
+
+impl<T: DynField> FieldConv<T> {
+    pub fn of() -> Self {
+        FieldConv {
+            to: T::to_field_value,
+            from: T::from_field_value,
+        }
+    }
+}
+
+impl<T: Debug> FieldConv<T> {
+    pub fn debug() -> Self {
+        fn to<T: Debug>(x: &T) -> FieldValue {
+            FieldValue::Str(format!("{:?}", x))
+        }
+        FieldConv {
+            to: to::<T>,
+            from: |_| None,
+        }
+    }
+}
+
+/*
+ * The derive gets the FieldConv of a field type with (&&FieldConvProbe::<T>::new()).field_conv():
+ * method resolution picks FieldConvViaDynField if T has DynField, FieldConvViaDebug if not.
+ */
+#[doc(hidden)]
+pub struct FieldConvProbe<T>(std::marker::PhantomData<T>);
+
+impl<T> FieldConvProbe<T> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        FieldConvProbe(std::marker::PhantomData)
+    }
+}
+
+#[doc(hidden)]
+pub trait FieldConvViaDynField<T> {
+    fn field_conv(&self) -> FieldConv<T>;
+}
+
+impl<T: DynField> FieldConvViaDynField<T> for &FieldConvProbe<T> {
+    fn field_conv(&self) -> FieldConv<T> {
+        FieldConv::of()
+    }
+}
+
+#[doc(hidden)]
+pub trait FieldConvViaDebug<T> {
+    fn field_conv(&self) -> FieldConv<T>;
+}
+
+impl<T: Debug> FieldConvViaDebug<T> for FieldConvProbe<T> {
+    fn field_conv(&self) -> FieldConv<T> {
+        FieldConv::debug()
+    }
+}
+
+macro_rules! dyn_field_uint {
+    ($($t:ty),*) => {
+        $(
+            impl DynField for $t {
+                fn to_field_value(&self) -> FieldValue {
+                    FieldValue::UInt(*self as u64)
+                }
+                fn from_field_value(v: &FieldValue) -> Option<Self> {
+                    v.as_u64().and_then(|x| <$t>::try_from(x).ok())
+                }
+            }
+        )*
+    };
+}
+
+dyn_field_uint!(u8, u16, u32, u64);
+
+impl DynField for i32 {
+    fn to_field_value(&self) -> FieldValue {
+        FieldValue::Int(*self as i64)
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        v.as_i64().and_then(|x| i32::try_from(x).ok())
+    }
+}
+
+impl DynField for bool {
+    fn to_field_value(&self) -> FieldValue {
+        FieldValue::Bool(*self)
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Bool(x) => Some(*x),
+            FieldValue::Str(s) => s.trim().parse().ok(),
+            x => x.as_u64().filter(|&x| x <= 1).map(|x| x == 1),
+        }
+    }
+}
+
+impl DynField for Ipv4Address {
+    fn to_field_value(&self) -> FieldValue {
+        FieldValue::Ipv4(self.clone())
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Ipv4(x) => Some(x.clone()),
+            FieldValue::Str(s) => Ipv4Address::from_str(s.trim()).ok(),
+            FieldValue::UInt(x) => u32::try_from(*x).ok().map(Ipv4Address::from),
+            _ => None,
+        }
+    }
+}
+
+impl DynField for MacAddr {
+    fn to_field_value(&self) -> FieldValue {
+        FieldValue::Mac(self.clone())
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Mac(x) => Some(x.clone()),
+            FieldValue::Str(s) => MacAddr::from_str(s.trim()).ok(),
+            FieldValue::Bytes(b) if b.len() == 6 => Some(MacAddr::from(&b[..])),
+            _ => None,
+        }
+    }
+}
+
+impl DynField for Vec<u8> {
+    fn to_field_value(&self) -> FieldValue {
+        FieldValue::Bytes(self.clone())
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Bytes(x) => Some(x.clone()),
+            FieldValue::Str(s) => Some(s.as_bytes().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: DynField> DynField for Value<T> {
+    fn to_field_value(&self) -> FieldValue {
+        self.to_field_value_with(FieldConv::of())
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        Self::from_field_value_with(v, FieldConv::of())
+    }
+}
+
+impl<T> Value<T> {
+    /* DynField of a Value, with the conversions of the type in it */
+    pub fn to_field_value_with(&self, conv: FieldConv<T>) -> FieldValue {
+        match self {
+            Self::Auto => FieldValue::Auto,
+            Self::Random => FieldValue::Random,
+            Self::Func(f) => (conv.to)(&f()),
+            Self::Set(x) => (conv.to)(x),
+            Self::Dyn(_) => FieldValue::Str("Dyn".to_string()),
+            Self::RandomWith(_) => FieldValue::Random,
+            Self::List(v) => {
+                let items: Vec<String> = v.iter().map(|x| (conv.to)(x).to_string()).collect();
+                FieldValue::Str(items.join(","))
+            }
+            Self::Range(lo, hi) => FieldValue::Str(format!("{}-{}", (conv.to)(lo), (conv.to)(hi))),
+        }
+    }
+
+    pub fn from_field_value_with(v: &FieldValue, conv: FieldConv<T>) -> Option<Self> {
+        match v {
+            FieldValue::Auto => Some(Self::Auto),
+            FieldValue::Random => Some(Self::Random),
+            FieldValue::Str(s) if s == "<auto>" || s.eq_ignore_ascii_case("auto") => {
+                Some(Self::Auto)
+            }
+            FieldValue::Str(s) if s == "<random>" || s.eq_ignore_ascii_case("random") => {
+                Some(Self::Random)
+            }
+            FieldValue::Str(s) => Self::parse_expand_with(s, conv),
+            x => (conv.from)(x).map(Self::Set),
+        }
+    }
+}
+
+/*
+ * Ranges step through the values by their position on a line: the number itself,
+ * or the address as a number. Returns the position and the width in bits.
+ */
+fn field_value_index(v: &FieldValue) -> Option<(u64, u32)> {
+    match v {
+        FieldValue::UInt(x) => Some((*x, 64)),
+        FieldValue::Int(x) => Some(((*x as u64) ^ (1 << 63), 64)),
+        FieldValue::Ipv4(x) => Some((u32::from(x.0) as u64, 32)),
+        FieldValue::Mac(x) => {
+            let b = x.0.bytes();
+            Some((b.iter().fold(0u64, |acc, x| (acc << 8) | *x as u64), 48))
+        }
+        _ => None,
+    }
+}
+
+fn field_value_at(like: &FieldValue, index: u64) -> Option<FieldValue> {
+    match like {
+        FieldValue::UInt(_) => Some(FieldValue::UInt(index)),
+        FieldValue::Int(_) => Some(FieldValue::Int((index ^ (1 << 63)) as i64)),
+        FieldValue::Ipv4(_) => u32::try_from(index)
+            .ok()
+            .map(|x| FieldValue::Ipv4(Ipv4Address::from(x))),
+        FieldValue::Mac(_) if index < 1 << 48 => {
+            let b = index.to_be_bytes();
+            Some(FieldValue::Mac(MacAddr::from(&b[2..8])))
+        }
+        _ => None,
+    }
+}
+
+impl<T: DynField> Value<T> {
+    /* the number of values a List or Range stands for, None for the other variants */
+    pub fn expand_len(&self) -> Option<u64> {
+        self.expand_len_with(FieldConv::of())
+    }
+
+    pub fn expand_nth(&self, n: u64) -> Option<T> {
+        self.expand_nth_with(n, FieldConv::of())
+    }
+
+    /*
+     * Parse a single value, or the forms which stand for several:
+     * "a,b,c", "lo-hi", a prefix like "10.0.0.0/30" and a MAC address
+     * with the trailing bytes as "*", like "00:11:22:33:*:*".
+     */
+    pub fn parse_expand(s: &str) -> Option<Self> {
+        Self::parse_expand_with(s, FieldConv::of())
+    }
+}
+
+impl<T> Value<T> {
+    pub fn expand_len_with(&self, conv: FieldConv<T>) -> Option<u64> {
+        match self {
+            Self::List(v) => Some(v.len() as u64),
+            Self::Range(lo, hi) => {
+                let (lo, _) = field_value_index(&(conv.to)(lo))?;
+                let (hi, _) = field_value_index(&(conv.to)(hi))?;
+                Some(if hi < lo {
+                    0
+                } else {
+                    (hi - lo).saturating_add(1)
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn expand_nth_with(&self, n: u64, conv: FieldConv<T>) -> Option<T> {
+        match self {
+            Self::List(v) => {
+                let x = v.get(usize::try_from(n).ok()?)?;
+                (conv.from)(&(conv.to)(x))
+            }
+            Self::Range(lo, _) if n < self.expand_len_with(conv)? => {
+                let lo = (conv.to)(lo);
+                let (start, _) = field_value_index(&lo)?;
+                (conv.from)(&field_value_at(&lo, start + n)?)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn parse_expand_with(s: &str, conv: FieldConv<T>) -> Option<Self> {
+        let s = s.trim();
+        let one = |x: &str| (conv.from)(&FieldValue::Str(x.trim().to_string()));
+        if let Some(x) = one(s) {
+            return Some(Self::Set(x));
+        }
+        if s.contains(',') {
+            return s
+                .split(',')
+                .map(one)
+                .collect::<Option<Vec<T>>>()
+                .map(Self::List);
+        }
+        if let Some((addr, len)) = s.split_once('/') {
+            let lo = (conv.to)(&one(addr)?);
+            let (start, width) = field_value_index(&lo)?;
+            let len: u32 = len.trim().parse().ok()?;
+            if width == 64 || len > width {
+                return None;
+            }
+            let host_mask = (1u64 << (width - len)) - 1;
+            let first = (conv.from)(&field_value_at(&lo, start & !host_mask)?)?;
+            let last = (conv.from)(&field_value_at(&lo, start | host_mask)?)?;
+            return Some(Self::Range(first, last));
+        }
+        if s.contains('*') {
+            let lo = one(&s.replace('*', "00"))?;
+            let hi = one(&s.replace('*', "ff"))?;
+            return Some(Self::Range(lo, hi));
+        }
+        let (lo, hi) = s.split_once('-')?;
+        Some(Self::Range(one(lo)?, one(hi)?))
+    }
+}
+
+/*
+ * What the layer macros and setters accept for the Value fields of numbers and
+ * addresses: the value itself, a Value, and lists and ranges of values.
+ */
+pub trait IntoValue<T> {
+    fn into_value(self) -> Value<T>;
+}
+
+impl<T> IntoValue<T> for Value<T> {
+    fn into_value(self) -> Value<T> {
+        self
+    }
+}
+
+impl<T, const N: usize> IntoValue<T> for [T; N] {
+    fn into_value(self) -> Value<T> {
+        Value::List(self.into())
+    }
+}
+
+impl<T> IntoValue<T> for Vec<T> {
+    fn into_value(self) -> Value<T> {
+        Value::List(self)
+    }
+}
+
+macro_rules! into_value_uint {
+    ($($t:ty),*) => {
+        $(
+            impl IntoValue<$t> for $t {
+                fn into_value(self) -> Value<$t> {
+                    Value::Set(self)
+                }
+            }
+
+            impl IntoValue<$t> for std::ops::Range<$t> {
+                fn into_value(self) -> Value<$t> {
+                    if self.start < self.end {
+                        Value::Range(self.start, self.end - 1)
+                    } else {
+                        Value::List(vec![])
+                    }
+                }
+            }
+
+            impl IntoValue<$t> for std::ops::RangeInclusive<$t> {
+                fn into_value(self) -> Value<$t> {
+                    let (lo, hi) = self.into_inner();
+                    Value::Range(lo, hi)
+                }
+            }
+        )*
+    };
+}
+
+into_value_uint!(u8, u16, u32, u64);
+
+macro_rules! into_value_from {
+    ($t:ty: $($from:ty),*) => {
+        $(
+            impl IntoValue<$t> for $from {
+                fn into_value(self) -> Value<$t> {
+                    Value::Set(self.into())
+                }
+            }
+        )*
+    };
+}
+
+into_value_from!(Ipv4Address: Ipv4Address, [u8; 4], u32);
+into_value_from!(MacAddr: MacAddr, [u8; 6], &[u8]);
+
+macro_rules! into_value_str {
+    ($($t:ty),*) => {
+        $(
+            impl IntoValue<$t> for &str {
+                fn into_value(self) -> Value<$t> {
+                    Value::parse_expand(self)
+                        .unwrap_or_else(|| panic!("can not parse {:?} as {}", self, stringify!($t)))
+                }
+            }
+
+            impl IntoValue<$t> for String {
+                fn into_value(self) -> Value<$t> {
+                    self.as_str().into_value()
+                }
+            }
+
+            impl IntoValue<$t> for &String {
+                fn into_value(self) -> Value<$t> {
+                    self.as_str().into_value()
+                }
+            }
+
+            impl<const N: usize> IntoValue<$t> for [&str; N] {
+                fn into_value(self) -> Value<$t> {
+                    Value::List(self.iter().map(|x| <$t>::from(*x)).collect())
+                }
+            }
+        )*
+    };
+}
+
+into_value_str!(Ipv4Address, MacAddr);
+
+/*
+ * For the field types without a natural FieldValue: they are shown by their Debug
+ * form and, with "from_str", set from a string. Without it they can not be set by name.
+ */
+#[macro_export]
+macro_rules! dyn_field_via_debug {
+    ($t:ty) => {
+        impl DynField for $t {
+            fn to_field_value(&self) -> FieldValue {
+                FieldValue::Str(format!("{:?}", self))
+            }
+            fn from_field_value(_v: &FieldValue) -> Option<Self> {
+                None
+            }
+        }
+    };
+    ($t:ty, from_str) => {
+        impl DynField for $t {
+            fn to_field_value(&self) -> FieldValue {
+                FieldValue::Str(format!("{:?}", self))
+            }
+            fn from_field_value(v: &FieldValue) -> Option<Self> {
+                match v {
+                    FieldValue::Str(s) => <$t as std::str::FromStr>::from_str(s).ok(),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+/* A field of a layer: its name, the type as declared and the default value */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldDesc {
+    pub name: &'static str,
+    pub typ: &'static str,
+    pub default: FieldValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldAccessError {
+    BadPath(String),
+    NoSuchLayer(String),
+    NoSuchField(String),
+    BadValue { field: String, value: FieldValue },
+}
+
+impl fmt::Display for FieldAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadPath(p) => write!(f, "bad field path {:?}", p),
+            Self::NoSuchLayer(l) => write!(f, "no layer {:?}", l),
+            Self::NoSuchField(n) => write!(f, "no field {:?}", n),
+            Self::BadValue { field, value } => {
+                write!(f, "can not set field {:?} to {:?}", field, value)
+            }
+        }
+    }
+}
+
+/* offset, hex and ASCII columns, 16 bytes per line */
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if (0x20..0x7f).contains(&b) {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:04x}  {:<47}  {}\n",
+            i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+impl fmt::Display for FieldMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "layer {} ({}): {} is {:#x}, expected {:#x}",
+            self.layer_index, self.layer, self.field, self.actual, self.expected
+        )
+    }
+}
+
+impl Index<usize> for EncodingVecVec<'_> {
+    type Output = [u8];
+
+    fn index(&self, idx: usize) -> &Self::Output {
+        if idx > self.curr_idx {
+            // the layers are encoded last to first, so only the ones above are there
+            let total = self.bytes().len();
+            &self.bytes()[total - self.from_end[idx]..total - self.from_end[idx + 1]]
+        } else {
+            panic!("encoding data at layer {} not yet ready", idx);
+        }
+    }
+}
+
+/* iterator returned by LayerStack::expand() and LayerStack::generate() */
+pub struct ExpandedStacks {
+    stack: LayerStack,
+    dims: Vec<(usize, &'static str, u64)>,
+    next: Option<Vec<u64>>,
+    /* packets made so far, for the FieldContext of the Dyn values */
+    counter: u64,
+    /* for generate(): start over until this many packets are made */
+    remaining: Option<u64>,
+    rng: Option<Box<dyn RngCore>>,
+}
+
+impl ExpandedStacks {
+    /* draw the Random values of all the packets from this generator */
+    pub fn with_rng<R: RngCore + 'static>(mut self, rng: R) -> Self {
+        self.rng = Some(Box::new(rng));
+        self
+    }
+}
+
+impl Iterator for ExpandedStacks {
+    type Item = LayerStack;
+
+    fn next(&mut self) -> Option<LayerStack> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        let mut idx = self.next.take()?;
+        let mut out = self.stack.clone();
+        for ((layer, field, _), n) in self.dims.iter().zip(&idx) {
+            out.layers[*layer].expand_field(field, *n);
+        }
+        let mut wrapped = true;
+        for i in (0..idx.len()).rev() {
+            idx[i] += 1;
+            if idx[i] < self.dims[i].2 {
+                wrapped = false;
+                break;
+            }
+            idx[i] = 0;
+        }
+        if !wrapped || self.remaining.is_some() {
+            self.next = Some(idx);
+        }
+        self.remaining = self.remaining.map(|r| r - 1);
+        let counter = self.counter;
+        self.counter += 1;
+        let out = match self.rng.as_mut() {
+            Some(rng) => out.fill_with(&mut FillContext {
+                counter,
+                rng: rng.as_mut(),
+            }),
+            None => out.fill_counted(counter),
+        };
+        Some(out)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerStack {
+    pub filled: bool,
+    pub layers: Vec<Box<dyn Layer>>,
+}
+
+/*
+impl Serialize for LayerStack {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut len = self.layers.len();
+        let mut seq = serializer.serialize_tuple(len)?;
+        for (i, ll) in (&self.layers).into_iter().enumerate() {
+            seq.serialize_element(ll)?;
+        }
+        seq.end()
+    }
+}
+*/
+/*
+impl Serialize for dyn Layer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = format!("FIXME {:?}", self);
+        serializer.serialize_str(&s)
+        // eprintln!("SERIALIZE: {}\n", s);
+        // self.downcast_mut::<T>().unwrap().serialize(serializer)
+        // self.downcast_ref::<T>().unwrap().serialize(serializer)
+    }
+}
+*/
+
+impl LayerStack {
+    pub fn gg<T: Layer + Clone>(layer: Box<dyn Layer>) -> T {
+        if layer.type_id() == TypeId::of::<T>() {
+            (*layer.downcast_ref::<T>().unwrap()).clone()
+        } else {
+            panic!(
+                " wrong typeid {:?} and {:?}",
+                layer.type_id(),
+                TypeId::of::<T>()
+            );
+        }
+    }
+
+    pub fn g<T: Layer>(&self, idx: T) -> &T {
+        self[TypeId::of::<T>()].downcast_ref().unwrap()
+    }
+
+    pub fn item_at<T: Layer>(&self, item: T, idx: usize) -> Option<&T> {
+        self.layers[idx].downcast_ref()
+    }
+
+    pub fn get_layer<T: Layer>(&self, item: T) -> Option<&T> {
+        for ll in &self.layers {
+            if ll.type_id_is(TypeId::of::<T>()) {
+                return Some(ll.downcast_ref().unwrap());
+            }
+        }
+        return None;
+    }
+    pub fn get_innermost_layer<T: Layer>(&self, item: T) -> Option<&T> {
+        for ref layer in self.layers.iter().rev() {
+            if layer.type_id_is(TypeId::of::<T>()) {
+                return Some(layer.downcast_ref().unwrap());
+            }
+        }
+        return None;
+    }
+    pub fn get_layer_mut<T: Layer>(&mut self, item: T) -> Option<&mut T> {
+        for ll in &mut self.layers {
+            if ll.type_id_is(TypeId::of::<T>()) {
+                return Some(ll.downcast_mut().unwrap());
+            }
+        }
+        return None;
+    }
+
+    pub fn layers_of<T: Layer>(&self, item: T) -> Vec<&T> {
         let mut out = vec![];
         for ll in &self.layers {
             if ll.type_id_is(TypeId::of::<T>()) {
@@ -743,36 +2412,376 @@ impl LayerStack {
     }
 
     pub fn encode(self) -> Vec<u8> {
+        let target = if self.filled { self } else { self.fill() };
+        let mut out = EncodingVecVec::with_buf(EncodingBuf::Owned(vec![]), target.layers.len());
+        target.encode_layers(&mut out, None);
+        out.into_vec()
+    }
+
+    /*
+     * Encode into the caller's buffer and return the length of the packet,
+     * which is at the start of the buffer. Each layer is still encoded into
+     * a vector of its own before it is copied into the buffer.
+     * A stack which is already filled is not copied.
+     */
+    pub fn encode_to(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let filled;
         let target = if self.filled {
             self
         } else {
-            self.clone().fill()
+            filled = self.fill();
+            &filled
         };
-        let mut out = EncodingVecVec {
-            data: vec![],
-            curr_idx: target.layers.len(),
+        let available = buf.len();
+        let mut out = EncodingVecVec::with_buf(EncodingBuf::Borrowed(buf), target.layers.len());
+        target.encode_layers(&mut out, None);
+        let len = out.used();
+        match out.buf {
+            EncodingBuf::Borrowed(b) => {
+                b.copy_within(out.start.., 0);
+                Ok(len)
+            }
+            EncodingBuf::Owned(_) => Err(EncodeError::BufferTooSmall {
+                needed: len,
+                available,
+            }),
+        }
+    }
+
+    /* encode the layers last to first, patching each one once it is in the buffer */
+    fn encode_layers(&self, out: &mut EncodingVecVec, mut maps: Option<&mut Vec<DissectionMap>>) {
+        for (i, ll) in self.layers.iter().enumerate().rev() {
+            out.curr_idx = i;
+            let ev = match maps {
+                Some(ref mut maps) => {
+                    let mut map = DissectionMap::new();
+                    map.enter(0, i);
+                    let ev = ll.encode_into_map(self, i, out, Some(&mut map));
+                    maps.push(map);
+                    ev
+                }
+                None => ll.encode(self, i, out),
+            };
+            out.prepend(i, &ev);
+            out.patch(self, i);
+        }
+    }
+
+    /* encode, and return the byte range each field was encoded into */
+    pub fn encode_with_map(self) -> (Vec<u8>, DissectionMap) {
+        let (bytes, layout) = self.encode_with_layout();
+        (bytes, layout.map)
+    }
+
+    /* encode, and return where each layer and each field were encoded */
+    pub fn encode_with_layout(self) -> (Vec<u8>, PacketLayout) {
+        let target = if self.filled { self } else { self.fill() };
+        let mut out = EncodingVecVec::with_buf(EncodingBuf::Owned(vec![]), target.layers.len());
+        let mut layer_maps = vec![];
+        target.encode_layers(&mut out, Some(&mut layer_maps));
+        layer_maps.reverse();
+
+        let used = out.used();
+        let mut layout = PacketLayout {
+            map: DissectionMap::new(),
+            starts: out
+                .from_end
+                .iter()
+                .map(|from_end| used - from_end)
+                .collect(),
         };
-        for (i, ll) in (&target.layers).into_iter().enumerate().rev() {
+        for (layer_map, offset) in layer_maps.into_iter().zip(layout.starts.clone()) {
+            for mut span in layer_map.spans {
+                span.offset += offset;
+                layout.map.spans.push(span);
+            }
+        }
+        (out.into_vec(), layout)
+    }
+
+    /*
+     * Encode once, for making the packet again and again with a few fields changed,
+     * see template::PacketTemplate.
+     */
+    pub fn compile(&self) -> template::PacketTemplate {
+        template::PacketTemplate::new(self)
+    }
+
+    /*
+     * Recompute the checksum and length fields of every layer that knows how to,
+     * and report the ones whose current values disagree.
+     */
+    pub fn validate(&self) -> Vec<FieldMismatch> {
+        let mut mismatches = vec![];
+        let mut out = EncodingVecVec::with_buf(EncodingBuf::Owned(vec![]), self.layers.len());
+        for (i, ll) in (&self.layers).into_iter().enumerate().rev() {
             out.curr_idx = i;
-            // println!("{}: {:?}", i, &ll);
-            let ev = ll.encode(&target, i, &out);
-            out.data.push(ev);
+            mismatches.extend(ll.verify(self, i, &out));
+            let ev = ll.encode(self, i, &out);
+            out.prepend(i, &ev);
+            out.patch(self, i);
         }
-        out.data.reverse();
-        itertools::concat(out.data)
+        mismatches.reverse();
+        mismatches
     }
 
     pub fn fill(&self) -> LayerStack {
+        self.fill_counted(0)
+    }
+
+    /* fill, giving the Dyn values the number of the packet being made */
+    pub fn fill_counted(&self, counter: u64) -> LayerStack {
+        let mut rng = rand::thread_rng();
+        self.fill_with(&mut FillContext {
+            counter,
+            rng: &mut rng,
+        })
+    }
+
+    /* fill with the Random values drawn from the given generator, e.g. a seeded one */
+    pub fn fill_with_rng<R: RngCore>(&self, rng: &mut R) -> LayerStack {
+        self.fill_with(&mut FillContext { counter: 0, rng })
+    }
+
+    pub fn fill_with(&self, fill_ctx: &mut FillContext) -> LayerStack {
         let mut out = LayerStack {
             layers: vec![],
             filled: true,
         };
         for (i, ll) in (&self.layers).into_iter().enumerate() {
-            ll.fill(&self, i, &mut out);
+            ll.fill_with(self, i, fill_ctx, &mut out);
+        }
+        out
+    }
+
+    /*
+     * The packets a stack with List or Range field values stands for, filled,
+     * made one at a time. The last such field varies fastest, like nested loops.
+     */
+    pub fn expand(&self) -> ExpandedStacks {
+        let dims: Vec<(usize, &'static str, u64)> = self
+            .layers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, l)| {
+                l.expand_counts()
+                    .into_iter()
+                    .map(move |(field, n)| (i, field, n))
+            })
+            .collect();
+        let next = if dims.iter().any(|d| d.2 == 0) {
+            None
+        } else {
+            Some(vec![0; dims.len()])
+        };
+        ExpandedStacks {
+            stack: self.clone(),
+            dims,
+            next,
+            counter: 0,
+            remaining: None,
+            rng: None,
+        }
+    }
+
+    /* count packets, going over the expand() set again as many times as needed */
+    pub fn generate(&self, count: u64) -> ExpandedStacks {
+        ExpandedStacks {
+            remaining: Some(count),
+            ..self.expand()
+        }
+    }
+
+    /*
+     * A copy with every field that is not Auto made Random, like scapy's fuzz():
+     * the lengths and checksums are still computed, everything else is random
+     * on each fill. See fuzz::Mutator for mutations of a given packet.
+     */
+    pub fn fuzz(&self) -> LayerStack {
+        let mut out = self.clone();
+        for l in out.layers.iter_mut() {
+            l.fuzz_fields();
+        }
+        out.filled = false;
+        out
+    }
+
+    /* how many packets expand() gives */
+    pub fn expand_count(&self) -> u64 {
+        self.layers
+            .iter()
+            .flat_map(|l| l.expand_counts())
+            .fold(1u64, |acc, (_, n)| acc.saturating_mul(n))
+    }
+
+    /*
+     * Indices of the layers carried as payload by the layer at my_index:
+     * everything above it, up to the trailing padding of this layer or of one below it.
+     * The padding left out by a layer above it is part of the payload; a padding
+     * without an owner ends the payload of all the layers before it.
+     */
+    pub fn payload_range(&self, my_index: usize) -> std::ops::Range<usize> {
+        use crate::protocols::padding::*;
+        let end = (my_index + 1..self.layers.len())
+            .find(|&i| match self.layers[i].downcast_ref::<padding>() {
+                Some(pad) => match pad.owner {
+                    Value::Set(distance) if distance > 0 => {
+                        i.saturating_sub(distance as usize) <= my_index
+                    }
+                    _ => true,
+                },
+                None => false,
+            })
+            .unwrap_or(self.layers.len());
+        my_index + 1..end
+    }
+
+    /*
+     * Encode, appending zero bytes up to min_len if the result is shorter,
+     * e.g. ETHER_MIN_LEN for Ethernet frames.
+     */
+    pub fn encode_padded(self, min_len: usize) -> Vec<u8> {
+        let mut out = self.encode();
+        if out.len() < min_len {
+            out.resize(min_len, 0);
+        }
+        out
+    }
+
+    /* an indented tree of the layers and their fields, as they are now */
+    pub fn show_string(&self) -> String {
+        let mut out = String::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            let indent = "  ".repeat(i);
+            out.push_str(&format!("{}###[ {} ]###\n", &indent, layer.layer_name()));
+            for fi in layer.field_infos() {
+                let symbolic = fi.symbolic.map(|s| format!(" ({})", s)).unwrap_or_default();
+                out.push_str(&format!(
+                    "{}  {:<10}= {}{}\n",
+                    &indent, fi.name, fi.value, symbolic
+                ));
+            }
         }
         out
     }
 
+    /* one line per packet, e.g. "Ether / IP 192.0.2.1 > 192.0.2.2 / UDP 1234 > 53" */
+    pub fn summary(&self) -> String {
+        self.layers
+            .iter()
+            .map(|l| l.summary())
+            .collect::<Vec<String>>()
+            .join(" / ")
+    }
+
+    /*
+     * Format a custom one-liner: "{Ip.src}" is replaced with the field of the
+     * first layer with that name, "{Udp[1].dport}" with that of the second one.
+     * Layer names are not case sensitive, unknown fields are shown as "??".
+     */
+    pub fn sprintf(&self, fmt: &str) -> String {
+        let mut out = String::new();
+        let mut rest = fmt;
+        while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                out.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            match tail.find('}') {
+                Some(end) if tail.starts_with('{') => {
+                    out.push_str(&self.sprintf_field(&tail[1..end]));
+                    rest = &tail[end + 1..];
+                }
+                _ => {
+                    out.push_str(&tail[..1]);
+                    rest = &tail[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn sprintf_field(&self, spec: &str) -> String {
+        match self.resolve_field_path(spec) {
+            Ok((idx, field)) => self.layers[idx]
+                .field_infos()
+                .into_iter()
+                .find(|fi| fi.name == field)
+                .map(|fi| fi.value)
+                .unwrap_or("??".to_string()),
+            Err(_) => "??".to_string(),
+        }
+    }
+
+    /* "Udp[1].dport" => the index of the second Udp layer and "dport" */
+    fn resolve_field_path<'p>(&self, path: &'p str) -> Result<(usize, &'p str), FieldAccessError> {
+        let bad_path = || FieldAccessError::BadPath(path.to_string());
+        let (layer_spec, field) = path.split_once('.').ok_or_else(bad_path)?;
+        let (layer_name, nth) = match layer_spec.split_once('[') {
+            Some((name, idx)) => match idx.strip_suffix(']').map(|i| i.parse::<usize>()) {
+                Some(Ok(n)) => (name, n),
+                _ => return Err(bad_path()),
+            },
+            None => (layer_spec, 0),
+        };
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.layer_name().eq_ignore_ascii_case(layer_name))
+            .nth(nth)
+            .map(|(idx, _)| (idx, field))
+            .ok_or_else(|| FieldAccessError::NoSuchLayer(layer_spec.to_string()))
+    }
+
+    /* the field by its path, e.g. get("IP.src") or get("UDP[1].dport") */
+    pub fn get(&self, path: &str) -> Result<FieldValue, FieldAccessError> {
+        let (idx, field) = self.resolve_field_path(path)?;
+        self.layers[idx]
+            .get_field(field)
+            .ok_or_else(|| FieldAccessError::NoSuchField(field.to_string()))
+    }
+
+    pub fn set(&mut self, path: &str, value: &str) -> Result<(), FieldAccessError> {
+        self.set_value(path, FieldValue::Str(value.to_string()))
+    }
+
+    pub fn set_value(&mut self, path: &str, value: FieldValue) -> Result<(), FieldAccessError> {
+        let (idx, field) = self.resolve_field_path(path)?;
+        self.layers[idx].set_field_value(field, value)
+    }
+
+    pub fn show(&self) {
+        print!("{}", self.show_string());
+    }
+
+    /* the tree after encoding and decoding again, so the computed fields have their values */
+    pub fn show2_string(&self) -> String {
+        if self.layers.is_empty() {
+            return String::new();
+        }
+        let bytes = self.clone().encode();
+        match self.layers[0].decode(&bytes) {
+            Some((decoded, _)) => decoded.show_string(),
+            None => self.fill().show_string(),
+        }
+    }
+
+    pub fn show2(&self) {
+        print!("{}", self.show2_string());
+    }
+
+    pub fn hexdump_string(&self) -> String {
+        hexdump(&self.clone().encode())
+    }
+
+    pub fn hexdump(&self) {
+        print!("{}", self.hexdump_string());
+    }
+
     pub fn indices_of<T: Layer>(&self, typ: T) -> Vec<usize> {
         let mut out = vec![];
         for (i, ref layer) in (&self.layers).into_iter().enumerate() {
@@ -866,8 +2875,21 @@ pub trait Layer: Debug + mopa::Any + New {
     fn get_layer_type_id(&self) -> TypeId {
         self.type_id()
     }
+    fn layer_name(&self) -> &'static str {
+        let full_name = std::any::type_name::<Self>();
+        full_name.rsplit("::").next().unwrap_or(full_name)
+    }
     /* fill the unknown fields based on the entire stack contents */
     fn fill(&self, stack: &LayerStack, my_index: usize, out_stack: &mut LayerStack);
+    fn fill_with(
+        &self,
+        stack: &LayerStack,
+        my_index: usize,
+        _fill_ctx: &mut FillContext,
+        out_stack: &mut LayerStack,
+    ) {
+        self.fill(stack, my_index, out_stack)
+    }
 
     /* default encode function encodes some dead beef */
     fn encode(
@@ -879,6 +2901,140 @@ pub trait Layer: Debug + mopa::Any + New {
         vec![0xde, 0xad, 0xbe, 0xef]
     }
 
+    /* decode, recording the field spans into the map if there is one */
+    fn decode_into_map(
+        &self,
+        buf: &[u8],
+        map: Option<&mut DissectionMap>,
+    ) -> Option<(LayerStack, usize)> {
+        self.decode(buf)
+    }
+    /* decode with the state in the context, and the options in it for the next layers */
+    fn decode_into_map_with_context(
+        &self,
+        buf: &[u8],
+        map: Option<&mut DissectionMap>,
+        ctx: &mut DecodeContext,
+    ) -> Option<(LayerStack, usize)> {
+        self.decode_into_map(buf, map)
+    }
+    fn decode_with_context(
+        &self,
+        buf: &[u8],
+        ctx: &mut DecodeContext,
+    ) -> Option<(LayerStack, usize)> {
+        self.decode_into_map_with_context(buf, None, ctx)
+    }
+    /* decode, looking up the next layers with the overrides of the options */
+    fn decode_into_map_with_options(
+        &self,
+        buf: &[u8],
+        map: Option<&mut DissectionMap>,
+        options: &DecodeOptions,
+    ) -> Option<(LayerStack, usize)> {
+        let mut ctx = DecodeContext::with_options(options.clone());
+        self.decode_into_map_with_context(buf, map, &mut ctx)
+    }
+    fn decode_with_options(
+        &self,
+        buf: &[u8],
+        options: &DecodeOptions,
+    ) -> Option<(LayerStack, usize)> {
+        self.decode_into_map_with_options(buf, None, options)
+    }
+    fn decode_with_map(&self, buf: &[u8]) -> Option<(LayerStack, usize, DissectionMap)> {
+        let mut map = DissectionMap::new();
+        let (stack, len) = self.decode_into_map(buf, Some(&mut map))?;
+        Some((stack, len, map))
+    }
+    /* encode, recording the field spans relative to the start of this layer */
+    fn encode_into_map(
+        &self,
+        stack: &LayerStack,
+        my_index: usize,
+        encoded_layers: &EncodingVecVec,
+        map: Option<&mut DissectionMap>,
+    ) -> Vec<u8> {
+        self.encode(stack, my_index, encoded_layers)
+    }
+
+    /* one-line description of the layer, for LayerStack::summary() */
+    fn summary(&self) -> String {
+        self.layer_name().to_string()
+    }
+
+    /* the fields with their current values, for show() */
+    fn field_infos(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    /* the names, types and defaults of the fields */
+    fn field_descs(&self) -> Vec<FieldDesc> {
+        vec![]
+    }
+
+    fn get_field(&self, name: &str) -> Option<FieldValue> {
+        None
+    }
+
+    fn set_field_value(&mut self, name: &str, value: FieldValue) -> Result<(), FieldAccessError> {
+        Err(FieldAccessError::NoSuchField(name.to_string()))
+    }
+
+    /* set a field from its textual form, e.g. set_field("ttl", "32") */
+    fn set_field(&mut self, name: &str, value: &str) -> Result<(), FieldAccessError> {
+        self.set_field_value(name, FieldValue::Str(value.to_string()))
+    }
+
+    /* the fields holding a List or Range, with the number of values in each */
+    fn expand_counts(&self) -> Vec<(&'static str, u64)> {
+        vec![]
+    }
+
+    /* set a List or Range field to its nth value */
+    fn expand_field(&mut self, _name: &str, _n: u64) -> bool {
+        false
+    }
+
+    /* make all the fields which are not Auto Random */
+    fn fuzz_fields(&mut self) {}
+
+    /*
+     * Fix up the layer once it is in the encoding buffer, before the layers below it
+     * are encoded: e.g. write a checksum over the header and the payload in place.
+     */
+    fn patch_encoded(
+        &self,
+        stack: &LayerStack,
+        my_index: usize,
+        header: &mut [u8],
+        payload: &[u8],
+    ) {
+    }
+
+    /*
+     * The fields computed from the rest of the packet, lengths and checksums,
+     * for the templates of LayerStack::compile() to keep right.
+     */
+    fn dependent_fields(
+        &self,
+        stack: &LayerStack,
+        my_index: usize,
+        layout: &PacketLayout,
+    ) -> Vec<DependentField> {
+        vec![]
+    }
+
+    /* check the layer's own fields against the encoded layers that follow it */
+    fn verify(
+        &self,
+        stack: &LayerStack,
+        my_index: usize,
+        encoded_layers: &EncodingVecVec,
+    ) -> Vec<FieldMismatch> {
+        vec![]
+    }
+
     fn decode_as_raw(&self, buf: &[u8]) -> LayerStack {
         use crate::protocols::raw::*;
         let mut layers = vec![];
@@ -897,6 +3053,27 @@ pub trait Layer: Debug + mopa::Any + New {
         let buflen = buf.len();
         Some((self.decode_as_raw(buf), buflen))
     }
+    /* the padding left out by the layer owner_distance layers before it */
+    fn decode_as_padding(&self, buf: &[u8], owner_distance: usize) -> LayerStack {
+        use crate::protocols::padding::*;
+        let mut layers = vec![];
+        if !buf.is_empty() {
+            /* an owner too far back to count is unknown rather than some other layer */
+            let owner = match u8::try_from(owner_distance) {
+                Ok(distance) => Value::Set(distance),
+                Err(_) => Value::Auto,
+            };
+            let layer = padding {
+                owner,
+                data: buf.to_vec(),
+            };
+            layers.push(layer.embox());
+        }
+        LayerStack {
+            layers,
+            filled: true,
+        }
+    }
 }
 
 mopafy!(Layer);
@@ -918,8 +3095,17 @@ impl <'a> Eq for LayerStack<'a> {
 }
 */
 
+pub mod bpf;
+pub mod display_filter;
 pub mod encdec;
+pub mod flow;
+pub mod frag;
+pub mod fuzz;
+pub mod parse;
 pub mod protocols;
+pub mod tcp_stream;
+pub mod template;
+pub mod tshark;
 pub mod typ;
 
 pub fn update_inet_sum(sum: u32, data: &[u8]) -> u32 {
@@ -940,5 +3126,9 @@ pub fn get_inet_sum(data: &[u8]) -> u32 {
 }
 
 pub fn fold_u32(data: u32) -> u16 {
-    0xffff ^ (((data >> 16) as u16) + ((data & 0xffff) as u16))
+    let mut data = data;
+    while data >> 16 != 0 {
+        data = (data >> 16) + (data & 0xffff);
+    }
+    0xffff ^ (data as u16)
 }
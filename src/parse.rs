@@ -0,0 +1,277 @@
+/*
+ * Build a LayerStack from the scapy-like text, e.g.
+ * Ether(dst="ff:ff:ff:ff:ff:ff")/IP(dst="1.2.3.4",ttl=5)/UDP(dport=53)/Raw(load="abc")
+ *
+ * The layers are looked up by name in the LAYER_NAMES registry, and the fields are set
+ * with set_field_value(), so the values are given the same way as to set_field().
+ */
+
+use crate::*;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketParseError {
+    Syntax {
+        pos: usize,
+        expected: &'static str,
+    },
+    UnknownLayer {
+        pos: usize,
+        name: String,
+    },
+    UnknownField {
+        pos: usize,
+        layer: String,
+        field: String,
+        fields: Vec<&'static str>,
+    },
+    BadValue {
+        pos: usize,
+        layer: String,
+        field: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for PacketParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { pos, expected } => write!(f, "at offset {}: expected {}", pos, expected),
+            Self::UnknownLayer { pos, name } => write!(
+                f,
+                "at offset {}: unknown layer {:?} (known: {})",
+                pos,
+                name,
+                layer_names().join(", ")
+            ),
+            Self::UnknownField {
+                pos,
+                layer,
+                field,
+                fields,
+            } => write!(
+                f,
+                "at offset {}: {} has no field {:?} (fields: {})",
+                pos,
+                layer,
+                field,
+                fields.join(", ")
+            ),
+            Self::BadValue {
+                pos,
+                layer,
+                field,
+                value,
+            } => write!(
+                f,
+                "at offset {}: bad value {:?} for {}.{}",
+                pos, value, layer, field
+            ),
+        }
+    }
+}
+
+pub fn parse_packet(s: &str) -> Result<LayerStack, PacketParseError> {
+    let mut p = Parser { s, pos: 0 };
+    let mut layers = vec![p.layer()?];
+    loop {
+        p.skip_ws();
+        if p.eat('/') {
+            layers.push(p.layer()?);
+        } else if p.pos == s.len() {
+            break;
+        } else {
+            return Err(p.syntax("'/' or the end"));
+        }
+    }
+    Ok(LayerStack {
+        layers,
+        filled: false,
+    })
+}
+
+impl FromStr for LayerStack {
+    type Err = PacketParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_packet(s)
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn syntax(&self, expected: &'static str) -> PacketParseError {
+        PacketParseError::Syntax {
+            pos: self.pos,
+            expected,
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, PacketParseError> {
+        self.skip_ws();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.syntax("a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    /* Name or Name(field=value, ...) */
+    fn layer(&mut self) -> Result<Box<dyn Layer>, PacketParseError> {
+        self.skip_ws();
+        let name_pos = self.pos;
+        let name = self.ident()?;
+        let mut layer = make_layer_by_name(name).ok_or(PacketParseError::UnknownLayer {
+            pos: name_pos,
+            name: name.to_string(),
+        })?;
+        self.skip_ws();
+        if !self.eat('(') {
+            return Ok(layer);
+        }
+        loop {
+            self.skip_ws();
+            if self.eat(')') {
+                return Ok(layer);
+            }
+            let field_pos = self.pos;
+            let field = self.ident()?;
+            self.skip_ws();
+            if !self.eat('=') {
+                return Err(self.syntax("'='"));
+            }
+            self.skip_ws();
+            let value_pos = self.pos;
+            let value = self.value()?;
+            self.set_field(&mut layer, field, field_pos, value, value_pos)?;
+            self.skip_ws();
+            if !self.eat(',') && self.peek() != Some(')') {
+                return Err(self.syntax("',' or ')'"));
+            }
+        }
+    }
+
+    fn set_field(
+        &self,
+        layer: &mut Box<dyn Layer>,
+        field: &str,
+        field_pos: usize,
+        value: FieldValue,
+        value_pos: usize,
+    ) -> Result<(), PacketParseError> {
+        match layer.set_field_value(field, value) {
+            Ok(()) => Ok(()),
+            Err(FieldAccessError::BadValue { value, .. }) => Err(PacketParseError::BadValue {
+                pos: value_pos,
+                layer: layer.layer_name().to_string(),
+                field: field.to_string(),
+                value: match value {
+                    FieldValue::Str(s) => s,
+                    x => x.to_string(),
+                },
+            }),
+            Err(_) => Err(PacketParseError::UnknownField {
+                pos: field_pos,
+                layer: layer.layer_name().to_string(),
+                field: field.to_string(),
+                fields: layer.field_descs().iter().map(|d| d.name).collect(),
+            }),
+        }
+    }
+
+    /* "text", 'text', b"bytes" or a bare word such as 53, 0x35 or 10.0.0.1 */
+    fn value(&mut self) -> Result<FieldValue, PacketParseError> {
+        let rest = self.rest();
+        if rest.starts_with("b\"") || rest.starts_with("b'") {
+            self.pos += 1;
+            return Ok(FieldValue::Bytes(self.quoted()?));
+        }
+        if rest.starts_with('"') || rest.starts_with('\'') {
+            let start = self.pos;
+            let bytes = self.quoted()?;
+            return String::from_utf8(bytes).map(FieldValue::Str).map_err(|_| {
+                PacketParseError::Syntax {
+                    pos: start,
+                    expected: "a UTF-8 string, use b\"...\" for bytes",
+                }
+            });
+        }
+        let len = rest
+            .find(|c: char| c == ',' || c == ')' || c == '/' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.syntax("a value"));
+        }
+        self.pos += len;
+        Ok(FieldValue::Str(rest[..len].to_string()))
+    }
+
+    fn quoted(&mut self) -> Result<Vec<u8>, PacketParseError> {
+        let quote = self.peek().unwrap();
+        self.pos += 1;
+        let mut out = vec![];
+        loop {
+            let c = self.peek().ok_or(self.syntax("the closing quote"))?;
+            self.pos += c.len_utf8();
+            if c == quote {
+                return Ok(out);
+            }
+            if c != '\\' {
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+            let esc = self.peek().ok_or(self.syntax("an escape"))?;
+            self.pos += esc.len_utf8();
+            match esc {
+                'n' => out.push(b'\n'),
+                'r' => out.push(b'\r'),
+                't' => out.push(b'\t'),
+                '0' => out.push(0),
+                '\\' | '\'' | '"' => out.push(esc as u8),
+                'x' => {
+                    let hex = self.rest().get(..2).ok_or(self.syntax("two hex digits"))?;
+                    let b =
+                        u8::from_str_radix(hex, 16).map_err(|_| self.syntax("two hex digits"))?;
+                    self.pos += 2;
+                    out.push(b);
+                }
+                _ => {
+                    self.pos -= esc.len_utf8();
+                    return Err(self.syntax("one of \\n \\r \\t \\0 \\\\ \\' \\\" \\xHH"));
+                }
+            }
+        }
+    }
+}
@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
     FromStringHashmap, NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize,
 )]
 #[nproto(register(ETHERTYPE_LAYERS, Ethertype = 0x0806))]
+#[nproto(summary = arp_summary)]
 pub struct Arp {
     #[nproto(default = 1)]
     pub hwtype: Value<u16>,
@@ -99,22 +100,35 @@ impl<'de> Deserialize<'de> for ArpHardwareAddress {
         D: Deserializer<'de>,
     {
         use serde::de::Error;
+        use serde::de::SeqAccess;
         use serde::de::Visitor;
         struct ArpHAVisitor {}
         impl<'de> Visitor<'de> for ArpHAVisitor {
             type Value = ArpHardwareAddress;
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("ArpHardwareAddress")
+                formatter.write_str("a MAC address string or an array of bytes")
             }
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: Error,
             {
-                panic!("TBD")
+                MacAddr::from_str(v)
+                    .map(Self::Value::Ether)
+                    .map_err(|_| E::custom(format!("invalid MAC address {:?}", v)))
+            }
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut bytes = vec![];
+                while let Some(b) = seq.next_element::<u8>()? {
+                    bytes.push(b);
+                }
+                Ok(Self::Value::Bytes(bytes))
             }
         }
 
-        return Ok(deserializer.deserialize_str(ArpHAVisitor {})?);
+        return Ok(deserializer.deserialize_any(ArpHAVisitor {})?);
     }
 }
 
@@ -137,9 +151,7 @@ impl FromStr for ArpHardwareAddress {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match MacAddr::from_str(s) {
             Ok(res) => Ok(Self::Ether(res)),
-            Err(e) => {
-                panic!("Could not parse");
-            }
+            Err(_) => Err(ValueParseError::Error),
         }
     }
 }
@@ -190,22 +202,35 @@ impl<'de> Deserialize<'de> for ArpProtocolAddress {
         D: Deserializer<'de>,
     {
         use serde::de::Error;
+        use serde::de::SeqAccess;
         use serde::de::Visitor;
         struct ArpPAVisitor {}
         impl<'de> Visitor<'de> for ArpPAVisitor {
             type Value = ArpProtocolAddress;
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("ArpProtocolAddress")
+                formatter.write_str("a IPv4 address string or an array of bytes")
             }
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: Error,
             {
-                panic!("TBD")
+                Ipv4Address::from_str(v)
+                    .map(Self::Value::IP)
+                    .map_err(|_| E::custom(format!("invalid IPv4 address {:?}", v)))
+            }
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut bytes = vec![];
+                while let Some(b) = seq.next_element::<u8>()? {
+                    bytes.push(b);
+                }
+                Ok(Self::Value::Bytes(bytes))
             }
         }
 
-        return Ok(deserializer.deserialize_str(ArpPAVisitor {})?);
+        return Ok(deserializer.deserialize_any(ArpPAVisitor {})?);
     }
 }
 
@@ -232,9 +257,22 @@ impl FromStr for ArpProtocolAddress {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match Ipv4Address::from_str(s) {
             Ok(res) => Ok(Self::IP(res)),
-            Err(e) => {
-                panic!("Could not parse");
-            }
+            Err(_) => Err(ValueParseError::Error),
+        }
+    }
+}
+
+impl DynField for ArpHardwareAddress {
+    fn to_field_value(&self) -> FieldValue {
+        match self {
+            Self::Ether(mac) => FieldValue::Mac(mac.clone()),
+            Self::Bytes(v) => FieldValue::Bytes(v.clone()),
+        }
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Bytes(b) => Some(Self::Bytes(b.clone())),
+            x => MacAddr::from_field_value(x).map(Self::Ether),
         }
     }
 }
@@ -244,3 +282,34 @@ impl From<&str> for ArpProtocolAddress {
         Self::from_str(s).unwrap()
     }
 }
+
+impl DynField for ArpProtocolAddress {
+    fn to_field_value(&self) -> FieldValue {
+        match self {
+            Self::IP(ip) => FieldValue::Ipv4(ip.clone()),
+            Self::Bytes(v) => FieldValue::Bytes(v.clone()),
+        }
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Bytes(b) => Some(Self::Bytes(b.clone())),
+            x => Ipv4Address::from_field_value(x).map(Self::IP),
+        }
+    }
+}
+
+fn arp_summary(me: &Arp) -> String {
+    let paddr = |a: &Value<ArpProtocolAddress>| match a {
+        Value::Set(ArpProtocolAddress::IP(ip)) => format!("{:?}", ip),
+        x => format!("{:?}", x),
+    };
+    let hwaddr = |a: &Value<ArpHardwareAddress>| match a {
+        Value::Set(ArpHardwareAddress::Ether(mac)) => format!("{:?}", mac),
+        x => format!("{:?}", x),
+    };
+    match me.op.value() {
+        1 => format!("ARP who has {} says {}", paddr(&me.pdst), paddr(&me.psrc)),
+        2 => format!("ARP is at {} says {}", hwaddr(&me.hwsrc), paddr(&me.psrc)),
+        op => format!("ARP op {} {} > {}", op, paddr(&me.psrc), paddr(&me.pdst)),
+    }
+}
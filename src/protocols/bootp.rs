@@ -158,6 +158,8 @@ fn encode_dhcp_opts<E: Encoder>(
 #[nproto(register(UDP_SRC_PORT_APPS, SrcPort = 67))]
 #[nproto(register(UDP_DST_PORT_APPS, DstPort = 68))]
 #[nproto(register(UDP_SRC_PORT_APPS, SrcPort = 68))]
+#[nproto(summary = bootp_summary)]
+#[nproto(heuristic(UDP_HEURISTICS = probe_bootp))]
 pub struct Bootp {
     #[nproto(default = 0x01)] // "Request" by default
     pub op: Value<u8>,
@@ -216,6 +218,9 @@ impl FromStr for BootpVendorData {
     }
 }
 
+dyn_field_via_debug!(BootpVendorData, from_str);
+dyn_field_via_debug!(Vec<DhcpOption>);
+
 fn decode_vend<D: Decoder>(buf: &[u8], me: &mut Bootp) -> Option<(BootpVendorData, usize)> {
     let mut ci = 0;
     if me.cookie == Value::Set(DHCP_COOKIE_VAL) {
@@ -237,3 +242,23 @@ fn encode_vend<E: Encoder>(
     // FIXME
     vec![]
 }
+
+fn bootp_summary(me: &Bootp) -> String {
+    let op = match me.op.value() {
+        1 => "request".to_string(),
+        2 => "reply".to_string(),
+        x => format!("op {}", x),
+    };
+    format!("BOOTP {} xid {:#010x}", op, me.xid.value())
+}
+
+/* request or reply over Ethernet, certain with the DHCP magic cookie */
+fn probe_bootp(buf: &[u8]) -> u8 {
+    if buf.len() < 236 || !(1..=2).contains(&buf[0]) || buf[1] != 1 || buf[2] != 6 {
+        return 0;
+    }
+    match buf.get(236..240) {
+        Some(cookie) if cookie == DHCP_COOKIE_VAL.to_be_bytes() => 95,
+        _ => 50,
+    }
+}
@@ -5,12 +5,13 @@ use serde::{Deserialize, Serialize};
     FromStringHashmap, NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize,
 )]
 #[nproto(register(ETHERTYPE_LAYERS, Ethertype = 0x8100))]
+#[nproto(summary = dot1q_summary)]
 pub struct dot1Q {
-    #[nproto(default = 0, encode = Skip, decode = Skip)]
+    #[nproto(default = 0, encode = Skip, decode = Skip, bits(0, 3))]
     pub prio: Value<u8>,
-    #[nproto(default = 0, encode = Skip, decode = Skip)]
+    #[nproto(default = 0, encode = Skip, decode = Skip, bits(3, 1))]
     pub id: Value<u8>,
-    #[nproto(default = 1, encode = encode_dot1q_tci, decode = decode_dot1q_tci, fill = fill_tci_auto)]
+    #[nproto(default = 1, encode = encode_dot1q_tci, decode = decode_dot1q_tci, fill = fill_tci_auto, bits(4, 12))]
     pub vlan: Value<u16>,
     #[nproto(next: ETHERTYPE_LAYERS => Ethertype)]
     pub etype: Value<u16>,
@@ -43,3 +44,7 @@ fn decode_dot1q_tci<D: Decoder>(buf: &[u8], me: &mut dot1Q) -> Option<(u16, usiz
 fn fill_tci_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> Value<u8> {
     Value::Auto
 }
+
+fn dot1q_summary(me: &dot1Q) -> String {
+    format!("802.1Q vlan {:?}", &me.vlan)
+}
@@ -3,29 +3,30 @@ use serde::{Deserialize, Serialize};
 
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(ETHERTYPE_LAYERS, Ethertype = 0x88be))]
+#[nproto(summary = erspan_summary)]
 pub struct erspan {
     // encoded/decoded by the next field encoders
-    #[nproto(encode = Skip, decode = Skip)]
+    #[nproto(encode = Skip, decode = Skip, bits(0, 4))]
     pub version: Value<ErspanType>,
-    #[nproto(encode = encode_version_and_vlan, decode = decode_version_and_vlan)]
+    #[nproto(encode = encode_version_and_vlan, decode = decode_version_and_vlan, bits(4, 12))]
     pub vlan: Value<u16>,
 
-    #[nproto(encode = Skip, decode = Skip)]
+    #[nproto(encode = Skip, decode = Skip, bits(0, 3))]
     pub cos: Value<u8>,
     // 2 bit
-    #[nproto(encode = Skip, decode = Skip)]
+    #[nproto(encode = Skip, decode = Skip, bits(3, 2))]
     pub encap_type: Value<u8>,
-    #[nproto(encode = Skip, decode = Skip)]
+    #[nproto(encode = Skip, decode = Skip, bits(5, 1))]
     pub truncated: Value<bool>,
     // 10 bit
-    #[nproto(encode = encode_second_u16_fields, decode = decode_second_u16_fields)]
+    #[nproto(encode = encode_second_u16_fields, decode = decode_second_u16_fields, bits(6, 10))]
     pub session_id: Value<u16>,
     // 20 bit
     // encoded/decoded by the next decoder
-    #[nproto(encode = Skip, decode = Skip)]
+    #[nproto(encode = Skip, decode = Skip, bits(12, 20))]
     pub port_index: Value<u32>,
     // reserved value
-    #[nproto(encode = encode_u32_reserved1, decode = decode_u32_reserved1)]
+    #[nproto(encode = encode_u32_reserved1, decode = decode_u32_reserved1, bits(0, 12))]
     pub reserved1: Value<u32>,
 }
 
@@ -65,8 +66,25 @@ impl FromStr for ErspanType {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // FIXME: implement properly
-        Ok(ErspanType::Type1)
+        match s {
+            "Type1" => Ok(ErspanType::Type1),
+            "Type2" => Ok(ErspanType::Type2),
+            "Type3" => Ok(ErspanType::Type3),
+            x => x
+                .parse::<u8>()
+                .map(ErspanType::from)
+                .map_err(|_| format!("invalid ERSPAN type {:?}", x)),
+        }
+    }
+}
+
+impl DynField for ErspanType {
+    fn to_field_value(&self) -> FieldValue {
+        let v: u8 = self.clone().into();
+        FieldValue::UInt(v as u64)
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        u8::from_field_value(v).map(ErspanType::from)
     }
 }
 
@@ -142,3 +160,7 @@ fn encode_u32_reserved1<E: Encoder>(
         | u32::from(me.reserved1.value() & 0xfff) << 20;
     E::encode_u32(the_u32)
 }
+
+fn erspan_summary(me: &erspan) -> String {
+    format!("ERSPAN session {:?} vlan {:?}", &me.session_id, &me.vlan)
+}
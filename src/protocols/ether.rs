@@ -1,9 +1,13 @@
 use crate::*;
 use serde::{Deserialize, Serialize};
 
+/* minimum Ethernet frame length, without the FCS */
+pub const ETHER_MIN_LEN: usize = 60;
+
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(encoder(BinaryBigEndian))]
 #[nproto(register(ETHERTYPE_LAYERS, Ethertype = 0x6558))]
+#[nproto(summary = ether_summary)]
 pub struct ether {
     #[nproto(fill = fill_dmac, default = "01:02:03:04:05:06")]
     // #[nproto(default = "01:02:03:04:05:06")]
@@ -24,3 +28,7 @@ fn fill_dmac(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> MacAddr
 fn fill_crc(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> u32 {
     0x1234
 }
+
+fn ether_summary(_me: &ether) -> String {
+    "Ether".to_string()
+}
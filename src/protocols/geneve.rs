@@ -8,15 +8,18 @@ use serde::{Deserialize, Serialize};
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(UDP_DST_PORT_APPS, DstPort = 6081))]
 #[nproto(register(UDP_SRC_PORT_APPS, SrcPort = 6081))]
+#[nproto(summary = geneve_summary)]
+#[nproto(heuristic(UDP_HEURISTICS = probe_geneve))]
 pub struct Geneve {
     /* INIT = ver + optlen + o + c + rsvd (all zeros) */
     #[nproto(default = 0x0)]
     pub init: Value<u16>,
     #[nproto(next: ETHERTYPE_LAYERS => Ethertype)]
     pub protocol: Value<u16>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "reserved_u8_2" encoder/decoder
+    #[nproto(encode = Skip, decode = Skip, bits(0, 24))]
+    // encoded/decoded by "reserved_u8_2" encoder/decoder
     pub vni: Value<u32>, // u24
-    #[nproto(encode = encode_vni_and_ru82, decode = decode_vni_and_ru82)]
+    #[nproto(encode = encode_vni_and_ru82, decode = decode_vni_and_ru82, bits(24, 8))]
     pub reserved_u8_2: Value<u8>,
 }
 
@@ -40,10 +43,30 @@ fn encode_vni_and_ru82<E: Encoder>(
  * decode the vni and "reserved_u8_2".
  * It returns "u8" because it is formally decoding the "reserved_u8_2" field which is u8.
  */
+#[allow(clippy::extra_unused_type_parameters)]
 fn decode_vni_and_ru82<D: Decoder>(buf: &[u8], me: &mut Geneve) -> Option<(u8, usize)> {
     use crate::Value::Set;
-    let mut ci = 0;
-    let (the_u8, _) = D::decode_vec(buf, 4)?;
+    let the_u8 = buf.get(..4)?;
     me.vni = Set(((the_u8[0] as u32) << 16) | ((the_u8[1] as u32) << 8) | (the_u8[2] as u32));
     Some((the_u8[3], 4))
 }
+
+fn geneve_summary(me: &Geneve) -> String {
+    format!("Geneve vni {:?}", &me.vni)
+}
+
+/* version 0, the options within the payload, and a known protocol type */
+fn probe_geneve(buf: &[u8]) -> u8 {
+    if buf.len() < 8 || buf[0] >> 6 != 0 || buf[1] & 0x3f != 0 {
+        return 0;
+    }
+    let opt_len = (buf[0] & 0x3f) as usize * 4;
+    if 8 + opt_len > buf.len() {
+        return 0;
+    }
+    match u16::from_be_bytes([buf[2], buf[3]]) {
+        0x6558 => 80,
+        0x0800 | 0x86dd => 60,
+        _ => 0,
+    }
+}
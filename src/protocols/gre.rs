@@ -12,33 +12,45 @@ use serde::{Deserialize, Serialize};
 
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(IANA_LAYERS, Proto = 47))]
+#[nproto(verify = verify_gre, patch = patch_gre)]
+#[nproto(dependent_fields = gre_dependent_fields)]
+#[nproto(summary = gre_summary)]
 pub struct Gre {
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(0, 1))]
+    // encoded/decoded by "version" field decoder
     pub chksum_present: Value<bool>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(1, 1))]
+    // encoded/decoded by "version" field decoder
     pub routing_present: Value<bool>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(2, 1))]
+    // encoded/decoded by "version" field decoder
     pub key_present: Value<bool>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(3, 1))]
+    // encoded/decoded by "version" field decoder
     pub seqnum_present: Value<bool>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(4, 1))]
+    // encoded/decoded by "version" field decoder
     pub strict_source_route: Value<bool>,
     // 3 bits
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(5, 3))]
+    // encoded/decoded by "version" field decoder
     pub recursion_control: Value<u8>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(8, 1))]
+    // encoded/decoded by "version" field decoder
     pub acknum_present: Value<bool>,
     // 4 bits remaining after pptp header definition
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "version" field decoder
+    #[nproto(encode = Skip, decode = Skip, bits(9, 4))]
+    // encoded/decoded by "version" field decoder
     pub flags: Value<u8>,
     // 3 bits
     // This encoder/decoder takes care of all of the fields above.
-    #[nproto(encode = encode_first_u16_fields, decode = decode_first_u16_fields)]
+    #[nproto(encode = encode_first_u16_fields, decode = decode_first_u16_fields, bits(13, 3))]
     pub version: Value<u8>,
     #[nproto(next: ETHERTYPE_LAYERS => Ethertype)]
     pub proto: Value<u16>,
     // taken into account only if chksum_present is true
     #[nproto(skip_encdec_unless(layer.chksum_present.value()))]
+    #[nproto(encode = encode_gre_chksum, fill = fill_gre_chksum_auto)]
     pub chksum: Value<u16>,
     // both chksum and routing_offset places in the packet must be decoded
     // if either of the flags are set
@@ -104,3 +116,74 @@ fn decode_first_u16_fields<D: Decoder>(buf: &[u8], me: &mut Gre) -> Option<(u8,
     let version = (7 & (the_u16)) as u8;
     Some((version, 2))
 }
+
+fn fill_gre_chksum_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> Value<u16> {
+    Value::Auto
+}
+
+/* the checksum covers the GRE header and the payload */
+fn gre_chksum(me: &Gre, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> u16 {
+    let encoded_gre_header = me.clone().chksum(0).encode(stack, my_index, encoded_data);
+    let sum = get_inet_sum(&encoded_gre_header);
+    fold_u32(update_inet_sum(sum, encoded_data.payload(stack, my_index)))
+}
+
+fn encode_gre_chksum<E: Encoder>(
+    me: &Gre,
+    _stack: &LayerStack,
+    _my_index: usize,
+    _encoded_data: &EncodingVecVec,
+) -> Vec<u8> {
+    if !me.chksum.is_auto() {
+        return me.chksum.value().encode::<E>();
+    }
+    /* a placeholder, patch_gre() sums the header once it is in the buffer */
+    0u16.encode::<E>()
+}
+
+/* the checksum, when there is one, follows the flags and the protocol */
+fn patch_gre(me: &Gre, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+    if me.chksum_present.value() && me.chksum.is_auto() {
+        let sum = fold_u32(update_inet_sum(get_inet_sum(header), payload));
+        header[4..6].copy_from_slice(&sum.encode::<BinaryBigEndian>());
+    }
+}
+
+fn gre_dependent_fields(
+    me: &Gre,
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+) -> Vec<DependentField> {
+    if !me.chksum_present.value() || !me.chksum.is_auto() {
+        return vec![];
+    }
+    let header_and_payload = layout.layer(my_index).start..layout.payload(stack, my_index).end;
+    vec![DependentField::checksum("chksum", vec![header_and_payload])]
+}
+
+fn verify_gre(
+    me: &Gre,
+    stack: &LayerStack,
+    my_index: usize,
+    encoded_data: &EncodingVecVec,
+) -> Vec<FieldMismatch> {
+    let mut out = vec![];
+    if me.chksum_present.value() {
+        if let Value::Set(chksum) = me.chksum {
+            let expected = gre_chksum(me, stack, my_index, encoded_data);
+            out.extend(FieldMismatch::check(
+                me, my_index, "chksum", expected, chksum,
+            ));
+        }
+    }
+    out
+}
+
+fn gre_summary(me: &Gre) -> String {
+    let mut out = format!("GRE proto {:#06x}", me.proto.value());
+    if me.key_present.value() {
+        out.push_str(&format!(" key {:?}", &me.key));
+    }
+    out
+}
@@ -5,6 +5,9 @@ use serde::{Deserialize, Serialize};
     FromStringHashmap, NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize,
 )]
 #[nproto(register(IANA_LAYERS, Proto = 1))]
+#[nproto(verify = verify_icmp, patch = patch_icmp)]
+#[nproto(dependent_fields = icmp_dependent_fields)]
+#[nproto(summary = icmp_summary)]
 pub struct Icmp {
     #[nproto(next: ICMP_TYPES => Type)]
     pub typ: Value<u8>,
@@ -35,24 +38,74 @@ fn fill_icmp_chksum_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize)
     Value::Auto
 }
 
-fn encode_icmp_chksum<E: Encoder>(
+fn icmp_chksum(
     me: &Icmp,
     stack: &LayerStack,
     my_index: usize,
     encoded_data: &EncodingVecVec,
+) -> u16 {
+    let encoded_icmp_header = me.clone().chksum(0).encode(stack, my_index, encoded_data);
+    let sum = get_inet_sum(&encoded_icmp_header);
+    fold_u32(update_inet_sum(sum, encoded_data.payload(stack, my_index)))
+}
+
+fn encode_icmp_chksum<E: Encoder>(
+    me: &Icmp,
+    _stack: &LayerStack,
+    _my_index: usize,
+    _encoded_data: &EncodingVecVec,
 ) -> Vec<u8> {
-    use std::convert::TryInto;
     if !me.chksum.is_auto() {
         return me.chksum.value().encode::<E>();
     }
+    /* a placeholder, patch_icmp() sums the header once it is in the buffer */
+    0u16.encode::<E>()
+}
 
-    let encoded_icmp_header = if let Some(icmp) = stack.item_at(ICMP!(), my_index) {
-        icmp.clone().chksum(0).encode(stack, my_index, encoded_data)
-    } else {
-        vec![]
+fn patch_icmp(me: &Icmp, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+    if me.chksum.is_auto() {
+        let sum = fold_u32(update_inet_sum(get_inet_sum(header), payload));
+        header[2..4].copy_from_slice(&sum.encode::<BinaryBigEndian>());
+    }
+}
+
+fn icmp_dependent_fields(
+    me: &Icmp,
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+) -> Vec<DependentField> {
+    if !me.chksum.is_auto() {
+        return vec![];
+    }
+    let header_and_payload = layout.layer(my_index).start..layout.payload(stack, my_index).end;
+    vec![DependentField::checksum("chksum", vec![header_and_payload])]
+}
+
+fn verify_icmp(
+    me: &Icmp,
+    stack: &LayerStack,
+    my_index: usize,
+    encoded_data: &EncodingVecVec,
+) -> Vec<FieldMismatch> {
+    let mut out = vec![];
+    if let Value::Set(chksum) = me.chksum {
+        let expected = icmp_chksum(me, stack, my_index, encoded_data);
+        out.extend(FieldMismatch::check(
+            me, my_index, "chksum", expected, chksum,
+        ));
+    }
+    out
+}
+
+fn icmp_summary(me: &Icmp) -> String {
+    let typ = match me.typ {
+        Value::Set(0) => "echo-reply".to_string(),
+        Value::Set(3) => "dest-unreach".to_string(),
+        Value::Set(5) => "redirect".to_string(),
+        Value::Set(8) => "echo-request".to_string(),
+        Value::Set(11) => "time-exceeded".to_string(),
+        ref x => format!("type {:?}", x),
     };
-    // eprintln!("Encoded IP header: {:02x?}", &encoded_ip_header);
-    let sum = get_inet_sum(&encoded_icmp_header);
-    let sum = fold_u32(sum);
-    sum.encode::<E>()
+    format!("ICMP {} {:?}", typ, &me.code)
 }
@@ -6,10 +6,13 @@ use serde::Serialize;
 )]
 #[nproto(register(ETHERTYPE_LAYERS, Ethertype = 0x800))]
 #[nproto(register(IANA_LAYERS, Proto = 4))]
+#[nproto(verify = verify_ip, decode_len = ip_decode_len)]
+#[nproto(patch = patch_ip, dependent_fields = ip_dependent_fields)]
+#[nproto(summary = ip_summary)]
 pub struct Ip {
-    #[nproto(default = 4, encode = Skip, decode = Skip)]
+    #[nproto(default = 4, encode = Skip, decode = Skip, bits(0, 4))]
     pub version: Value<u8>,
-    #[nproto(encode = encode_ver_ihl, decode = decode_ver_ihl, fill = fill_ihl_auto)]
+    #[nproto(encode = encode_ver_ihl, decode = decode_ver_ihl, fill = fill_ihl_auto, bits(4, 4))]
     pub ihl: Value<u8>,
     pub tos: Value<u8>,
     #[nproto(encode = encode_ip_len, fill = fill_ip_len_auto)]
@@ -33,6 +36,25 @@ pub struct Ip {
     pub options: Vec<IpOption>,
 }
 
+/* flags and offset share the "frag" field; the offset is in 8-byte units */
+pub const IP_FLAG_DF: u16 = 0x4000;
+pub const IP_FLAG_MF: u16 = 0x2000;
+pub const IP_FRAG_OFFSET_MASK: u16 = 0x1fff;
+
+impl Ip {
+    /* payload byte offset of this fragment */
+    pub fn frag_offset(&self) -> usize {
+        ((self.frag.value() & IP_FRAG_OFFSET_MASK) as usize) * 8
+    }
+    pub fn more_fragments(&self) -> bool {
+        self.frag.value() & IP_FLAG_MF != 0
+    }
+    /* the payload is only a part of the original datagram */
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.frag_offset() > 0
+    }
+}
+
 use std::num::ParseIntError;
 
 #[derive(Clone, Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
@@ -65,6 +87,9 @@ impl FromStr for IpFlags {
     }
 }
 
+dyn_field_via_debug!(IpFlags, from_str);
+dyn_field_via_debug!(Vec<IpOption>);
+
 impl Distribution<IpFlags> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> IpFlags {
         IpFlags {}
@@ -120,42 +145,138 @@ fn fill_ip_chksum_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -
     Value::Auto
 }
 
+/* the declared length bounds the payload, the rest of the buffer is padding */
+fn ip_decode_len(me: &Ip) -> Option<usize> {
+    match me.len {
+        Value::Set(len) => Some(len as usize),
+        _ => None,
+    }
+}
+
+/* saturated, for a payload too long for the field */
+fn ip_len(me: &Ip, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> u16 {
+    let mut data_len: usize = 0;
+
+    for i in stack.payload_range(my_index) {
+        data_len += encoded_data[i].len();
+    }
+    data_len += 20; // IP HDR
+    u16::try_from(data_len).unwrap_or(u16::MAX)
+}
+
 fn encode_ip_len<E: Encoder>(
     me: &Ip,
     stack: &LayerStack,
     my_index: usize,
     encoded_data: &EncodingVecVec,
 ) -> Vec<u8> {
-    use std::convert::TryInto;
-    let mut data_len: usize = 0;
-
-    for i in my_index + 1..encoded_data.len() {
-        data_len += encoded_data[i].len();
+    if !me.len.is_auto() {
+        return me.len.value().encode::<E>();
     }
-    data_len += 20; // IP HDR
-    let len: u16 = data_len.try_into().unwrap();
+    ip_len(me, stack, my_index, encoded_data).encode::<E>()
+}
 
-    len.encode::<E>()
+fn ip_chksum(me: &Ip, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> u16 {
+    let encoded_ip_header = me.clone().chksum(0).encode(stack, my_index, encoded_data);
+    // eprintln!("Encoded IP header: {:02x?}", &encoded_ip_header);
+    let sum = get_inet_sum(&encoded_ip_header);
+    fold_u32(sum)
 }
 
 fn encode_ip_chksum<E: Encoder>(
     me: &Ip,
-    stack: &LayerStack,
-    my_index: usize,
-    encoded_data: &EncodingVecVec,
+    _stack: &LayerStack,
+    _my_index: usize,
+    _encoded_data: &EncodingVecVec,
 ) -> Vec<u8> {
-    use std::convert::TryInto;
     if !me.chksum.is_auto() {
         return me.chksum.value().encode::<E>();
     }
+    /* a placeholder, patch_ip() sums the header once it is in the buffer */
+    0u16.encode::<E>()
+}
 
-    let encoded_ip_header = if let Some(ip) = stack.item_at(IP!(), my_index) {
-        ip.clone().chksum(0).encode(stack, my_index, encoded_data)
-    } else {
-        vec![]
-    };
-    // eprintln!("Encoded IP header: {:02x?}", &encoded_ip_header);
-    let sum = get_inet_sum(&encoded_ip_header);
-    let sum = fold_u32(sum);
-    sum.encode::<E>()
+/* the header is in the buffer with a zero checksum, so sum it as it is */
+fn patch_ip(me: &Ip, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+    if me.chksum.is_auto() {
+        let sum = fold_u32(get_inet_sum(header));
+        header[10..12].copy_from_slice(&sum.encode::<BinaryBigEndian>());
+    }
+}
+
+/* the sum of the pseudo header of the UDP and TCP checksums, with the length given */
+pub fn pseudo_header_sum<E: Encoder>(ip: &Ip, total_len: u16) -> u32 {
+    let sum = get_inet_sum(&ip.src.value().encode::<E>());
+    let sum = update_inet_sum(sum, &ip.dst.value().encode::<E>());
+    let sum = update_inet_sum(sum, &((ip.proto.value() as u16).encode::<E>()));
+    update_inet_sum(sum, &total_len.encode::<E>())
+}
+
+fn ip_dependent_fields(
+    me: &Ip,
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+) -> Vec<DependentField> {
+    let mut out = vec![];
+    if me.len.is_auto() {
+        out.push(DependentField::length("len"));
+    }
+    if me.chksum.is_auto() {
+        out.push(DependentField::checksum(
+            "chksum",
+            vec![layout.layer(my_index)],
+        ));
+    }
+    out
+}
+
+/* the sums of the UDP and TCP checksums: the pseudo header, then the header and the payload */
+pub fn pseudo_header_sums(
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+    len_field: Option<&str>,
+) -> Vec<std::ops::Range<usize>> {
+    let mut sums = vec![];
+    if my_index > 0 && stack.item_at(IP!(), my_index - 1).is_some() {
+        sums.extend(layout.field(my_index - 1, "src"));
+        sums.extend(layout.field(my_index - 1, "dst"));
+        if let Some(len_field) = len_field {
+            sums.extend(layout.field(my_index, len_field));
+        }
+        sums.push(layout.layer(my_index).start..layout.payload(stack, my_index).end);
+    }
+    sums
+}
+
+fn verify_ip(
+    me: &Ip,
+    stack: &LayerStack,
+    my_index: usize,
+    encoded_data: &EncodingVecVec,
+) -> Vec<FieldMismatch> {
+    let mut out = vec![];
+    if let Value::Set(len) = me.len {
+        let expected = ip_len(me, stack, my_index, encoded_data);
+        out.extend(FieldMismatch::check(me, my_index, "len", expected, len));
+    }
+    if let Value::Set(chksum) = me.chksum {
+        let expected = ip_chksum(me, stack, my_index, encoded_data);
+        out.extend(FieldMismatch::check(
+            me, my_index, "chksum", expected, chksum,
+        ));
+    }
+    out
+}
+
+fn ip_summary(me: &Ip) -> String {
+    let mut out = format!("IP {:?} > {:?}", &me.src, &me.dst);
+    if me.is_fragment() {
+        out.push_str(&format!(" frag {}", me.frag_offset()));
+        if me.more_fragments() {
+            out.push('+');
+        }
+    }
+    out
 }
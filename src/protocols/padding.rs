@@ -0,0 +1,28 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+/*
+ * Bytes trailing the length declared by the enclosing protocol,
+ * e.g. Ethernet frames padded to the minimum size.
+ */
+#[derive(
+    FromStringHashmap, NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize,
+)]
+#[nproto(decode_suppress)]
+#[nproto(summary = padding_summary)]
+pub struct padding {
+    /*
+     * How many layers before this one is the layer whose length left it out, if known.
+     * It is relative: adding or removing layers between the two makes it point at another
+     * layer, so it has to be set again then, or set to Auto to end the payload of every
+     * layer before it.
+     */
+    #[nproto(encode = Skip, decode = Skip)]
+    pub owner: Value<u8>,
+    #[nproto(decode = Skip)]
+    pub data: Vec<u8>,
+}
+
+fn padding_summary(me: &padding) -> String {
+    format!("Padding {} bytes", me.data.len())
+}
@@ -1,4 +1,6 @@
 use crate::encdec::binary_little_endian::BinaryLittleEndian;
+use crate::protocols::ether::*;
+use crate::protocols::raw::*;
 use crate::*;
 use serde::{Deserialize, Serialize};
 
@@ -24,6 +26,68 @@ impl pcapFile {
     pub fn write(&self, fname: &str) -> Result<(), std::io::Error> {
         std::fs::write(fname, self.clone().to_stack().encode())
     }
+
+    /*
+     * Decode the Ethernet frames in the order of the capture, all with the same context,
+     * so what a protocol learnt from one packet is there for the packets after it.
+     * A frame which does not decode is kept as Raw.
+     */
+    pub fn decode_packets(&self, ctx: &mut DecodeContext) -> Vec<LayerStack> {
+        self.d
+            .packets
+            .iter()
+            .map(|p| match Ether!().decode_with_context(&p.data, ctx) {
+                Some((stack, _)) => stack,
+                None => Raw!().decode_as_raw(&p.data),
+            })
+            .collect()
+    }
+
+    /*
+     * The reverse of examples/pcap2json.rs: an array with the layers of each packet.
+     * A packet may also be given as {"ts_sec": .., "ts_usec": .., "layers": [..]}
+     * to keep its timestamp, otherwise the timestamp is zero. The Auto fields are filled.
+     */
+    pub fn from_json(json: &str) -> Result<pcapFile, serde_json::Error> {
+        use serde::de::Error;
+        /* by the shape of each packet, so the error is the one of its layers or fields */
+        let packets: Vec<serde_json::Value> = serde_json::from_str(json)?;
+        let mut out = PcapFile!();
+        for p in packets {
+            let (ts_sec, ts_usec, layers) = match p {
+                serde_json::Value::Array(_) => (0, 0, serde_json::from_value(p)?),
+                serde_json::Value::Object(_) => {
+                    let p: TimedJsonPacket = serde_json::from_value(p)?;
+                    (p.ts_sec, p.ts_usec, p.layers)
+                }
+                x => {
+                    return Err(serde_json::Error::custom(format!(
+                        "a packet is an array of layers or an object with them, not {}",
+                        x
+                    )))
+                }
+            };
+            let stack = LayerStack {
+                layers,
+                filled: false,
+            };
+            out.push(PcapPacket!(
+                ts_sec = ts_sec,
+                ts_usec = ts_usec,
+                data = stack.encode()
+            ));
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Deserialize)]
+struct TimedJsonPacket {
+    #[serde(default)]
+    ts_sec: u32,
+    #[serde(default)]
+    ts_usec: u32,
+    layers: Vec<Box<dyn Layer>>,
 }
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct pcapFileData {
@@ -56,6 +120,19 @@ pub struct pcapPacket {
     pub data: Vec<u8>, /* incl_len bytes worth of data */
 }
 
+impl pcapPacket {
+    /* capture time since the epoch */
+    pub fn timestamp(&self) -> std::time::Duration {
+        std::time::Duration::new(
+            self.ts_sec.value() as u64,
+            self.ts_usec.value().saturating_mul(1000),
+        )
+    }
+}
+
+dyn_field_via_debug!(pcapFileData);
+dyn_field_via_debug!(Vec<pcapPacket>);
+
 fn encode_data<E: Encoder>(
     me: &pcapFile,
     stack: &LayerStack,
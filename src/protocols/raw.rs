@@ -5,8 +5,9 @@ use serde::{Deserialize, Serialize};
     FromStringHashmap, NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize,
 )]
 #[nproto(decode_suppress)]
+#[nproto(summary = raw_summary)]
 pub struct raw {
-    #[nproto(decode = Skip)]
+    #[nproto(decode = Skip, alias = "load")]
     pub data: Vec<u8>,
 }
 
@@ -30,3 +31,7 @@ impl Layer for String {
         self.as_bytes().to_owned()
     }
 }
+
+fn raw_summary(me: &raw) -> String {
+    format!("Raw {} bytes", me.data.len())
+}
@@ -4,6 +4,10 @@ use serde::Serialize;
 
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(IANA_LAYERS, Proto = 6))]
+#[nproto(verify = verify_tcp, patch = patch_tcp)]
+#[nproto(dependent_fields = tcp_dependent_fields)]
+#[nproto(summary = tcp_summary)]
+#[nproto(heuristics(TCP_HEURISTICS))]
 pub struct Tcp {
     #[nproto(fill = fill_tcp_sport)]
     pub sport: Value<u16>,
@@ -14,9 +18,9 @@ pub struct Tcp {
     #[nproto(default = 0)]
     pub ack: Value<u32>,
     // u4 really, encoded with "reserved"
-    #[nproto(default = 5, encode = Skip, decode = Skip)]
+    #[nproto(default = 5, encode = Skip, decode = Skip, bits(0, 4))]
     pub dataofs: Value<u8>,
-    #[nproto(default = 0, encode = encode_tcp_reserved, decode = decode_tcp_reserved)]
+    #[nproto(default = 0, encode = encode_tcp_reserved, decode = decode_tcp_reserved, bits(4, 4))]
     pub reserved: Value<u8>,
     #[nproto(default = 2)] // syn
     pub flags: Value<u8>,
@@ -30,6 +34,13 @@ pub struct Tcp {
     // pub options: Vec<TcpOption>,
 }
 
+pub const TCP_FLAG_FIN: u8 = 0x01;
+pub const TCP_FLAG_SYN: u8 = 0x02;
+pub const TCP_FLAG_RST: u8 = 0x04;
+pub const TCP_FLAG_PSH: u8 = 0x08;
+pub const TCP_FLAG_ACK: u8 = 0x10;
+pub const TCP_FLAG_URG: u8 = 0x20;
+
 fn fill_tcp_sport(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> u16 {
     0xffff
 }
@@ -63,65 +74,128 @@ fn fill_tcp_chksum_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize)
     Value::Auto
 }
 
-fn encode_tcp_chksum<E: Encoder>(
+fn tcp_chksum<E: Encoder>(
     me: &Tcp,
     stack: &LayerStack,
     my_index: usize,
     encoded_data: &EncodingVecVec,
-) -> Vec<u8> {
-    use std::convert::TryInto;
-    if !me.chksum.is_auto() {
-        return me.chksum.value().encode::<E>();
-    }
-
-    let encoded_tcp_header = if let Some(tcp) = stack.item_at(TCP!(), my_index) {
-        tcp.clone().chksum(0).encode(stack, my_index, encoded_data)
-    } else {
-        vec![]
-    };
+) -> Option<u16> {
+    let encoded_tcp_header = me.clone().chksum(0).encode(stack, my_index, encoded_data);
     // println!("TCP HDR {}: {:02x?}", encoded_tcp_header.len(), &encoded_tcp_header);
 
-    let mut sum: u32 = 0;
     let mut data_len: usize = 0;
     // fixme
-    let mut tcp_hdr_len: u16 = 20;
+    let tcp_hdr_len: usize = 20;
 
-    for i in my_index + 1..encoded_data.len() {
+    for i in stack.payload_range(my_index) {
         data_len += encoded_data[i].len();
     }
 
-    let total_len: u16 = u16::try_from(data_len).unwrap() + tcp_hdr_len;
+    let total_len = u16::try_from(data_len + tcp_hdr_len).unwrap_or(u16::MAX);
 
     if my_index > 0 {
         if let Some(ip) = stack.item_at(IP!(), my_index - 1) {
-            let mut ph: Vec<u8> = vec![];
-            /*
-            ph.extend_from_slice(&ip.src.value().encode::<E>());
-            ph.extend_from_slice(&ip.dst.value().encode::<E>());
-            ph.extend_from_slice(&((ip.proto.value() as u16).encode::<E>()));
-            ph.extend_from_slice(&total_len.encode::<E>());
-            eprintln!("Pseudoheader: {:02x?}", &ph);
-            let sum = get_inet_sum(&ph);
-            */
-            let sum = get_inet_sum(&ip.src.value().encode::<E>());
-            let sum = update_inet_sum(sum, &ip.dst.value().encode::<E>());
-            let sum = update_inet_sum(sum, &((ip.proto.value() as u16).encode::<E>()));
-            let sum = update_inet_sum(sum, &total_len.encode::<E>());
-
-            let mut sum = update_inet_sum(sum, &encoded_tcp_header);
+            let sum = pseudo_header_sum::<E>(ip, total_len);
+            let sum = update_inet_sum(sum, &encoded_tcp_header);
             // eprintln!("CHECKSUM B4 data: {:04x}", sum);
-            for i in my_index + 1..encoded_data.len() {
-                sum = update_inet_sum(sum, &encoded_data[i]);
-            }
-            let sum = fold_u32(sum);
+            let sum = update_inet_sum(sum, encoded_data.payload(stack, my_index));
             // eprintln!("CHECKSUM: {:04x}", sum);
-            sum.encode::<E>()
+            Some(fold_u32(sum))
         } else {
-            vec![0xdd, 0xdd]
+            None
         }
     } else {
-        vec![0xee, 0xea]
+        None
+    }
+}
+
+fn encode_tcp_chksum<E: Encoder>(
+    me: &Tcp,
+    _stack: &LayerStack,
+    my_index: usize,
+    _encoded_data: &EncodingVecVec,
+) -> Vec<u8> {
+    if !me.chksum.is_auto() {
+        return me.chksum.value().encode::<E>();
+    }
+    if my_index == 0 {
+        return vec![0xee, 0xea];
+    }
+    /* a placeholder, patch_tcp() sums the header once it is in the buffer */
+    0u16.encode::<E>()
+}
+
+fn patch_tcp(me: &Tcp, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+    if !me.chksum.is_auto() || my_index == 0 {
+        return;
+    }
+    let total_len = u16::try_from(header.len() + payload.len()).unwrap_or(u16::MAX);
+    let sum = match stack.item_at(IP!(), my_index - 1) {
+        Some(ip) => {
+            let sum = pseudo_header_sum::<BinaryBigEndian>(ip, total_len);
+            let sum = update_inet_sum(sum, header);
+            fold_u32(update_inet_sum(sum, payload))
+        }
+        None => 0xdddd,
+    };
+    header[16..18].copy_from_slice(&sum.encode::<BinaryBigEndian>());
+}
+
+/* the length in the pseudo header does not change with the values of the fields */
+fn tcp_dependent_fields(
+    me: &Tcp,
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+) -> Vec<DependentField> {
+    if !me.chksum.is_auto() {
+        return vec![];
+    }
+    let sums = pseudo_header_sums(stack, my_index, layout, None);
+    vec![DependentField::checksum("chksum", sums)]
+}
+
+fn verify_tcp(
+    me: &Tcp,
+    stack: &LayerStack,
+    my_index: usize,
+    encoded_data: &EncodingVecVec,
+) -> Vec<FieldMismatch> {
+    let mut out = vec![];
+    if let Value::Set(chksum) = me.chksum {
+        if let Some(expected) = tcp_chksum::<BinaryBigEndian>(me, stack, my_index, encoded_data) {
+            out.extend(FieldMismatch::check(
+                me, my_index, "chksum", expected, chksum,
+            ));
+        }
     }
+    out
 }
 
 pub enum TcpOption {}
+
+/* flags as letters in the usual FSRPAU order, e.g. "SA" for SYN+ACK */
+pub fn tcp_flags_letters(flags: u8) -> String {
+    let letters = [
+        (TCP_FLAG_FIN, 'F'),
+        (TCP_FLAG_SYN, 'S'),
+        (TCP_FLAG_RST, 'R'),
+        (TCP_FLAG_PSH, 'P'),
+        (TCP_FLAG_ACK, 'A'),
+        (TCP_FLAG_URG, 'U'),
+    ];
+    letters
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, c)| *c)
+        .collect()
+}
+
+fn tcp_summary(me: &Tcp) -> String {
+    format!(
+        "TCP {:?} > {:?} {}",
+        &me.sport,
+        &me.dport,
+        tcp_flags_letters(me.flags.value())
+    )
+}
@@ -4,6 +4,10 @@ use serde::Serialize;
 
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(IANA_LAYERS, Proto = 17))]
+#[nproto(verify = verify_udp, decode_len = udp_decode_len)]
+#[nproto(patch = patch_udp, dependent_fields = udp_dependent_fields)]
+#[nproto(summary = udp_summary)]
+#[nproto(heuristics(UDP_HEURISTICS))]
 pub struct Udp {
     #[nproto(fill = fill_udp_sport)]
     #[nproto(next: UDP_SRC_PORT_APPS => SrcPort )]
@@ -21,88 +25,153 @@ fn fill_udp_len_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize) ->
     Value::Auto
 }
 
+/* the declared length bounds the payload, the rest of the buffer is padding */
+fn udp_decode_len(me: &Udp) -> Option<usize> {
+    match me.len {
+        Value::Set(len) => Some(len as usize),
+        _ => None,
+    }
+}
+
+/* saturated, for a payload too long for the field */
+fn udp_len(me: &Udp, stack: &LayerStack, my_index: usize, encoded_data: &EncodingVecVec) -> u16 {
+    let mut data_len: usize = 0;
+
+    for i in stack.payload_range(my_index) {
+        data_len += encoded_data[i].len();
+    }
+    data_len += 8; // UDP HDR
+    u16::try_from(data_len).unwrap_or(u16::MAX)
+}
+
 fn encode_udp_len<E: Encoder>(
     me: &Udp,
     stack: &LayerStack,
     my_index: usize,
     encoded_data: &EncodingVecVec,
 ) -> Vec<u8> {
-    use std::convert::TryInto;
-    let mut data_len: usize = 0;
-
-    for i in my_index + 1..encoded_data.len() {
-        data_len += encoded_data[i].len();
+    if !me.len.is_auto() {
+        return me.len.value().encode::<E>();
     }
-    data_len += 8; // UDP HDR
-    let len: u16 = data_len.try_into().unwrap();
-
-    len.encode::<E>()
+    udp_len(me, stack, my_index, encoded_data).encode::<E>()
 }
 
 fn fill_udp_chksum_auto(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> Value<u16> {
     Value::Auto
 }
 
-fn encode_udp_chksum<E: Encoder>(
+fn udp_chksum<E: Encoder>(
     me: &Udp,
     stack: &LayerStack,
     my_index: usize,
     encoded_data: &EncodingVecVec,
+) -> Option<u16> {
+    let encoded_udp_header = me.clone().chksum(0).encode(stack, my_index, encoded_data);
+    // the length in the pseudoheader is the one in the UDP header, whether computed or set
+    let total_len: u16 = if me.len.is_auto() {
+        udp_len(me, stack, my_index, encoded_data)
+    } else {
+        me.len.value()
+    };
+
+    if my_index > 0 {
+        if let Some(ip) = stack.item_at(IP!(), my_index - 1) {
+            let sum = pseudo_header_sum::<E>(ip, total_len);
+            let sum = update_inet_sum(sum, &encoded_udp_header);
+            // eprintln!("CHECKSUM B4 data: {:04x}", sum);
+            let sum = update_inet_sum(sum, encoded_data.payload(stack, my_index));
+            // eprintln!("CHECKSUM: {:04x}", sum);
+            Some(fold_u32(sum))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+fn encode_udp_chksum<E: Encoder>(
+    me: &Udp,
+    _stack: &LayerStack,
+    my_index: usize,
+    _encoded_data: &EncodingVecVec,
 ) -> Vec<u8> {
-    use std::convert::TryInto;
     if !me.chksum.is_auto() {
         return me.chksum.value().encode::<E>();
     }
+    if my_index == 0 {
+        return vec![0xee, 0xea];
+    }
+    /* a placeholder, patch_udp() sums the header once it is in the buffer */
+    0u16.encode::<E>()
+}
 
-    let encoded_udp_header = if let Some(udp) = stack.item_at(UDP!(), my_index) {
-        udp.clone().chksum(0).encode(stack, my_index, encoded_data)
+fn patch_udp(me: &Udp, stack: &LayerStack, my_index: usize, header: &mut [u8], payload: &[u8]) {
+    if !me.chksum.is_auto() || my_index == 0 {
+        return;
+    }
+    let total_len: u16 = if me.len.is_auto() {
+        u16::try_from(header.len() + payload.len()).unwrap_or(u16::MAX)
     } else {
-        vec![]
+        me.len.value()
     };
+    let sum = match stack.item_at(IP!(), my_index - 1) {
+        Some(ip) => {
+            let sum = pseudo_header_sum::<BinaryBigEndian>(ip, total_len);
+            let sum = update_inet_sum(sum, header);
+            fold_u32(update_inet_sum(sum, payload))
+        }
+        None => 0xdddd,
+    };
+    header[6..8].copy_from_slice(&sum.encode::<BinaryBigEndian>());
+}
 
-    let mut sum: u32 = 0;
-    let mut data_len: usize = 0;
-    // fixme
-    let mut udp_hdr_len: u16 = 8;
-
-    for i in my_index + 1..encoded_data.len() {
-        data_len += encoded_data[i].len();
+fn udp_dependent_fields(
+    me: &Udp,
+    stack: &LayerStack,
+    my_index: usize,
+    layout: &PacketLayout,
+) -> Vec<DependentField> {
+    let mut out = vec![];
+    if me.len.is_auto() {
+        out.push(DependentField::length("len"));
     }
+    if me.chksum.is_auto() {
+        let sums = pseudo_header_sums(stack, my_index, layout, Some("len"));
+        out.push(DependentField::checksum("chksum", sums));
+    }
+    out
+}
 
-    let total_len: u16 = u16::try_from(data_len).unwrap() + udp_hdr_len;
-
-    if my_index > 0 {
-        if let Some(ip) = stack.item_at(IP!(), my_index - 1) {
-            let mut ph: Vec<u8> = vec![];
-            /*
-            ph.extend_from_slice(&ip.src.value().encode::<E>());
-            ph.extend_from_slice(&ip.dst.value().encode::<E>());
-            ph.extend_from_slice(&((ip.proto.value() as u16).encode::<E>()));
-            ph.extend_from_slice(&total_len.encode::<E>());
-            eprintln!("Pseudoheader: {:02x?}", &ph);
-            let sum = get_inet_sum(&ph);
-            */
-            let sum = get_inet_sum(&ip.src.value().encode::<E>());
-            let sum = update_inet_sum(sum, &ip.dst.value().encode::<E>());
-            let sum = update_inet_sum(sum, &((ip.proto.value() as u16).encode::<E>()));
-            let sum = update_inet_sum(sum, &total_len.encode::<E>());
-
-            let mut sum = update_inet_sum(sum, &encoded_udp_header);
-            // eprintln!("CHECKSUM B4 data: {:04x}", sum);
-            for i in my_index + 1..encoded_data.len() {
-                sum = update_inet_sum(sum, &encoded_data[i]);
+fn verify_udp(
+    me: &Udp,
+    stack: &LayerStack,
+    my_index: usize,
+    encoded_data: &EncodingVecVec,
+) -> Vec<FieldMismatch> {
+    let mut out = vec![];
+    if let Value::Set(len) = me.len {
+        let expected = udp_len(me, stack, my_index, encoded_data);
+        out.extend(FieldMismatch::check(me, my_index, "len", expected, len));
+    }
+    // zero checksum means "not computed" for UDP over IPv4
+    if let Value::Set(chksum) = me.chksum {
+        if chksum != 0 {
+            if let Some(expected) = udp_chksum::<BinaryBigEndian>(me, stack, my_index, encoded_data)
+            {
+                out.extend(FieldMismatch::check(
+                    me, my_index, "chksum", expected, chksum,
+                ));
             }
-            let sum = fold_u32(sum);
-            // eprintln!("CHECKSUM: {:04x}", sum);
-            sum.encode::<E>()
-        } else {
-            vec![0xdd, 0xdd]
         }
-    } else {
-        vec![0xee, 0xea]
     }
+    out
 }
 
 fn fill_udp_sport(layer: &dyn Layer, stack: &LayerStack, my_index: usize) -> u16 {
     0xffff
 }
+
+fn udp_summary(me: &Udp) -> String {
+    format!("UDP {:?} > {:?}", &me.sport, &me.dport)
+}
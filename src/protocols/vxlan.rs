@@ -7,14 +7,17 @@ use serde::{Deserialize, Serialize};
 #[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[nproto(register(UDP_DST_PORT_APPS, DstPort = 4789))]
 #[nproto(register(UDP_SRC_PORT_APPS, SrcPort = 4789))]
+#[nproto(summary = vxlan_summary)]
+#[nproto(heuristic(UDP_HEURISTICS = probe_vxlan))]
 pub struct Vxlan {
     #[nproto(default = 0x08)] // just set the "I" bit
     pub flags: Value<u8>,
     pub reserved_u8: Value<u8>,
     pub reserved_u16: Value<u16>,
-    #[nproto(encode = Skip, decode = Skip)] // encoded/decoded by "reserved_u8_2" encoder/decoder
+    #[nproto(encode = Skip, decode = Skip, bits(0, 24))]
+    // encoded/decoded by "reserved_u8_2" encoder/decoder
     pub vni: Value<u32>, // u24
-    #[nproto(encode = encode_vni_and_ru82, decode = decode_vni_and_ru82)]
+    #[nproto(encode = encode_vni_and_ru82, decode = decode_vni_and_ru82, bits(24, 8))]
     pub reserved_u8_2: Value<u8>,
 }
 
@@ -38,10 +41,25 @@ fn encode_vni_and_ru82<E: Encoder>(
  * decode the vni and "reserved_u8_2".
  * It returns "u8" because it is formally decoding the "reserved_u8_2" field which is u8.
  */
+#[allow(clippy::extra_unused_type_parameters)]
 fn decode_vni_and_ru82<D: Decoder>(buf: &[u8], me: &mut Vxlan) -> Option<(u8, usize)> {
     use crate::Value::Set;
-    let mut ci = 0;
-    let (the_u8, _) = D::decode_vec(buf, 4)?;
+    let the_u8 = buf.get(..4)?;
     me.vni = Set(((the_u8[0] as u32) << 16) | ((the_u8[1] as u32) << 8) | (the_u8[2] as u32));
     Some((the_u8[3], 4))
 }
+
+fn vxlan_summary(me: &Vxlan) -> String {
+    format!("VXLAN vni {:?}", &me.vni)
+}
+
+/* the I flag alone and the reserved bytes zero; more so with an Ethernet header after it */
+fn probe_vxlan(buf: &[u8]) -> u8 {
+    if buf.len() < 8 || buf[0] != 0x08 || buf[1..4] != [0, 0, 0] || buf[7] != 0 {
+        return 0;
+    }
+    match buf.get(20..22) {
+        Some([0x08, 0x00]) | Some([0x86, 0xdd]) | Some([0x08, 0x06]) | Some([0x81, 0x00]) => 80,
+        _ => 40,
+    }
+}
@@ -0,0 +1,407 @@
+/*
+ * TCP stream reassembly: turns decoded segments into ordered byte streams
+ */
+
+use crate::protocols::ether::*;
+use crate::protocols::ip::*;
+use crate::protocols::pcap_file::*;
+use crate::protocols::tcp::*;
+use crate::*;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TcpEndpoint {
+    pub addr: Ipv4Address,
+    pub port: u16,
+}
+
+impl fmt::Display for TcpEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}:{}", &self.addr, self.port)
+    }
+}
+
+/* The client is the side that sent the SYN, or the first one seen mid-stream */
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TcpFlowKey {
+    pub client: TcpEndpoint,
+    pub server: TcpEndpoint,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TcpDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpStreamState {
+    SynSent,
+    SynReceived,
+    Established,
+    /* one side has sent FIN */
+    Closing,
+    Closed,
+    Reset,
+}
+
+/* One direction of a connection */
+#[derive(Clone, Debug, Default)]
+pub struct TcpHalf {
+    /* sequence number of the first payload byte */
+    pub base_seq: Option<u32>,
+    /* bytes delivered so far, i.e. the relative offset of the next expected byte */
+    pub delivered: u64,
+    pub fin_seen: bool,
+    pub fin_delivered: bool,
+    pub last_ack: Option<u32>,
+    pub segments: usize,
+    pub retransmitted_bytes: usize,
+    pub out_of_order_segments: usize,
+    pub overlapping_bytes: usize,
+    fin_offset: Option<u64>,
+    pending: BTreeMap<u64, Vec<u8>>,
+}
+
+impl TcpHalf {
+    /* bytes waiting for a gap to be filled */
+    pub fn pending_bytes(&self) -> usize {
+        self.pending.values().map(|v| v.len()).sum()
+    }
+
+    fn relative(&self, seq: u32) -> i64 {
+        let base = self.base_seq.unwrap();
+        seq.wrapping_sub(base) as i32 as i64
+    }
+
+    /* take a segment, return the bytes that became deliverable in order */
+    fn add_segment(&mut self, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        self.segments += 1;
+        if flags & TCP_FLAG_SYN != 0 {
+            if self.base_seq.is_none() {
+                self.base_seq = Some(seq.wrapping_add(1));
+            }
+            return vec![];
+        }
+        if self.base_seq.is_none() {
+            /* picked up mid-stream */
+            self.base_seq = Some(seq);
+        }
+        let rel = self.relative(seq);
+        if flags & TCP_FLAG_FIN != 0 && self.fin_offset.is_none() {
+            self.fin_seen = true;
+            self.fin_offset = Some((rel + payload.len() as i64).max(0) as u64);
+        }
+
+        let mut out = vec![];
+        let end = rel + payload.len() as i64;
+        if end <= self.delivered as i64 {
+            self.retransmitted_bytes += payload.len();
+        } else if rel <= self.delivered as i64 {
+            let skip = (self.delivered as i64 - rel) as usize;
+            self.overlapping_bytes += skip;
+            out.extend_from_slice(&payload[skip..]);
+            self.delivered += (payload.len() - skip) as u64;
+        } else {
+            self.out_of_order_segments += 1;
+            let entry = self.pending.entry(rel as u64).or_default();
+            /* the first copy of the data wins, a longer copy only extends it */
+            if payload.len() > entry.len() {
+                let old_len = entry.len();
+                entry.extend_from_slice(&payload[old_len..]);
+            }
+        }
+
+        /* fill in from the out of order segments that now fit */
+        while let Some((&start, _)) = self.pending.iter().next() {
+            if start > self.delivered {
+                break;
+            }
+            let data = self.pending.remove(&start).unwrap();
+            let skip = (self.delivered - start) as usize;
+            if skip < data.len() {
+                self.overlapping_bytes += skip;
+                out.extend_from_slice(&data[skip..]);
+                self.delivered += (data.len() - skip) as u64;
+            } else {
+                self.retransmitted_bytes += data.len();
+            }
+        }
+        if let Some(fin_offset) = self.fin_offset {
+            if self.delivered >= fin_offset {
+                self.fin_delivered = true;
+            }
+        }
+        out
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpStream {
+    pub key: TcpFlowKey,
+    pub state: TcpStreamState,
+    pub client: TcpHalf,
+    pub server: TcpHalf,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    /* the reassembled data of both directions, in the order it was delivered */
+    chunks: Vec<(TcpDirection, Vec<u8>)>,
+}
+
+impl TcpStream {
+    fn new(key: TcpFlowKey, now: Duration) -> Self {
+        TcpStream {
+            key,
+            state: TcpStreamState::Established,
+            client: Default::default(),
+            server: Default::default(),
+            first_seen: now,
+            last_seen: now,
+            chunks: vec![],
+        }
+    }
+
+    pub fn half(&self, dir: TcpDirection) -> &TcpHalf {
+        match dir {
+            TcpDirection::ClientToServer => &self.client,
+            TcpDirection::ServerToClient => &self.server,
+        }
+    }
+
+    fn half_mut(&mut self, dir: TcpDirection) -> &mut TcpHalf {
+        match dir {
+            TcpDirection::ClientToServer => &mut self.client,
+            TcpDirection::ServerToClient => &mut self.server,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state == TcpStreamState::Closed || self.state == TcpStreamState::Reset
+    }
+
+    /* all the reassembled bytes sent in one direction */
+    pub fn data(&self, dir: TcpDirection) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|(d, _)| *d == dir)
+            .flat_map(|(_, data)| data.iter().cloned())
+            .collect()
+    }
+
+    /* the conversation as alternating chunks, like "follow TCP stream" */
+    pub fn follow(&self) -> &[(TcpDirection, Vec<u8>)] {
+        &self.chunks
+    }
+
+    /*
+     * Text rendering of the conversation: server data is indented by a tab,
+     * non-printable bytes are shown as dots.
+     */
+    pub fn follow_text(&self) -> String {
+        let sep = "=".repeat(67);
+        let mut out = format!(
+            "{}\nFollow: tcp,ascii\nNode 0: {}\nNode 1: {}\n",
+            &sep, &self.key.client, &self.key.server
+        );
+        for (dir, data) in &self.chunks {
+            let indent = if *dir == TcpDirection::ServerToClient {
+                "\t"
+            } else {
+                ""
+            };
+            out.push_str(&format!("{}{}\n", indent, data.len()));
+            let text: String = data
+                .iter()
+                .map(|&b| {
+                    if b == b'\n' || (0x20..0x7f).contains(&b) {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            out.push_str(&text);
+            out.push('\n');
+        }
+        out.push_str(&sep);
+        out.push('\n');
+        out
+    }
+
+    fn push_chunk(&mut self, dir: TcpDirection, data: Vec<u8>) {
+        if let Some((last_dir, last_data)) = self.chunks.last_mut() {
+            if *last_dir == dir {
+                last_data.extend_from_slice(&data);
+                return;
+            }
+        }
+        self.chunks.push((dir, data));
+    }
+}
+
+type TcpDataCallback = Box<dyn FnMut(&TcpFlowKey, TcpDirection, &[u8])>;
+type TcpCloseCallback = Box<dyn FnMut(&TcpStream)>;
+
+/*
+ * Tracks TCP connections by their endpoints and reassembles the payload
+ * of each direction.
+ */
+#[derive(Default)]
+pub struct TcpReassembler {
+    streams: HashMap<TcpFlowKey, TcpStream>,
+    /* connections whose key has been reused by a new SYN */
+    finished: Vec<TcpStream>,
+    on_data: Option<TcpDataCallback>,
+    on_close: Option<TcpCloseCallback>,
+}
+
+impl TcpReassembler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /* called with each run of newly in-order bytes */
+    pub fn on_data<F: FnMut(&TcpFlowKey, TcpDirection, &[u8]) + 'static>(&mut self, f: F) {
+        self.on_data = Some(Box::new(f));
+    }
+
+    /* called once both sides have finished, or on reset */
+    pub fn on_close<F: FnMut(&TcpStream) + 'static>(&mut self, f: F) {
+        self.on_close = Some(Box::new(f));
+    }
+
+    pub fn stream(&self, key: &TcpFlowKey) -> Option<&TcpStream> {
+        self.streams.get(key)
+    }
+
+    /* current streams, ordered by the time they were first seen */
+    pub fn streams(&self) -> Vec<&TcpStream> {
+        let mut out: Vec<&TcpStream> = self.finished.iter().collect();
+        out.extend(self.streams.values());
+        out.sort_by_key(|s| s.first_seen);
+        out
+    }
+
+    /* decode the packet as Ethernet and feed it */
+    pub fn push_packet(&mut self, pkt: &pcapPacket) {
+        if let Some((stack, _)) = Ether!().decode(&pkt.data) {
+            self.push(&stack, pkt.timestamp());
+        }
+    }
+
+    /* feed a decoded stack; anything without Ip and Tcp is ignored */
+    pub fn push(&mut self, stack: &LayerStack, now: Duration) {
+        let tcp_index = match stack.indices_of(TCP!()).first() {
+            Some(&i) if i > 0 => i,
+            _ => return,
+        };
+        let ip = match stack.item_at(IP!(), tcp_index - 1) {
+            Some(ip) => ip,
+            None => return,
+        };
+        let tcp = stack.item_at(TCP!(), tcp_index).unwrap();
+        let payload = tcp_payload(stack, tcp_index);
+        let flags = tcp.flags.value();
+
+        let src = TcpEndpoint {
+            addr: ip.src.value(),
+            port: tcp.sport.value(),
+        };
+        let dst = TcpEndpoint {
+            addr: ip.dst.value(),
+            port: tcp.dport.value(),
+        };
+        let fwd = TcpFlowKey {
+            client: src.clone(),
+            server: dst.clone(),
+        };
+        let rev = TcpFlowKey {
+            client: dst,
+            server: src,
+        };
+        let syn_only = flags & (TCP_FLAG_SYN | TCP_FLAG_ACK) == TCP_FLAG_SYN;
+
+        /* a fresh SYN on a finished connection starts a new one */
+        if syn_only {
+            if let Some(old) = self.streams.get(&fwd) {
+                if old.is_closed() {
+                    let old = self.streams.remove(&fwd).unwrap();
+                    self.finished.push(old);
+                }
+            }
+        }
+
+        let (key, dir) = if self.streams.contains_key(&fwd) {
+            (fwd, TcpDirection::ClientToServer)
+        } else if self.streams.contains_key(&rev) {
+            (rev, TcpDirection::ServerToClient)
+        } else if flags & (TCP_FLAG_SYN | TCP_FLAG_ACK) == TCP_FLAG_SYN | TCP_FLAG_ACK {
+            /* the SYN was missed, the sender of SYN+ACK is the server */
+            let mut stream = TcpStream::new(rev.clone(), now);
+            stream.state = TcpStreamState::SynReceived;
+            self.streams.insert(rev.clone(), stream);
+            (rev, TcpDirection::ServerToClient)
+        } else {
+            let mut stream = TcpStream::new(fwd.clone(), now);
+            if syn_only {
+                stream.state = TcpStreamState::SynSent;
+            }
+            self.streams.insert(fwd.clone(), stream);
+            (fwd, TcpDirection::ClientToServer)
+        };
+
+        let stream = self.streams.get_mut(&key).unwrap();
+        stream.last_seen = now;
+        if stream.is_closed() {
+            return;
+        }
+        let half = stream.half_mut(dir);
+        if flags & TCP_FLAG_ACK != 0 {
+            half.last_ack = Some(tcp.ack.value());
+        }
+        let data = half.add_segment(tcp.seq.value(), flags, &payload);
+
+        if flags & TCP_FLAG_RST != 0 {
+            stream.state = TcpStreamState::Reset;
+        } else if flags & TCP_FLAG_SYN != 0 {
+            if flags & TCP_FLAG_ACK != 0 {
+                stream.state = TcpStreamState::SynReceived;
+            }
+        } else if stream.client.fin_delivered && stream.server.fin_delivered {
+            stream.state = TcpStreamState::Closed;
+        } else if stream.client.fin_seen || stream.server.fin_seen {
+            stream.state = TcpStreamState::Closing;
+        } else {
+            stream.state = TcpStreamState::Established;
+        }
+
+        if !data.is_empty() {
+            if let Some(cb) = self.on_data.as_mut() {
+                cb(&key, dir, &data);
+            }
+            stream.push_chunk(dir, data);
+        }
+        if stream.is_closed() {
+            if let Some(cb) = self.on_close.as_mut() {
+                cb(stream);
+            }
+        }
+    }
+}
+
+/* the bytes after the TCP header, options excluded, padding excluded */
+fn tcp_payload(stack: &LayerStack, tcp_index: usize) -> Vec<u8> {
+    let tcp = stack.item_at(TCP!(), tcp_index).unwrap();
+    let end = stack.payload_range(tcp_index).end;
+    let sub = LayerStack {
+        layers: stack.layers[tcp_index..end].to_vec(),
+        filled: true,
+    };
+    let bytes = sub.encode();
+    let hdr_len = ((tcp.dataofs.value() as usize) * 4)
+        .max(20)
+        .min(bytes.len());
+    bytes[hdr_len..].to_vec()
+}
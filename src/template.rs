@@ -0,0 +1,329 @@
+/*
+ * Packet templates: a stack encoded once, with the byte positions of its fields,
+ * made again with a few fields changed without filling and encoding it again.
+ * The checksums over the changed bytes are updated incrementally (RFC 1624),
+ * and so are the checksums over those checksums, e.g. of GRE over an inner UDP.
+ */
+
+use crate::*;
+use std::cmp::{max, min};
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    NoSuchField(String),
+    /* a length or a checksum, which the template keeps right itself */
+    ComputedField(String),
+    /* wider than 64 bits, or not encoded at all */
+    UnsupportedField(String),
+    ValueTooLarge {
+        field: String,
+        value: u64,
+    },
+    BufferTooSmall {
+        needed: usize,
+        available: usize,
+    },
+    /* a slot past the end of the packet, e.g. of another template */
+    SlotOutOfRange {
+        field: String,
+        offset: usize,
+        length: usize,
+    },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NoSuchField(name) => write!(f, "no field {}", name),
+            TemplateError::ComputedField(name) => {
+                write!(f, "{} is computed from the rest of the packet", name)
+            }
+            TemplateError::UnsupportedField(name) => {
+                write!(f, "{} can not be set in a template", name)
+            }
+            TemplateError::ValueTooLarge { field, value } => {
+                write!(f, "{} does not fit in {}", value, field)
+            }
+            TemplateError::BufferTooSmall { needed, available } => write!(
+                f,
+                "the packet needs {} bytes, the buffer has {}",
+                needed, available
+            ),
+            TemplateError::SlotOutOfRange {
+                field,
+                offset,
+                length,
+            } => write!(
+                f,
+                "{} at {}..{} is not in the packet",
+                field,
+                offset,
+                offset + length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/* where a field is in the packet; only valid with the template it came from */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TemplateSlot {
+    pub layer_index: usize,
+    pub field: &'static str,
+    pub offset: usize,
+    pub length: usize,
+    pub bits: Option<(usize, usize)>,
+}
+
+impl TemplateSlot {
+    fn width(&self) -> usize {
+        self.bits.map(|(_, width)| width).unwrap_or(self.length * 8)
+    }
+}
+
+/* the fields are given to render() by their path, e.g. "UDP.sport", or by their slot */
+pub trait TemplateKey {
+    fn slot(&self, template: &PacketTemplate) -> Result<TemplateSlot, TemplateError>;
+}
+
+impl TemplateKey for &str {
+    fn slot(&self, template: &PacketTemplate) -> Result<TemplateSlot, TemplateError> {
+        template.slot(self)
+    }
+}
+
+impl TemplateKey for TemplateSlot {
+    fn slot(&self, template: &PacketTemplate) -> Result<TemplateSlot, TemplateError> {
+        template.check_slot(self)?;
+        Ok(*self)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TemplateChecksum {
+    at: usize,
+    sums: Vec<Range<usize>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PacketTemplate {
+    stack: LayerStack,
+    bytes: Vec<u8>,
+    layout: PacketLayout,
+    /* (layer index, field) of the lengths and checksums */
+    computed: Vec<(usize, &'static str)>,
+    checksums: Vec<TemplateChecksum>,
+}
+
+impl PacketTemplate {
+    pub fn new(stack: &LayerStack) -> PacketTemplate {
+        let stack = if stack.filled {
+            stack.clone()
+        } else {
+            stack.fill()
+        };
+        let (bytes, layout) = stack.clone().encode_with_layout();
+        let mut computed = vec![];
+        let mut checksums = vec![];
+        for (i, layer) in stack.layers.iter().enumerate() {
+            for dep in layer.dependent_fields(&stack, i, &layout) {
+                computed.push((i, dep.field));
+                if dep.sums.is_empty() {
+                    continue;
+                }
+                if let Some(span) = layout.field(i, dep.field).filter(|s| s.len() == 2) {
+                    checksums.push(TemplateChecksum {
+                        at: span.start,
+                        sums: dep.sums,
+                    });
+                }
+            }
+        }
+        PacketTemplate {
+            stack,
+            bytes,
+            layout,
+            computed,
+            checksums,
+        }
+    }
+
+    /* the packet as compiled */
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /* the field by its path, like LayerStack::get(), to render with it without the lookup */
+    pub fn slot(&self, path: &str) -> Result<TemplateSlot, TemplateError> {
+        let (idx, field) = self
+            .stack
+            .resolve_field_path(path)
+            .map_err(|_| TemplateError::NoSuchField(path.to_string()))?;
+        if self.computed.iter().any(|&(i, f)| i == idx && f == field) {
+            return Err(TemplateError::ComputedField(path.to_string()));
+        }
+        let span = self
+            .layout
+            .map
+            .field(idx, field)
+            .ok_or_else(|| TemplateError::NoSuchField(path.to_string()))?;
+        if span.length == 0 || span.length > 8 {
+            return Err(TemplateError::UnsupportedField(path.to_string()));
+        }
+        Ok(TemplateSlot {
+            layer_index: idx,
+            field: span.field,
+            offset: span.offset,
+            length: span.length,
+            bits: span.bits,
+        })
+    }
+
+    /* a slot given by the caller may be of another template, or made up */
+    fn check_slot(&self, slot: &TemplateSlot) -> Result<(), TemplateError> {
+        if self
+            .computed
+            .iter()
+            .any(|&(i, f)| i == slot.layer_index && f == slot.field)
+        {
+            return Err(TemplateError::ComputedField(slot.field.to_string()));
+        }
+        let in_packet = slot
+            .offset
+            .checked_add(slot.length)
+            .is_some_and(|end| end <= self.bytes.len());
+        if !in_packet {
+            return Err(TemplateError::SlotOutOfRange {
+                field: slot.field.to_string(),
+                offset: slot.offset,
+                length: slot.length,
+            });
+        }
+        let bits_fit = slot.bits.is_none_or(|(bit_offset, bit_width)| {
+            bit_width > 0 && bit_offset + bit_width <= slot.length * 8
+        });
+        if slot.length == 0 || slot.length > 8 || !bits_fit {
+            return Err(TemplateError::UnsupportedField(slot.field.to_string()));
+        }
+        Ok(())
+    }
+
+    /*
+     * Write the packet with the fields set to the values into the start of buf,
+     * and return its length. The values are written big-endian into the bytes
+     * or the bits of the field, e.g. an IPv4 address as its u32.
+     */
+    pub fn render<K: TemplateKey>(
+        &self,
+        buf: &mut [u8],
+        values: &[(K, u64)],
+    ) -> Result<usize, TemplateError> {
+        let len = self.bytes.len();
+        if buf.len() < len {
+            return Err(TemplateError::BufferTooSmall {
+                needed: len,
+                available: buf.len(),
+            });
+        }
+        let out = &mut buf[..len];
+        out.copy_from_slice(&self.bytes);
+        for (key, value) in values {
+            let slot = key.slot(self)?;
+            self.set(out, &slot, *value)?;
+        }
+        Ok(len)
+    }
+
+    pub fn render_vec<K: TemplateKey>(
+        &self,
+        values: &[(K, u64)],
+    ) -> Result<Vec<u8>, TemplateError> {
+        let mut out = vec![0; self.bytes.len()];
+        self.render(&mut out, values)?;
+        Ok(out)
+    }
+
+    fn set(&self, out: &mut [u8], slot: &TemplateSlot, value: u64) -> Result<(), TemplateError> {
+        let width = slot.width();
+        if width < 64 && value >> width != 0 {
+            return Err(TemplateError::ValueTooLarge {
+                field: slot.field.to_string(),
+                value,
+            });
+        }
+        let range = slot.offset..slot.offset + slot.length;
+        let mut old = [0u8; 8];
+        old[..slot.length].copy_from_slice(&out[range.clone()]);
+
+        let total = slot.length * 8;
+        let shift = match slot.bits {
+            Some((bit_offset, bit_width)) => total - bit_offset - bit_width,
+            None => 0,
+        };
+        let mask = if width == 64 {
+            u64::MAX
+        } else {
+            ((1u64 << width) - 1) << shift
+        };
+        let current = out[range.clone()]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let new = (current & !mask) | (value << shift);
+        for (i, b) in out[range.clone()].iter_mut().enumerate() {
+            *b = (new >> (8 * (slot.length - 1 - i))) as u8;
+        }
+        self.update_checksums(out, range, &old[..slot.length]);
+        Ok(())
+    }
+
+    /* the bytes in range were old, update the checksums over them, then the ones over those */
+    fn update_checksums(&self, out: &mut [u8], range: Range<usize>, old: &[u8]) {
+        for ck in &self.checksums {
+            if ck.at < range.end && range.start < ck.at + 2 {
+                continue;
+            }
+            /* HC' = ~(~HC + ~m + m') */
+            let mut sum = !u16::from_be_bytes([out[ck.at], out[ck.at + 1]]) as u32;
+            let mut touched = false;
+            for sum_range in &ck.sums {
+                let start = max(sum_range.start, range.start);
+                let end = min(sum_range.end, range.end);
+                if start >= end {
+                    continue;
+                }
+                touched = true;
+                let byte = |p: usize, old_bytes: bool| -> u32 {
+                    if p >= sum_range.end {
+                        0
+                    } else if old_bytes && range.contains(&p) {
+                        old[p - range.start] as u32
+                    } else {
+                        out[p] as u32
+                    }
+                };
+                let mut word = sum_range.start + (start - sum_range.start) / 2 * 2;
+                while word < end {
+                    let m = (byte(word, true) << 8) | byte(word + 1, true);
+                    let m_new = (byte(word, false) << 8) | byte(word + 1, false);
+                    sum += (!m & 0xffff) + m_new;
+                    word += 2;
+                }
+            }
+            if touched {
+                let old_ck = [out[ck.at], out[ck.at + 1]];
+                out[ck.at..ck.at + 2].copy_from_slice(&fold_u32(sum).to_be_bytes());
+                self.update_checksums(out, ck.at..ck.at + 2, &old_ck);
+            }
+        }
+    }
+}
@@ -0,0 +1,777 @@
+/*
+ * Export decoded packets in the shape tshark produces with "-T json" and "-T pdml",
+ * so the output can be fed to the tools that already consume those formats.
+ */
+
+use crate::protocols::ether::*;
+use crate::protocols::pcap_file::*;
+use crate::*;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/* how tshark shows a field value */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsharkShow {
+    Dec,
+    /* 0x-prefixed, zero padded to the given number of digits */
+    Hex(usize),
+    /* header length in 32-bit words, shown in bytes */
+    Words,
+    /* the flags and fragment offset both live in the IP "frag" field */
+    IpFlags,
+    IpFragOffset,
+    /* addresses and anything else that already prints the way tshark does */
+    Text,
+    /* bytes as aa:bb:cc */
+    Bytes,
+    /* the number of bytes */
+    Len,
+}
+
+/* one of our fields under its tshark name */
+#[derive(Clone, Copy, Debug)]
+pub struct TsharkField {
+    pub field: &'static str,
+    pub name: &'static str,
+    pub label: &'static str,
+    pub show: TsharkShow,
+}
+
+/*
+ * A layer under its tshark protocol name. With merge set, the fields are added to
+ * the closest preceding protocol of the same name instead (padding goes into "eth").
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct TsharkProto {
+    pub layer: &'static str,
+    pub name: &'static str,
+    pub title: &'static str,
+    pub merge: bool,
+    pub fields: &'static [TsharkField],
+}
+
+const fn f(
+    field: &'static str,
+    name: &'static str,
+    label: &'static str,
+    show: TsharkShow,
+) -> TsharkField {
+    TsharkField {
+        field,
+        name,
+        label,
+        show,
+    }
+}
+
+use TsharkShow::*;
+
+pub static TSHARK_PROTOS: &[TsharkProto] = &[
+    TsharkProto {
+        layer: "ether",
+        name: "eth",
+        title: "Ethernet II",
+        merge: false,
+        fields: &[
+            f("dst", "eth.dst", "Destination", Text),
+            f("src", "eth.src", "Source", Text),
+            f("etype", "eth.type", "Type", Hex(4)),
+        ],
+    },
+    TsharkProto {
+        layer: "padding",
+        name: "eth",
+        title: "Ethernet II",
+        merge: true,
+        fields: &[f("data", "eth.padding", "Padding", Bytes)],
+    },
+    TsharkProto {
+        layer: "dot1Q",
+        name: "vlan",
+        title: "802.1Q Virtual LAN",
+        merge: false,
+        fields: &[
+            f("prio", "vlan.priority", "Priority", Dec),
+            f("id", "vlan.dei", "DEI", Dec),
+            f("vlan", "vlan.id", "ID", Dec),
+            f("etype", "vlan.etype", "Type", Hex(4)),
+        ],
+    },
+    TsharkProto {
+        layer: "Arp",
+        name: "arp",
+        title: "Address Resolution Protocol",
+        merge: false,
+        fields: &[
+            f("hwtype", "arp.hw.type", "Hardware type", Dec),
+            f("ptype", "arp.proto.type", "Protocol type", Hex(4)),
+            f("hwlen", "arp.hw.size", "Hardware size", Dec),
+            f("plen", "arp.proto.size", "Protocol size", Dec),
+            f("op", "arp.opcode", "Opcode", Dec),
+            f("hwsrc", "arp.src.hw_mac", "Sender MAC address", Text),
+            f("psrc", "arp.src.proto_ipv4", "Sender IP address", Text),
+            f("hwdst", "arp.dst.hw_mac", "Target MAC address", Text),
+            f("pdst", "arp.dst.proto_ipv4", "Target IP address", Text),
+        ],
+    },
+    TsharkProto {
+        layer: "Ip",
+        name: "ip",
+        title: "Internet Protocol Version 4",
+        merge: false,
+        fields: &[
+            f("version", "ip.version", "Version", Dec),
+            f("ihl", "ip.hdr_len", "Header Length", Words),
+            f("tos", "ip.dsfield", "Differentiated Services Field", Hex(2)),
+            f("len", "ip.len", "Total Length", Dec),
+            f("id", "ip.id", "Identification", Hex(4)),
+            f("frag", "ip.flags", "Flags", IpFlags),
+            f("frag", "ip.frag_offset", "Fragment Offset", IpFragOffset),
+            f("ttl", "ip.ttl", "Time to Live", Dec),
+            f("proto", "ip.proto", "Protocol", Dec),
+            f("chksum", "ip.checksum", "Header Checksum", Hex(4)),
+            f("src", "ip.src", "Source Address", Text),
+            f("dst", "ip.dst", "Destination Address", Text),
+        ],
+    },
+    TsharkProto {
+        layer: "Icmp",
+        name: "icmp",
+        title: "Internet Control Message Protocol",
+        merge: false,
+        fields: &[
+            f("typ", "icmp.type", "Type", Dec),
+            f("code", "icmp.code", "Code", Dec),
+            f("chksum", "icmp.checksum", "Checksum", Hex(4)),
+        ],
+    },
+    TsharkProto {
+        layer: "echo",
+        name: "icmp",
+        title: "Internet Control Message Protocol",
+        merge: true,
+        fields: &[
+            f("identifier", "icmp.ident", "Identifier", Dec),
+            f("sequence", "icmp.seq", "Sequence Number", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "echoReply",
+        name: "icmp",
+        title: "Internet Control Message Protocol",
+        merge: true,
+        fields: &[
+            f("identifier", "icmp.ident", "Identifier", Dec),
+            f("sequence", "icmp.seq", "Sequence Number", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "Udp",
+        name: "udp",
+        title: "User Datagram Protocol",
+        merge: false,
+        fields: &[
+            f("sport", "udp.srcport", "Source Port", Dec),
+            f("dport", "udp.dstport", "Destination Port", Dec),
+            f("len", "udp.length", "Length", Dec),
+            f("chksum", "udp.checksum", "Checksum", Hex(4)),
+        ],
+    },
+    TsharkProto {
+        layer: "Tcp",
+        name: "tcp",
+        title: "Transmission Control Protocol",
+        merge: false,
+        fields: &[
+            f("sport", "tcp.srcport", "Source Port", Dec),
+            f("dport", "tcp.dstport", "Destination Port", Dec),
+            f("seq", "tcp.seq_raw", "Sequence Number (raw)", Dec),
+            f("ack", "tcp.ack_raw", "Acknowledgment number (raw)", Dec),
+            f("dataofs", "tcp.hdr_len", "Header Length", Words),
+            f("flags", "tcp.flags", "Flags", Hex(4)),
+            f("window", "tcp.window_size_value", "Window", Dec),
+            f("chksum", "tcp.checksum", "Checksum", Hex(4)),
+            f("urgptr", "tcp.urgent_pointer", "Urgent Pointer", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "Gre",
+        name: "gre",
+        title: "Generic Routing Encapsulation",
+        merge: false,
+        fields: &[
+            f("version", "gre.flags.version", "Version", Dec),
+            f("proto", "gre.proto", "Protocol Type", Hex(4)),
+            f("chksum", "gre.checksum", "Checksum", Hex(4)),
+            f("key", "gre.key", "Key", Hex(8)),
+            f(
+                "sequence_number",
+                "gre.sequence_number",
+                "Sequence Number",
+                Dec,
+            ),
+        ],
+    },
+    TsharkProto {
+        layer: "Vxlan",
+        name: "vxlan",
+        title: "Virtual eXtensible Local Area Network",
+        merge: false,
+        fields: &[
+            f("flags", "vxlan.flags", "Flags", Hex(2)),
+            f("vni", "vxlan.vni", "VXLAN Network Identifier (VNI)", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "Geneve",
+        name: "geneve",
+        title: "Generic Network Virtualization Encapsulation",
+        merge: false,
+        fields: &[
+            f("protocol", "geneve.proto_type", "Protocol Type", Hex(4)),
+            f("vni", "geneve.vni", "Virtual Network Identifier (VNI)", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "erspan",
+        name: "erspan",
+        title: "Encapsulated Remote Switch Packet ANalysis",
+        merge: false,
+        fields: &[
+            f("version", "erspan.version", "Version", Dec),
+            f("vlan", "erspan.vlan", "Vlan", Dec),
+            f("cos", "erspan.cos", "COS", Dec),
+            f("encap_type", "erspan.en", "Encap", Dec),
+            f("truncated", "erspan.truncated", "Truncated", Dec),
+            f("session_id", "erspan.spanid", "SpanID", Dec),
+            f("port_index", "erspan.index", "Index", Dec),
+        ],
+    },
+    TsharkProto {
+        layer: "Bootp",
+        name: "dhcp",
+        title: "Dynamic Host Configuration Protocol",
+        merge: false,
+        fields: &[
+            f("op", "dhcp.type", "Message type", Dec),
+            f("htype", "dhcp.hw.type", "Hardware type", Hex(2)),
+            f("hlen", "dhcp.hw.len", "Hardware address length", Dec),
+            f("hops", "dhcp.hops", "Hops", Dec),
+            f("xid", "dhcp.id", "Transaction ID", Hex(8)),
+            f("secs", "dhcp.secs", "Seconds elapsed", Dec),
+            f("flags", "dhcp.flags", "Bootp flags", Hex(4)),
+            f("ciaddr", "dhcp.ip.client", "Client IP address", Text),
+            f("yiaddr", "dhcp.ip.your", "Your (client) IP address", Text),
+            f("siaddr", "dhcp.ip.server", "Next server IP address", Text),
+            f("giaddr", "dhcp.ip.relay", "Relay agent IP address", Text),
+        ],
+    },
+    TsharkProto {
+        layer: "raw",
+        name: "data",
+        title: "Data",
+        merge: false,
+        fields: &[
+            f("data", "data.data", "Data", Bytes),
+            f("data", "data.len", "Length", Len),
+        ],
+    },
+];
+
+pub fn tshark_proto(layer: &str) -> Option<&'static TsharkProto> {
+    TSHARK_PROTOS.iter().find(|p| p.layer == layer)
+}
+
+fn show_value(show: TsharkShow, v: &FieldValue) -> String {
+    let num = v.as_u64();
+    match (show, v, num) {
+        (Bytes, FieldValue::Bytes(b), _) => colon_hex(b),
+        (Len, FieldValue::Bytes(b), _) => b.len().to_string(),
+        (Text, FieldValue::Mac(m), _) => format!("{:?}", m).to_lowercase(),
+        (Text, FieldValue::Bytes(b), _) => colon_hex(b),
+        (Dec, _, Some(x)) => x.to_string(),
+        (Hex(w), _, Some(x)) => format!("0x{:0w$x}", x, w = w),
+        (Words, _, Some(x)) => (x * 4).to_string(),
+        (IpFlags, _, Some(x)) => format!("0x{:02x}", (x >> 8) & 0xe0),
+        (IpFragOffset, _, Some(x)) => ((x & 0x1fff) * 8).to_string(),
+        _ => v.to_string(),
+    }
+}
+
+fn colon_hex(b: &[u8]) -> String {
+    b.iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn plain_hex(b: &[u8]) -> String {
+    b.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/* a field of a dissected packet, with the position of its bytes in the frame */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsharkFieldOut {
+    pub name: String,
+    pub label: String,
+    pub show: String,
+    pub pos: usize,
+    pub size: usize,
+    pub bitmask: u64,
+    /* the field bytes in hex */
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsharkProtoOut {
+    pub name: String,
+    pub showname: String,
+    pub pos: usize,
+    pub size: usize,
+    pub fields: Vec<TsharkFieldOut>,
+}
+
+impl TsharkProtoOut {
+    pub fn field(&self, name: &str) -> Option<&TsharkFieldOut> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+/* a packet as tshark would dissect it, the first protocol is always "frame" */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsharkPacket {
+    pub number: usize,
+    pub timestamp: Option<Duration>,
+    pub bytes: Vec<u8>,
+    pub protos: Vec<TsharkProtoOut>,
+}
+
+impl TsharkPacket {
+    /*
+     * Dissect the packet the way show2() does: encode it, then decode the bytes,
+     * so the computed fields are filled in and the positions are real.
+     */
+    pub fn new(stack: &LayerStack, number: usize, timestamp: Option<Duration>) -> Self {
+        let bytes = stack.clone().encode();
+        let decoded = stack
+            .layers
+            .first()
+            .and_then(|l| l.decode_with_map(&bytes))
+            .map(|(decoded, _, map)| (decoded, map));
+        let (decoded, map) = match decoded {
+            Some(x) => x,
+            None => {
+                let (_, map) = stack.clone().encode_with_map();
+                (stack.clone(), map)
+            }
+        };
+        Self::from_dissection(&decoded, &map, bytes, number, timestamp)
+    }
+
+    /* dissect an already decoded stack, given the map of the bytes it was decoded from */
+    pub fn from_dissection(
+        stack: &LayerStack,
+        map: &DissectionMap,
+        bytes: Vec<u8>,
+        number: usize,
+        timestamp: Option<Duration>,
+    ) -> Self {
+        let starts: Vec<Option<usize>> = (0..stack.layers.len())
+            .map(|i| map.layer_spans(i).iter().map(|s| s.offset).min())
+            .collect();
+        let mut protos: Vec<TsharkProtoOut> = vec![];
+        let mut last_end = 0;
+
+        for (i, layer) in stack.layers.iter().enumerate() {
+            let pos = starts[i].unwrap_or(last_end);
+            let end = starts[i + 1..]
+                .iter()
+                .flatten()
+                .next()
+                .copied()
+                .unwrap_or(bytes.len())
+                .max(pos);
+            last_end = end;
+
+            let fields_of = |name: &str, label: &str, field: &str, show: Option<TsharkShow>| {
+                let span = map.field(i, field)?;
+                let v = layer.get_field(field)?;
+                let show = match show {
+                    Some(s) => show_value(s, &v),
+                    None => v.to_string(),
+                };
+                let value = bytes
+                    .get(span.offset..span.offset + span.length)
+                    .map(plain_hex)
+                    .unwrap_or_default();
+                let bitmask = match (name, span.bits) {
+                    ("ip.flags", _) => 0xe000,
+                    ("ip.frag_offset", _) => 0x1fff,
+                    (_, Some((off, width))) => {
+                        let total = span.length * 8;
+                        ((1u64 << width) - 1) << (total - off - width)
+                    }
+                    _ => 0,
+                };
+                Some(TsharkFieldOut {
+                    name: name.to_string(),
+                    label: label.to_string(),
+                    show,
+                    pos: span.offset,
+                    size: span.length,
+                    bitmask,
+                    value,
+                })
+            };
+
+            let (out, merge) = match tshark_proto(layer.layer_name()) {
+                Some(p) => {
+                    let fields = p
+                        .fields
+                        .iter()
+                        .filter_map(|tf| fields_of(tf.name, tf.label, tf.field, Some(tf.show)))
+                        .collect();
+                    let out = TsharkProtoOut {
+                        name: p.name.to_string(),
+                        showname: p.title.to_string(),
+                        pos,
+                        size: end - pos,
+                        fields,
+                    };
+                    (out, p.merge)
+                }
+                None => {
+                    /* not in the table: lowercase layer name and our own field names */
+                    let name = layer.layer_name().to_lowercase();
+                    let fields = map
+                        .layer_spans(i)
+                        .iter()
+                        .filter_map(|s| {
+                            let fname = format!("{}.{}", name, s.field);
+                            fields_of(&fname, s.field, s.field, None)
+                        })
+                        .collect();
+                    let out = TsharkProtoOut {
+                        name,
+                        showname: layer.summary(),
+                        pos,
+                        size: end - pos,
+                        fields,
+                    };
+                    (out, false)
+                }
+            };
+
+            let target = if merge {
+                protos.iter_mut().rev().find(|p| p.name == out.name)
+            } else {
+                None
+            };
+            match target {
+                Some(t) => {
+                    t.size = t.size.max(end.saturating_sub(t.pos));
+                    t.fields.extend(out.fields);
+                }
+                None => protos.push(out),
+            }
+        }
+
+        let frame = frame_proto(number, timestamp, &bytes, &protos);
+        protos.insert(0, frame);
+        TsharkPacket {
+            number,
+            timestamp,
+            bytes,
+            protos,
+        }
+    }
+
+    /* a packet from a pcap file with Ethernet link type */
+    pub fn from_pcap_packet(pkt: &pcapPacket, number: usize) -> Self {
+        match Ether!().decode_with_map(&pkt.data) {
+            Some((stack, _, map)) => Self::from_dissection(
+                &stack,
+                &map,
+                pkt.data.clone(),
+                number,
+                Some(pkt.timestamp()),
+            ),
+            None => Self::from_dissection(
+                &LayerStack {
+                    layers: vec![],
+                    filled: true,
+                },
+                &DissectionMap::new(),
+                pkt.data.clone(),
+                number,
+                Some(pkt.timestamp()),
+            ),
+        }
+    }
+
+    pub fn proto(&self, name: &str) -> Option<&TsharkProtoOut> {
+        self.protos.iter().find(|p| p.name == name)
+    }
+
+    pub fn to_json(&self, raw: bool) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out, raw);
+        out
+    }
+
+    /*
+     * The tshark "-T json" object for this packet. With raw set, every field also gets
+     * a "<name>_raw" array of [hex, pos, size, bitmask, type] like "-x" adds;
+     * the field type is not tracked and is always 0.
+     */
+    fn write_json(&self, out: &mut String, raw: bool) {
+        let date = self.timestamp.map(|t| t.as_secs()).unwrap_or(0);
+        let _ = writeln!(out, "  {{");
+        let _ = writeln!(
+            out,
+            "    \"_index\": {},",
+            json_str(&format!("packets-{}", epoch_date(date)))
+        );
+        let _ = writeln!(out, "    \"_type\": \"doc\",");
+        let _ = writeln!(out, "    \"_score\": null,");
+        let _ = writeln!(out, "    \"_source\": {{");
+        let _ = writeln!(out, "      \"layers\": {{");
+        for (i, p) in self.protos.iter().enumerate() {
+            let mut entries: Vec<(String, String)> = vec![];
+            for f in &p.fields {
+                entries.push((f.name.clone(), json_str(&f.show)));
+                if raw {
+                    entries.push((
+                        format!("{}_raw", f.name),
+                        format!(
+                            "[{}, {}, {}, {}, 0]",
+                            json_str(&f.value),
+                            f.pos,
+                            f.size,
+                            f.bitmask
+                        ),
+                    ));
+                }
+            }
+            let _ = write!(out, "        {}: {{", json_str(&p.name));
+            for (j, (k, v)) in entries.iter().enumerate() {
+                let sep = if j + 1 < entries.len() { "," } else { "" };
+                let _ = write!(out, "\n          {}: {}{}", json_str(k), v, sep);
+            }
+            let close = if entries.is_empty() {
+                "}"
+            } else {
+                "\n        }"
+            };
+            let sep = if i + 1 < self.protos.len() || raw {
+                ","
+            } else {
+                ""
+            };
+            let _ = writeln!(out, "{}{}", close, sep);
+            if raw {
+                let bytes = self.bytes.get(p.pos..p.pos + p.size).unwrap_or(&[]);
+                let sep = if i + 1 < self.protos.len() { "," } else { "" };
+                let _ = writeln!(
+                    out,
+                    "        {}: [{}, {}, {}, 0, 1]{}",
+                    json_str(&format!("{}_raw", p.name)),
+                    json_str(&plain_hex(bytes)),
+                    p.pos,
+                    p.size,
+                    sep
+                );
+            }
+        }
+        let _ = writeln!(out, "      }}");
+        let _ = writeln!(out, "    }}");
+        let _ = write!(out, "  }}");
+    }
+
+    /* the <packet> element of tshark "-T pdml" */
+    pub fn to_pdml(&self) -> String {
+        let mut out = String::new();
+        let len = self.bytes.len();
+        let _ = writeln!(out, "<packet>");
+        let _ = writeln!(
+            out,
+            "  <proto name=\"geninfo\" pos=\"0\" showname=\"General information\" size=\"{}\">",
+            len
+        );
+        let mut geninfo = vec![
+            ("num", "Number", self.number.to_string(), self.number as u64),
+            ("len", "Frame Length", len.to_string(), len as u64),
+            ("caplen", "Captured Length", len.to_string(), len as u64),
+        ];
+        if let Some(ts) = self.timestamp {
+            geninfo.push(("timestamp", "Captured Time", epoch_time(ts), ts.as_secs()));
+        }
+        for (name, label, show, value) in geninfo {
+            let _ = writeln!(
+                out,
+                "    <field name=\"{}\" pos=\"0\" show=\"{}\" showname=\"{}\" value=\"{:x}\" size=\"{}\"/>",
+                name,
+                xml_escape(&show),
+                label,
+                value,
+                len
+            );
+        }
+        let _ = writeln!(out, "  </proto>");
+        for p in &self.protos {
+            let _ = writeln!(
+                out,
+                "  <proto name=\"{}\" showname=\"{}\" size=\"{}\" pos=\"{}\">",
+                xml_escape(&p.name),
+                xml_escape(&p.showname),
+                p.size,
+                p.pos
+            );
+            for f in &p.fields {
+                /* like tshark, bit fields show the masked value and the whole bytes separately */
+                let (value, mask) = match u64::from_str_radix(&f.value, 16) {
+                    Ok(raw) if f.bitmask != 0 => (
+                        format!("{:x}", (raw & f.bitmask) >> f.bitmask.trailing_zeros()),
+                        format!(" unmaskedvalue=\"{}\"", f.value),
+                    ),
+                    _ => (f.value.clone(), String::new()),
+                };
+                let _ = writeln!(
+                    out,
+                    "    <field name=\"{}\" showname=\"{}: {}\" size=\"{}\" pos=\"{}\" show=\"{}\" value=\"{}\"{}/>",
+                    xml_escape(&f.name),
+                    xml_escape(&f.label),
+                    xml_escape(&f.show),
+                    f.size,
+                    f.pos,
+                    xml_escape(&f.show),
+                    value,
+                    mask
+                );
+            }
+            let _ = writeln!(out, "  </proto>");
+        }
+        let _ = write!(out, "</packet>");
+        out
+    }
+}
+
+fn frame_proto(
+    number: usize,
+    timestamp: Option<Duration>,
+    bytes: &[u8],
+    protos: &[TsharkProtoOut],
+) -> TsharkProtoOut {
+    let len = bytes.len();
+    let field = |name: &str, label: &str, show: String| TsharkFieldOut {
+        name: name.to_string(),
+        label: label.to_string(),
+        show,
+        pos: 0,
+        size: 0,
+        bitmask: 0,
+        value: String::new(),
+    };
+    let mut fields = vec![];
+    if let Some(ts) = timestamp {
+        fields.push(field("frame.time_epoch", "Epoch Time", epoch_time(ts)));
+    }
+    fields.push(field("frame.number", "Frame Number", number.to_string()));
+    fields.push(field("frame.len", "Frame Length", len.to_string()));
+    fields.push(field("frame.cap_len", "Capture Length", len.to_string()));
+    let names: Vec<&str> = protos.iter().map(|p| p.name.as_str()).collect();
+    fields.push(field(
+        "frame.protocols",
+        "Protocols in frame",
+        names.join(":"),
+    ));
+    TsharkProtoOut {
+        name: "frame".to_string(),
+        showname: format!(
+            "Frame {}: {} bytes on wire, {} bytes captured",
+            number, len, len
+        ),
+        pos: 0,
+        size: len,
+        fields,
+    }
+}
+
+/* "-T json" output for a list of packets */
+pub fn to_tshark_json(packets: &[TsharkPacket], raw: bool) -> String {
+    let mut out = String::from("[\n");
+    for (i, p) in packets.iter().enumerate() {
+        p.write_json(&mut out, raw);
+        out.push_str(if i + 1 < packets.len() { ",\n" } else { "\n" });
+    }
+    out.push_str("]\n");
+    out
+}
+
+/* "-T pdml" output for a list of packets */
+pub fn to_pdml(packets: &[TsharkPacket]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<pdml version=\"0\" creator=\"scarust\">\n");
+    for p in packets {
+        out.push_str(&p.to_pdml());
+        out.push('\n');
+    }
+    out.push_str("</pdml>\n");
+    out
+}
+
+/* number the stacks from 1, without timestamps */
+pub fn tshark_packets(stacks: &[LayerStack]) -> Vec<TsharkPacket> {
+    stacks
+        .iter()
+        .enumerate()
+        .map(|(i, s)| TsharkPacket::new(s, i + 1, None))
+        .collect()
+}
+
+pub fn tshark_packets_from_pcap(pcap: &pcapFile) -> Vec<TsharkPacket> {
+    pcap.d
+        .packets
+        .iter()
+        .enumerate()
+        .map(|(i, p)| TsharkPacket::from_pcap_packet(p, i + 1))
+        .collect()
+}
+
+fn json_str(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn epoch_time(ts: Duration) -> String {
+    format!("{}.{:09}", ts.as_secs(), ts.subsec_nanos())
+}
+
+/* YYYY-MM-DD of a unix time, for the "_index" of the JSON output */
+fn epoch_date(secs: u64) -> String {
+    /* days to civil date, from Howard Hinnant's algorithm */
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
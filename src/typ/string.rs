@@ -25,6 +25,7 @@ use crate::Decoder;
 use crate::Encode;
 use crate::Encoder;
 use crate::Value::Random;
+use crate::{DynField, FieldValue};
 
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
@@ -111,6 +112,21 @@ impl<N: ArrayLength> Distribution<FixedSizeString<N>> for Standard {
     }
 }
 
+impl<N: ArrayLength> DynField for FixedSizeString<N> {
+    fn to_field_value(&self) -> FieldValue {
+        match std::str::from_utf8(&self.0) {
+            Ok(s) => FieldValue::Str(s.trim_end_matches("\u{0}").to_string()),
+            Err(_) => FieldValue::Bytes(self.0.to_vec()),
+        }
+    }
+    fn from_field_value(v: &FieldValue) -> Option<Self> {
+        match v {
+            FieldValue::Str(s) => Self::try_from(s.as_str()).ok(),
+            _ => None,
+        }
+    }
+}
+
 impl<N> FixedSizeString<N>
 where
     N: ArrayLength,
@@ -303,9 +319,7 @@ impl Serialize for F64 {
     where
         S: Serializer,
     {
-        let data = &self.0;
-        let out = f64::from_bits((data).to_bits().to_be());
-        serializer.serialize_f64(out)
+        serializer.serialize_f64(self.0)
     }
 }
 
@@ -325,8 +339,21 @@ impl<'de> Deserialize<'de> for F64 {
             where
                 E: de::Error,
             {
-                let f64_value = f64::from_bits(value.to_bits().to_be());
-                Ok(F64(f64_value))
+                Ok(F64(value))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(F64(value as f64))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(F64(value as f64))
             }
         }
 
@@ -521,8 +548,7 @@ impl<T: Serialize + Default + Debug, N: ArrayLength> Serialize for FixedSizeArra
     }
 }
 */
-#[derive(Copy, Clone, Default, Deserialize)]
-#[serde(bound = "T: Deserialize<'de> + Default")]
+#[derive(Copy, Clone, Default)]
 pub struct SizedEnum<T, X>(T, PhantomData<X>); // This is the sized enum declaration, It's a unit struct
 
 impl<T: Debug, X> fmt::Debug for SizedEnum<T, X> {
@@ -555,11 +581,37 @@ impl<T: Serialize + Copy + AsU32 + Debug, X: Serialize + Debug + TryFrom<u32>> S
     }
 }
 
+impl<T, X> SizedEnum<T, X> {
+    pub fn new(data: T) -> Self {
+        SizedEnum(data, PhantomData)
+    }
+    pub fn get(&self) -> &T {
+        &self.0
+    }
+}
+
 pub trait AsU32 {
     fn as_u32(data: Self) -> u32;
 }
 
-/* FIXME: add deserializer for SizedEnum */
+/* the reverse of the above: the value is stored as X, a one element tuple */
+impl<'de, T: TryFrom<u32>, X: Deserialize<'de> + Into<u32>> Deserialize<'de> for SizedEnum<T, X> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (data_x,): (X,) = Deserialize::deserialize(deserializer)?;
+        let data_u32: u32 = data_x.into();
+        match T::try_from(data_u32) {
+            Ok(data) => Ok(SizedEnum(data, PhantomData)),
+            Err(_) => Err(de::Error::custom(format!(
+                "{} does not fit {}",
+                data_u32,
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+}
 
 #[derive(Clone, Default, Debug)]
 pub struct VariableSizeArray<T>(pub Vec<T>);
@@ -616,16 +668,8 @@ impl<'de, T: Deserialize<'de> + Debug> Deserialize<'de> for VariableSizeArray<T>
                                     .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
                 */
 
-                loop {
-                    let nxt = seq.next_element();
-                    if nxt.is_err() {
-                        break;
-                    }
-                    let nxt = nxt?;
-                    if nxt.is_some() {
-                        let nxt: T = nxt.unwrap();
-                        res.push(nxt);
-                    }
+                while let Some(nxt) = seq.next_element()? {
+                    res.push(nxt);
                 }
                 /*
                 for i in 0..length {
@@ -767,6 +811,23 @@ impl<'de, T: Debug + Clone + AsEnumFlag + Deserialize<'de>> Deserialize<'de> for
                 }
                 return Ok(EnumFlag::<T>(res));
             }
+            /* self-describing formats like JSON give all the integers as u64 */
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: Error,
+            {
+                let size = T::size_of_enum_flag();
+                if size < 64 && v >> size != 0 {
+                    return Err(E::custom(format!("{} does not fit {} bit flags", v, size)));
+                }
+                let mut res: Vec<T> = vec![];
+                for bit in (0..size.min(32)).rev() {
+                    if v & (1 << bit) != 0 {
+                        res.push(T::from_u32(1 << bit));
+                    }
+                }
+                return Ok(EnumFlag::<T>(res));
+            }
             fn visit_u8<E>(self, v: u8) -> Result<Self::Value, E>
             where
                 E: Error,
@@ -0,0 +1,55 @@
+/* the helpers shared by the tests; each test uses only some of them */
+#![allow(dead_code)]
+
+use pcap_parser::traits::PcapReaderIterator;
+use pcap_parser::*;
+use scarust::protocols::pcap_file::*;
+use scarust::*;
+use std::fs::File;
+use std::path::PathBuf;
+
+pub fn get_pcap_path(name: &str) -> PathBuf {
+    /* tests/common/mod.rs */
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.pop();
+    path.push("pcap");
+    path.push(name);
+    path
+}
+
+/* the data of the packets, read with pcap_parser */
+pub fn read_pcap(pcapname: &str) -> Vec<Vec<u8>> {
+    let mut out: Vec<Vec<u8>> = vec![];
+    let path = get_pcap_path(pcapname);
+    let file = File::open(path).unwrap_or_else(|_| panic!("File open failed for {}", &pcapname));
+    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
+    loop {
+        match reader.next() {
+            Ok((offset, block)) => {
+                match block {
+                    PcapBlockOwned::LegacyHeader(_hdr) => {}
+                    PcapBlockOwned::Legacy(b) => {
+                        out.push(b.data.to_vec());
+                    }
+                    PcapBlockOwned::NG(_) => unreachable!(),
+                }
+                reader.consume(offset);
+            }
+            Err(PcapError::Eof) => break,
+            Err(PcapError::Incomplete) => {
+                reader.refill().unwrap();
+            }
+            Err(e) => panic!("error while reading: {:?}", e),
+        }
+    }
+    out
+}
+
+/* the file decoded with PcapFile!() */
+pub fn read_pcap_file(name: &str) -> pcapFile {
+    let bytes = std::fs::read(get_pcap_path(name)).unwrap();
+    let pcap = PcapFile!().decode(&bytes).unwrap().0;
+    pcap.get_layer(PcapFile!()).unwrap().clone()
+}
@@ -0,0 +1,375 @@
+use scarust::bpf::*;
+use scarust::protocols::all::*;
+use scarust::protocols::pcap_file::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+
+mod common;
+use common::*;
+
+/* what "tcpdump -d" prints for the filter, on an Ethernet interface */
+fn assert_listing(filter: &str, tcpdump: &[&str]) {
+    let prog = BpfProgram::compile(filter).unwrap();
+    let expected: String = tcpdump.iter().map(|l| format!("{}\n", l)).collect();
+    assert_eq!(prog.dump(), expected, "filter {:?}", filter);
+}
+
+#[test]
+fn bpf_tcpdump_listings() {
+    assert_listing(
+        "ip",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x800           jt 2\tjf 3",
+            "(002) ret      #262144",
+            "(003) ret      #0",
+        ],
+    );
+    assert_listing(
+        "tcp",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x86dd          jt 2\tjf 7",
+            "(002) ldb      [20]",
+            "(003) jeq      #0x6             jt 10\tjf 4",
+            "(004) jeq      #0x2c            jt 5\tjf 11",
+            "(005) ldb      [54]",
+            "(006) jeq      #0x6             jt 10\tjf 11",
+            "(007) jeq      #0x800           jt 8\tjf 11",
+            "(008) ldb      [23]",
+            "(009) jeq      #0x6             jt 10\tjf 11",
+            "(010) ret      #262144",
+            "(011) ret      #0",
+        ],
+    );
+    assert_listing(
+        "tcp port 80",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x86dd          jt 2\tjf 8",
+            "(002) ldb      [20]",
+            "(003) jeq      #0x6             jt 4\tjf 19",
+            "(004) ldh      [54]",
+            "(005) jeq      #0x50            jt 18\tjf 6",
+            "(006) ldh      [56]",
+            "(007) jeq      #0x50            jt 18\tjf 19",
+            "(008) jeq      #0x800           jt 9\tjf 19",
+            "(009) ldb      [23]",
+            "(010) jeq      #0x6             jt 11\tjf 19",
+            "(011) ldh      [20]",
+            "(012) jset     #0x1fff          jt 19\tjf 13",
+            "(013) ldxb     4*([14]&0xf)",
+            "(014) ldh      [x + 14]",
+            "(015) jeq      #0x50            jt 18\tjf 16",
+            "(016) ldh      [x + 16]",
+            "(017) jeq      #0x50            jt 18\tjf 19",
+            "(018) ret      #262144",
+            "(019) ret      #0",
+        ],
+    );
+    assert_listing(
+        "port 53",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x86dd          jt 2\tjf 10",
+            "(002) ldb      [20]",
+            "(003) jeq      #0x84            jt 6\tjf 4",
+            "(004) jeq      #0x6             jt 6\tjf 5",
+            "(005) jeq      #0x11            jt 6\tjf 23",
+            "(006) ldh      [54]",
+            "(007) jeq      #0x35            jt 22\tjf 8",
+            "(008) ldh      [56]",
+            "(009) jeq      #0x35            jt 22\tjf 23",
+            "(010) jeq      #0x800           jt 11\tjf 23",
+            "(011) ldb      [23]",
+            "(012) jeq      #0x84            jt 15\tjf 13",
+            "(013) jeq      #0x6             jt 15\tjf 14",
+            "(014) jeq      #0x11            jt 15\tjf 23",
+            "(015) ldh      [20]",
+            "(016) jset     #0x1fff          jt 23\tjf 17",
+            "(017) ldxb     4*([14]&0xf)",
+            "(018) ldh      [x + 14]",
+            "(019) jeq      #0x35            jt 22\tjf 20",
+            "(020) ldh      [x + 16]",
+            "(021) jeq      #0x35            jt 22\tjf 23",
+            "(022) ret      #262144",
+            "(023) ret      #0",
+        ],
+    );
+    assert_listing(
+        "host 192.168.1.1",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x800           jt 2\tjf 6",
+            "(002) ld       [26]",
+            "(003) jeq      #0xc0a80101      jt 12\tjf 4",
+            "(004) ld       [30]",
+            "(005) jeq      #0xc0a80101      jt 12\tjf 13",
+            "(006) jeq      #0x806           jt 8\tjf 7",
+            "(007) jeq      #0x8035          jt 8\tjf 13",
+            "(008) ld       [28]",
+            "(009) jeq      #0xc0a80101      jt 12\tjf 10",
+            "(010) ld       [38]",
+            "(011) jeq      #0xc0a80101      jt 12\tjf 13",
+            "(012) ret      #262144",
+            "(013) ret      #0",
+        ],
+    );
+    assert_listing(
+        "tcp[13] & 2 != 0",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x800           jt 2\tjf 10",
+            "(002) ldb      [23]",
+            "(003) jeq      #0x6             jt 4\tjf 10",
+            "(004) ldh      [20]",
+            "(005) jset     #0x1fff          jt 10\tjf 6",
+            "(006) ldxb     4*([14]&0xf)",
+            "(007) ldb      [x + 27]",
+            "(008) jset     #0x2             jt 9\tjf 10",
+            "(009) ret      #262144",
+            "(010) ret      #0",
+        ],
+    );
+    assert_listing(
+        "less 100",
+        &[
+            "(000) ld       #pktlen",
+            "(001) jgt      #0x64            jt 2\tjf 3",
+            "(002) ret      #0",
+            "(003) ret      #262144",
+        ],
+    );
+}
+
+#[test]
+fn bpf_listing_combined() {
+    assert_listing(
+        "udp and dst port 4789",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x86dd          jt 2\tjf 6",
+            "(002) ldb      [20]",
+            "(003) jeq      #0x11            jt 4\tjf 15",
+            "(004) ldh      [56]",
+            "(005) jeq      #0x12b5          jt 14\tjf 15",
+            "(006) jeq      #0x800           jt 7\tjf 15",
+            "(007) ldb      [23]",
+            "(008) jeq      #0x11            jt 9\tjf 15",
+            "(009) ldh      [20]",
+            "(010) jset     #0x1fff          jt 15\tjf 11",
+            "(011) ldxb     4*([14]&0xf)",
+            "(012) ldh      [x + 16]",
+            "(013) jeq      #0x12b5          jt 14\tjf 15",
+            "(014) ret      #262144",
+            "(015) ret      #0",
+        ],
+    );
+    assert_listing(
+        "ip host 10.0.0.1",
+        &[
+            "(000) ldh      [12]",
+            "(001) jeq      #0x800           jt 2\tjf 7",
+            "(002) ld       [26]",
+            "(003) jeq      #0xa000001       jt 6\tjf 4",
+            "(004) ld       [30]",
+            "(005) jeq      #0xa000001       jt 6\tjf 7",
+            "(006) ret      #262144",
+            "(007) ret      #0",
+        ],
+    );
+}
+
+fn vxlan() -> Vec<u8> {
+    (Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 12345, dport = 4789)
+        / VXLAN!(vni = 42)
+        / Ether!()
+        / IP!()
+        / Raw!("x".into()))
+    .encode()
+}
+
+fn vlan(id: u16, src: &str) -> Vec<u8> {
+    (Ether!() / Dot1Q!(vlan = id) / IP!(src = src, dst = "10.0.0.2") / UDP!(dport = 53)).encode()
+}
+
+#[test]
+fn bpf_run() {
+    let vx = BpfProgram::compile("udp and dst port 4789").unwrap();
+    assert!(vx.matches(&vxlan()));
+    let dns = (Ether!() / IP!() / UDP!(sport = 4789, dport = 53)).encode();
+    assert!(!vx.matches(&dns));
+    assert_eq!(vx.run(&vxlan()), DEFAULT_SNAPLEN);
+
+    let v = BpfProgram::compile("vlan 100 and ip host 10.0.0.1").unwrap();
+    assert!(v.matches(&vlan(100, "10.0.0.1")));
+    assert!(!v.matches(&vlan(200, "10.0.0.1")));
+    assert!(!v.matches(&vlan(100, "10.0.0.3")));
+    /* without the tag the addresses are at other offsets */
+    assert!(!v.matches(&(Ether!() / IP!(src = "10.0.0.1")).encode()));
+
+    let syn =
+        BpfProgram::compile("tcp[tcpflags] & tcp-syn != 0 and not src net 10.0.0.0/8").unwrap();
+    let tcp = |flags: u8, src: &str| {
+        (Ether!() / IP!(src = src) / TCP!(flags = flags, dport = 22)).encode()
+    };
+    assert!(syn.matches(&tcp(0x02, "192.0.2.1")));
+    assert!(!syn.matches(&tcp(0x10, "192.0.2.1")));
+    assert!(!syn.matches(&tcp(0x02, "10.1.2.3")));
+
+    let ports = BpfProgram::compile("tcp dst port 22 or udp portrange 50-60").unwrap();
+    assert!(ports.matches(&tcp(0x02, "192.0.2.1")));
+    assert!(ports.matches(&dns));
+    assert!(!ports.matches(&vxlan()));
+
+    /* the loads past the end of the capture reject the packet */
+    let bytes = vxlan();
+    assert!(!vx.matches(&bytes[..20]));
+}
+
+#[test]
+fn bpf_run_arithmetic() {
+    let tcp = |flags: u8, data: &str| {
+        (Ether!() / IP!() / TCP!(flags = flags) / Raw!(data.as_bytes().to_vec())).encode()
+    };
+    let get = tcp(0x18, "GET / HTTP/1.0");
+    let synack = tcp(0x12, "");
+    let rst = tcp(0x04, "");
+
+    let f = BpfProgram::compile("tcp[tcpflags] & (tcp-syn|tcp-ack) != 0").unwrap();
+    assert!(f.matches(&synack));
+    assert!(f.matches(&get));
+    assert!(!f.matches(&rst));
+
+    /* the start of the payload comes from the data offset of the TCP header */
+    let f = BpfProgram::compile("tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x47455420").unwrap();
+    assert!(f.matches(&get));
+    assert!(!f.matches(&tcp(0x18, "PUT / HTTP/1.0")));
+    assert!(!f.matches(&synack));
+
+    let f = BpfProgram::compile("len - 14 > 100").unwrap();
+    assert!(f.matches(&tcp(0x18, &"x".repeat(80))));
+    assert!(!f.matches(&get));
+
+    let dns = (Ether!() / IP!() / UDP!(dport = 53) / Raw!(vec![0, 1, 0, 0])).encode();
+    let f = BpfProgram::compile("udp[8:2] + 1 = 2").unwrap();
+    assert!(f.matches(&dns));
+    assert!(!f.matches(&get));
+
+    /* the expressions on both sides, with the precedence of C */
+    let f = BpfProgram::compile("ip[9] * 2 + 1 = 13 and 5 + 2 * len > ip[2:2] * 2").unwrap();
+    assert!(f.matches(&get));
+    assert!(!f.matches(&dns));
+    let f = BpfProgram::compile("(udp[2:2] - 3) % 10 == -1 * -(2 ^ 2)").unwrap();
+    assert!(f.matches(&dns));
+    assert!(BpfProgram::compile("(udp[2:2] = 53) or tcp")
+        .unwrap()
+        .matches(&dns));
+}
+
+#[test]
+fn bpf_run_pcap_packet() {
+    let bytes = vxlan();
+    let short = PcapPacket!(data = bytes[..60].to_vec(), orig_len = 1000);
+    let big = BpfProgram::compile("greater 500 and udp").unwrap();
+    assert!(big.matches_packet(&short));
+    assert!(!big.matches(&short.data));
+    let small = BpfProgram::compile("less 100").unwrap();
+    assert!(!small.matches_packet(&short));
+}
+
+#[test]
+fn bpf_run_pcap_file() {
+    let count = |name: &str, filter: &str| {
+        let prog = BpfProgram::compile(filter).unwrap();
+        let pcap = read_pcap_file(name);
+        pcap.d
+            .packets
+            .iter()
+            .filter(|p| prog.matches_packet(p))
+            .count()
+    };
+    assert_eq!(count("dhcp.pcap", "udp port 67 or udp port 68"), 6);
+    assert_eq!(count("dhcp.pcap", "udp dst port 67"), 3);
+    assert_eq!(count("dhcp.pcap", "tcp"), 0);
+    assert_eq!(count("vxlan1.pcap", "udp and dst port 4789"), 1);
+}
+
+#[test]
+fn bpf_errors() {
+    let err = |f: &str| BpfProgram::compile(f).unwrap_err();
+    assert_eq!(
+        err("tcp port"),
+        BpfError::Syntax {
+            pos: 8,
+            expected: "a value"
+        }
+    );
+    assert!(matches!(
+        err("host example.com"),
+        BpfError::Unsupported { pos: 5, .. }
+    ));
+    assert!(matches!(
+        err("ip host 10.0.0.1 and"),
+        BpfError::Syntax { .. }
+    ));
+    assert!(matches!(err("(ip or arp"), BpfError::Syntax { .. }));
+    assert!(matches!(err("net 10.0.0.1/8"), BpfError::BadValue { .. }));
+    assert!(matches!(err("ether"), BpfError::Syntax { pos: 0, .. }));
+    assert!(matches!(
+        err("vlan 5000"),
+        BpfError::BadValue { pos: 5, .. }
+    ));
+    assert!(matches!(
+        err("ip proto 300"),
+        BpfError::BadValue { pos: 9, .. }
+    ));
+    assert!(matches!(
+        err("ether proto 0x10000"),
+        BpfError::BadValue { .. }
+    ));
+    assert!(matches!(
+        err("portrange 1-70000"),
+        BpfError::BadValue { .. }
+    ));
+    assert!(matches!(
+        err("len / 0 > 1"),
+        BpfError::BadValue { pos: 4, .. }
+    ));
+    for (f, pos, what) in [
+        ("ether broadcast", 6, "broadcast"),
+        ("ip multicast", 3, "multicast"),
+        ("mpls", 0, "mpls"),
+    ] {
+        assert_eq!(
+            err(f),
+            BpfError::Unsupported {
+                pos,
+                what: format!("the '{}' primitive", what)
+            }
+        );
+    }
+    assert_eq!("udp".parse::<BpfProgram>(), BpfProgram::compile("udp"));
+    /* an empty filter takes everything */
+    assert_eq!(
+        BpfProgram::compile("").unwrap().run(b"abc"),
+        DEFAULT_SNAPLEN
+    );
+}
+
+#[test]
+fn bpf_sock_filter_layout() {
+    assert_eq!(std::mem::size_of::<BpfInsn>(), 8);
+    let prog = BpfProgram::compile_with_snaplen("arp", 96).unwrap();
+    assert_eq!(
+        prog.insns,
+        vec![
+            BpfInsn::stmt(BPF_LD | BPF_H | BPF_ABS, 12),
+            BpfInsn::jump(BPF_JMP | BPF_JEQ | BPF_K, 0x806, 0, 1),
+            BpfInsn::stmt(BPF_RET | BPF_K, 96),
+            BpfInsn::stmt(BPF_RET | BPF_K, 0),
+        ]
+    );
+}
@@ -50,7 +50,7 @@ fn decode_test_raw() {
         .0;
     println!("decode result: {:?}", &x);
     assert_eq!(x.indices_of(Ether!()), vec![0]);
-    assert_eq!(x.indices_of(IP!()), vec![]);
+    assert_eq!(x.indices_of(IP!()), Vec::<usize>::new());
     assert_eq!(x.indices_of(Raw!()), vec![1]);
     let eth = &x[Ether!()];
     assert_eq!(eth.dst, "41:41:41:41:41:41".into());
@@ -0,0 +1,129 @@
+use scarust::protocols::all::*;
+use scarust::protocols::pcap_file::*;
+use scarust::*;
+#[macro_use]
+extern crate scarust_derive;
+
+use serde::{Deserialize, Serialize};
+
+/* announces the port of the data which follows, and its payload type, like SDP does for RTP */
+#[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[nproto(register(UDP_DST_PORT_APPS, DstPort = 40100))]
+#[nproto(decode_hook = control_seen)]
+pub struct testControl {
+    pub data_port: Value<u16>,
+    pub payload_type: Value<u8>,
+}
+
+/* no port of its own, and the payload type is not on the wire */
+#[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[nproto(decode_hook = data_seen)]
+pub struct testData {
+    pub seq: Value<u16>,
+    #[nproto(encode = Skip, decode = Skip)]
+    pub payload_type: Value<u8>,
+}
+
+#[derive(Default)]
+struct Negotiated {
+    payload_type: Option<u8>,
+    data_packets: usize,
+}
+
+fn control_seen(me: &mut testControl, _buf: &[u8], _ci: usize, ctx: &mut DecodeContext) {
+    ctx.state_mut::<Negotiated>().payload_type = Some(me.payload_type.value());
+    let port = me.data_port.value() as u64;
+    ctx.options
+        .bind("UDP_DST_PORT_APPS", port, "TestData")
+        .unwrap();
+}
+
+fn data_seen(me: &mut testData, buf: &[u8], ci: usize, ctx: &mut DecodeContext) {
+    assert_eq!(ci, 2);
+    assert!(buf.len() >= ci);
+    let state = ctx.state_mut::<Negotiated>();
+    state.data_packets += 1;
+    if let Some(pt) = state.payload_type {
+        me.payload_type = Value::Set(pt);
+    }
+}
+
+fn data(seq: u16) -> Vec<u8> {
+    (Ether!() / IP!() / UDP!(sport = 5000, dport = 40200) / TestData!(seq = seq)).encode()
+}
+
+fn control() -> Vec<u8> {
+    (Ether!()
+        / IP!()
+        / UDP!(sport = 5000, dport = 40100)
+        / TestControl!(data_port = 40200, payload_type = 96))
+    .encode()
+}
+
+fn names(x: &LayerStack) -> Vec<&'static str> {
+    x.layers.iter().map(|l| l.layer_name()).collect()
+}
+
+#[test]
+fn decode_context_across_packets() {
+    let mut ctx = DecodeContext::new();
+    let (x, _) = Ether!().decode_with_context(&data(1), &mut ctx).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+    let (x, _) = Ether!().decode_with_context(&control(), &mut ctx).unwrap();
+    assert_eq!(names(&x)[3], "testControl");
+    assert_eq!(ctx.state::<Negotiated>().unwrap().payload_type, Some(96));
+
+    let (x, _) = Ether!().decode_with_context(&data(2), &mut ctx).unwrap();
+    assert_eq!(names(&x)[3], "testData");
+    assert_eq!(
+        x.get("testData.payload_type").unwrap(),
+        FieldValue::UInt(96)
+    );
+    assert_eq!(x.get("testData.seq").unwrap(), FieldValue::UInt(2));
+
+    /* a fresh context knows nothing of the negotiation */
+    let (x, _) = Ether!().decode(&data(3)).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+}
+
+#[test]
+fn decode_context_pcap() {
+    let mut pcap = PcapFile!();
+    for bytes in [data(1), control(), data(2), data(3), vec![1, 2, 3]] {
+        pcap.push(PcapPacket!(data = bytes));
+    }
+    let mut ctx = DecodeContext::new();
+    let stacks = pcap.decode_packets(&mut ctx);
+    assert_eq!(stacks.len(), 5);
+    assert_eq!(names(&stacks[0])[3], "raw");
+    assert_eq!(names(&stacks[2])[3], "testData");
+    assert_eq!(names(&stacks[3])[3], "testData");
+    assert_eq!(names(&stacks[4]), vec!["raw"]);
+    assert_eq!(ctx.state::<Negotiated>().unwrap().data_packets, 2);
+
+    /* the options in the context apply from the start */
+    let mut opts = DecodeOptions::new();
+    opts.bind("UDP_DST_PORT_APPS", 40200, "TestData").unwrap();
+    let mut ctx = DecodeContext::with_options(opts);
+    let stacks = pcap.decode_packets(&mut ctx);
+    assert_eq!(names(&stacks[0])[3], "testData");
+    assert_eq!(
+        stacks[0].get("testData.payload_type").unwrap(),
+        FieldValue::Auto
+    );
+}
+
+#[test]
+fn decode_context_state() {
+    let mut ctx = DecodeContext::new();
+    assert!(ctx.state::<u32>().is_none());
+    *ctx.state_mut::<u32>() += 5;
+    ctx.set_state(String::from("hello"));
+    assert_eq!(ctx.state::<u32>(), Some(&5));
+    assert_eq!(ctx.take_state::<String>().unwrap(), "hello");
+    assert!(ctx.state::<String>().is_none());
+    ctx.options.enable_heuristics(50);
+    ctx.clear();
+    assert!(ctx.state::<u32>().is_none());
+    assert!(format!("{:?}", ctx).contains("states: 0"));
+}
@@ -0,0 +1,101 @@
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+
+fn udp_to(dport: u16) -> Vec<u8> {
+    (Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 12345, dport = dport)
+        / VXLAN!(vni = 42)
+        / Raw!("the inner frame".as_bytes().to_vec()))
+    .encode()
+}
+
+fn names(x: &LayerStack) -> Vec<&'static str> {
+    x.layers.iter().map(|l| l.layer_name()).collect()
+}
+
+#[test]
+fn decode_options_bind() {
+    let bytes = udp_to(8472);
+    let (plain, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(names(&plain), vec!["ether", "Ip", "Udp", "raw"]);
+
+    let mut opts = DecodeOptions::new();
+    opts.bind("UDP_DST_PORT_APPS", 8472, "vxlan").unwrap();
+    let (x, len) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(len, bytes.len());
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "Vxlan", "raw"]);
+    assert_eq!(x.get("Vxlan.vni").unwrap(), FieldValue::UInt(42));
+
+    /* the registered port still works, and the options do not stay around */
+    let (x, _) = Ether!().decode_with_options(&udp_to(4789), &opts).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "Vxlan", "raw"]);
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "raw"]);
+}
+
+#[test]
+fn decode_options_unbind_and_precedence() {
+    let bytes = udp_to(4789);
+    let mut opts = DecodeOptions::new();
+    opts.unbind("UDP_DST_PORT_APPS", 4789).unwrap();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "raw"]);
+
+    /* the last bind or unbind of a key wins over the registered layer */
+    opts.bind("UDP_DST_PORT_APPS", 4789, "Raw").unwrap();
+    opts.bind("udp_dst_port_apps", 4789, "Ether").unwrap();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "ether");
+
+    opts.reset("UDP_DST_PORT_APPS", 4789).unwrap();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "Vxlan");
+
+    /* the spans are recorded for the layers found through the options too */
+    opts.bind("UDP_DST_PORT_APPS", 8472, "VXLAN").unwrap();
+    let mut map = DissectionMap::new();
+    let bytes = udp_to(8472);
+    Ether!()
+        .decode_into_map_with_options(&bytes, Some(&mut map), &opts)
+        .unwrap();
+    let vni = map.field(3, "vni").unwrap();
+    assert_eq!((vni.offset, vni.length), (46, 3));
+}
+
+#[test]
+fn decode_options_listing() {
+    let mut opts = DecodeOptions::new();
+    let before = opts.bindings("UDP_DST_PORT_APPS").unwrap();
+    assert!(before
+        .iter()
+        .any(|b| b.key == 4789 && b.layer == "VXLAN" && b.source == BindingSource::Static));
+
+    opts.bind("UDP_DST_PORT_APPS", 8472, "VXLAN").unwrap();
+    opts.unbind("UDP_DST_PORT_APPS", 4789).unwrap();
+    let after = opts.bindings("UDP_DST_PORT_APPS").unwrap();
+    assert!(!after.iter().any(|b| b.key == 4789));
+    let b = after.iter().find(|b| b.key == 8472).unwrap();
+    assert_eq!(b.source, BindingSource::Session);
+    assert_eq!(b.to_string(), "UDP_DST_PORT_APPS 8472 => VXLAN (session)");
+    assert_eq!(after.len(), before.len());
+    assert!(after.windows(2).all(|w| w[0].key <= w[1].key));
+
+    let ether = opts.bindings("ETHERTYPE_LAYERS").unwrap();
+    assert!(ether.iter().any(|b| b.key == 0x800));
+}
+
+#[test]
+fn decode_options_errors() {
+    let mut opts = DecodeOptions::new();
+    assert_eq!(
+        opts.bind("UDP_PORTS", 1, "VXLAN"),
+        Err(DecodeOptionsError::UnknownRegistry("UDP_PORTS".into()))
+    );
+    assert_eq!(
+        opts.bind("UDP_DST_PORT_APPS", 1, "Nosuch"),
+        Err(DecodeOptionsError::UnknownLayer("Nosuch".into()))
+    );
+    assert!(opts.bindings("nosuch").is_err());
+}
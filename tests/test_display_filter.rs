@@ -0,0 +1,153 @@
+use scarust::display_filter::*;
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+
+mod common;
+use common::*;
+
+/* a VXLAN packet as it comes off the wire */
+fn vxlan_packet() -> LayerStack {
+    let x = Ether!(src = "52:54:00:12:34:56", dst = "52:54:00:ab:cd:ef")
+        / IP!(ttl = 3, src = "192.0.2.1", dst = "10.1.2.3")
+        / UDP!(sport = 12345, dport = 4789)
+        / VXLAN!(vni = 100)
+        / Ether!(src = "02:00:00:00:00:01", dst = "02:00:00:00:00:02")
+        / IP!(src = "172.16.0.1", dst = "172.16.0.2")
+        / UDP!(sport = 1000, dport = 53)
+        / Raw!("hello world".as_bytes().to_vec());
+    let bytes = x.encode();
+    let mut x = Ether!().decode(&bytes).unwrap().0;
+    /* VXLAN leaves the inner frame as Raw, so decode it here */
+    let inner = x.layers.pop().unwrap().get_field("load").unwrap();
+    let inner = match inner {
+        FieldValue::Bytes(b) => Ether!().decode(&b).unwrap().0,
+        x => panic!("{:?}", x),
+    };
+    x.layers.extend(inner.layers);
+    x
+}
+
+fn check(filter: &str) -> bool {
+    DisplayFilter::parse(filter)
+        .unwrap_or_else(|e| panic!("{:?}: {}", filter, e))
+        .matches(&vxlan_packet())
+}
+
+#[test]
+fn display_filter_compare() {
+    assert!(check("Ip.ttl < 5 && Udp.dport == 4789 && Vxlan.vni == 100"));
+    assert!(!check("Ip.ttl < 5 && Vxlan.vni == 101"));
+    assert!(check("vxlan.vni eq 100 and ip.ttl ge 3"));
+    assert!(check("Vxlan.vni == 0x64"));
+    assert!(check("Ip.dst == 10.1.2.3"));
+    assert!(check("Ip.dst == 10.0.0.0/8"));
+    assert!(check("Ip.src > 192.0.2.0"));
+    assert!(check("Ether.src == 52:54:00:12:34:56"));
+    /* any of the layers: the inner UDP has dport 53 */
+    assert!(check("Udp.dport == 53"));
+    assert!(check("Udp[1].dport == 53"));
+    assert!(!check("Udp[0].dport == 53"));
+    /* "!=" is true only if no instance is equal */
+    assert!(!check("Udp.dport != 53"));
+    assert!(check("Udp.dport != 80"));
+    assert!(check("Udp.sport > Udp.dport"));
+    assert!(check("!(Ip.ttl > 100) || Tcp"));
+    assert!(!check("!(Ip.ttl > 10) || Tcp"));
+}
+
+#[test]
+fn display_filter_contains_in_and_presence() {
+    assert!(check("Vxlan"));
+    assert!(!check("Tcp"));
+    assert!(check("not Tcp and Raw"));
+    assert!(check("Raw.load contains \"world\""));
+    assert!(check("Raw.load contains 6c:6c:6f"));
+    assert!(!check("Raw.load contains \"planet\""));
+    assert!(check("Udp.dport in {53 80 443}"));
+    assert!(check("Udp.dport in {1000..2000, 4789}"));
+    assert!(!check("Udp.sport in {1..999}"));
+    assert!(check("Ip.dst in {172.16.0.0/12}"));
+}
+
+#[test]
+fn display_filter_slices() {
+    assert!(check("Raw.load[0:5] == \"hello\""));
+    assert!(check("Raw.load[-5:] == \"world\""));
+    assert!(check("Raw.load[6-7] == 77:6f"));
+    assert!(check("Raw.load[0] == 68"));
+    assert!(check("Ether.dst[:3] == 52:54:00"));
+    assert!(check("Ip.dst[0] == 0a"));
+    /* the bytes of the layer: version and header length, then the protocol at offset 9 */
+    assert!(check("Ip[0] == 45"));
+    assert!(check("Ip[9:1] == 11"));
+    assert!(!check("Raw.load[100:2] == 00:00"));
+}
+
+#[test]
+fn display_filter_errors() {
+    match DisplayFilter::parse("Nosuch.x == 1") {
+        Err(DisplayFilterError::UnknownLayer { pos: 0, name }) => assert_eq!(name, "Nosuch"),
+        x => panic!("{:?}", x),
+    }
+    match DisplayFilter::parse("Udp.dport == 1 && Ip.nosuch == 2") {
+        Err(DisplayFilterError::UnknownField {
+            pos, layer, field, ..
+        }) => assert_eq!((pos, layer.as_str(), field.as_str()), (21, "Ip", "nosuch")),
+        x => panic!("{:?}", x),
+    }
+    assert!(matches!(
+        DisplayFilter::parse("Udp.dport =="),
+        Err(DisplayFilterError::Syntax { pos: 12, .. })
+    ));
+    assert!(matches!(
+        DisplayFilter::parse("(Udp"),
+        Err(DisplayFilterError::Syntax { pos: 4, .. })
+    ));
+    assert!(matches!(
+        DisplayFilter::parse("Raw.load[x:1] == 00"),
+        Err(DisplayFilterError::BadValue { pos: 9, .. })
+    ));
+    /* the literals and slices the field can never match */
+    for (filter, pos, value) in [
+        ("Ip.src == 10.0.0.0/40", 10, "10.0.0.0/40"),
+        ("Ip.ttl == 300", 10, "300"),
+        ("1.2.3 < Ip.dst", 0, "1.2.3"),
+        ("Udp.dport in {53 70000}", 17, "70000"),
+        ("Udp.dport in {1..x}", 17, "x"),
+        ("Ip.ttl == \"abc\"", 10, "abc"),
+        ("Ip.ttl[0:1] == 40", 7, "0:1"),
+        ("Ip.src[2:4] == 00", 7, "2:4"),
+        ("Ether.dst[-7:] == 00", 10, "-7:"),
+    ] {
+        assert_eq!(
+            DisplayFilter::parse(filter),
+            Err(DisplayFilterError::BadValue {
+                pos,
+                value: value.to_string()
+            }),
+            "{}",
+            filter
+        );
+    }
+    assert!(DisplayFilter::parse("Ip.src[2:2] == 00:00").is_ok());
+    let e = "Ip.nosuch"
+        .parse::<DisplayFilter>()
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("ttl"), "{}", e);
+}
+
+#[test]
+fn display_filter_pcap() {
+    let pcap = read_pcap_file("dhcp.pcap");
+    let filter: DisplayFilter = "Udp.dport == 67".parse().unwrap();
+    let requests = pcap
+        .d
+        .packets
+        .iter()
+        .map(|p| Ether!().decode(&p.data).unwrap().0)
+        .filter(|x| filter.matches(x))
+        .count();
+    assert!(requests > 0 && requests < pcap.d.packets.len());
+}
@@ -0,0 +1,79 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn udp_packet() -> LayerStack {
+    Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!("hello".as_bytes().to_vec())
+}
+
+fn span(
+    map: &DissectionMap,
+    layer_index: usize,
+    field: &str,
+) -> (usize, usize, Option<(usize, usize)>) {
+    let s = map
+        .field(layer_index, field)
+        .unwrap_or_else(|| panic!("no span for {} {}", layer_index, field));
+    (s.offset, s.length, s.bits)
+}
+
+#[test]
+fn decode_map_offsets() {
+    let bytes = udp_packet().encode();
+    let (x, _, map) = Ether!().decode_with_map(&bytes).unwrap();
+    assert_eq!(x.layers.len(), 4);
+    assert_eq!(span(&map, 0, "dst"), (0, 6, None));
+    assert_eq!(span(&map, 0, "etype"), (12, 2, None));
+    assert_eq!(span(&map, 1, "version"), (14, 1, Some((0, 4))));
+    assert_eq!(span(&map, 1, "ihl"), (14, 1, Some((4, 4))));
+    assert_eq!(span(&map, 1, "len"), (16, 2, None));
+    assert_eq!(span(&map, 1, "dst"), (30, 4, None));
+    assert_eq!(span(&map, 2, "dport"), (36, 2, None));
+    assert_eq!(span(&map, 3, "data"), (42, 5, None));
+    assert_eq!(map.field(3, "data").unwrap().layer, "raw");
+
+    let clicked: Vec<&str> = map.at(17).iter().map(|s| s.field).collect();
+    assert_eq!(clicked, vec!["len"]);
+    assert_eq!(map.layer_spans(2).len(), 4);
+}
+
+#[test]
+fn encode_map_matches_decode() {
+    let (bytes, enc_map) = udp_packet().encode_with_map();
+    let (_, _, dec_map) = Ether!().decode_with_map(&bytes).unwrap();
+    let key = |m: &DissectionMap| {
+        let mut v: Vec<(usize, &'static str, usize, usize)> = m
+            .spans
+            .iter()
+            .map(|s| (s.layer_index, s.field, s.offset, s.length))
+            .collect();
+        v.sort();
+        v
+    };
+    assert_eq!(key(&enc_map), key(&dec_map));
+}
+
+#[test]
+fn decode_map_gre_bits() {
+    let x = IP!(id = 1) / GRE!(key_present = true, key = 7u32) / IP!(id = 2);
+    let bytes = x.encode();
+    let (_, _, map) = IP!().decode_with_map(&bytes).unwrap();
+    assert_eq!(span(&map, 1, "key_present"), (20, 1, Some((2, 1))));
+    assert_eq!(span(&map, 1, "flags"), (20, 2, Some((9, 4))));
+    assert_eq!(span(&map, 1, "version"), (20, 2, Some((13, 3))));
+    assert_eq!(span(&map, 1, "key"), (24, 4, None));
+    assert_eq!(span(&map, 2, "src"), (40, 4, None));
+}
+
+#[test]
+fn decode_map_failed_layer_rolled_back() {
+    // UDP header truncated to 3 bytes: no Udp layer, the bytes go to raw
+    let mut bytes = (IP!(id = 1, proto = 17) / Raw!(vec![1, 2, 3])).encode();
+    bytes.truncate(23);
+    let (x, _, map) = IP!().decode_with_map(&bytes).unwrap();
+    assert!(x.get_layer(UDP!()).is_none());
+    assert!(map.spans.iter().all(|s| s.layer != "Udp"));
+    assert_eq!(span(&map, 1, "data"), (20, 3, None));
+}
@@ -0,0 +1,67 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+#[test]
+fn dyn_counter_per_generated_packet() {
+    let x = IP!() / TCP!(seq = Value::from_fn(|ctx| 1000 + ctx.counter as u32));
+    let seqs: Vec<u32> = x
+        .generate(3)
+        .map(|p| p.get_layer(TCP!()).unwrap().seq.value())
+        .collect();
+    assert_eq!(seqs, vec![1000, 1001, 1002]);
+
+    /* a plain fill() is packet 0 */
+    let filled = x.fill();
+    assert_eq!(filled.get_layer(TCP!()).unwrap().seq, Value::Set(1000));
+}
+
+#[test]
+fn dyn_from_other_layer() {
+    /* the multicast MAC address of the destination IP */
+    let mcast_mac = Value::from_fn(|ctx| {
+        let dst = ctx.stack.get_layer(IP!()).unwrap().dst.value();
+        let o = format!("{:?}", dst);
+        let o: Vec<u8> = o.split('.').map(|x| x.parse().unwrap()).collect();
+        MacAddr::new(0x01, 0x00, 0x5e, o[1] & 0x7f, o[2], o[3])
+    });
+    let x = Ether!(dst = mcast_mac) / IP!(dst = "239.1.2.3,224.0.0.251") / UDP!();
+    let macs: Vec<MacAddr> = x
+        .expand()
+        .map(|p| p.get_layer(Ether!()).unwrap().dst.value())
+        .collect();
+    assert_eq!(
+        macs,
+        vec![
+            MacAddr::new(0x01, 0x00, 0x5e, 0x01, 0x02, 0x03),
+            MacAddr::new(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb)
+        ]
+    );
+}
+
+#[test]
+fn dyn_context() {
+    let x = IP!(ttl = 7)
+        / UDP!(
+            sport = Value::from_fn(|ctx| {
+                assert_eq!(ctx.layer_index, 1);
+                ctx.get("IP.ttl").unwrap().as_u64().unwrap() as u16 * 100
+            })
+        );
+    let filled = x.fill();
+    assert_eq!(filled.get_layer(UDP!()).unwrap().sport, Value::Set(700));
+    assert_eq!(x.encode()[20..22], [0x02, 0xbc]);
+}
+
+#[test]
+fn dyn_serialize_and_compare() {
+    let v: Value<u16> = Value::from_fn(|ctx| ctx.counter as u16);
+    assert_eq!(v, v.clone());
+    assert_ne!(v, Value::from_fn(|ctx| ctx.counter as u16));
+    assert_eq!(format!("{:?}", v), "Dyn");
+
+    let x = IP!() / UDP!(dport = v);
+    let err = serde_json::to_string(&x.layers).unwrap_err();
+    assert!(err.to_string().contains("fill() the stack first"));
+    let filled = x.fill();
+    assert!(serde_json::to_string(&filled.layers).is_ok());
+}
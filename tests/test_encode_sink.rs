@@ -0,0 +1,117 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+mod common;
+use common::*;
+
+fn stacks() -> Vec<LayerStack> {
+    vec![
+        Ether!() / IP!(src = "192.0.2.1", dst = "192.0.2.2") / UDP!() / Raw!("hello".into()),
+        Ether!() / IP!() / TCP!(sport = 1234, dport = 80) / Raw!("odd".into()),
+        IP!() / ICMP!() / Echo!(identifier = 7, sequence = 1) / Raw!("ping".into()),
+        IP!() / GRE!(chksum_present = true, proto = 0x6558) / Ether!() / IP!() / UDP!(),
+        Ether!() / IP!() / UDP!() / Raw!("short".into()) / Padding!(vec![0; 10]),
+        Raw!(vec![]).to_stack(),
+    ]
+}
+
+#[test]
+fn encode_to_matches_encode() {
+    for x in stacks() {
+        let x = x.fill();
+        let expected = x.clone().encode();
+        let mut buf = [0xaau8; 256];
+        let len = x.encode_to(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+
+        /* an exactly sized buffer is enough */
+        let mut buf = vec![0; expected.len()];
+        assert_eq!(x.encode_to(&mut buf), Ok(expected.len()));
+        assert_eq!(buf, expected);
+    }
+}
+
+#[test]
+fn encode_to_small_buffer() {
+    let x = Ether!() / IP!() / UDP!() / Raw!(vec![1; 100]);
+    let len = x.clone().encode().len();
+    let mut buf = [0u8; 50];
+    assert_eq!(
+        x.encode_to(&mut buf),
+        Err(EncodeError::BufferTooSmall {
+            needed: len,
+            available: 50
+        })
+    );
+    assert_eq!(
+        x.encode_to(&mut []).unwrap_err().to_string(),
+        format!("the packet needs {} bytes, the buffer has 0", len)
+    );
+}
+
+#[test]
+fn encode_lengths_saturate() {
+    /* a payload too long for the length fields does not panic */
+    let udp = (IP!() / UDP!() / Raw!(vec![0; 70000])).encode();
+    assert_eq!(udp.len(), 70028);
+    assert_eq!(&udp[2..4], &[0xff, 0xff]);
+    assert_eq!(&udp[24..26], &[0xff, 0xff]);
+    let tcp = (IP!() / TCP!() / Raw!(vec![0; 70000])).encode();
+    assert_eq!(tcp.len(), 70040);
+}
+
+#[test]
+fn encode_patched_checksums() {
+    for x in stacks() {
+        let bytes = x.clone().encode();
+        let (decoded, _) = x.layers[0].decode(&bytes).unwrap();
+        assert_eq!(decoded.validate(), vec![]);
+    }
+
+    /* the right checksums of a capture come out the same when computed again */
+    let mut patched = 0;
+    for name in ["pcap1.pcap", "pcap_3pkts.pcap"] {
+        for p in &read_pcap_file(name).d.packets {
+            let (mut x, _) = Ether!().decode(&p.data).unwrap();
+            if !x.validate().is_empty() {
+                /* sent with the checksum offloaded */
+                continue;
+            }
+            for l in x.layers.iter_mut() {
+                if l.get_field("chksum").is_some() {
+                    l.set_field_value("chksum", FieldValue::Auto).unwrap();
+                    patched += 1;
+                }
+            }
+            let mut buf = [0u8; 1600];
+            let len = x.encode_to(&mut buf).unwrap();
+            assert_eq!(&buf[..len], &p.data[..]);
+            assert_eq!(x.encode(), p.data);
+        }
+    }
+    assert!(patched > 3);
+}
+
+#[test]
+fn encode_with_map_offsets() {
+    let x = Ether!() / IP!() / UDP!(sport = 1, dport = 2) / Raw!("abc".into());
+    let (bytes, map) = x.encode_with_map();
+    let dport = map.field(2, "dport").unwrap();
+    assert_eq!((dport.offset, dport.length), (36, 2));
+    assert_eq!(&bytes[36..38], &[0, 2]);
+}
+
+#[test]
+fn encode_checksum_odd_layers() {
+    /* the payload is summed in one piece, even when a layer in it has an odd length */
+    let x = IP!(id = 1) / UDP!(sport = 1, dport = 2) / Raw!("abc".into()) / Raw!("de".into());
+    let bytes = x.clone().encode();
+    let one_piece = (IP!(id = 1) / UDP!(sport = 1, dport = 2) / Raw!("abcde".into())).encode();
+    assert_eq!(bytes, one_piece);
+
+    let (decoded, _) = IP!().decode(&bytes).unwrap();
+    let mut x = x.fill();
+    x.set_value("Udp.chksum", decoded.get("Udp.chksum").unwrap())
+        .unwrap();
+    assert_eq!(x.validate(), vec![]);
+}
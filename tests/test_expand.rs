@@ -0,0 +1,126 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+#[test]
+fn expand_cidr_and_list() {
+    let x = IP!(dst = "10.0.0.0/30") / TCP!(dport = [22, 80, 443]);
+    assert_eq!(x.expand_count(), 12);
+    let pkts: Vec<LayerStack> = x.expand().collect();
+    assert_eq!(pkts.len(), 12);
+    let pairs: Vec<(Ipv4Address, u16)> = pkts
+        .iter()
+        .map(|p| {
+            (
+                p.get_layer(IP!()).unwrap().dst.value(),
+                p.get_layer(TCP!()).unwrap().dport.value(),
+            )
+        })
+        .collect();
+    /* the last field varies fastest */
+    assert_eq!(pairs[0], (Ipv4Address::new(10, 0, 0, 0), 22));
+    assert_eq!(pairs[1], (Ipv4Address::new(10, 0, 0, 0), 80));
+    assert_eq!(pairs[3], (Ipv4Address::new(10, 0, 0, 1), 22));
+    assert_eq!(pairs[11], (Ipv4Address::new(10, 0, 0, 3), 443));
+    /* each packet is filled */
+    for p in &pkts {
+        assert!(p.filled);
+        let ip = p.get_layer(IP!()).unwrap();
+        assert_eq!(ip.proto, Value::Set(6));
+        assert!(matches!(ip.id, Value::Set(_)));
+    }
+}
+
+#[test]
+fn expand_ranges() {
+    let x = UDP!(sport = 1000..1003, dport = 53..=54).to_stack();
+    assert_eq!(x.expand_count(), 6);
+    let ports: Vec<(u16, u16)> = x
+        .expand()
+        .map(|p| {
+            let udp = p.get_layer(UDP!()).unwrap();
+            (udp.sport.value(), udp.dport.value())
+        })
+        .collect();
+    assert_eq!(
+        ports,
+        vec![
+            (1000, 53),
+            (1000, 54),
+            (1001, 53),
+            (1001, 54),
+            (1002, 53),
+            (1002, 54)
+        ]
+    );
+    assert_eq!(UDP!(sport = 5..5).to_stack().expand().count(), 0);
+    /* without a list or a range there is just the packet itself */
+    assert_eq!((IP!() / UDP!()).expand().count(), 1);
+}
+
+#[test]
+fn expand_address_strings() {
+    let x = IP!(dst = "192.0.2.1,192.0.2.5");
+    assert_eq!(x.expand_counts(), vec![("dst", 2)]);
+    assert_eq!(
+        x.dst,
+        Value::List(vec![
+            Ipv4Address::new(192, 0, 2, 1),
+            Ipv4Address::new(192, 0, 2, 5)
+        ])
+    );
+    let x = IP!(src = "192.0.2.250-192.0.3.1").to_stack();
+    assert_eq!(x.expand_count(), 8);
+    let last = x.expand().last().unwrap();
+    assert_eq!(
+        last.get_layer(IP!()).unwrap().src.value(),
+        Ipv4Address::new(192, 0, 3, 1)
+    );
+
+    let x = Ether!(dst = "00:11:22:33:44:*").to_stack();
+    assert_eq!(x.expand_count(), 256);
+    let dsts: Vec<MacAddr> = x
+        .expand()
+        .take(2)
+        .map(|p| p.get_layer(Ether!()).unwrap().dst.value())
+        .collect();
+    assert_eq!(
+        dsts,
+        vec![
+            MacAddr::new(0, 0x11, 0x22, 0x33, 0x44, 0),
+            MacAddr::new(0, 0x11, 0x22, 0x33, 0x44, 1)
+        ]
+    );
+    let x = Ether!(src = ["00:00:00:00:00:01", "00:00:00:00:00:02"]).to_stack();
+    assert_eq!(x.expand_count(), 2);
+}
+
+#[test]
+fn expand_by_name_and_text() {
+    let mut x = IP!() / TCP!();
+    x.set("TCP.dport", "20-22").unwrap();
+    x.set("IP.dst", "10.1.0.0/31").unwrap();
+    assert_eq!(x.expand_count(), 6);
+
+    let x: LayerStack = "IP(dst=\"10.0.0.0/30\")/TCP(dport=\"22,80,443\")"
+        .parse()
+        .unwrap();
+    assert_eq!(x.expand().count(), 12);
+}
+
+#[test]
+fn expand_serde() {
+    let x = IP!(dst = "10.0.0.0/30") / TCP!(dport = [22, 80]);
+    let json = serde_json::to_string(&x.layers).unwrap();
+    assert!(json.contains("{\"range\":[\"10.0.0.0\",\"10.0.0.3\"]}"));
+    assert!(json.contains("{\"list\":[22,80]}"));
+    let layers: Vec<Box<dyn Layer>> = serde_json::from_str(&json).unwrap();
+    let back = LayerStack {
+        layers,
+        filled: false,
+    };
+    assert_eq!(back.expand_count(), 8);
+    assert_eq!(
+        back.get_layer(IP!()).unwrap().dst,
+        x.get_layer(IP!()).unwrap().dst
+    );
+}
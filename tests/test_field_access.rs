@@ -0,0 +1,200 @@
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+#[macro_use]
+extern crate scarust_derive;
+
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/* a field type of a protocol outside the crate, without DynField */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum testKind {
+    #[default]
+    Plain,
+    Marked(u8),
+}
+
+impl std::str::FromStr for testKind {
+    type Err = ValueParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Plain" => Ok(testKind::Plain),
+            _ => Err(ValueParseError::Error),
+        }
+    }
+}
+
+impl Distribution<testKind> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> testKind {
+        testKind::Marked(rng.gen())
+    }
+}
+
+#[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct testOpaque {
+    #[nproto(encode = encode_kind, decode = decode_kind)]
+    pub kind: Value<testKind>,
+    pub len: Value<u16>,
+}
+
+fn encode_kind<E: Encoder>(
+    me: &testOpaque,
+    _stack: &LayerStack,
+    _my_index: usize,
+    _encoded_layers: &EncodingVecVec,
+) -> Vec<u8> {
+    match me.kind.value() {
+        testKind::Plain => vec![0],
+        testKind::Marked(x) => vec![1, x],
+    }
+}
+
+fn decode_kind<D: Decoder>(buf: &[u8], _me: &mut testOpaque) -> Option<(testKind, usize)> {
+    match buf.first()? {
+        0 => Some((testKind::Plain, 1)),
+        _ => Some((testKind::Marked(*buf.get(1)?), 2)),
+    }
+}
+
+#[test]
+fn layer_get_set_field() {
+    let mut ip = IP!(src = "192.0.2.1");
+    assert_eq!(ip.get_field("ttl"), Some(FieldValue::UInt(64)));
+    assert_eq!(ip.get_field("len"), Some(FieldValue::Auto));
+    assert_eq!(
+        ip.get_field("src"),
+        Some(FieldValue::Ipv4("192.0.2.1".into()))
+    );
+    assert_eq!(ip.get_field("nosuch"), None);
+
+    ip.set_field("ttl", "32").unwrap();
+    assert_eq!(ip.ttl, Value::Set(32));
+    ip.set_field("tos", "0x10").unwrap();
+    assert_eq!(ip.tos, Value::Set(0x10));
+    ip.set_field("dst", "198.51.100.1").unwrap();
+    assert_eq!(ip.dst, Value::Set("198.51.100.1".into()));
+    ip.set_field_value("id", FieldValue::UInt(7)).unwrap();
+    assert_eq!(ip.id, Value::Set(7));
+    ip.set_field("id", "auto").unwrap();
+    assert_eq!(ip.id, Value::Auto);
+}
+
+#[test]
+fn layer_set_field_errors() {
+    let mut ip = IP!();
+    assert_eq!(
+        ip.set_field("ttl", "300"),
+        Err(FieldAccessError::BadValue {
+            field: "ttl".to_string(),
+            value: FieldValue::Str("300".to_string())
+        })
+    );
+    assert!(ip.set_field("src", "not-an-address").is_err());
+    assert_eq!(
+        ip.set_field("nosuch", "1"),
+        Err(FieldAccessError::NoSuchField("nosuch".to_string()))
+    );
+    assert_eq!(ip.ttl, Value::Set(64));
+}
+
+#[test]
+fn layer_field_descs() {
+    let descs = UDP!().field_descs();
+    let names: Vec<&str> = descs.iter().map(|d| d.name).collect();
+    assert_eq!(names, vec!["sport", "dport", "len", "chksum"]);
+    assert_eq!(descs[0].typ, "Value<u16>");
+    assert_eq!(descs[2].default, FieldValue::Auto);
+
+    let descs = IP!().field_descs();
+    let ttl = descs.iter().find(|d| d.name == "ttl").unwrap();
+    assert_eq!(ttl.default, FieldValue::UInt(64));
+    let id = descs.iter().find(|d| d.name == "id").unwrap();
+    assert_eq!(id.default, FieldValue::Random);
+}
+
+#[test]
+fn stack_get_set_by_path() {
+    let mut x = Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 4789, dport = 4789)
+        / VXLAN!()
+        / Ether!()
+        / IP!(src = "10.0.0.1", dst = "10.0.0.2")
+        / UDP!(sport = 1000, dport = 2000);
+
+    assert_eq!(x.get("IP.src"), Ok(FieldValue::Ipv4("192.0.2.1".into())));
+    assert_eq!(x.get("Ip[1].src"), Ok(FieldValue::Ipv4("10.0.0.1".into())));
+    assert_eq!(x.get("UDP[1].dport"), Ok(FieldValue::UInt(2000)));
+
+    x.set("UDP[1].dport", "53").unwrap();
+    x.set("ether[1].dst", "02:00:00:00:00:02").unwrap();
+    assert_eq!(x.get("UDP.dport"), Ok(FieldValue::UInt(4789)));
+
+    assert_eq!(x.get("UDP[1].dport"), Ok(FieldValue::UInt(53)));
+    assert_eq!(
+        x.get("ether[1].dst"),
+        Ok(FieldValue::Mac("02:00:00:00:00:02".into()))
+    );
+
+    let mut x = Ether!() / IP!() / IP!(src = "10.0.0.1") / UDP!();
+    x.set("IP[1].ttl", "5").unwrap();
+    let y = Ether!().decode(&x.encode()).unwrap().0;
+    assert_eq!(y.get("IP[1].ttl"), Ok(FieldValue::UInt(5)));
+    assert_eq!(y.get("IP[1].src"), Ok(FieldValue::Ipv4("10.0.0.1".into())));
+}
+
+#[test]
+fn stack_path_errors() {
+    let mut x = IP!() / UDP!();
+    assert_eq!(
+        x.get("TCP.sport"),
+        Err(FieldAccessError::NoSuchLayer("TCP".to_string()))
+    );
+    assert_eq!(
+        x.get("UDP[1].sport"),
+        Err(FieldAccessError::NoSuchLayer("UDP[1]".to_string()))
+    );
+    assert_eq!(
+        x.get("UDP.nosuch"),
+        Err(FieldAccessError::NoSuchField("nosuch".to_string()))
+    );
+    assert_eq!(
+        x.set("UDP", "1"),
+        Err(FieldAccessError::BadPath("UDP".to_string()))
+    );
+}
+
+#[test]
+fn arp_addresses_by_name() {
+    let mut arp = ARP!();
+    arp.set_field("pdst", "192.0.2.7").unwrap();
+    arp.set_field("hwsrc", "02:00:00:00:00:01").unwrap();
+    assert_eq!(arp.pdst, "192.0.2.7".into());
+    assert_eq!(
+        arp.get_field("hwsrc"),
+        Some(FieldValue::Mac("02:00:00:00:00:01".into()))
+    );
+    assert!(arp.set_field("psrc", "nonsense").is_err());
+}
+
+#[test]
+fn field_type_without_dyn_field() {
+    let mut x = TestOpaque!(len = 3);
+    x.kind = Value::Set(testKind::Marked(7));
+    assert_eq!(
+        x.get_field("kind"),
+        Some(FieldValue::Str("Marked(7)".into()))
+    );
+    assert_eq!(x.get_field("len"), Some(FieldValue::UInt(3)));
+    /* it is shown, but can not be set by name */
+    assert!(x.set_field("kind", "Plain").is_err());
+    x.set_field("kind", "auto").unwrap();
+    assert_eq!(x.kind, Value::Auto);
+
+    let bytes = (TestOpaque!(len = 3) / Raw!("x".into())).encode();
+    assert_eq!(bytes, vec![0, 0, 3, b'x']);
+    let (decoded, _) = TestOpaque!().decode(&bytes).unwrap();
+    assert_eq!(decoded.get("testOpaque.len").unwrap(), FieldValue::UInt(3));
+}
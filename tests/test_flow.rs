@@ -0,0 +1,135 @@
+use scarust::flow::*;
+use scarust::protocols::all::*;
+use scarust::protocols::pcap_file::*;
+use scarust::*;
+use std::time::Duration;
+
+fn tcp(client: bool, flags: u8, data: &str) -> LayerStack {
+    let (src, dst, smac, dmac, sport, dport) = if client {
+        (
+            "192.0.2.10",
+            "192.0.2.1",
+            "02:00:00:00:00:0a",
+            "02:00:00:00:00:01",
+            40000,
+            80,
+        )
+    } else {
+        (
+            "192.0.2.1",
+            "192.0.2.10",
+            "02:00:00:00:00:01",
+            "02:00:00:00:00:0a",
+            80,
+            40000,
+        )
+    };
+    Ether!(src = smac, dst = dmac)
+        / IP!(src = src, dst = dst, id = 1)
+        / TCP!(sport = sport, dport = dport, flags = flags)
+        / Raw!(data.as_bytes().to_vec())
+}
+
+fn udp() -> LayerStack {
+    Ether!(src = "02:00:00:00:00:0a", dst = "02:00:00:00:00:01")
+        / IP!(src = "192.0.2.10", dst = "192.0.2.53", id = 2)
+        / UDP!(sport = 5353, dport = 53)
+        / Raw!(vec![0; 10])
+}
+
+fn table() -> FlowTable {
+    let mut ft = FlowTable::new();
+    let pkts = vec![
+        tcp(true, TCP_FLAG_SYN, ""),
+        tcp(false, TCP_FLAG_SYN | TCP_FLAG_ACK, ""),
+        tcp(true, TCP_FLAG_ACK, "hello"),
+        udp(),
+        tcp(false, TCP_FLAG_FIN | TCP_FLAG_ACK, ""),
+    ];
+    for (i, p) in pkts.into_iter().enumerate() {
+        let (d, _) = Ether!().decode(&p.encode()).unwrap();
+        ft.push(&d, Duration::from_millis(1000 + 500 * i as u64));
+    }
+    ft
+}
+
+#[test]
+fn flow_symmetric_keys() {
+    let ft = table();
+    assert_eq!(ft.conversations(ConversationType::Ethernet).len(), 1);
+    assert_eq!(ft.conversations(ConversationType::Ipv4).len(), 2);
+    let tcp = ft.conversations(ConversationType::Tcp);
+    assert_eq!(tcp.len(), 1);
+    let (key, stats) = tcp[0];
+    assert_eq!(
+        key,
+        &FlowKey::Tcp {
+            a: "192.0.2.1".into(),
+            a_port: 80,
+            b: "192.0.2.10".into(),
+            b_port: 40000,
+        }
+    );
+    assert_eq!(stats.a_to_b.packets, 2);
+    assert_eq!(stats.b_to_a.packets, 2);
+    assert_eq!(stats.b_to_a.bytes, 54 + 59);
+    assert_eq!(stats.a_to_b.syn, 1);
+    assert_eq!(stats.a_to_b.fin, 1);
+    assert_eq!(tcp_flags_string(stats.a_to_b.tcp_flags), "FIN,SYN,ACK");
+    assert_eq!(stats.first_seen, Duration::from_millis(1000));
+    assert_eq!(stats.duration(), Duration::from_millis(2000));
+}
+
+#[test]
+fn flow_csv_export() {
+    let ft = table();
+    let csv = ft.to_csv(ConversationType::Udp);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("\"Address A\",\"Port A\",\"Address B\",\"Port B\""));
+    assert_eq!(
+        lines[1],
+        "\"192.0.2.10\",\"5353\",\"192.0.2.53\",\"53\",\"1\",\"52\",\"1\",\"52\",\"0\",\"0\",\"1.500000\",\"0.000000\",\"\",\"\""
+    );
+
+    /* the flags seen in each direction, for TCP only */
+    let csv = ft.to_csv(ConversationType::Tcp);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].ends_with("\"TCP Flags A \u{2192} B\",\"TCP Flags B \u{2192} A\""));
+    assert!(
+        lines[1].ends_with("\"2.000000\",\"FIN,SYN,ACK\",\"SYN,ACK\""),
+        "{}",
+        lines[1]
+    );
+}
+
+#[test]
+fn flow_json_export() {
+    let ft = table();
+    let v: serde_json::Value = serde_json::from_str(&ft.to_json()).unwrap();
+    assert_eq!(v["ethernet"][0]["packets"], 5);
+    assert_eq!(v["tcp"][0]["port_a"], 80);
+    assert_eq!(v["tcp"][0]["tcp_flags_b_to_a"], "SYN,ACK");
+    assert_eq!(v["udp"][0]["rel_start"], 1.5);
+    assert!(v["ipv4"][0]["port_a"].is_null());
+}
+
+#[test]
+fn flow_from_pcap() {
+    let mut pcap = PcapFile!();
+    pcap.push(PcapPacket!(
+        ts_sec = 10,
+        ts_usec = 250000,
+        data = udp().encode()
+    ));
+    pcap.push(PcapPacket!(ts_sec = 11, ts_usec = 0, data = udp().encode()));
+    let bytes = pcap.to_stack().encode();
+    let (decoded, _) = PcapFile!().decode(&bytes).unwrap();
+    let mut ft = FlowTable::new();
+    for p in &decoded.get_layer(PcapFile!()).unwrap().d.packets {
+        ft.push_packet(p);
+    }
+    let udp = ft.conversations(ConversationType::Udp);
+    assert_eq!(udp[0].1.packets(), 2);
+    assert_eq!(udp[0].1.duration(), Duration::from_millis(750));
+}
@@ -0,0 +1,176 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use scarust::frag::*;
+use scarust::protocols::all::*;
+use scarust::*;
+use std::time::Duration;
+
+fn big_udp() -> LayerStack {
+    let payload: Vec<u8> = (0..1000).map(|x| (x % 251) as u8).collect();
+    Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 0x1234)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!(payload)
+}
+
+fn decode_all(frags: &[LayerStack]) -> Vec<LayerStack> {
+    frags
+        .iter()
+        .map(|f| Ether!().decode(&f.clone().encode()).unwrap().0)
+        .collect()
+}
+
+#[test]
+fn fragment_offsets() {
+    let frags = fragment(&big_udp(), 500);
+    // 1008 bytes of IP payload, 480 per fragment
+    assert_eq!(frags.len(), 3);
+    let decoded = decode_all(&frags);
+    let mut expected_offset = 0;
+    for (i, d) in decoded.iter().enumerate() {
+        let ip = d.get_layer(IP!()).unwrap();
+        assert!(ip.len.value() <= 500);
+        assert_eq!(ip.frag_offset(), expected_offset);
+        assert_eq!(ip.more_fragments(), i < 2);
+        assert!(d.validate().iter().all(|m| m.layer != "Ip"));
+        expected_offset += ip.len.value() as usize - 20;
+    }
+    assert_eq!(expected_offset, 1008);
+}
+
+#[test]
+fn defragment_reverse_order() {
+    let orig = big_udp();
+    let frags = fragment_with(
+        &orig,
+        &FragmentOptions {
+            mtu: 300,
+            order: FragmentOrder::Reverse,
+            ..Default::default()
+        },
+    );
+    let mut defrag = Defragmenter::new();
+    let mut out = vec![];
+    for d in decode_all(&frags) {
+        out.extend(defrag.push(d, Duration::from_secs(1)));
+    }
+    assert_eq!(out.len(), 1);
+    assert_eq!(defrag.pending(), 0);
+    assert!(out[0].get_layer(UDP!()).is_some());
+    assert_eq!(out[0].clone().encode(), orig.encode());
+}
+
+#[test]
+fn defragment_tiny_overlapping() {
+    let orig = big_udp();
+    let opts = FragmentOptions {
+        mtu: 200,
+        first_fragment_len: Some(8),
+        overlap: 16,
+        order: FragmentOrder::Random,
+    };
+    let frags = fragment_with(&orig, &opts);
+    let decoded = decode_all(&frags);
+    let first = decoded
+        .iter()
+        .find(|d| d.get_layer(IP!()).unwrap().frag_offset() == 0)
+        .unwrap();
+    assert_eq!(first.get_layer(IP!()).unwrap().len, Value::Set(28));
+
+    let mut defrag = Defragmenter::new();
+    let out: Vec<LayerStack> = decoded
+        .into_iter()
+        .filter_map(|d| defrag.push(d, Duration::from_secs(1)))
+        .collect();
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].clone().encode(), orig.encode());
+}
+
+fn conflicting_fragments() -> Vec<LayerStack> {
+    let ip = IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 7, proto = 253);
+    vec![
+        ip.clone().frag(IP_FLAG_MF) / Raw!(vec![1; 16]),
+        ip.clone().frag(1) / Raw!(vec![2; 16]),
+    ]
+}
+
+#[test]
+fn defragment_overlap_policy() {
+    for (policy, expected) in [(OverlapPolicy::First, 1), (OverlapPolicy::Last, 2)] {
+        let mut defrag = Defragmenter::new().overlap_policy(policy);
+        let mut out = vec![];
+        for f in conflicting_fragments() {
+            let (d, _) = IP!().decode(&f.encode()).unwrap();
+            out.extend(defrag.push(d, Duration::from_secs(1)));
+        }
+        assert_eq!(out.len(), 1);
+        let data = &out[0].get_layer(Raw!()).unwrap().data;
+        assert_eq!(data.len(), 24);
+        assert_eq!(data[0], 1);
+        assert_eq!(data[8], expected);
+        assert_eq!(data[23], 2);
+    }
+}
+
+#[test]
+fn defragment_timeout() {
+    let mut defrag = Defragmenter::new().timeout(Duration::from_secs(10));
+    let frags = conflicting_fragments();
+    let (d, _) = IP!().decode(&frags[0].clone().encode()).unwrap();
+    assert!(defrag.push(d, Duration::from_secs(1)).is_none());
+    assert_eq!(defrag.pending(), 1);
+
+    // the rest of the datagram arrives too late
+    let (d, _) = IP!().decode(&frags[1].clone().encode()).unwrap();
+    assert!(defrag.push(d, Duration::from_secs(20)).is_none());
+    assert_eq!(defrag.pending(), 1);
+    assert_eq!(defrag.expire(Duration::from_secs(40)), 1);
+    assert_eq!(defrag.pending(), 0);
+}
+
+#[test]
+fn unfragmented_passes_through() {
+    let mut defrag = Defragmenter::new();
+    let (d, _) = Ether!().decode(&big_udp().encode()).unwrap();
+    let out = defrag.push(d.clone(), Duration::from_secs(0)).unwrap();
+    assert!(out.get_layer(UDP!()).is_some());
+}
+
+#[test]
+fn fragment_random_order_seeded() {
+    let opts = FragmentOptions {
+        mtu: 100,
+        order: FragmentOrder::Random,
+        ..Default::default()
+    };
+    let offsets = |seed: u64| -> Vec<usize> {
+        fragment_with_rng(&big_udp(), &opts, &mut StdRng::seed_from_u64(seed))
+            .iter()
+            .map(|f| f.get_layer(IP!()).unwrap().frag_offset())
+            .collect()
+    };
+    assert_eq!(offsets(42), offsets(42));
+    assert_ne!(offsets(42), offsets(43));
+    let mut sorted = offsets(42);
+    sorted.sort();
+    assert_eq!(sorted, offsets_in_order(&big_udp(), 100));
+}
+
+fn offsets_in_order(stack: &LayerStack, mtu: usize) -> Vec<usize> {
+    fragment(stack, mtu)
+        .iter()
+        .map(|f| f.get_layer(IP!()).unwrap().frag_offset())
+        .collect()
+}
+
+#[test]
+fn defragment_bogus_length() {
+    /* MF set and a length of 0, as after GRO, or shorter than the header */
+    for len in [0u16, 10] {
+        let x = Ether!() / IP!(id = 7, frag = IP_FLAG_MF, len = len) / Raw!(vec![1; 40]);
+        let (d, _) = Ether!().decode(&x.encode()).unwrap();
+        let mut defrag = Defragmenter::new();
+        assert!(defrag.push(d, Duration::from_secs(0)).is_none());
+        assert_eq!(defrag.pending(), 1);
+    }
+}
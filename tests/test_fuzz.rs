@@ -0,0 +1,134 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use scarust::fuzz::*;
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn seed() -> LayerStack {
+    let bytes = (Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1234)
+        / UDP!(sport = 1024, dport = 53)
+        / Raw!("hello".into()))
+    .encode();
+    Ether!().decode(&bytes).unwrap().0
+}
+
+fn checksum_mismatches(bytes: &[u8]) -> Vec<&'static str> {
+    match Ether!().decode(bytes) {
+        Some((stack, _)) => stack
+            .validate()
+            .iter()
+            .map(|m| m.field)
+            .filter(|f| is_checksum_field(f))
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[test]
+fn fuzz_randomizes_set_fields() {
+    let x = IP!(dst = "192.0.2.1", ttl = 5) / UDP!(dport = 53);
+    let f = x.fuzz();
+    let ip = f.get_layer(IP!()).unwrap();
+    assert_eq!(ip.dst, Value::Random);
+    assert_eq!(ip.ttl, Value::Random);
+    /* the Auto fields are left for fill() to compute */
+    assert_eq!(ip.chksum, Value::Auto);
+    assert_eq!(f.get_layer(UDP!()).unwrap().dport, Value::Random);
+
+    let a = f.fill_with_rng(&mut StdRng::seed_from_u64(5)).encode();
+    let b = f.fill_with_rng(&mut StdRng::seed_from_u64(5)).encode();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn fuzz_mutator_seeded() {
+    let run = |s| -> Vec<Mutant> { Mutator::new(&seed()).seeded(s).take(50).collect() };
+    let a = run(1);
+    assert_eq!(a, run(1));
+    assert_ne!(a, run(2));
+    let orig = seed().encode();
+    assert!(a
+        .iter()
+        .all(|m| m.bytes != orig || matches!(m.mutation, Mutation::Truncate { .. })));
+}
+
+/* the boundary mutants that leave the layers decoding the same way */
+fn same_dissection(mode: ChecksumMode) -> Vec<Mutant> {
+    Mutator::new(&seed())
+        .seeded(3)
+        .checksums(mode)
+        .kinds(&[MutationKind::Boundary])
+        .take(100)
+        .filter(|m| match m.mutation {
+            Mutation::Boundary { field, .. } => {
+                !is_length_field(field) && !["etype", "version", "proto"].contains(&field)
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+#[test]
+fn fuzz_mutator_checksums() {
+    let fixed = same_dissection(ChecksumMode::Fix);
+    assert!(!fixed.is_empty());
+    for m in fixed.iter() {
+        assert!(checksum_mismatches(&m.bytes).is_empty(), "{:?}", m.mutation);
+    }
+
+    let broken = same_dissection(ChecksumMode::Break);
+    assert!(!broken.is_empty());
+    for m in broken.iter() {
+        assert_eq!(checksum_mismatches(&m.bytes).len(), 1, "{:?}", m.mutation);
+    }
+
+    /* Keep leaves the checksums of the seed, which no longer match */
+    let kept = same_dissection(ChecksumMode::Keep);
+    assert!(kept
+        .iter()
+        .any(|m| !checksum_mismatches(&m.bytes).is_empty()));
+}
+
+#[test]
+fn fuzz_mutator_truncate_and_length() {
+    let len = seed().encode().len();
+    for m in Mutator::new(&seed())
+        .seeded(9)
+        .kinds(&[MutationKind::Truncate])
+        .take(30)
+    {
+        match m.mutation {
+            Mutation::Truncate { len: l } => {
+                assert!(l < len);
+                assert_eq!(m.bytes.len(), l);
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+    for m in Mutator::new(&seed())
+        .seeded(9)
+        .kinds(&[MutationKind::LengthLie])
+        .take(30)
+    {
+        match m.mutation {
+            Mutation::LengthLie { field, .. } => assert!(is_length_field(field)),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
+
+#[test]
+fn fuzz_boundary_values() {
+    assert_eq!(boundary_values(1), vec![0, 1]);
+    assert_eq!(boundary_values(8), vec![0, 1, 127, 128, 254, 255]);
+    assert_eq!(boundary_values(64)[5], u64::MAX);
+
+    let all = Mutator::new(&seed()).boundary_mutants();
+    let ttls: Vec<u8> = all
+        .iter()
+        .filter(|m| matches!(m.mutation, Mutation::Boundary { field: "ttl", .. }))
+        .map(|m| m.bytes[14 + 8])
+        .collect();
+    assert_eq!(ttls, vec![0, 1, 127, 128, 254, 255]);
+}
@@ -0,0 +1,108 @@
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+
+mod common;
+use common::*;
+
+fn names(x: &LayerStack) -> Vec<&'static str> {
+    x.layers.iter().map(|l| l.layer_name()).collect()
+}
+
+/* VXLAN on a port nobody registered */
+fn vxlan_on(dport: u16, inner: LayerStack) -> Vec<u8> {
+    let mut x = Ether!() / IP!() / UDP!(sport = 40000, dport = dport) / VXLAN!(vni = 7);
+    x.layers.extend(inner.layers);
+    x.encode()
+}
+
+#[test]
+fn heuristics_off_by_default() {
+    let bytes = vxlan_on(9999, Ether!() / IP!());
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "raw"]);
+    let (x, _) = Ether!()
+        .decode_with_options(&bytes, &DecodeOptions::new())
+        .unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "raw"]);
+}
+
+#[test]
+fn heuristics_vxlan_and_geneve() {
+    let mut opts = DecodeOptions::new();
+    opts.enable_heuristics(50);
+    let bytes = vxlan_on(9999, Ether!() / IP!());
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Udp", "Vxlan", "raw"]);
+    assert_eq!(x.get("Vxlan.vni").unwrap(), FieldValue::UInt(7));
+
+    /* without an Ethernet header after it the guess is weaker */
+    let bytes = vxlan_on(
+        9999,
+        Raw!("abcdefghijklmnopqrstuvwxyz".as_bytes().to_vec()).to_stack(),
+    );
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+    opts.enable_heuristics(30);
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "Vxlan");
+
+    use scarust::protocols::geneve::*;
+    let bytes = (Ether!()
+        / IP!()
+        / UDP!(sport = 40000, dport = 7777)
+        / GENEVE!(vni = 9, protocol = 0x6558)
+        / Ether!()
+        / IP!())
+    .encode();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "Geneve");
+    assert_eq!(names(&x)[4], "ether");
+
+    opts.skip_heuristic("Geneve").unwrap();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+    opts.disable_heuristics();
+    let bytes = vxlan_on(9999, Ether!() / IP!());
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+}
+
+#[test]
+fn heuristics_dhcp_on_other_ports() {
+    let pcap = read_pcap_file("dhcp.pcap");
+    let mut bytes = pcap.d.packets[0].data.clone();
+    /* move both UDP ports away from 67 and 68 */
+    bytes[34..38].copy_from_slice(&[0x10, 0x00, 0x10, 0x01]);
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(names(&x)[3], "raw");
+
+    let mut opts = DecodeOptions::new();
+    opts.enable_heuristics(50);
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "Bootp");
+    assert!(names(&x).contains(&"Dhcp"));
+
+    let guesses = probe_heuristics(&UDP_HEURISTICS, &bytes[42..]);
+    assert_eq!(guesses[0], ("BOOTP", 95));
+}
+
+#[test]
+fn heuristics_after_registries() {
+    /* a registered port is decoded by its layer, even if a probe would say otherwise */
+    let pcap = read_pcap_file("dhcp.pcap");
+    let mut bytes = pcap.d.packets[0].data.clone();
+    bytes[34..36].copy_from_slice(&4000u16.to_be_bytes());
+    bytes[36..38].copy_from_slice(&4789u16.to_be_bytes());
+    let mut opts = DecodeOptions::new();
+    opts.enable_heuristics(1);
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x)[3], "Vxlan");
+
+    /* nothing in the TCP table guesses an HTTP request */
+    let bytes =
+        (Ether!() / IP!() / TCP!() / Raw!("GET / HTTP/1.0\r\n".as_bytes().to_vec())).encode();
+    let (x, _) = Ether!().decode_with_options(&bytes, &opts).unwrap();
+    assert_eq!(names(&x), vec!["ether", "Ip", "Tcp", "raw"]);
+    assert!(probe_heuristics(&TCP_HEURISTICS, b"GET / HTTP/1.0\r\n").is_empty());
+}
@@ -0,0 +1,122 @@
+use scarust::*;
+
+use scarust::protocols::all::*;
+
+mod common;
+use common::*;
+
+#[test]
+fn decode_ether_padding() {
+    let pkts = read_pcap("pcap_3pkts.pcap");
+    let d = &pkts[2];
+    let (x, len) = Ether!().decode(d).unwrap();
+    assert_eq!(len, d.len());
+    let pad = x.get_layer(Padding!()).expect("padding layer");
+    assert_eq!(pad.data, vec![0; 6]);
+    assert!(x.get_layer(Raw!()).is_none());
+    assert_eq!(x.validate(), vec![]);
+    assert_eq!(&x.encode(), d);
+}
+
+#[test]
+fn decode_udp_len_bounds_payload() {
+    let mut bytes =
+        (IP!(id = 1) / UDP!(sport = 1234, dport = 5678) / Raw!("hello".as_bytes().to_vec()))
+            .encode();
+    bytes.extend_from_slice(&[0xaa, 0xbb]);
+    // IP covers the two extra bytes, UDP does not
+    bytes[3] += 2;
+    bytes[10] = 0;
+    bytes[11] = 0;
+    let (x, len) = IP!().decode(&bytes).unwrap();
+    assert_eq!(len, bytes.len());
+    assert_eq!(x.get_layer(Raw!()).unwrap().data, "hello".as_bytes());
+    assert_eq!(x.get_layer(Padding!()).unwrap().data, vec![0xaa, 0xbb]);
+    /* the padding left out by UDP is still in the IP length */
+    let mismatches = x.validate();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!((mismatches[0].layer, mismatches[0].field), ("Ip", "chksum"));
+    assert_eq!(x.encode(), bytes);
+}
+
+#[test]
+fn decode_ip_len_bounds_frame() {
+    let inner = (IP!(id = 1) / UDP!(sport = 1234, dport = 5678) / Raw!("hello".into())).encode();
+    let mut bytes = (Ether!(etype = 0x800) / Raw!(inner)).encode();
+    /* UDP leaves out two bytes, IP three more, the frame is the rest */
+    bytes.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee]);
+    bytes[14 + 3] += 2;
+    let (mut x, len) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(len, bytes.len());
+    let pads: Vec<Vec<u8>> = x
+        .layers_of(Padding!())
+        .iter()
+        .map(|p| p.data.clone())
+        .collect();
+    assert_eq!(pads, vec![vec![0xaa, 0xbb], vec![0xcc, 0xdd, 0xee]]);
+    assert_eq!(x.payload_range(1), 2..5);
+    assert_eq!(x.payload_range(2), 3..4);
+    assert_eq!(x.payload_range(0), 1..6);
+
+    /* the checksums were left as they were, the lengths are right */
+    let mismatches = x.validate();
+    assert!(
+        mismatches.iter().all(|m| m.field == "chksum"),
+        "{:?}",
+        mismatches
+    );
+    x.set_value("Ip.chksum", FieldValue::Auto).unwrap();
+    x.set_value("Udp.chksum", FieldValue::Auto).unwrap();
+    let (y, _) = Ether!().decode(&x.clone().encode()).unwrap();
+    assert_eq!(y.validate(), vec![]);
+    assert_eq!(y.encode(), x.encode());
+}
+
+#[test]
+fn encode_padded_ether() {
+    let x = Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!("hi".as_bytes().to_vec());
+    let short = x.clone().encode();
+    assert_eq!(short.len(), 44);
+    let padded = x.encode_padded(ETHER_MIN_LEN);
+    assert_eq!(padded.len(), ETHER_MIN_LEN);
+    assert_eq!(&padded[..44], &short[..]);
+
+    let (decoded, _) = Ether!().decode(&padded).unwrap();
+    assert_eq!(decoded.get_layer(Raw!()).unwrap().data, "hi".as_bytes());
+    assert_eq!(decoded.get_layer(Padding!()).unwrap().data, vec![0; 16]);
+    assert_eq!(decoded.validate(), vec![]);
+}
+
+#[test]
+fn padding_owner_restacked() {
+    let x = Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!("hi".as_bytes().to_vec());
+    let (decoded, _) = Ether!().decode(&x.encode_padded(ETHER_MIN_LEN)).unwrap();
+    assert_eq!(decoded.get_layer(Padding!()).unwrap().owner, Value::Set(3));
+    assert_eq!(decoded.payload_range(1), 2..4);
+
+    /* a layer added before the padding makes the owner point at UDP instead of IP */
+    let mut y = decoded.clone();
+    y.layers.insert(4, Raw!("!".into()).embox());
+    assert_eq!(y.payload_range(1), 2..6);
+    assert_eq!(y.payload_range(2), 3..5);
+
+    /* once set again, the padding is out of the IP length again */
+    y.layers[5] = Padding!(owner = 4, data = vec![0; 16]).embox();
+    assert_eq!(y.payload_range(1), 2..5);
+    y.set_value("Ip.len", FieldValue::Auto).unwrap();
+    y.set_value("Udp.len", FieldValue::Auto).unwrap();
+    let (z, _) = Ether!().decode(&y.clone().encode()).unwrap();
+    assert_eq!(z.get_layer(IP!()).unwrap().len.value(), 31);
+    assert_eq!(z.get_layer(UDP!()).unwrap().len.value(), 11);
+    assert_eq!(z.get_layer(Padding!()).unwrap().data, vec![0; 16]);
+
+    /* or as Auto, when the owner is not known */
+    y.layers[5] = Padding!(data = vec![0; 16]).embox();
+    assert_eq!(y.payload_range(1), 2..5);
+}
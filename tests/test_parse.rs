@@ -0,0 +1,88 @@
+use scarust::parse::*;
+use scarust::protocols::all::*;
+use scarust::*;
+
+#[test]
+fn parse_scapy_expression() {
+    let x = parse_packet(
+        r#"Ether(dst="ff:ff:ff:ff:ff:ff")/IP(dst="1.2.3.4",ttl=5)/UDP(dport=53)/Raw(load="abc")"#,
+    )
+    .unwrap();
+    let y = Ether!(dst = "ff:ff:ff:ff:ff:ff")
+        / IP!(dst = "1.2.3.4", ttl = 5)
+        / UDP!(dport = 53)
+        / Raw!("abc".as_bytes().to_vec());
+    assert_eq!(x.layers.len(), 4);
+    assert_eq!(x[IP!()].ttl, Value::Set(5));
+    assert_eq!(x[Raw!()].data, b"abc".to_vec());
+    /* the IP id is random by default */
+    let mut x = x;
+    x.set("IP.id", "1").unwrap();
+    let mut y = y;
+    y.set("IP.id", "1").unwrap();
+    assert_eq!(x.encode(), y.encode());
+}
+
+#[test]
+fn parse_layer_names_and_spacing() {
+    let x: LayerStack = " ip ( src = '192.0.2.1' , tos=0x10, ) / udp / Raw "
+        .parse()
+        .unwrap();
+    assert_eq!(
+        x.summary(),
+        "IP 192.0.2.1 > 127.0.0.1 / UDP Auto > Auto / Raw 0 bytes"
+    );
+    assert_eq!(x.get("IP.tos"), Ok(FieldValue::UInt(0x10)));
+    assert!(layer_names().contains(&"IP"));
+    assert!(make_layer_by_name("ether").is_some());
+}
+
+#[test]
+fn parse_bytes_and_escapes() {
+    let x = parse_packet(r#"Raw(load=b"\x00\xff\n\"")"#).unwrap();
+    assert_eq!(x[Raw!()].data, vec![0x00, 0xff, b'\n', b'"']);
+    let x = parse_packet(r#"Raw(data='it\'s')"#).unwrap();
+    assert_eq!(x[Raw!()].data, b"it's".to_vec());
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(
+        parse_packet("IP()/UPD()").unwrap_err(),
+        PacketParseError::UnknownLayer {
+            pos: 5,
+            name: "UPD".to_string()
+        }
+    );
+    let err = parse_packet("IP(ttll=5)").unwrap_err();
+    assert_eq!(
+        err,
+        PacketParseError::UnknownField {
+            pos: 3,
+            layer: "Ip".to_string(),
+            field: "ttll".to_string(),
+            fields: IP!().field_descs().iter().map(|d| d.name).collect(),
+        }
+    );
+    assert!(err
+        .to_string()
+        .starts_with("at offset 3: Ip has no field \"ttll\" (fields: version, ihl, tos, len, id"));
+    let err = parse_packet("IP(ttl=300)").unwrap_err();
+    assert_eq!(err.to_string(), "at offset 7: bad value \"300\" for Ip.ttl");
+    assert_eq!(
+        parse_packet("IP(ttl 5)").unwrap_err(),
+        PacketParseError::Syntax {
+            pos: 7,
+            expected: "'='"
+        }
+    );
+    assert_eq!(
+        parse_packet("IP(dst=\"1.2.3.4)").unwrap_err(),
+        PacketParseError::Syntax {
+            pos: 16,
+            expected: "the closing quote"
+        }
+    );
+    assert!(parse_packet("IP() UDP()").is_err());
+    assert!(parse_packet("").is_err());
+}
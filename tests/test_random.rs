@@ -0,0 +1,88 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn packet() -> LayerStack {
+    Ether!() / IP!(dst = "192.0.2.1") / UDP!(sport = Value::Random, dport = 53)
+}
+
+#[test]
+fn random_seeded_fill() {
+    let a = packet().fill_with_rng(&mut StdRng::seed_from_u64(42));
+    let b = packet().fill_with_rng(&mut StdRng::seed_from_u64(42));
+    let c = packet().fill_with_rng(&mut StdRng::seed_from_u64(43));
+    assert!(matches!(a.get_layer(IP!()).unwrap().id, Value::Set(_)));
+    assert_eq!(a.clone().encode(), b.encode());
+    assert_ne!(a.encode(), c.encode());
+}
+
+#[test]
+fn random_seeded_generate() {
+    let run = || -> Vec<Vec<u8>> {
+        packet()
+            .generate(4)
+            .with_rng(StdRng::seed_from_u64(7))
+            .map(|p| p.encode())
+            .collect()
+    };
+    let first = run();
+    assert_eq!(first, run());
+    /* the packets draw different values from the same generator */
+    assert_ne!(first[0], first[1]);
+}
+
+#[test]
+fn random_constraints() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let x = IP!(
+        id = Value::random_range(100, 103),
+        src = Value::random_mask(
+            Ipv4Address::new(10, 1, 0, 0),
+            Ipv4Address::new(0, 0, 255, 255)
+        )
+    ) / TCP!(dport = Value::random_choice(vec![22, 80, 443]));
+    let mut ids = vec![];
+    for _ in 0..50 {
+        let p = x.fill_with_rng(&mut rng);
+        let ip = p.get_layer(IP!()).unwrap();
+        ids.push(ip.id.value());
+        let src = format!("{:?}", ip.src.value());
+        assert!(src.starts_with("10.1."), "{}", src);
+        let dport = p.get_layer(TCP!()).unwrap().dport.value();
+        assert!([22, 80, 443].contains(&dport));
+    }
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids, vec![100, 101, 102, 103]);
+}
+
+#[test]
+fn random_constraints_value() {
+    /* outside of fill() the first value stands for the others, every time */
+    for _ in 0..10 {
+        assert_eq!(Value::random_range(100u16, 103).value(), 100);
+        assert_eq!(Value::random_choice(vec![22u16, 80, 443]).value(), 22);
+        assert_eq!(Value::random_mask(0x1200u16, 0xff).value(), 0x1200);
+    }
+    let mac: MacAddr = Value::random_choice(vec![
+        MacAddr::new(0, 1, 2, 3, 4, 5),
+        MacAddr::new(0, 1, 2, 3, 4, 6),
+    ])
+    .into();
+    assert_eq!(mac, MacAddr::new(0, 1, 2, 3, 4, 5));
+    assert_eq!(Value::<u16>::random_choice(vec![]).value(), 0);
+}
+
+#[test]
+fn random_constraints_serde() {
+    let x = UDP!(
+        sport = Value::random_range(1024, 2047),
+        dport = Value::random_choice(vec![53, 5353])
+    );
+    let json = serde_json::to_string(&x).unwrap();
+    assert!(json.contains("{\"random\":{\"range\":[1024,2047]}}"));
+    let back: Udp = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, x);
+    assert_eq!(format!("{}", back.sport), "Random(1024..=2047)");
+}
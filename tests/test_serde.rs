@@ -0,0 +1,280 @@
+use scarust::protocols::all::*;
+use scarust::protocols::geneve::*;
+use scarust::protocols::pcap_file::*;
+use scarust::protocols::vxlan::*;
+use scarust::typ::string::*;
+use scarust::*;
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use typenum::{U16, U4};
+
+fn get_pcap_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.push("pcap");
+    path.push(name);
+    path
+}
+
+fn json_roundtrip(x: &LayerStack) -> LayerStack {
+    let j = serde_json::to_string(x).unwrap();
+    let y: LayerStack = serde_json::from_str(&j).unwrap_or_else(|e| panic!("{}: {}", e, &j));
+    assert_eq!(serde_json::to_string(&y).unwrap(), j);
+    y
+}
+
+/* the same type back from its JSON, and the same JSON again from that */
+fn value_roundtrip<T: Serialize + for<'de> Deserialize<'de>>(x: &T) -> T {
+    let j = serde_json::to_string(x).unwrap();
+    let y: T = serde_json::from_str(&j).unwrap_or_else(|e| panic!("{}: {}", e, &j));
+    assert_eq!(serde_json::to_string(&y).unwrap(), j);
+    y
+}
+
+#[test]
+fn serde_crafted_layers() {
+    let stacks = vec![
+        Ether!(src = "02:00:00:00:00:01")
+            / IP!(src = "192.0.2.1", id = 1)
+            / UDP!(dport = 53)
+            / Raw!("abc".as_bytes().to_vec()),
+        Ether!() / ARP!(pdst = "192.0.2.7"),
+        Ether!() / Dot1Q!(vlan = 10) / IP!(id = 2) / TCP!(sport = 80, flags = TCP_FLAG_SYN),
+        IP!(id = 3) / ICMP!() / Raw!(vec![1, 2, 3]),
+        IP!(id = 4) / GRE!(key_present = true, key = 5) / IP!(id = 5),
+        IP!(id = 6) / UDP!(sport = 4789) / VXLAN!(vni = 7) / Ether!() / Padding!(vec![0; 4]),
+        IP!(id = 7) / UDP!(sport = 6081) / GENEVE!(vni = 8),
+        Ether!() / Erspan!(session_id = 3),
+        IP!(id = 8) / UDP!(sport = 68, dport = 67) / BOOTP!(xid = 0x1234),
+    ];
+    for x in stacks {
+        let y = json_roundtrip(&x);
+        assert_eq!(x.clone().encode(), y.encode(), "{}", x.summary());
+    }
+}
+
+#[test]
+fn serde_decoded_pcaps() {
+    let pcaps = [
+        "pcap1.pcap",
+        "pcap2.pcap",
+        "pcap3.pcap",
+        "pcap_3pkts.pcap",
+        "vxlan1.pcap",
+        "vxlan2.pcap",
+        "dhcp.pcap",
+    ];
+    for name in pcaps {
+        let bytes = std::fs::read(get_pcap_path(name)).unwrap();
+        let pcap = PcapFile!().decode(&bytes).unwrap().0;
+        let pcap = pcap.get_layer(PcapFile!()).unwrap();
+        for p in &pcap.d.packets {
+            let x = Ether!().decode(&p.data).unwrap().0;
+            let y = json_roundtrip(&x);
+            assert_eq!(x.clone().encode(), y.encode(), "{}: {}", name, x.summary());
+        }
+    }
+}
+
+#[test]
+fn serde_pcap2json_output() {
+    /* the same as examples/pcap2json.rs writes: one array of layers per packet */
+    let bytes = std::fs::read(get_pcap_path("pcap_3pkts.pcap")).unwrap();
+    let pcap = PcapFile!().decode(&bytes).unwrap().0;
+    let pcap = pcap.get_layer(PcapFile!()).unwrap();
+    let jsons: Vec<String> = pcap
+        .d
+        .packets
+        .iter()
+        .map(|p| serde_json::to_string(&Ether!().decode(&p.data).unwrap().0.layers).unwrap())
+        .collect();
+    let all = format!("[{}]", jsons.join(",\n"));
+    let packets: Vec<Vec<Box<dyn Layer>>> = serde_json::from_str(&all).unwrap();
+    for (p, layers) in pcap.d.packets.iter().zip(packets) {
+        let x = LayerStack {
+            layers,
+            filled: false,
+        };
+        assert_eq!(x.encode(), p.data);
+    }
+}
+
+#[test]
+fn serde_pcap_file_layer() {
+    let mut pcap = PcapFile!();
+    pcap.push(PcapPacket!(data = vec![1, 2, 3]));
+    let x = pcap.to_stack();
+    let y = json_roundtrip(&x);
+    assert_eq!(x.encode(), y.encode());
+}
+
+#[test]
+fn serde_value_variants() {
+    let mut x = IP!();
+    x.ttl = Value::Random;
+    let y = json_roundtrip(&x.to_stack());
+    assert_eq!(y[IP!()].ttl, Value::Random);
+    /* a function is stored as the value it gives */
+    let mut x = UDP!();
+    x.sport = Value::Func(|| 1234);
+    let y = json_roundtrip(&x.to_stack());
+    assert_eq!(y[UDP!()].sport, Value::Set(1234));
+
+    let bad: Result<LayerStack, _> = serde_json::from_str(
+        r#"{"filled":false,"layers":[{"layertype":"Ip","src":"1.2.3","dst":"<auto>"}]}"#,
+    );
+    assert!(bad.is_err());
+}
+
+#[test]
+fn serde_address_types() {
+    let ip: Ipv4Address = "192.0.2.1".into();
+    assert_eq!(value_roundtrip(&ip), ip);
+    let mac: MacAddr = "02:00:00:00:00:01".into();
+    assert_eq!(value_roundtrip(&mac), mac);
+    assert!(serde_json::from_str::<Ipv4Address>("\"300.1.1.1\"").is_err());
+    assert!(serde_json::from_str::<MacAddr>("\"02:00\"").is_err());
+
+    let ha = ArpHardwareAddress::Bytes(vec![1, 2, 3, 4]);
+    assert_eq!(value_roundtrip(&ha), ha);
+    let pa = ArpProtocolAddress::IP("192.0.2.1".into());
+    assert_eq!(value_roundtrip(&pa), pa);
+    let pa = ArpProtocolAddress::Bytes(vec![0, 0, 1, 1, 1, 1]);
+    assert_eq!(value_roundtrip(&pa), pa);
+    for t in [
+        ErspanType::Type1,
+        ErspanType::Type2,
+        ErspanType::Type3,
+        ErspanType::Unknown(9),
+    ] {
+        let v = Value::Set(t.clone());
+        assert_eq!(value_roundtrip(&v), v);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+enum Color {
+    Red = 1,
+    Green = 2,
+    Blue = 4,
+}
+
+impl AsU32 for Color {
+    fn as_u32(data: Self) -> u32 {
+        data as u32
+    }
+}
+
+impl TryFrom<u32> for Color {
+    type Error = ();
+    fn try_from(v: u32) -> Result<Self, ()> {
+        match v {
+            1 => Ok(Color::Red),
+            2 => Ok(Color::Green),
+            4 => Ok(Color::Blue),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AsEnumFlag for Color {
+    fn as_u32(data: &Self) -> u32 {
+        *data as u32
+    }
+    fn from_u32(data: u32) -> Self {
+        Color::try_from(data).unwrap()
+    }
+    fn size_of_enum_flag() -> u32 {
+        8
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let v = u32::deserialize(d)?;
+        Color::try_from(v).map_err(|_| serde::de::Error::custom("bad color"))
+    }
+}
+
+#[test]
+fn serde_typ_types() {
+    let s = FixedSizeString::<U16>::try_from("hello").unwrap();
+    assert!(value_roundtrip(&s).equals_str("hello"));
+    let s = VariableSizeString::try_from("hello").unwrap();
+    value_roundtrip(&s);
+    let f = F64(1.5);
+    assert_eq!(value_roundtrip(&f).0, 1.5);
+    let a = FixedSizeArray::<u16, U4>::try_from(vec![1, 2, 3]).unwrap();
+    assert_eq!(value_roundtrip(&a).0.to_vec(), vec![1, 2, 3, 0]);
+    let a = VariableSizeArray(vec![5u32, 6, 7]);
+    assert_eq!(value_roundtrip(&a).0, vec![5, 6, 7]);
+    let e = SizedEnum::<Color, u8>::new(Color::Blue);
+    assert_eq!(*value_roundtrip(&e).get(), Color::Blue);
+    let f = EnumFlag::try_from(vec![Color::Red, Color::Blue]).unwrap();
+    let g = value_roundtrip(&f);
+    assert_eq!(g.sum(), 5);
+    assert!(g.contains(Color::Blue) && !g.contains(Color::Green));
+}
+
+#[test]
+fn json_to_pcap() {
+    let bytes = std::fs::read(get_pcap_path("pcap_3pkts.pcap")).unwrap();
+    let pcap = PcapFile!().decode(&bytes).unwrap().0;
+    let pcap = pcap.get_layer(PcapFile!()).unwrap();
+    let jsons: Vec<String> = pcap
+        .d
+        .packets
+        .iter()
+        .map(|p| serde_json::to_string(&Ether!().decode(&p.data).unwrap().0.layers).unwrap())
+        .collect();
+    let json = format!("[{}]", jsons.join(",\n"));
+
+    let out = pcapFile::from_json(&json).unwrap();
+    let encoded = out.to_stack().encode();
+    let back = PcapFile!().decode(&encoded).unwrap().0;
+    let back = back.get_layer(PcapFile!()).unwrap();
+    assert_eq!(back.d.packets.len(), 3);
+    for (a, b) in pcap.d.packets.iter().zip(back.d.packets.iter()) {
+        assert_eq!(a.data, b.data);
+        assert_eq!(b.incl_len, Value::Set(a.data.len() as u32));
+        assert_eq!(b.ts_sec, Value::Set(0));
+    }
+}
+
+#[test]
+fn json_to_pcap_timestamps_and_auto_fields() {
+    let x = IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1, ttl = 9) / UDP!(dport = 53);
+    let json = format!(
+        r#"[{{"ts_sec": 100, "ts_usec": 5, "layers": {}}}, {}]"#,
+        serde_json::to_string(&x.layers).unwrap(),
+        serde_json::to_string(&x.layers).unwrap()
+    );
+    let out = pcapFile::from_json(&json).unwrap();
+    assert_eq!(out.d.packets.len(), 2);
+    assert_eq!(out.d.packets[0].ts_sec, Value::Set(100));
+    assert_eq!(out.d.packets[0].ts_usec, Value::Set(5));
+    assert_eq!(out.d.packets[1].ts_sec, Value::Set(0));
+    let y = IP!().decode(&out.d.packets[0].data).unwrap().0;
+    assert_eq!(y[IP!()].len, Value::Set(28));
+    assert_eq!(y[UDP!()].len, Value::Set(8));
+    assert_eq!(y.validate(), vec![]);
+
+    /* the error says what is wrong in the packet */
+    let err = pcapFile::from_json(r#"[{"ts_sec": 1}]"#).unwrap_err();
+    assert!(
+        err.to_string().contains("missing field `layers`"),
+        "{}",
+        err
+    );
+    let err = pcapFile::from_json(r#"[{"ts_sec": "x", "layers": []}]"#).unwrap_err();
+    assert!(err.to_string().contains("invalid type"), "{}", err);
+    let err = pcapFile::from_json(r#"[[{"layertype":"NoSuchLayer"}]]"#).unwrap_err();
+    assert!(err.to_string().contains("NoSuchLayer"), "{}", err);
+    let err = pcapFile::from_json("[5]").unwrap_err();
+    assert!(err.to_string().contains("not 5"), "{}", err);
+    let err = pcapFile::from_json("[[]\n, [").unwrap_err();
+    assert_eq!(err.line(), 2);
+}
@@ -0,0 +1,51 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn udp_packet() -> LayerStack {
+    Ether!(src = "02:00:00:00:00:01", dst = "02:00:00:00:00:02")
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!("hello".as_bytes().to_vec())
+}
+
+#[test]
+fn show_tree() {
+    let x = udp_packet();
+    let s = x.show_string();
+    println!("{}", &s);
+    assert!(s.starts_with("###[ ether ]###\n  dst       = 02:00:00:00:00:02\n"));
+    assert!(s.contains("\n  ###[ Ip ]###\n"));
+    assert!(s.contains("\n    len       = Auto\n"));
+    assert!(s.contains("\n    ###[ Udp ]###\n      sport     = 1234\n"));
+}
+
+#[test]
+fn show2_computed_fields() {
+    let x = udp_packet();
+    let s = x.show2_string();
+    println!("{}", &s);
+    assert!(s.contains("  etype     = 2048 (IP)\n"));
+    assert!(s.contains("    len       = 33\n"));
+    assert!(s.contains("    proto     = 17 (UDP)\n"));
+    assert!(s.contains("      len       = 13\n"));
+    assert!(!s.contains("chksum    = Auto"));
+}
+
+#[test]
+fn hexdump_columns() {
+    let x = IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1, chksum = 0)
+        / UDP!(sport = 1234, dport = 5678, chksum = 0)
+        / Raw!("hello".as_bytes().to_vec());
+    let s = x.hexdump_string();
+    println!("{}", &s);
+    let lines: Vec<&str> = s.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "0000  45 00 00 21 00 01 00 00 40 11 00 00 C0 00 02 01  E..!....@......."
+    );
+    assert_eq!(
+        lines[2],
+        "0020  6F                                               o"
+    );
+}
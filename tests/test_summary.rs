@@ -0,0 +1,63 @@
+use scarust::protocols::all::*;
+use scarust::*;
+
+fn udp_packet() -> LayerStack {
+    Ether!() / IP!(src = "192.0.2.1", dst = "192.0.2.2") / UDP!(sport = 1234, dport = 53)
+}
+
+#[test]
+fn summary_udp() {
+    let x = udp_packet();
+    assert_eq!(
+        x.summary(),
+        "Ether / IP 192.0.2.1 > 192.0.2.2 / UDP 1234 > 53"
+    );
+    let x = x / Raw!("hello".as_bytes().to_vec());
+    assert_eq!(
+        x.summary(),
+        "Ether / IP 192.0.2.1 > 192.0.2.2 / UDP 1234 > 53 / Raw 5 bytes"
+    );
+}
+
+#[test]
+fn summary_tcp_flags() {
+    let x = IP!(src = "192.0.2.2", dst = "192.0.2.1")
+        / TCP!(
+            sport = 80,
+            dport = 40000,
+            flags = TCP_FLAG_SYN | TCP_FLAG_ACK
+        );
+    assert_eq!(x.summary(), "IP 192.0.2.2 > 192.0.2.1 / TCP 80 > 40000 SA");
+    assert_eq!(tcp_flags_letters(TCP_FLAG_PSH | TCP_FLAG_ACK), "PA");
+}
+
+#[test]
+fn summary_decoded_arp() {
+    let bytes: Vec<u8> = vec![
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x08, 0x06, 0x00,
+        0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x00, 0xa0, 0x00, 0xa0, 0xb0, 0x0c, 0x01, 0x01,
+        0x01, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x02, 0x02, 0x02,
+    ];
+    let x = Ether!().decode(&bytes).unwrap().0;
+    assert_eq!(x.summary(), "Ether / ARP who has 2.2.2.2 says 1.1.1.1");
+}
+
+#[test]
+fn sprintf_fields() {
+    let x = udp_packet();
+    assert_eq!(x.sprintf("{IP.src}:{UDP.sport}"), "192.0.2.1:1234");
+    assert_eq!(x.sprintf("{{{Udp.dport}}}"), "{53}");
+    assert_eq!(x.sprintf("{IP.nosuch} {Tcp.sport} {IP[1].src}"), "?? ?? ??");
+}
+
+#[test]
+fn sprintf_indexed_layer() {
+    let x = Ether!()
+        / IP!(src = "10.0.0.1", dst = "10.0.0.2")
+        / GRE!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2");
+    assert_eq!(
+        x.sprintf("{IP.dst} via {IP[1].dst}"),
+        "10.0.0.2 via 192.0.2.2"
+    );
+}
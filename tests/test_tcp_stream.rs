@@ -0,0 +1,146 @@
+use scarust::protocols::all::*;
+use scarust::protocols::pcap_file::*;
+use scarust::tcp_stream::*;
+use scarust::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn seg(client: bool, seq: u32, ack: u32, flags: u8, data: &str) -> LayerStack {
+    let (src, dst, sport, dport) = if client {
+        ("192.0.2.1", "192.0.2.2", 40000, 80)
+    } else {
+        ("192.0.2.2", "192.0.2.1", 80, 40000)
+    };
+    let x = Ether!()
+        / IP!(src = src, dst = dst, id = 1)
+        / TCP!(
+            sport = sport,
+            dport = dport,
+            seq = seq,
+            ack = ack,
+            flags = flags
+        );
+    if data.is_empty() {
+        x
+    } else {
+        x / Raw!(data.as_bytes().to_vec())
+    }
+}
+
+/* a short HTTP-ish exchange with reordering, a retransmission and an overlap */
+fn conversation() -> Vec<LayerStack> {
+    let ack = TCP_FLAG_ACK;
+    vec![
+        seg(true, 1000, 0, TCP_FLAG_SYN, ""),
+        seg(false, 5000, 1001, TCP_FLAG_SYN | ack, ""),
+        seg(true, 1001, 5001, ack, ""),
+        // "GET / HTTP/1.0\r\n\r\n" split in three, the middle one arrives last
+        seg(true, 1001, 5001, ack, "GET "),
+        seg(true, 1012, 5001, ack, "1.0\r\n\r\n"),
+        seg(true, 1005, 5001, ack, "/ HTTP/"),
+        // retransmission and a segment overlapping already delivered data
+        seg(true, 1001, 5001, ack, "GET "),
+        seg(false, 5001, 1019, ack, "HTTP/1.0 200"),
+        seg(false, 5009, 1019, ack, " 200 OK\r\n"),
+        seg(false, 5018, 1019, TCP_FLAG_FIN | ack, ""),
+        seg(true, 1019, 5019, TCP_FLAG_FIN | ack, ""),
+        seg(false, 5019, 1020, ack, ""),
+    ]
+}
+
+fn key() -> TcpFlowKey {
+    TcpFlowKey {
+        client: TcpEndpoint {
+            addr: "192.0.2.1".into(),
+            port: 40000,
+        },
+        server: TcpEndpoint {
+            addr: "192.0.2.2".into(),
+            port: 80,
+        },
+    }
+}
+
+#[test]
+fn reassemble_conversation() {
+    let mut r = TcpReassembler::new();
+    for (i, s) in conversation().into_iter().enumerate() {
+        let (d, _) = Ether!().decode(&s.encode()).unwrap();
+        r.push(&d, Duration::from_millis(i as u64));
+    }
+    let stream = r.stream(&key()).unwrap();
+    assert_eq!(stream.state, TcpStreamState::Closed);
+    assert_eq!(
+        stream.data(TcpDirection::ClientToServer),
+        b"GET / HTTP/1.0\r\n\r\n"
+    );
+    assert_eq!(
+        stream.data(TcpDirection::ServerToClient),
+        b"HTTP/1.0 200 OK\r\n"
+    );
+    assert_eq!(stream.client.out_of_order_segments, 1);
+    assert_eq!(stream.client.retransmitted_bytes, 4);
+    assert_eq!(stream.server.overlapping_bytes, 4);
+    assert_eq!(stream.follow().len(), 2);
+    let text = stream.follow_text();
+    assert!(text.contains("Node 0: 192.0.2.1:40000\n"));
+    assert!(text.contains("\t17\nHTTP/1.0 200 OK.\n\n"));
+}
+
+#[test]
+fn reassemble_callbacks_from_pcap() {
+    let mut pcap = PcapFile!();
+    for s in conversation() {
+        pcap.push(PcapPacket!(data = s.encode()));
+    }
+    let bytes = pcap.to_stack().encode();
+    let (decoded, _) = PcapFile!().decode(&bytes).unwrap();
+    let pcap = decoded.get_layer(PcapFile!()).unwrap();
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let closed = Rc::new(RefCell::new(0));
+    let mut r = TcpReassembler::new();
+    let seen_cb = seen.clone();
+    r.on_data(move |_key, dir, data| seen_cb.borrow_mut().push((dir, data.to_vec())));
+    let closed_cb = closed.clone();
+    r.on_close(move |_stream| *closed_cb.borrow_mut() += 1);
+    for p in &pcap.d.packets {
+        r.push_packet(p);
+    }
+    let seen = seen.borrow();
+    assert_eq!(seen[0], (TcpDirection::ClientToServer, b"GET ".to_vec()));
+    // the late middle segment releases the buffered one too
+    assert_eq!(
+        seen[1],
+        (TcpDirection::ClientToServer, b"/ HTTP/1.0\r\n\r\n".to_vec())
+    );
+    assert_eq!(seen.len(), 4);
+    assert_eq!(*closed.borrow(), 1);
+}
+
+#[test]
+fn reset_and_reuse() {
+    let mut r = TcpReassembler::new();
+    let t = Duration::from_secs(0);
+    r.push(&seg(true, 1000, 0, TCP_FLAG_SYN, ""), t);
+    r.push(&seg(false, 0, 1001, TCP_FLAG_RST | TCP_FLAG_ACK, ""), t);
+    assert_eq!(r.stream(&key()).unwrap().state, TcpStreamState::Reset);
+
+    r.push(&seg(true, 7000, 0, TCP_FLAG_SYN, ""), t);
+    r.push(&seg(true, 7001, 0, TCP_FLAG_ACK, "hi"), t);
+    assert_eq!(r.streams().len(), 2);
+    let stream = r.stream(&key()).unwrap();
+    assert_eq!(stream.data(TcpDirection::ClientToServer), b"hi");
+}
+
+#[test]
+fn midstream_pickup() {
+    let mut r = TcpReassembler::new();
+    let t = Duration::from_secs(0);
+    r.push(&seg(true, 3000, 9000, TCP_FLAG_ACK, "abc"), t);
+    r.push(&seg(true, 3003, 9000, TCP_FLAG_ACK, "def"), t);
+    let stream = r.stream(&key()).unwrap();
+    assert_eq!(stream.state, TcpStreamState::Established);
+    assert_eq!(stream.data(TcpDirection::ClientToServer), b"abcdef");
+}
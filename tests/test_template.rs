@@ -0,0 +1,193 @@
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::template::*;
+use scarust::*;
+
+fn vxlan_stack(sport: u16, src: &str, ttl: u8, vni: u32) -> LayerStack {
+    Ether!(src = "00:01:02:03:04:05")
+        / IP!(id = 1, src = src, dst = "192.0.2.2", ttl = ttl)
+        / UDP!(sport = sport)
+        / VXLAN!(vni = vni)
+        / Ether!()
+        / IP!(id = 2)
+        / UDP!(sport = 7, dport = 9)
+        / Raw!("inner".into())
+}
+
+#[test]
+fn template_render_matches_encode() {
+    let t = vxlan_stack(1, "192.0.2.1", 64, 1).compile();
+    assert_eq!(t.bytes(), &vxlan_stack(1, "192.0.2.1", 64, 1).encode()[..]);
+
+    for (sport, src, ttl, vni) in [
+        (1u16, 0xc0000201u32, 64u8, 1u32),
+        (5000, 0xc0000201, 64, 1),
+        (65535, 0x0a000001, 1, 0xffffff),
+        (4242, 0xc0a80105, 255, 0x123456),
+    ] {
+        let src_str = std::net::Ipv4Addr::from(src).to_string();
+        let expected = vxlan_stack(sport, &src_str, ttl, vni).encode();
+        let out = t
+            .render_vec(&[
+                ("Udp.sport", sport as u64),
+                ("Ip.src", src as u64),
+                ("Ip.ttl", ttl as u64),
+                ("Vxlan.vni", vni as u64),
+            ])
+            .unwrap();
+        assert_eq!(out, expected);
+        let (decoded, _) = Ether!().decode(&out).unwrap();
+        assert_eq!(decoded.validate(), vec![]);
+    }
+}
+
+#[test]
+fn template_nested_checksums() {
+    /* the inner UDP checksum changes, and the GRE checksum over it with it */
+    let x = |sport: u16, dst: &str| {
+        IP!(id = 1)
+            / GRE!(chksum_present = true, proto = 0x6558)
+            / Ether!()
+            / IP!(id = 2, dst = dst)
+            / UDP!(sport = sport, dport = 53)
+            / Raw!("query".into())
+    };
+    let t = x(1, "192.0.2.9").compile();
+    let out = t
+        .render_vec(&[("Udp.sport", 1234), ("Ip[1].dst", 0xc0000263)])
+        .unwrap();
+    assert_eq!(out, x(1234, "192.0.2.99").encode());
+    let (decoded, _) = IP!().decode(&out).unwrap();
+    assert_eq!(decoded.validate(), vec![]);
+}
+
+#[test]
+fn template_bits_and_slots() {
+    let x = |tos: u8, seq: u32, flags: u8| {
+        IP!(id = 1, tos = tos) / TCP!(sport = 1, dport = 80, seq = seq, flags = flags)
+    };
+    let t = x(0, 0, 2).compile();
+    let seq = t.slot("TCP.seq").unwrap();
+    assert_eq!((seq.layer_index, seq.offset, seq.length), (1, 24, 4));
+    let mut buf = [0u8; 128];
+    for n in [1u32, 0xdeadbeef, 0xffffffff] {
+        let len = t.render(&mut buf, &[(seq, n as u64)]).unwrap();
+        assert_eq!(&buf[..len], &x(0, n, 2).encode()[..]);
+    }
+    let out = t
+        .render_vec(&[("Ip.tos", 0x10), ("Tcp.flags", 0x12)])
+        .unwrap();
+    assert_eq!(out, x(0x10, 0, 0x12).encode());
+
+    /* a field in some bits of a byte keeps the bits around it */
+    let t = (IP!(id = 1, ihl = 5) / UDP!()).compile();
+    let version = t.slot("Ip.version").unwrap();
+    assert_eq!((version.offset, version.bits), (0, Some((0, 4))));
+    let out = t.render_vec(&[(version, 6)]).unwrap();
+    assert_eq!(out, (IP!(id = 1, ihl = 5, version = 6) / UDP!()).encode());
+    assert!(t.render_vec(&[(version, 16)]).is_err());
+}
+
+#[test]
+fn template_errors() {
+    let t = (IP!() / UDP!() / Raw!("x".into())).compile();
+    assert_eq!(
+        t.slot("Ip.chksum"),
+        Err(TemplateError::ComputedField("Ip.chksum".into()))
+    );
+    assert_eq!(
+        t.slot("UDP.len"),
+        Err(TemplateError::ComputedField("UDP.len".into()))
+    );
+    assert_eq!(
+        t.slot("Tcp.sport"),
+        Err(TemplateError::NoSuchField("Tcp.sport".into()))
+    );
+    assert_eq!(
+        t.slot("Udp.nope"),
+        Err(TemplateError::NoSuchField("Udp.nope".into()))
+    );
+    assert_eq!(
+        t.render_vec(&[("Ip.ttl", 256)]),
+        Err(TemplateError::ValueTooLarge {
+            field: "ttl".into(),
+            value: 256
+        })
+    );
+    let mut buf = [0u8; 10];
+    let err = t.render::<&str>(&mut buf, &[]).unwrap_err();
+    assert_eq!(
+        err,
+        TemplateError::BufferTooSmall {
+            needed: t.len(),
+            available: 10
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        format!("the packet needs {} bytes, the buffer has 10", t.len())
+    );
+
+    /* a checksum set by hand is a field like any other */
+    let t = (IP!(chksum = 0x1234) / UDP!()).compile();
+    assert!(t.slot("Ip.chksum").is_ok());
+    assert!(t.slot("Udp.chksum").is_err());
+}
+
+#[test]
+fn template_checks_given_slots() {
+    let t = (IP!() / UDP!()).compile();
+    let big = (IP!() / TCP!() / Raw!("payload".into())).compile();
+    let seq = big.slot("TCP.seq").unwrap();
+    let mut far = seq;
+    far.offset = 30;
+    assert_eq!(
+        t.render_vec(&[(far, 1)]),
+        Err(TemplateError::SlotOutOfRange {
+            field: "seq".into(),
+            offset: 30,
+            length: 4
+        })
+    );
+    far.offset = usize::MAX;
+    assert!(matches!(
+        t.render_vec(&[(far, 1)]),
+        Err(TemplateError::SlotOutOfRange { .. })
+    ));
+
+    /* the checksum of the IP header, made up by hand */
+    let chksum = TemplateSlot {
+        layer_index: 0,
+        field: "chksum",
+        offset: 10,
+        length: 2,
+        bits: None,
+    };
+    assert_eq!(
+        t.render_vec(&[(chksum, 1)]),
+        Err(TemplateError::ComputedField("chksum".into()))
+    );
+    let wide = TemplateSlot {
+        layer_index: 0,
+        field: "src",
+        offset: 0,
+        length: 16,
+        bits: None,
+    };
+    assert_eq!(
+        t.render_vec(&[(wide, 1)]),
+        Err(TemplateError::UnsupportedField("src".into()))
+    );
+    /* four bits from the seventh one of a byte */
+    let bits = TemplateSlot {
+        layer_index: 0,
+        field: "ttl",
+        offset: 8,
+        length: 1,
+        bits: Some((6, 4)),
+    };
+    assert_eq!(
+        t.render_vec(&[(bits, 1)]),
+        Err(TemplateError::UnsupportedField("ttl".into()))
+    );
+}
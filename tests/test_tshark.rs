@@ -0,0 +1,138 @@
+use scarust::protocols::all::*;
+use scarust::tshark::*;
+use scarust::*;
+#[macro_use]
+extern crate scarust_derive;
+
+use serde::{Deserialize, Serialize};
+
+mod common;
+use common::*;
+
+fn udp_packet() -> LayerStack {
+    Ether!(src = "52:54:00:12:34:56", dst = "ff:ff:ff:ff:ff:ff")
+        / IP!(id = 0x1234, src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 1234, dport = 53)
+        / Raw!("hi".as_bytes().to_vec())
+}
+
+#[test]
+fn tshark_fields_and_positions() {
+    let p = TsharkPacket::new(&udp_packet(), 1, None);
+    let names: Vec<&str> = p.protos.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, vec!["frame", "eth", "ip", "udp", "data"]);
+
+    let frame = p.proto("frame").unwrap();
+    assert_eq!(
+        frame.field("frame.protocols").unwrap().show,
+        "eth:ip:udp:data"
+    );
+    assert_eq!(frame.field("frame.len").unwrap().show, "44");
+
+    let ip = p.proto("ip").unwrap();
+    assert_eq!((ip.pos, ip.size), (14, 20));
+    let src = ip.field("ip.src").unwrap();
+    assert_eq!(src.show, "192.0.2.1");
+    assert_eq!((src.pos, src.size), (26, 4));
+    assert_eq!(src.value, "c0000201");
+    assert_eq!(ip.field("ip.id").unwrap().show, "0x1234");
+    assert_eq!(ip.field("ip.hdr_len").unwrap().show, "20");
+    /* the checksum is the computed one, not "Auto" */
+    assert!(ip.field("ip.checksum").unwrap().show.starts_with("0x"));
+
+    let udp = p.proto("udp").unwrap();
+    assert_eq!(udp.field("udp.dstport").unwrap().show, "53");
+    assert_eq!(udp.field("udp.length").unwrap().show, "10");
+    let data = p.proto("data").unwrap();
+    assert_eq!(data.field("data.data").unwrap().show, "68:69");
+    assert_eq!(data.field("data.len").unwrap().show, "2");
+}
+
+#[test]
+fn tshark_json_structure() {
+    let p = TsharkPacket::new(&udp_packet(), 1, None);
+    let json = to_tshark_json(&[p], true);
+    let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let layers = &v[0]["_source"]["layers"];
+    assert_eq!(v[0]["_type"], "doc");
+    assert_eq!(layers["eth"]["eth.src"], "52:54:00:12:34:56");
+    assert_eq!(layers["ip"]["ip.dst"], "192.0.2.2");
+    assert_eq!(layers["ip"]["ip.dst_raw"][0], "c0000202");
+    assert_eq!(layers["ip"]["ip.dst_raw"][1], 30);
+    assert_eq!(layers["ip"]["ip.dst_raw"][2], 4);
+    assert_eq!(layers["udp_raw"][1], 34);
+    assert_eq!(layers["udp"]["udp.srcport"], "1234");
+}
+
+#[test]
+fn tshark_pcap_padding_and_timestamps() {
+    let pcap = read_pcap_file("pcap_3pkts.pcap");
+    let packets = tshark_packets_from_pcap(&pcap);
+    assert_eq!(packets.len(), 3);
+    let json = to_tshark_json(&packets, false);
+    let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(v.as_array().unwrap().len(), 3);
+    assert_eq!(v[0]["_index"], "packets-2023-10-17");
+    let layers = &v[0]["_source"]["layers"];
+    assert_eq!(layers["frame"]["frame.time_epoch"], "1697538804.078602000");
+    assert_eq!(layers["tcp"]["tcp.srcport"], "443");
+    assert_eq!(layers["tcp"]["tcp.flags"], "0x0018");
+    assert!(layers["ip"].get("ip.src_raw").is_none());
+    /* padding is shown as part of the ethernet header, like tshark does */
+    let layers = &v[2]["_source"]["layers"];
+    assert_eq!(layers["eth"]["eth.padding"], "00:00:00:00:00:00");
+    assert_eq!(layers["frame"]["frame.protocols"], "eth:ip:tcp");
+    assert_eq!(v[1]["_source"]["layers"]["ip"]["ip.flags"], "0x40");
+}
+
+#[test]
+fn tshark_pdml() {
+    let p = TsharkPacket::new(&udp_packet(), 7, None);
+    let pdml = to_pdml(&[p]);
+    assert!(pdml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<pdml"));
+    assert!(pdml.ends_with("</pdml>\n"));
+    assert!(pdml.contains(
+        "<proto name=\"ip\" showname=\"Internet Protocol Version 4\" size=\"20\" pos=\"14\">"
+    ));
+    assert!(pdml.contains(
+        "<field name=\"ip.src\" showname=\"Source Address: 192.0.2.1\" size=\"4\" pos=\"26\" show=\"192.0.2.1\" value=\"c0000201\"/>"
+    ));
+    assert!(pdml.contains("<field name=\"num\" pos=\"0\" show=\"7\""));
+    /* bit fields carry the masked value and the whole bytes */
+    assert!(pdml.contains("show=\"4\" value=\"4\" unmaskedvalue=\"45\"/>"));
+}
+
+#[test]
+fn tshark_vxlan() {
+    use scarust::protocols::vxlan::*;
+    let x = IP!(src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 1, dport = 4789)
+        / VXLAN!(vni = 42);
+    let p = TsharkPacket::new(&x, 1, None);
+    assert_eq!(tshark_proto("Udp").unwrap().name, "udp");
+    let vxlan = p.proto("vxlan").unwrap();
+    assert_eq!(vxlan.field("vxlan.vni").unwrap().show, "42");
+    assert_eq!(vxlan.pos, 28);
+}
+
+#[derive(NetworkProtocol, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[nproto(register(UDP_DST_PORT_APPS, DstPort = 40001))]
+pub struct testMagic {
+    pub magic: Value<u32>,
+    pub kind: Value<u8>,
+}
+
+#[test]
+fn tshark_unmapped_layer() {
+    let x = IP!(src = "192.0.2.1", dst = "192.0.2.2")
+        / UDP!(sport = 1, dport = 40001)
+        / TestMagic!(magic = 0xfeedf00d, kind = 3);
+    let p = TsharkPacket::new(&x, 1, None);
+    /* layers outside the table use the lowercase layer name and our field names */
+    let proto = p.proto("testmagic").unwrap();
+    assert_eq!((proto.pos, proto.size), (28, 5));
+    let magic = proto.field("testmagic.magic").unwrap();
+    assert_eq!(magic.show, "4277006349");
+    assert_eq!(magic.value, "feedf00d");
+    assert_eq!(proto.field("testmagic.kind").unwrap().show, "3");
+}
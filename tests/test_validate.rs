@@ -0,0 +1,132 @@
+use scarust::*;
+
+use scarust::protocols::all::*;
+
+mod common;
+use common::*;
+
+fn udp_packet_bytes() -> Vec<u8> {
+    let x = Ether!()
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", id = 1)
+        / UDP!(sport = 1234, dport = 5678)
+        / Raw!("hello".as_bytes().to_vec());
+    x.encode()
+}
+
+#[test]
+fn validate_encoded_udp() {
+    let bytes = udp_packet_bytes();
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(x.validate(), vec![]);
+}
+
+#[test]
+fn validate_bad_ip_chksum() {
+    let mut bytes = udp_packet_bytes();
+    let good_chksum = u16::from_be_bytes([bytes[24], bytes[25]]);
+    bytes[25] ^= 0x55;
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    let res = x.validate();
+    println!("validate: {:?}", &res);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].layer_index, 1);
+    assert_eq!(res[0].layer, "Ip");
+    assert_eq!(res[0].field, "chksum");
+    assert_eq!(res[0].expected, good_chksum as u64);
+    assert_eq!(res[0].actual, (good_chksum ^ 0x55) as u64);
+}
+
+#[test]
+fn validate_bad_udp_len() {
+    let mut bytes = udp_packet_bytes();
+    // UDP length lies about the payload
+    bytes[39] = 0x20;
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    let res = x.validate();
+    println!("validate: {:?}", &res);
+    let len_err = res
+        .iter()
+        .find(|m| m.layer == "Udp" && m.field == "len")
+        .expect("UDP len mismatch");
+    assert_eq!(len_err.layer_index, 2);
+    assert_eq!(len_err.expected, 13);
+    assert_eq!(len_err.actual, 0x20);
+}
+
+#[test]
+fn validate_bad_tcp_chksum() {
+    let x = IP!(id = 1) / TCP!(sport = 20, dport = 80);
+    let mut bytes = x.encode();
+    bytes[37] ^= 1;
+    let (x, _) = IP!().decode(&bytes).unwrap();
+    let res = x.validate();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].field, "chksum");
+    assert_eq!(res[0].layer, "Tcp");
+    assert_eq!(res[0].expected, 0x917c);
+}
+
+#[test]
+fn validate_oversized_tcp() {
+    /* as captured after GRO: an IP length of 0 and more than 64K of payload */
+    let x = IP!(id = 1, len = 0) / TCP!(sport = 20, dport = 80) / Raw!(vec![1; 70000]);
+    let bytes = x.encode();
+    let (x, _) = IP!().decode(&bytes).unwrap();
+    let res = x.validate();
+    assert!(res.iter().all(|m| m.layer != "Tcp"), "{:?}", &res);
+
+    let x =
+        IP!(id = 1, len = 0) / TCP!(sport = 20, dport = 80, chksum = 0x1234) / Raw!(vec![1; 70000]);
+    let res = x.validate();
+    let chksum_err = res
+        .iter()
+        .find(|m| m.layer == "Tcp")
+        .expect("TCP chksum mismatch");
+    assert_eq!(chksum_err.field, "chksum");
+    assert_eq!(chksum_err.actual, 0x1234);
+}
+
+#[test]
+fn validate_icmp_echo() {
+    let x = IP!(id = 1)
+        / ICMP!()
+        / Echo!(identifier = 1, sequence = 2)
+        / Raw!("ping".as_bytes().to_vec());
+    let mut bytes = x.encode();
+    let (decoded, _) = IP!().decode(&bytes).unwrap();
+    assert_eq!(decoded.validate(), vec![]);
+
+    // corrupting the payload is caught by the ICMP checksum
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    let (decoded, _) = IP!().decode(&bytes).unwrap();
+    let res = decoded.validate();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].layer, "Icmp");
+}
+
+#[test]
+fn validate_gre_chksum() {
+    let x = IP!(id = 1) / GRE!(chksum_present = true) / IP!(id = 2) / UDP!(sport = 1, dport = 2);
+    let bytes = x.encode();
+    let (decoded, _) = IP!().decode(&bytes).unwrap();
+    let gre = decoded.get_layer(GRE!()).unwrap();
+    assert_ne!(gre.chksum, Value::Set(0));
+    assert_eq!(decoded.validate(), vec![]);
+
+    let mut bytes = bytes;
+    bytes[24] ^= 0x10;
+    let (decoded, _) = IP!().decode(&bytes).unwrap();
+    let res = decoded.validate();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].layer, "Gre");
+    assert_eq!(res[0].field, "chksum");
+}
+
+#[test]
+fn validate_pcap3() {
+    for d in read_pcap("pcap3.pcap") {
+        let (x, _) = Ether!().decode(&d).unwrap();
+        assert_eq!(x.validate(), vec![]);
+    }
+}
@@ -0,0 +1,293 @@
+use scarust::protocols::all::*;
+use scarust::protocols::vxlan::*;
+use scarust::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+mod common;
+use common::*;
+
+/* counts the allocations of the current thread, for the tests run in parallel */
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn allocations<R>(f: impl FnOnce() -> R) -> (usize, R) {
+    let before = ALLOCATIONS.with(|n| n.get());
+    let r = f();
+    (ALLOCATIONS.with(|n| n.get()) - before, r)
+}
+
+fn view_names(v: &StackView) -> Vec<&'static str> {
+    v.layers().map(|l| l.name()).collect()
+}
+
+/* the decoded layers, without the ones a view leaves as bytes */
+fn decoded_names(x: &LayerStack) -> Vec<&'static str> {
+    x.layers
+        .iter()
+        .map(|l| l.layer_name())
+        .filter(|n| *n != "raw" && *n != "padding")
+        .collect()
+}
+
+#[test]
+fn view_fields() {
+    let bytes = (Ether!(src = "00:01:02:03:04:05")
+        / IP!(src = "192.0.2.1", dst = "192.0.2.2", ttl = 17)
+        / UDP!(sport = 1234)
+        / VXLAN!(vni = 0x123456)
+        / Raw!("inner".into()))
+    .encode();
+    let v = StackView::new(ether::VIEW, &bytes);
+    assert_eq!(view_names(&v), vec!["ether", "Ip", "Udp", "Vxlan"]);
+    assert_eq!(v.rest(), b"inner");
+
+    let e = v.get::<etherView>().unwrap();
+    assert_eq!(e.src(), MacAddr::from("00:01:02:03:04:05"));
+    assert_eq!(e.etype(), 0x800);
+    let ip = v.get::<IpView>().unwrap();
+    assert_eq!(ip.version(), 4);
+    assert_eq!(ip.ihl(), 5);
+    assert_eq!(ip.ttl(), 17);
+    assert_eq!(ip.src(), Ipv4Address::from("192.0.2.1"));
+    assert_eq!(ip.len() as usize, bytes.len() - 14);
+    assert_eq!(ip.header().len(), 20);
+    assert_eq!(ip.payload().len(), bytes.len() - 34);
+    let udp = v.get::<UdpView>().unwrap();
+    assert_eq!((udp.sport(), udp.dport()), (1234, 4789));
+    assert_eq!(v.get::<VxlanView>().unwrap().vni(), 0x123456);
+    assert!(v.get::<TcpView>().is_none());
+
+    let l = v.layer(1).unwrap();
+    assert_eq!((l.offset, l.header_len), (14, 20));
+    assert!(v.layer(4).is_none());
+}
+
+#[test]
+fn view_to_owned() {
+    let bytes = (Ether!() / IP!() / TCP!(dport = 22) / Raw!("ssh".into())).encode_padded(60);
+    let v = StackView::new(ether::VIEW, &bytes);
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    assert_eq!(v.to_stack().show_string(), x.show_string());
+    assert_eq!(
+        &v.get::<TcpView>().unwrap().to_layer(),
+        x.get_layer(TCP!()).unwrap()
+    );
+    /* the padding after the IP datagram is not the TCP payload */
+    assert_eq!(v.rest(), b"ssh");
+    assert_eq!(IpView::new(&bytes[14..]).unwrap().payload().len(), 23);
+}
+
+#[test]
+fn view_short_buffers() {
+    let bytes = (IP!() / UDP!()).encode();
+    assert!(IpView::new(&bytes[..10]).is_none());
+    let v = StackView::new(Ip::VIEW, &bytes[..10]);
+    assert!(v.is_empty());
+    assert_eq!(v.rest(), &bytes[..10]);
+    assert_eq!(decoded_names(&v.to_stack()), Vec::<&str>::new());
+
+    let v = StackView::new(Ip::VIEW, &bytes[..24]);
+    assert_eq!(view_names(&v), vec!["Ip"]);
+    assert_eq!(v.rest().len(), 4);
+}
+
+#[test]
+fn view_matches_decode() {
+    for name in ["pcap1.pcap", "pcap_3pkts.pcap", "dhcp.pcap", "vxlan1.pcap"] {
+        for p in &read_pcap_file(name).d.packets {
+            let v = StackView::new(ether::VIEW, &p.data);
+            let (x, _) = Ether!().decode(&p.data).unwrap();
+            assert_eq!(view_names(&v), decoded_names(&x));
+            for (l, d) in v.layers().zip(x.layers.iter()) {
+                assert_eq!(l.name(), d.layer_name());
+            }
+            if let Some(ip) = v.get::<IpView>() {
+                assert_eq!(&ip.to_layer(), x.get_layer(IP!()).unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn view_without_allocating() {
+    let bytes = (Ether!(src = "00:01:02:03:04:05")
+        / IP!(ttl = 17)
+        / UDP!()
+        / VXLAN!(vni = 42)
+        / Raw!("inner".into()))
+    .encode();
+    /* the first walk fills the registries */
+    StackView::new(ether::VIEW, &bytes);
+    let (n, sum) = allocations(|| {
+        let v = StackView::new(ether::VIEW, &bytes);
+        let e = v.get::<etherView>().unwrap();
+        let ip = v.get::<IpView>().unwrap();
+        let udp = v.get::<UdpView>().unwrap();
+        let vx = v.get::<VxlanView>().unwrap();
+        assert_eq!(e.src(), MacAddr::from("00:01:02:03:04:05"));
+        (ip.ttl() as u32) + (udp.dport() as u32) + vx.vni() + v.len() as u32
+    });
+    assert_eq!(sum, 17 + 4789 + 42 + 4);
+    assert_eq!(n, 0);
+}
+
+/* the getters read the fields at their offsets and bits, the same as decode() gives */
+#[test]
+fn view_getters_match_decode() {
+    let bytes = (Ether!()
+        / Dot1Q!(vlan = 100, prio = 5, id = 1)
+        / IP!(tos = 0x10, id = 0x1234, frag = 0x4000, ttl = 7)
+        / GRE!(key_present = true, key = 5u32, proto = 0x88be)
+        / Erspan!(
+            vlan = 33,
+            cos = 6,
+            encap_type = 2,
+            truncated = true,
+            session_id = 0x155,
+            port_index = 0xabcde
+        ))
+    .encode();
+    let (x, _) = Ether!().decode(&bytes).unwrap();
+    let v = StackView::new(ether::VIEW, &bytes);
+
+    let d = x.get_layer(Dot1Q!()).unwrap();
+    let dv = v.get::<dot1QView>().unwrap();
+    assert_eq!(
+        (dv.prio(), dv.id(), dv.vlan(), dv.etype()),
+        (
+            d.prio.value(),
+            d.id.value(),
+            d.vlan.value(),
+            d.etype.value()
+        )
+    );
+    assert_eq!((dv.prio(), dv.id(), dv.vlan()), (5, 1, 100));
+
+    let ip = x.get_layer(IP!()).unwrap();
+    let ipv = v.get::<IpView>().unwrap();
+    assert_eq!(
+        (
+            ipv.version(),
+            ipv.ihl(),
+            ipv.tos(),
+            ipv.len(),
+            ipv.id(),
+            ipv.frag()
+        ),
+        (
+            ip.version.value(),
+            ip.ihl.value(),
+            ip.tos.value(),
+            ip.len.value(),
+            ip.id.value(),
+            ip.frag.value()
+        )
+    );
+    assert_eq!(
+        (ipv.ttl(), ipv.proto(), ipv.chksum(), ipv.src(), ipv.dst()),
+        (
+            ip.ttl.value(),
+            ip.proto.value(),
+            ip.chksum.value(),
+            ip.src.value(),
+            ip.dst.value()
+        )
+    );
+
+    let gre = x.get_layer(GRE!()).unwrap();
+    let grev = v.get::<GreView>().unwrap();
+    assert_eq!(
+        (
+            grev.chksum_present(),
+            grev.key_present(),
+            grev.recursion_control(),
+            grev.version(),
+            grev.proto(),
+            grev.key()
+        ),
+        (
+            gre.chksum_present.value(),
+            gre.key_present.value(),
+            gre.recursion_control.value(),
+            gre.version.value(),
+            gre.proto.value(),
+            gre.key.value()
+        )
+    );
+
+    let er = x.get_layer(Erspan!()).unwrap();
+    let erv = v.get::<erspanView>().unwrap();
+    assert_eq!(
+        (
+            erv.version(),
+            erv.vlan(),
+            erv.cos(),
+            erv.encap_type(),
+            erv.truncated(),
+            erv.session_id(),
+            erv.port_index(),
+            erv.reserved1()
+        ),
+        (
+            er.version.value(),
+            er.vlan.value(),
+            er.cos.value(),
+            er.encap_type.value(),
+            er.truncated.value(),
+            er.session_id.value(),
+            er.port_index.value(),
+            er.reserved1.value()
+        )
+    );
+    assert_eq!((erv.session_id(), erv.port_index()), (0x155, 0xabcde));
+
+    let bytes = (IP!()
+        / TCP!(
+            sport = 1,
+            dport = 2,
+            seq = 3,
+            ack = 4,
+            flags = 0x18,
+            window = 5,
+            urgptr = 6
+        ))
+    .encode();
+    let (x, _) = IP!().decode(&bytes).unwrap();
+    let tcp = x.get_layer(TCP!()).unwrap();
+    let tcpv = StackView::new(Ip::VIEW, &bytes).get::<TcpView>().unwrap();
+    assert_eq!(&tcpv.to_layer(), tcp);
+    assert_eq!(
+        (
+            tcpv.dataofs(),
+            tcpv.reserved(),
+            tcpv.flags(),
+            tcpv.window(),
+            tcpv.urgptr()
+        ),
+        (
+            tcp.dataofs.value(),
+            tcp.reserved.value(),
+            tcp.flags.value(),
+            tcp.window.value(),
+            tcp.urgptr.value()
+        )
+    );
+    assert_eq!((tcpv.seq(), tcpv.ack(), tcpv.dataofs()), (3, 4, 5));
+}